@@ -1,4 +1,5 @@
 use failure::Error;
+use std::time::Duration;
 
 pub trait Act {
     /// Activate or re-activate the act, making it
@@ -16,9 +17,26 @@ pub trait Act {
     /// cancellation failed.
     fn cancel(&mut self) -> Result<(), Error>;
 
+    /// Like `cancel`, but lets the act end gradually over the
+    /// given duration, e.g. by fading out sound.
+    ///
+    /// The act is not `done` until the fade is over. Cancels
+    /// right away by default.
+    fn cancel_with_fade(&mut self, fade: Duration) -> Result<(), Error> {
+        let _ = fade;
+        self.cancel()
+    }
+
     /// Tries to check if the act has either completed
     /// or been cancelled.
     ///
     /// Returns an error only if the check failed.
     fn done(&self) -> Result<bool, Error>;
+
+    /// Time until the act finishes by itself, if known.
+    ///
+    /// `None` by default.
+    fn remaining(&self) -> Option<Duration> {
+        None
+    }
 }
@@ -1,34 +1,96 @@
-use crate::acts::{Act, Ensemble, Ring, SoundSpec, Wait};
+use crate::acts::{Act, Ensemble, Ring, SharedVoice, SystemVoice, Wait};
 use crate::err::compound_result;
 use crate::evt::{Event, Responder, ResponderState};
 use crate::phone::Phone;
 use crate::result::Result;
 use crate::states::State;
 use log::{debug, error, warn};
-use std::mem::replace;
+use std::cell::RefCell;
+use std::mem::{replace, take};
+use std::rc::Rc;
 use std::sync::{Arc, Mutex, PoisonError};
-use tavla::{any_voice, Voice};
+use std::time::Duration;
+
+#[cfg(test)]
+use crate::acts::SoundSpec;
+
+/// What an active act does, to report progress.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum ActKind {
+    Speech,
+    /// Rings the phone, or silently waits if there is none.
+    Ring,
+}
 
 pub struct Actuators {
-    active: Vec<Box<dyn Act>>,
+    active: Vec<(ActKind, Box<dyn Act>)>,
     phone: Option<Arc<Mutex<Phone>>>,
-    ensemble: Ensemble,
+    /// Shared so the sounds can outlive the actuators, e.g. to
+    /// fade out after switching books.
+    ensemble: Rc<RefCell<Ensemble>>,
+    /// Speaks the text of states.
+    voice: SharedVoice,
+    /// Sounds that are left behind in a transition fade out over
+    /// this duration.
+    fade_out: Duration,
+    /// Volume scale of the sounds while speaking or ringing, or
+    /// `None` to keep the volume.
+    duck_level: Option<f32>,
 }
 
 impl Actuators {
+    #[cfg(test)]
     pub fn new(phone: &Option<Arc<Mutex<Phone>>>, sound_specs: &[SoundSpec]) -> Result<Self> {
-        let actuators = Actuators {
+        let ensemble = Rc::new(RefCell::new(Ensemble::from_specs(sound_specs)?));
+        Ok(Self::with_ensemble(phone, &ensemble))
+    }
+
+    /// Makes actuators that play sounds through the given ensemble.
+    pub fn with_ensemble(
+        phone: &Option<Arc<Mutex<Phone>>>,
+        ensemble: &Rc<RefCell<Ensemble>>,
+    ) -> Self {
+        Actuators {
             active: vec![],
-            ensemble: Ensemble::from_specs(sound_specs)?,
+            ensemble: Rc::clone(ensemble),
             phone: phone.as_ref().map(Arc::clone),
-        };
+            voice: Arc::new(SystemVoice),
+            fade_out: Duration::from_secs(0),
+            duck_level: None,
+        }
+    }
 
-        Ok(actuators)
+    /// Speaks with the given voice instead of any voice of the
+    /// system.
+    pub fn with_voice(mut self, voice: &SharedVoice) -> Self {
+        self.voice = Arc::clone(voice);
+        self
+    }
+
+    /// Lowers the volume of sounds to the given scale while
+    /// speaking or ringing, or keeps it with `None`.
+    pub fn set_duck_level(&mut self, duck_level: Option<f32>) {
+        self.duck_level = duck_level;
+    }
+
+    /// Fades out sounds that are not part of the next state over
+    /// the given duration instead of stopping them right away,
+    /// e.g. when hanging up.
+    pub fn set_fade_out(&mut self, fade_out: Duration) {
+        self.fade_out = fade_out;
     }
 
     /// Sets all actuators back into the initial state.
     pub fn reset(&mut self) -> Result<()> {
-        self.ensemble.reset()
+        self.ensemble.borrow_mut().reset()
+    }
+
+    /// Stops all sounds, speech and ringing right away, e.g. for
+    /// an emergency stop.
+    pub fn cancel_all_sounds(&mut self) -> Result<()> {
+        let acts = cancel_all(&mut take(&mut self.active));
+        let sounds = self.ensemble.borrow_mut().stop_all();
+        compound_result(vec![acts, sounds])
     }
 
     fn do_update(&mut self) -> Result<()> {
@@ -36,7 +98,7 @@ impl Actuators {
         let update_errs: Vec<_> = self
             .active
             .iter_mut()
-            .map(|a| (*a).update())
+            .map(|(_, a)| a.update())
             .filter_map(Result::err)
             .collect();
 
@@ -45,13 +107,22 @@ impl Actuators {
         }
 
         // remove finished acts
-        self.active.retain(|a| {
+        self.active.retain(|(_, a)| {
             let done = a.done().unwrap_or(false);
             !done
         });
 
+        // duck sounds while speaking or ringing
+        let duck = match self.duck_level {
+            Some(level) if self.is_active(ActKind::Speech) || self.is_active(ActKind::Ring) => {
+                level
+            }
+            _ => 1.0,
+        };
+        self.ensemble.borrow_mut().set_duck(duck);
+
         // update sounds
-        let ensemble_update = self.ensemble.update();
+        let ensemble_update = self.ensemble.borrow_mut().update();
         if let Err(err) = ensemble_update {
             error!("Sound update failures: {:?}", err);
         }
@@ -64,22 +135,45 @@ impl Actuators {
     /// Returns `false` if some actuators are still working, e.g.
     /// speech is still ongoing.
     pub fn done(&self) -> bool {
-        self.active.is_empty() && self.ensemble.non_loop_sounds_idle()
+        self.active.is_empty() && self.ensemble.borrow().is_idle()
+    }
+
+    /// `true` if all sounds that do not loop have been played.
+    pub fn sounds_done(&self) -> bool {
+        self.ensemble.borrow().all_non_loop_sounds_done()
+    }
+
+    /// `true` if nothing is being spoken.
+    pub fn speech_done(&self) -> bool {
+        !self.is_active(ActKind::Speech)
+    }
+
+    /// Time until the phone stops ringing, or `None` if it does
+    /// not ring.
+    pub fn ring_remaining(&self) -> Option<Duration> {
+        self.active
+            .iter()
+            .filter(|(kind, _)| *kind == ActKind::Ring)
+            .find_map(|(_, act)| act.remaining())
+    }
+
+    fn is_active(&self, kind: ActKind) -> bool {
+        self.active
+            .iter()
+            .any(|(k, act)| *k == kind && !act.done().unwrap_or(false))
     }
 
     pub fn transition_to(&mut self, state: &State) -> Result<()> {
-        self.ensemble.transition_to(state.sounds())?;
-        self.transition_content(self.make_act_states(state))?;
-        Ok(())
+        self.transition_content(state.sounds(), self.make_act_states(state))
     }
 
-    fn make_act_states(&self, state: &State) -> Vec<Box<dyn Act>> {
-        let mut acts: Vec<Box<dyn Act>> = vec![];
+    fn make_act_states(&self, state: &State) -> Vec<(ActKind, Box<dyn Act>)> {
+        let mut acts: Vec<(ActKind, Box<dyn Act>)> = vec![];
 
         if !state.speech().is_empty() {
-            acts.push(Box::new(
-                any_voice()
-                    .expect("Could not load a voice")
+            acts.push((
+                ActKind::Speech,
+                self.voice
                     .speak(state.speech())
                     .expect("Could not start speech for state"),
             ));
@@ -87,35 +181,47 @@ impl Actuators {
 
         if let Some(duration) = state.ring_time() {
             if let Some(phone) = self.phone.as_ref() {
-                acts.push(Box::new(
-                    Ring::new(phone, duration).expect("Failed to start ring"),
+                acts.push((
+                    ActKind::Ring,
+                    Box::new(Ring::new(phone, duration).expect("Failed to start ring")),
                 ))
             } else {
                 // If no real bell available, do a silent bell for timeout purposes only
-                acts.push(Box::new(Wait::new(duration)))
+                acts.push((ActKind::Ring, Box::new(Wait::new(duration))))
             }
         }
 
         acts
     }
 
-    fn transition_content(&mut self, next_acts: Vec<Box<dyn Act>>) -> Result<()> {
+    fn transition_content(
+        &mut self,
+        next_sounds: &[usize],
+        next_acts: Vec<(ActKind, Box<dyn Act>)>,
+    ) -> Result<()> {
+        // fade out sounds that are left behind
+        self.ensemble
+            .borrow_mut()
+            .transition_with_fade(next_sounds, self.fade_out)?;
+
         // replace self.active with new
         if let Err(errs) = cancel_all(&mut replace(&mut self.active, next_acts)) {
             warn!("Some acts could not be cancelled: {}", errs);
         };
 
         // and activate replaced contents
-        compound_result(self.active.iter_mut().map(|a| (*a).activate()))
+        compound_result(self.active.iter_mut().map(|(_, a)| a.activate()))
     }
 }
 
-fn cancel_all(acts: &mut Vec<Box<dyn Act>>) -> Result<()> {
-    compound_result(acts.iter_mut().map(|a| (*a).cancel()))
+fn cancel_all(acts: &mut Vec<(ActKind, Box<dyn Act>)>) -> Result<()> {
+    compound_result(acts.iter_mut().map(|(_, a)| a.cancel()))
 }
 
 impl Drop for Actuators {
     fn drop(&mut self) {
+        // cancel rings while the phone is still around, then make
+        // sure it is quiet before releasing it
         let mut acts = &mut replace(&mut self.active, vec![]);
 
         match cancel_all(&mut acts) {
@@ -136,7 +242,7 @@ impl Drop for Actuators {
 impl Responder<State> for Actuators {
     fn respond(&mut self, event: &Event<State>) -> Result<()> {
         match event {
-            Event::Start { initial } => {
+            Event::Start { initial } | Event::Reset { initial, .. } => {
                 self.reset()?;
                 self.transition_to(initial)
             }
@@ -159,7 +265,7 @@ impl Responder<State> for Actuators {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::testutil::{assert_duration, MediaInfo, WILHELM_SCREAM};
+    use crate::testutil::{assert_duration, MediaInfo, MockVoice, WILHELM_SCREAM};
     use std::thread::yield_now;
     use std::time::{Duration, Instant};
 
@@ -196,6 +302,66 @@ mod test {
         assert_eq!(state_after, ResponderState::Idle);
     }
 
+    #[test]
+    fn speaks_with_injected_voice() {
+        // given
+        let voice = MockVoice::default();
+        let mut actuators = Actuators::new(&None, &[])
+            .expect("could not create actuators")
+            .with_voice(&(Arc::new(voice.clone()) as SharedVoice));
+        let speaking = &State::builder().speech("Hello").build();
+
+        // when
+        actuators
+            .respond(&Event::Start { initial: speaking })
+            .expect("failed to respond");
+
+        // then
+        assert_eq!(voice.spoken_texts(), vec!["Hello".to_string()]);
+    }
+
+    #[test]
+    fn sounds_ducked_while_speaking() {
+        // given
+        let voice = MockVoice::speaking_for(Duration::from_millis(200));
+        let mut actuators = Actuators::new(&None, &[])
+            .expect("could not create actuators")
+            .with_voice(&(Arc::new(voice) as SharedVoice));
+        actuators.set_duck_level(Some(0.3));
+        let speaking = &State::builder().speech("Hello").build();
+
+        // when
+        actuators
+            .respond(&Event::Start { initial: speaking })
+            .expect("failed to respond");
+        actuators.update().unwrap();
+        let duck_while_speaking = actuators.ensemble.borrow().duck();
+        while let ResponderState::Running = actuators.update().unwrap() {
+            yield_now();
+        }
+        let duck_after_speaking = actuators.ensemble.borrow().duck();
+
+        // then
+        assert_eq!(duck_while_speaking, 0.3);
+        assert_eq!(duck_after_speaking, 1.0);
+    }
+
+    #[test]
+    fn sounds_not_ducked_without_duck_level() {
+        // given
+        let mut actuators = Actuators::new(&None, &[]).expect("could not create actuators");
+        let ringing = &State::builder().ring_for(Duration::from_secs(1)).build();
+
+        // when
+        actuators
+            .respond(&Event::Start { initial: ringing })
+            .expect("failed to respond");
+        actuators.update().unwrap();
+
+        // then
+        assert_eq!(actuators.ensemble.borrow().duck(), 1.0);
+    }
+
     #[cfg_attr(not(feature = "expensive_tests"), ignore)]
     #[test]
     fn responder_state_changes_to_idle_when_non_loop_music_finished() {
@@ -1,15 +1,27 @@
+//! Sounds that can be played outside of a phonebook, e.g. by
+//! custom responders.
+//!
+//! Sounds are made from a `SoundSpec` and a `PlayerContext`,
+//! and are controlled through the `Act` trait, like silent waits
+//! made with `Wait::builder`.
+
 mod act;
 mod actuators;
 mod ring;
 mod sounds;
-pub mod speech;
+mod speech;
 mod wait;
 
 pub use act::Act;
-pub use actuators::Actuators;
-pub use ring::Ring;
-pub use sounds::{Ensemble, Sound, SoundSpec};
-pub use wait::Wait;
+pub(crate) use actuators::Actuators;
+pub(crate) use ring::Ring;
+pub(crate) use sounds::Player;
+pub use sounds::{
+    Ensemble, PlayerContext, ReenterBehavior, SharedPlayerContext, Sound, SoundSpec,
+    SoundSpecBuilder, SoundSpecBuilderNeedingSource,
+};
+pub use speech::{SharedVoice, SystemVoice, Voice};
+pub use wait::{Wait, WaitBuilder};
 
 #[cfg(test)]
 mod test {
@@ -1,33 +1,61 @@
 use crate::acts::Act;
 use crate::phone::Phone;
 use failure::Error;
+use std::io;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
-pub struct Ring {
-    phone: Arc<Mutex<Phone>>,
+/// Something that can ring, usually the phone.
+pub trait Bell {
+    fn ring(&mut self) -> io::Result<()>;
+    fn unring(&mut self) -> io::Result<()>;
+}
+
+impl Bell for Phone {
+    fn ring(&mut self) -> io::Result<()> {
+        Phone::ring(self)
+    }
+
+    fn unring(&mut self) -> io::Result<()> {
+        Phone::unring(self)
+    }
+}
+
+pub struct Ring<B: Bell = Phone> {
+    bell: Arc<Mutex<B>>,
     start: Instant,
     duration: Duration,
+    is_ringing: bool,
     is_done: bool,
 }
 
-impl Ring {
-    pub fn new(phone: &Arc<Mutex<Phone>>, duration: Duration) -> Result<Self, Error> {
+impl<B: Bell> Ring<B> {
+    pub fn new(bell: &Arc<Mutex<B>>, duration: Duration) -> Result<Self, Error> {
         let ring = Ring {
-            phone: Arc::clone(phone),
+            bell: Arc::clone(bell),
             start: Instant::now(),
             duration,
+            is_ringing: false,
             is_done: false,
         };
 
         Ok(ring)
     }
+
+    /// `true` after activation until the ring has been cancelled
+    /// or the duration is over.
+    #[cfg(test)]
+    pub fn is_ringing(&self) -> bool {
+        self.is_ringing
+    }
 }
 
-impl Act for Ring {
+impl<B: Bell> Act for Ring<B> {
     fn activate(&mut self) -> Result<(), Error> {
-        let mut phone = self.phone.lock().expect("Failed to obtain lock on phone");
-        Ok(phone.ring()?)
+        let mut bell = self.bell.lock().expect("Failed to obtain lock on phone");
+        bell.ring()?;
+        self.is_ringing = true;
+        Ok(())
     }
 
     fn update(&mut self) -> Result<(), Error> {
@@ -45,10 +73,107 @@ impl Act for Ring {
         Ok(self.is_done)
     }
 
+    fn remaining(&self) -> Option<Duration> {
+        if self.is_done {
+            None
+        } else {
+            Some(self.duration.saturating_sub(self.start.elapsed()))
+        }
+    }
+
+    /// A bell cannot ring more quietly, so instead the ring is
+    /// shortened to end after the fade, unless it ends earlier anyway.
+    fn cancel_with_fade(&mut self, fade: Duration) -> Result<(), Error> {
+        if self.is_done {
+            return Ok(());
+        }
+
+        let end = self.start.elapsed() + fade;
+        if end < self.duration {
+            self.duration = end;
+        }
+        Ok(())
+    }
+
+    /// Unrings the bell, regardless of whether the duration is
+    /// over yet, unless already done.
     fn cancel(&mut self) -> Result<(), Error> {
-        let mut phone = self.phone.lock().expect("Failed to obtain lock on phone");
-        phone.unring()?;
+        if self.is_done {
+            return Ok(());
+        }
+
+        let mut bell = self.bell.lock().expect("Failed to obtain lock on phone");
+        bell.unring()?;
+        self.is_ringing = false;
         self.is_done = true;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread::sleep;
+
+    #[derive(Default)]
+    struct CountingBell {
+        rings: usize,
+        unrings: usize,
+    }
+
+    impl Bell for CountingBell {
+        fn ring(&mut self) -> io::Result<()> {
+            self.rings += 1;
+            Ok(())
+        }
+
+        fn unring(&mut self) -> io::Result<()> {
+            self.unrings += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn cancel_before_duration_unrings_once() {
+        // given
+        let bell = Arc::new(Mutex::new(CountingBell::default()));
+        let mut ring = Ring::new(&bell, Duration::from_secs(5)).unwrap();
+
+        // when
+        ring.activate().unwrap();
+        let ringing_after_activate = ring.is_ringing();
+        sleep(Duration::from_millis(100));
+        ring.update().unwrap();
+        ring.cancel().unwrap();
+        ring.update().unwrap();
+        ring.cancel().unwrap();
+
+        // then
+        assert!(ringing_after_activate);
+        assert!(!ring.is_ringing());
+        assert!(ring.done().unwrap());
+        let bell = bell.lock().unwrap();
+        assert_eq!(bell.rings, 1);
+        assert_eq!(bell.unrings, 1);
+    }
+
+    #[test]
+    fn cancel_with_fade_unrings_after_fade() {
+        // given
+        let bell = Arc::new(Mutex::new(CountingBell::default()));
+        let mut ring = Ring::new(&bell, Duration::from_secs(5)).unwrap();
+        ring.activate().unwrap();
+
+        // when
+        ring.cancel_with_fade(Duration::from_millis(100)).unwrap();
+        ring.update().unwrap();
+        let done_during_fade = ring.done().unwrap();
+        sleep(Duration::from_millis(150));
+        ring.update().unwrap();
+
+        // then
+        assert!(!done_during_fade);
+        assert!(ring.done().unwrap());
+        assert_eq!(bell.lock().unwrap().unrings, 1);
+    }
+}
@@ -2,17 +2,85 @@ use crate::result::Result;
 
 use failure::format_err;
 
+use std::cell::RefCell;
+use std::ops::Deref;
+use std::rc::Rc;
+
 /// Manages resources required for creating players.
 pub struct PlayerContext(vlc::Instance);
 
+thread_local! {
+    /// Lazily initialized context for reading media on this thread.
+    static THREAD_CONTEXT: RefCell<Option<Rc<PlayerContext>>> = const { RefCell::new(None) };
+}
+
 impl PlayerContext {
+    /// Initializes libvlc, which can take a moment.
     pub fn new() -> Result<Self> {
         vlc::Instance::new()
             .ok_or_else(|| format_err!("Could not load libvlc"))
             .map(PlayerContext)
     }
 
+    /// Initializes libvlc to play on the ALSA device with the
+    /// given name instead of the default device.
+    pub fn with_audio_device(device: &str) -> Result<Self> {
+        vlc::Instance::with_args(Some(vec![format!("--alsa-audio-device={}", device)]))
+            .ok_or_else(|| format_err!("Could not load libvlc for audio device {:?}", device))
+            .map(PlayerContext)
+    }
+
+    /// A context shared by everything on the current thread that
+    /// reads media, e.g. for checking sound durations when compiling,
+    /// so libvlc is only initialized once rather than per book.
+    pub fn for_current_thread() -> Result<Rc<Self>> {
+        THREAD_CONTEXT.with(|ctx| {
+            let mut ctx = ctx.borrow_mut();
+            if ctx.is_none() {
+                *ctx = Some(Rc::new(PlayerContext::new()?));
+            }
+
+            Ok(Rc::clone(ctx.as_ref().unwrap())) // safe: either was already there or just created
+        })
+    }
+
     pub(crate) fn vlc_instance(&self) -> &vlc::Instance {
         &self.0
     }
 }
+
+/// A player context that can be used by multiple ensembles,
+/// e.g. to avoid initializing VLC again on every book switch.
+///
+/// Cloning is cheap and refers to the same context.
+#[derive(Clone)]
+pub struct SharedPlayerContext(Rc<PlayerContext>);
+
+impl SharedPlayerContext {
+    pub fn new() -> Result<Self> {
+        PlayerContext::new().map(|ctx| SharedPlayerContext(Rc::new(ctx)))
+    }
+}
+
+impl Deref for SharedPlayerContext {
+    type Target = PlayerContext;
+
+    fn deref(&self) -> &PlayerContext {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn thread_context_is_initialized_once() {
+        // when
+        let first = PlayerContext::for_current_thread().unwrap();
+        let second = PlayerContext::for_current_thread().unwrap();
+
+        // then
+        assert!(Rc::ptr_eq(&first, &second));
+    }
+}
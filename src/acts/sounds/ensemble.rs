@@ -1,14 +1,18 @@
-use super::PlayerContext;
+use super::{Player, SharedPlayerContext};
 use crate::acts::Act;
 use crate::acts::{Sound, SoundSpec};
-use crate::err::compound_result;
-use failure::Error;
+use crate::err::{compound_error, compound_result};
+use crate::states::{Clock, SystemClock};
+use failure::{format_err, Error};
+use std::mem::swap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 /// Responsible for playing back multiple sounds at the same time
 /// and transitioning between them.
 pub struct Ensemble {
     /// Shared resources of the sounds.
-    _player_ctx: PlayerContext,
+    _player_ctx: SharedPlayerContext,
     /// The spec that was used to create the sounds
     /// in the sound vector.
     ///
@@ -20,52 +24,268 @@ pub struct Ensemble {
     /// Index is also its unique ID.
     /// Indexes/IDs are paired with the specs vector.
     sounds: Vec<Sound>,
+    /// Volume scale of all sounds, from `0.0` for silence to `1.0`
+    /// for full volume.
+    volume: f32,
+    /// Additional volume scale while speech or ringing is going on,
+    /// `1.0` when not ducked.
+    duck: f32,
+    /// If set, the volume changes on update until the ramp is done.
+    ramp: Option<VolumeRamp>,
+    /// Time for sounds to fade in and out on transitions.
+    crossfade: Duration,
+    /// Source of the time for volume ramps.
+    clock: Rc<dyn Clock>,
+}
+
+/// Linear change of the volume over time.
+struct VolumeRamp {
+    ramp_start: Instant,
+    ramp_from: f32,
+    ramp_to: f32,
+    ramp_duration: Duration,
+}
+
+impl VolumeRamp {
+    /// Volume at the given time, `ramp_to` when done.
+    fn current(&self, now: Instant) -> f32 {
+        let elapsed = now.saturating_duration_since(self.ramp_start);
+        if elapsed >= self.ramp_duration {
+            self.ramp_to
+        } else {
+            let progress = elapsed.as_secs_f32() / self.ramp_duration.as_secs_f32();
+            self.ramp_from + (self.ramp_to - self.ramp_from) * progress
+        }
+    }
+
+    fn done(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.ramp_start) >= self.ramp_duration
+    }
 }
 
 impl Ensemble {
+    /// Makes an ensemble with its own player context.
+    /// Currently only used in tests.
+    #[cfg(test)]
     pub fn from_specs<'a, I: IntoIterator<Item = &'a SoundSpec>>(sounds: I) -> Result<Self, Error> {
+        let ctx = SharedPlayerContext::new()
+            .map_err(|e| format_err!("Failed to initialize VLC player context: {}", e))?;
+        Self::from_specs_with_shared_ctx(sounds, &ctx)
+    }
+
+    /// Like `from_specs`, but creates the players with a context that
+    /// may also be used by other ensembles.
+    ///
+    /// All sounds are read at the same time, so loading takes about
+    /// as long as the slowest sound rather than all of them together.
+    ///
+    /// If some sounds cannot be loaded, the error lists every failed
+    /// sound with its source path.
+    pub fn from_specs_with_shared_ctx<'a, I: IntoIterator<Item = &'a SoundSpec>>(
+        sounds: I,
+        ctx: &SharedPlayerContext,
+    ) -> Result<Self, Error> {
         let specs = sounds.into_iter().cloned().collect::<Vec<SoundSpec>>();
-        let ctx = PlayerContext::new()?;
+        let ctx = ctx.clone();
 
-        specs
+        // VLC reads media on its own threads, start all of them
+        // before waiting for the first one
+        let loading: Vec<_> = specs
             .iter()
-            .map(|s| Sound::from_spec_with_ctx(s, &ctx))
-            .collect::<Result<Vec<_>, Error>>()
-            .map(|sounds| Ensemble {
-                _player_ctx: ctx,
-                specs,
-                sounds,
-            })
+            .map(|spec| Player::start_loading(spec.source(), &ctx))
+            .collect();
+
+        let mut sounds = Vec::with_capacity(specs.len());
+        let mut errors = vec![];
+        for (spec, player) in specs.iter().zip(loading) {
+            match player
+                .and_then(|player| player.finish())
+                .and_then(|player| Sound::new(player, spec))
+            {
+                Ok(sound) => sounds.push(sound),
+                Err(err) => errors.push(format_err!(
+                    "Failed to load sound {:?}: {}",
+                    spec.source(),
+                    err
+                )),
+            }
+        }
+        compound_error(errors)?;
+
+        Ok(Ensemble {
+            _player_ctx: ctx,
+            specs,
+            sounds,
+            volume: 1.0,
+            duck: 1.0,
+            ramp: None,
+            crossfade: Duration::from_secs(0),
+            clock: Rc::new(SystemClock),
+        })
+    }
+
+    /// Replaces the clock of the ensemble and its sounds.
+    #[cfg(test)]
+    pub fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        self.sounds
+            .iter_mut()
+            .for_each(|sound| sound.set_clock(Rc::clone(&clock)));
+        self.clock = clock;
+    }
+
+    /// Takes over sounds from a previous ensemble, e.g. of another
+    /// book, if they play the same content the same way, so they
+    /// keep their playback position.
+    ///
+    /// The sounds that were replaced go to the previous ensemble
+    /// in exchange. Returns the number of reused sounds.
+    pub fn reuse_matching(&mut self, previous: &mut Ensemble) -> usize {
+        let mut taken = vec![false; previous.specs.len()];
+        let mut reused = 0;
+
+        for (spec, sound) in self.specs.iter().zip(self.sounds.iter_mut()) {
+            let matching = previous
+                .specs
+                .iter()
+                .enumerate()
+                .find(|(idx, other)| !taken[*idx] && spec.semantically_equal(other))
+                .map(|(idx, _)| idx);
+
+            if let Some(idx) = matching {
+                taken[idx] = true;
+                swap(sound, &mut previous.sounds[idx]);
+                reused += 1;
+            }
+        }
+
+        reused
+    }
+
+    #[cfg(test)]
+    pub fn sound(&self, idx: usize) -> &Sound {
+        &self.sounds[idx]
     }
 
-    /// Rewinds and stops all sounds.
+    /// Stops all sounds and rewinds them to their start offsets.
+    ///
+    /// The players are kept, so starting over is quicker than
+    /// making a new ensemble.
     pub fn reset(&mut self) -> Result<(), Error> {
         compound_result(self.sounds.iter_mut().map(Sound::reset))
     }
 
+    /// Pauses all sounds right away, without fading out.
+    pub fn stop_all(&mut self) -> Result<(), Error> {
+        compound_result(self.sounds.iter_mut().map(Sound::stop))
+    }
+
     /// Activates all sounds at the given indexes and cancels all
     /// others.
     ///
+    /// Sounds fade in and out over the crossfade duration, or the
+    /// fade in and fade out of their specs if longer.
+    ///
     /// The indexes originate from the insertion order using the iterator
-    /// passed to `from_specs`.
+    /// passed to `from_specs_with_shared_ctx`.
     pub fn transition_to(&mut self, target_sound_ids: &[usize]) -> Result<(), Error> {
+        self.transition_with_fade(target_sound_ids, Duration::from_secs(0))
+    }
+
+    /// Like `transition_to`, but sounds that are not in the target
+    /// set fade out over at least the given duration.
+    pub fn transition_with_fade(
+        &mut self,
+        target_sound_ids: &[usize],
+        fade_out: Duration,
+    ) -> Result<(), Error> {
+        let fade_in = self.crossfade;
+        let fade_out = fade_out.max(self.crossfade);
         compound_result(self.sounds.iter_mut().enumerate().map(|(id, sound)| {
             if target_sound_ids.contains(&id) {
                 // Activate sound or keep it active if in the target set
-                sound.activate()
+                sound.activate_with_fade(fade_in)
             } else {
                 // Cancel sounds that are not in the new set or keep them cancelled
-                sound.cancel()
+                sound.cancel_with_fade(fade_out)
             }
         }))
     }
 
+    /// Fades in sounds entering and fades out sounds leaving on
+    /// every following transition over the given duration.
+    pub fn set_crossfade(&mut self, crossfade: Duration) {
+        self.crossfade = crossfade;
+    }
+
+    /// `true` while any sound fades in or out.
+    pub fn is_fading(&self) -> bool {
+        self.sounds.iter().any(Sound::fading)
+    }
+
+    /// Scales the volume of all sounds, from `0.0` for silence
+    /// to `1.0` for full volume, e.g. to fade between ensembles.
+    ///
+    /// Stops any ongoing volume ramp.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.ramp = None;
+        self.apply_volume(volume);
+    }
+
+    /// Linearly changes the volume of all sounds from the current
+    /// volume to the target volume over the given duration.
+    ///
+    /// The volume is adjusted on every `update`, so the ramp is as
+    /// smooth as the tick interval.
+    pub fn volume_ramp(&mut self, target: f32, duration: Duration) {
+        self.ramp = Some(VolumeRamp {
+            ramp_start: self.clock.now(),
+            ramp_from: self.volume,
+            ramp_to: target,
+            ramp_duration: duration,
+        });
+    }
+
+    /// The current volume scale of all sounds.
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    /// Lowers the volume of all sounds to the given scale on top of
+    /// the ensemble volume, e.g. to make speech easier to hear over
+    /// music. Set to `1.0` to release.
+    pub fn set_duck(&mut self, duck: f32) {
+        if self.duck != duck {
+            self.duck = duck;
+            self.apply_volume(self.volume);
+        }
+    }
+
+    /// The current duck scale, `1.0` if not ducked.
+    pub fn duck(&self) -> f32 {
+        self.duck
+    }
+
+    fn apply_volume(&mut self, volume: f32) {
+        self.volume = volume;
+        let scale = volume * self.duck;
+        self.sounds.iter_mut().for_each(|s| s.set_volume(scale));
+    }
+
     pub fn update(&mut self) -> Result<(), Error> {
+        if let Some(ramp) = self.ramp.as_ref() {
+            let now = self.clock.now();
+            let volume = ramp.current(now);
+            if ramp.done(now) {
+                self.ramp = None;
+            }
+            self.apply_volume(volume);
+        }
+
         compound_result(self.sounds.iter_mut().map(|s| (*s).update()))
     }
 
     /// Checks if all non-loop sounds are done.
-    pub fn non_loop_sounds_idle(&self) -> bool {
+    pub fn all_non_loop_sounds_done(&self) -> bool {
         self.sounds
             .iter()
             .zip(self.specs.iter())
@@ -75,11 +295,37 @@ impl Ensemble {
                     sound.done().unwrap_or(false)
             })
     }
+
+    /// Checks if the ensemble has nothing left to play, that is,
+    /// all non-loop sounds are done and no sound is fading.
+    pub fn is_idle(&self) -> bool {
+        self.all_non_loop_sounds_done() && !self.is_fading()
+    }
+
+    /// Checks if any non-loop sound is still playing or about to play,
+    /// or any sound is fading.
+    pub fn has_active_sounds(&self) -> bool {
+        !self.is_idle()
+    }
+
+    #[deprecated(note = "use `is_idle` or `all_non_loop_sounds_done` instead")]
+    pub fn non_loop_sounds_idle(&self) -> bool {
+        self.is_idle()
+    }
+
+    /// Allows tests in other modules to check if any sound
+    /// is currently audible.
+    #[cfg(test)]
+    pub fn any_playing(&self) -> bool {
+        self.sounds.iter().any(Sound::playing)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::err::CompoundError;
+    use crate::states::MockClock;
     use std::thread::sleep;
     use std::time::Duration;
 
@@ -340,6 +586,62 @@ mod test {
         );
     }
 
+    #[test]
+    fn crossfade_takes_crossfade_duration() {
+        // given
+        let crossfade = Duration::from_millis(300);
+        let clock = MockClock::new();
+        let spec = SoundSpec::builder()
+            .source(crate::testutil::TEST_MUSIC)
+            .looping(true)
+            .build();
+        let mut ensemble =
+            Ensemble::from_specs(&[spec.clone(), spec]).expect("could not make ensemble");
+        ensemble.set_clock(Rc::new(clock.clone()));
+        ensemble.set_crossfade(crossfade);
+        ensemble.transition_to(&[0]).unwrap();
+        clock.advance(crossfade);
+        ensemble.update().unwrap();
+
+        // when
+        ensemble.transition_to(&[1]).unwrap();
+        ensemble.update().unwrap();
+        let both_playing = ensemble.sounds.iter().all(Sound::playing);
+        let idle_while_fading = ensemble.is_idle();
+        clock.advance(crossfade - Duration::from_millis(1));
+        ensemble.update().unwrap();
+        let fading_before_end = ensemble.is_fading();
+        clock.advance(Duration::from_millis(1));
+        ensemble.update().unwrap();
+
+        // then
+        assert!(both_playing, "expected both sounds to play during the fade");
+        assert!(!idle_while_fading, "expected not to be idle while fading");
+        assert!(fading_before_end, "expected fade to take {:?}", crossfade);
+        assert!(!ensemble.is_fading());
+        assert!(ensemble.sounds[1].playing());
+        assert!(!ensemble.sounds[0].playing());
+    }
+
+    #[test]
+    fn idle_agrees_with_active_sounds() {
+        // given
+        let specs = [SoundSpec::builder()
+            .source(crate::testutil::WILHELM_SCREAM)
+            .build()];
+        let mut ensemble = Ensemble::from_specs(&specs).expect("could not make ensemble");
+
+        // when
+        let initially = (ensemble.is_idle(), ensemble.has_active_sounds());
+        ensemble.transition_to(&[0]).unwrap();
+        ensemble.update().unwrap();
+        let after_enter = (ensemble.is_idle(), ensemble.has_active_sounds());
+
+        // then
+        assert_eq!(initially, (true, false));
+        assert_eq!(after_enter, (false, true));
+    }
+
     #[test]
     fn not_idle_after_reenter_finished() {
         // given
@@ -349,22 +651,22 @@ mod test {
         let mut ensemble = Ensemble::from_specs(&specs).expect("could not make ensemble");
 
         // when
-        let initially_idle = ensemble.non_loop_sounds_idle();
+        let initially_idle = ensemble.is_idle();
 
         ensemble.transition_to(&[0]).unwrap();
         ensemble.update().unwrap();
 
-        let idle_after_enter = ensemble.non_loop_sounds_idle();
+        let idle_after_enter = ensemble.is_idle();
 
         ensemble.sounds[0].fast_forward(Duration::from_millis(200));
         ensemble.update().unwrap();
 
-        let idle_after_ff = ensemble.non_loop_sounds_idle();
+        let idle_after_ff = ensemble.is_idle();
 
         sleep(Duration::from_millis(500));
         ensemble.update().unwrap();
 
-        let idle_after_finish = ensemble.non_loop_sounds_idle();
+        let idle_after_finish = ensemble.is_idle();
 
         ensemble.update().unwrap();
         ensemble.transition_to(&[]).unwrap(); // leave
@@ -372,10 +674,10 @@ mod test {
         ensemble.transition_to(&[0]).unwrap(); // re-enter
         ensemble.update().unwrap();
 
-        let idle_after_reenter = ensemble.non_loop_sounds_idle();
+        let idle_after_reenter = ensemble.is_idle();
         sleep(Duration::from_millis(50));
         ensemble.update().unwrap();
-        let idle_after_reenter_and_wait = ensemble.non_loop_sounds_idle();
+        let idle_after_reenter_and_wait = ensemble.is_idle();
 
         // then
         assert!(initially_idle);
@@ -386,6 +688,190 @@ mod test {
         assert!(!idle_after_reenter_and_wait);
     }
 
+    #[test]
+    fn reuse_playing_sound_with_same_content() {
+        // given
+        let dir = tempfile::tempdir().unwrap();
+        let copied_music = dir.path().join("copy.mp3");
+        std::fs::copy(crate::testutil::TEST_MUSIC, &copied_music).unwrap();
+        let mut previous = Ensemble::from_specs(&[SoundSpec::builder()
+            .source(crate::testutil::TEST_MUSIC)
+            .build()])
+        .unwrap();
+        let mut next = Ensemble::from_specs(&[
+            SoundSpec::builder()
+                .source(crate::testutil::WILHELM_SCREAM)
+                .build(),
+            SoundSpec::builder().source(&copied_music).build(),
+        ])
+        .unwrap();
+        previous.transition_to(&[0]).unwrap();
+        previous.update().unwrap();
+        sleep(Duration::from_millis(100));
+
+        // when
+        let reused = next.reuse_matching(&mut previous);
+        drop(previous);
+        next.transition_to(&[1]).unwrap();
+        next.update().unwrap();
+
+        // then
+        assert_eq!(reused, 1);
+        assert!(!next.sounds[0].playing());
+        assert!(
+            next.sounds[1].playing(),
+            "expected reused sound to keep playing"
+        );
+    }
+
+    /// Compares loading ten sounds at once with loading them one
+    /// after another.
+    #[cfg_attr(not(feature = "expensive_tests"), ignore)]
+    #[test]
+    fn loading_sounds_at_once_is_faster() {
+        // given
+        let ctx = SharedPlayerContext::new().unwrap();
+        let specs: Vec<SoundSpec> = (0..10)
+            .map(|_| {
+                SoundSpec::builder()
+                    .source(crate::testutil::WILHELM_SCREAM)
+                    .build()
+            })
+            .collect();
+
+        // when
+        let start = Instant::now();
+        for spec in specs.iter() {
+            Sound::from_spec_with_ctx(spec, &ctx).unwrap();
+        }
+        let one_by_one = start.elapsed();
+        let start = Instant::now();
+        Ensemble::from_specs_with_shared_ctx(&specs, &ctx).unwrap();
+        let at_once = start.elapsed();
+
+        // then
+        assert!(
+            at_once < one_by_one,
+            "expected loading at once to be faster, but took {:?} instead of {:?}",
+            at_once,
+            one_by_one
+        );
+    }
+
+    #[test]
+    fn independent_playback_with_shared_ctx() {
+        // given
+        let ctx = SharedPlayerContext::new().unwrap();
+        let specs = [SoundSpec::builder()
+            .source(crate::testutil::TEST_MUSIC)
+            .build()];
+        let mut first = Ensemble::from_specs_with_shared_ctx(&specs, &ctx).unwrap();
+        let mut second = Ensemble::from_specs_with_shared_ctx(&specs, &ctx).unwrap();
+
+        // when
+        first.transition_to(&[0]).unwrap();
+        first.update().unwrap();
+        second.update().unwrap();
+        let playing_before_drop = (first.any_playing(), second.any_playing());
+        drop(first);
+        second.transition_to(&[0]).unwrap();
+        second.update().unwrap();
+
+        // then
+        assert_eq!(playing_before_drop, (true, false));
+        assert!(
+            second.any_playing(),
+            "expected second ensemble to play after first was dropped"
+        );
+    }
+
+    #[test]
+    fn reset_rewinds_to_start_offset() {
+        // given
+        let start_offset = Duration::from_secs(2);
+        let mut ensemble = Ensemble::from_specs(&[SoundSpec::builder()
+            .source(crate::testutil::TEST_MUSIC)
+            .start_offset(2)
+            .unwrap()
+            .build()])
+        .unwrap();
+        ensemble.transition_to(&[0]).unwrap();
+        ensemble.update().unwrap();
+        sleep(Duration::from_millis(500));
+
+        // when
+        ensemble.reset().unwrap();
+        ensemble.update().unwrap();
+
+        // then
+        assert!(!ensemble.any_playing());
+        assert!(
+            almost_equal(ensemble.sounds[0].played(), start_offset),
+            "expected rewind to start offset, but played {:?}",
+            ensemble.sounds[0].played()
+        );
+    }
+
+    #[test]
+    fn volume_ramp_reaches_target() {
+        // given
+        let ramp_duration = Duration::from_millis(200);
+        let clock = MockClock::new();
+        let mut ensemble = Ensemble::from_specs(&[]).unwrap();
+        ensemble.set_clock(Rc::new(clock.clone()));
+        ensemble.set_volume(0.2);
+
+        // when
+        ensemble.volume_ramp(1.0, ramp_duration);
+        clock.advance(ramp_duration / 2);
+        ensemble.update().unwrap();
+        let halfway = ensemble.volume();
+        clock.advance(ramp_duration / 2);
+        ensemble.update().unwrap();
+
+        // then
+        assert!(
+            (halfway - 0.6).abs() < 0.001,
+            "expected volume in between halfway through the ramp, but was {}",
+            halfway
+        );
+        assert_eq!(ensemble.volume(), 1.0);
+    }
+
+    #[test]
+    fn simultaneous_sounds_keep_their_own_volume() {
+        // given
+        let mut ensemble = Ensemble::from_specs(&[
+            SoundSpec::builder()
+                .source(crate::testutil::TEST_MUSIC)
+                .volume(0.25)
+                .unwrap()
+                .build(),
+            SoundSpec::builder()
+                .source(crate::testutil::WILHELM_SCREAM)
+                .build(),
+        ])
+        .unwrap();
+
+        // when
+        ensemble.transition_to(&[0, 1]).unwrap();
+        ensemble.update().unwrap();
+        let initial = [
+            ensemble.sounds[0].player_volume(),
+            ensemble.sounds[1].player_volume(),
+        ];
+        ensemble.set_volume(0.5);
+        let scaled = [
+            ensemble.sounds[0].player_volume(),
+            ensemble.sounds[1].player_volume(),
+        ];
+
+        // then
+        assert!(ensemble.sounds.iter().all(Sound::playing));
+        assert_eq!(initial, [0.25, 1.0]);
+        assert_eq!(scaled, [0.125, 0.5]);
+    }
+
     fn delta(duration1: Duration, duration2: Duration) -> Duration {
         if duration1 > duration2 {
             duration1 - duration2
@@ -400,4 +886,24 @@ mod test {
         const EPS: Duration = Duration::from_millis(250);
         delta(duration1, duration2) <= EPS
     }
+
+    #[test]
+    fn every_failed_sound_is_reported() {
+        // given
+        let specs = [
+            SoundSpec::builder().source("test/missing-1.wav").build(),
+            SoundSpec::builder().source("test/missing-2.wav").build(),
+        ];
+
+        // when
+        let err = Ensemble::from_specs(specs.iter()).err().unwrap();
+
+        // then
+        let compound = err
+            .downcast_ref::<CompoundError>()
+            .expect("expected an error for every sound");
+        assert_eq!(compound.errors.len(), 2);
+        assert!(compound.errors[0].to_string().contains("missing-1.wav"));
+        assert!(compound.errors[1].to_string().contains("missing-2.wav"));
+    }
 }
@@ -1,11 +1,12 @@
 mod ctx;
 mod ensemble;
 mod play;
+mod silence;
 mod sound;
 mod spec;
 
-pub use ctx::PlayerContext;
+pub use ctx::{PlayerContext, SharedPlayerContext};
 pub use ensemble::Ensemble;
 pub use play::Player;
 pub use sound::Sound;
-pub use spec::{ReenterBehavior, SoundSpec};
+pub use spec::{ReenterBehavior, SoundSpec, SoundSpecBuilder, SoundSpecBuilderNeedingSource};
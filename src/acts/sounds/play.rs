@@ -1,16 +1,18 @@
 use super::PlayerContext;
 use failure::{bail, format_err, Error};
-use log::warn;
+use log::{debug, warn};
 use std::cmp::min;
 use std::convert::TryInto;
-use std::path::Path;
-use std::sync::mpsc::channel;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
 use std::time::Duration;
 use std::time::Instant;
-use vlc::{self, Media, MediaPlayer, State};
+use vlc::{self, Media, MediaPlayer, MediaPlayerAudioEx, State};
 
 const READ_DURATION_TIMEOUT: Duration = Duration::from_secs(4);
 const PAUSE_DIRTY_TIMEOUT: Duration = Duration::from_millis(50);
+/// VLC can amplify up to twice the original volume.
+pub(crate) const MAX_VOLUME: f32 = 2.0;
 
 /// Responsible for playback of a single file.
 pub struct Player {
@@ -25,11 +27,51 @@ pub struct Player {
     /// When trying to seek but the media is paused, caching it here.
     /// This also happens upon construction, seeking the start.
     pending_seek: Option<Duration>,
+    /// Volume between `0.0` and `MAX_VOLUME`, applied again on every play
+    /// since VLC may reject it before audio output has started.
+    volume: f32,
+    /// Playback speed, `1.0` for the original speed, applied again
+    /// on every play.
+    rate: f32,
     /// When player context is not managed by client code, keep it here
     /// and free it when player is destroyed.
     _ctx: Option<PlayerContext>,
 }
 
+/// A player with media that is still being read by VLC.
+pub struct LoadingPlayer {
+    media: Media,
+    player: MediaPlayer,
+    file: PathBuf,
+    durations: Receiver<Duration>,
+    /// Time to give up on reading the duration.
+    deadline: Instant,
+}
+
+impl LoadingPlayer {
+    /// Waits until the duration of the media is known, giving up
+    /// if it takes longer than `READ_DURATION_TIMEOUT` from the
+    /// start of loading.
+    pub fn finish(self) -> Result<Player, Error> {
+        let timeout = self.deadline.saturating_duration_since(Instant::now());
+        let duration = self
+            .durations
+            .recv_timeout(timeout)
+            .map_err(|_| format_err!("Could not obtain media duration: {:?}", self.file))?;
+
+        Ok(Player {
+            media: self.media,
+            player: self.player,
+            duration,
+            last_pause_request: None,
+            pending_seek: Some(Duration::from_micros(0)),
+            volume: 1.0,
+            rate: 1.0,
+            _ctx: None,
+        })
+    }
+}
+
 impl Player {
     /// Makes a new player that manages its own context.
     /// Currently only used in tests.
@@ -49,41 +91,38 @@ impl Player {
     /// Creates a new player with a caller-managed player
     /// context.
     pub fn new_with_ctx(file: impl AsRef<Path>, ctx: &PlayerContext) -> Result<Self, Error> {
-        let instance = ctx.vlc_instance();
-
-        let media = Media::new_path(instance, file.as_ref())
-            .ok_or_else(|| format_err!("Could not load media {:?}", file.as_ref()))?;
+        Self::start_loading(file, ctx)?.finish()
+    }
 
-        let player = MediaPlayer::new(instance)
+    /// Starts reading the media in the background and returns
+    /// without waiting for it, so multiple players can load at the
+    /// same time.
+    pub fn start_loading(
+        file: impl AsRef<Path>,
+        ctx: &PlayerContext,
+    ) -> Result<LoadingPlayer, Error> {
+        let (media, durations) = load_media(file.as_ref(), ctx)?;
+        let player = MediaPlayer::new(ctx.vlc_instance())
             .ok_or_else(|| format_err!("Could not load media {:?}", file.as_ref()))?;
 
-        let (tx, rx) = channel::<Duration>();
-        media
-            .event_manager()
-            .attach(vlc::EventType::MediaDurationChanged, move |e, _| {
-                if let vlc::Event::MediaDurationChanged(duration) = e {
-                    tx.send(Duration::from_millis(duration.try_into().unwrap_or(0)))
-                        .ok();
-                }
-            })
-            .map_err(|_| format_err!("Could not obtain media duration: {:?}", file.as_ref()))?;
-
-        media.parse();
-
-        let duration = rx
-            .recv_timeout(READ_DURATION_TIMEOUT)
-            .map_err(|_| format_err!("Could not obtain media duration: {:?}", file.as_ref()))?;
-
-        Ok(Player {
+        Ok(LoadingPlayer {
             media,
             player,
-            duration,
-            last_pause_request: None,
-            pending_seek: Some(Duration::from_micros(0)),
-            _ctx: None,
+            file: file.as_ref().to_path_buf(),
+            durations,
+            deadline: Instant::now() + READ_DURATION_TIMEOUT,
         })
     }
 
+    /// Reads only the duration of the media, without creating a
+    /// player for it, e.g. for checking sounds when compiling.
+    pub fn read_duration(file: impl AsRef<Path>, ctx: &PlayerContext) -> Result<Duration, Error> {
+        let (_media, durations) = load_media(file.as_ref(), ctx)?;
+        durations
+            .recv_timeout(READ_DURATION_TIMEOUT)
+            .map_err(|_| format_err!("Could not obtain media duration: {:?}", file.as_ref()))
+    }
+
     #[cfg(test)]
     fn preserve_ctx(&mut self, ctx: PlayerContext) {
         self._ctx = Some(ctx);
@@ -124,6 +163,8 @@ impl Player {
         }
 
         self.last_pause_request = Some((Instant::now(), false));
+        self.apply_volume();
+        self.apply_rate();
 
         if let Some(to) = self.pending_seek.take() {
             self.seek(to);
@@ -197,6 +238,9 @@ impl Player {
     }
 
     /// Full duration of the played media.
+    ///
+    /// Like `played`, this is the time in the media, playing takes
+    /// shorter if the rate is faster than `1.0`.
     pub fn duration(&self) -> Duration {
         self.duration
     }
@@ -226,6 +270,67 @@ impl Player {
     pub fn rewind(&mut self) {
         self.seek(Duration::from_millis(0));
     }
+
+    /// Sets the playback volume, from `0.0` for silence to `1.0`
+    /// for the original volume, or up to `2.0` to amplify.
+    ///
+    /// If VLC is not ready for audio output yet, the volume is
+    /// applied when playback starts.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, MAX_VOLUME);
+        self.apply_volume();
+    }
+
+    /// The volume last set with `set_volume`, even if VLC has not
+    /// applied it yet.
+    #[cfg(test)]
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    /// Sets the playback speed, e.g. `2.0` to play twice as fast.
+    ///
+    /// Applied when playback starts if the player is not playing
+    /// yet.
+    pub fn set_rate(&mut self, rate: f32) {
+        self.rate = rate;
+        self.apply_rate();
+    }
+
+    fn apply_rate(&self) {
+        if self.player.set_rate(self.rate).is_err() {
+            debug!("Could not set rate yet, applying on next play.");
+        }
+    }
+
+    fn apply_volume(&self) {
+        let percent = (self.volume * 100.0).round() as i32;
+        if self.player.set_volume(percent).is_err() {
+            debug!("Could not set volume yet, applying on next play.");
+        }
+    }
+}
+
+/// Opens the media and starts parsing it in the background, sending
+/// the duration once it is known.
+fn load_media(file: &Path, ctx: &PlayerContext) -> Result<(Media, Receiver<Duration>), Error> {
+    let media = Media::new_path(ctx.vlc_instance(), file)
+        .ok_or_else(|| format_err!("Could not load media {:?}", file))?;
+
+    let (tx, rx) = channel::<Duration>();
+    media
+        .event_manager()
+        .attach(vlc::EventType::MediaDurationChanged, move |e, _| {
+            if let vlc::Event::MediaDurationChanged(duration) = e {
+                tx.send(Duration::from_millis(duration.try_into().unwrap_or(0)))
+                    .ok();
+            }
+        })
+        .map_err(|_| format_err!("Could not obtain media duration: {:?}", file))?;
+
+    media.parse_async();
+
+    Ok((media, rx))
 }
 
 #[cfg(test)]
@@ -387,6 +492,30 @@ mod test {
         );
     }
 
+    /// Checks that playing twice as fast takes about half as long.
+    #[cfg_attr(not(feature = "expensive_tests"), ignore)]
+    #[test]
+    fn double_rate_takes_half_the_time() {
+        // given
+        const PLAY_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+        let info = MediaInfo::obtain(WILHELM_SCREAM).expect("could not inspect wilhelm scream");
+        let expected_duration = info.playing_duration() - info.media_duration() / 2;
+        let max_play_loop_time = info.playing_duration() + Duration::from_secs(2);
+
+        // when
+        let mut player = Player::new(WILHELM_SCREAM).expect("could not make player");
+        player.set_rate(2.0);
+        let player_start_time = Instant::now();
+        player.play().expect("could not play");
+        while player_start_time.elapsed() < max_play_loop_time && player.playing().unwrap() {
+            sleep(PLAY_CHECK_INTERVAL);
+        }
+        let playing_time = player_start_time.elapsed();
+
+        // then
+        assert_duration("double rate playing time", expected_duration, playing_time);
+    }
+
     /// Starts playing and fast forwards to near the end.
     /// Checks if stops after reaching the end.
     #[cfg_attr(not(feature = "expensive_tests"), ignore)]
@@ -0,0 +1,144 @@
+use crate::result::Result;
+
+use hound::{SampleFormat, WavReader};
+
+use std::path::Path;
+use std::time::Duration;
+
+/// Length of the windows the RMS is calculated for.
+const WINDOW_MS: u64 = 10;
+
+/// Finds the position where the trailing silence of the WAV file
+/// at the given path starts.
+///
+/// Audio is considered silent when the RMS of a window of 10ms,
+/// on a scale from `0.0` to `1.0`, is below the threshold. `None`
+/// is returned when the trailing silence is shorter than the given
+/// minimum duration.
+pub fn trailing_silence(
+    path: &Path,
+    threshold_rms: f64,
+    min_duration: Duration,
+) -> Result<Option<Duration>> {
+    let mut reader = WavReader::open(path)?;
+    let spec = reader.spec();
+    let sample_rate = u64::from(spec.sample_rate);
+    let window_len = (sample_rate * WINDOW_MS / 1000).max(1) * u64::from(spec.channels);
+
+    let samples: Box<dyn Iterator<Item = Result<f64>>> = match spec.sample_format {
+        SampleFormat::Float => Box::new(
+            reader
+                .samples::<f32>()
+                .map(|s| s.map(f64::from).map_err(From::from)),
+        ),
+        SampleFormat::Int => {
+            let max = (1_i64 << (spec.bits_per_sample - 1)) as f64;
+            Box::new(
+                reader
+                    .samples::<i32>()
+                    .map(move |s| s.map(|s| f64::from(s) / max).map_err(From::from)),
+            )
+        }
+    };
+
+    let mut total = 0_u64;
+    let mut loud_until = 0_u64;
+    let mut window_start = 0_u64;
+    let mut window_squares = 0.0;
+    for sample in samples {
+        let sample = sample?;
+        window_squares += sample * sample;
+        total += 1;
+
+        let window_samples = total - window_start;
+        if window_samples == window_len {
+            if (window_squares / window_samples as f64).sqrt() >= threshold_rms {
+                loud_until = total;
+            }
+            window_start = total;
+            window_squares = 0.0;
+        }
+    }
+
+    let window_samples = total - window_start;
+    if window_samples > 0 && (window_squares / window_samples as f64).sqrt() >= threshold_rms {
+        loud_until = total;
+    }
+
+    let to_duration = |samples: u64| {
+        let frames = samples / u64::from(spec.channels);
+        Duration::from_nanos(frames * 1_000_000_000 / sample_rate)
+    };
+
+    if to_duration(total - loud_until) >= min_duration {
+        Ok(Some(to_duration(loud_until)))
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hound::{WavSpec, WavWriter};
+    use std::f64::consts::PI;
+    use tempfile::tempdir;
+
+    const SAMPLE_RATE: u32 = 44100;
+
+    #[test]
+    fn detect_two_seconds_of_trailing_silence() {
+        // given
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tone_then_silence.wav");
+        write_tone_then_silence(&path, Duration::from_secs(1), Duration::from_secs(2));
+
+        // when
+        let silence = trailing_silence(&path, 0.01, Duration::from_millis(1500)).unwrap();
+
+        // then
+        let silence = silence.expect("Expected trailing silence to be detected");
+        assert!(
+            silence >= Duration::from_millis(990) && silence <= Duration::from_millis(1010),
+            "Expected silence to start after one second, but was {:?}",
+            silence
+        );
+    }
+
+    #[test]
+    fn ignore_silence_shorter_than_min_duration() {
+        // given
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tone_then_silence.wav");
+        write_tone_then_silence(&path, Duration::from_secs(1), Duration::from_secs(2));
+
+        // when
+        let silence = trailing_silence(&path, 0.01, Duration::from_secs(3)).unwrap();
+
+        // then
+        assert_eq!(silence, None);
+    }
+
+    fn write_tone_then_silence(path: &Path, tone: Duration, silence: Duration) {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(path, spec).unwrap();
+        let frames = |duration: Duration| duration.as_millis() as u32 * SAMPLE_RATE / 1000;
+
+        for frame in 0..frames(tone) {
+            let t = f64::from(frame) / f64::from(SAMPLE_RATE);
+            let sample = ((2.0 * PI * 440.0 * t).sin() * 0.5 * f64::from(i16::MAX)) as i16;
+            writer.write_sample(sample).unwrap();
+            writer.write_sample(sample).unwrap();
+        }
+        for _ in 0..frames(silence) * 2 {
+            writer.write_sample(0_i16).unwrap();
+        }
+
+        writer.finalize().unwrap();
+    }
+}
@@ -1,10 +1,12 @@
 use super::{Player, PlayerContext, ReenterBehavior, SoundSpec};
 use crate::acts::Act;
+use crate::states::{Clock, SystemClock};
 use derivative::Derivative;
 use failure::Error;
 use log::debug;
 use std::cmp::max;
-use std::time::Duration;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 /// Plays a sound file in the background.
 #[derive(Derivative)]
@@ -18,15 +20,75 @@ pub struct Sound {
     /// is `false`.
     activated: bool,
     never_activated: bool,
+    /// Volume scale set from outside, e.g. by an ensemble.
+    #[derivative(Hash = "ignore", PartialEq = "ignore")]
+    volume: f32,
+    /// If set, the sound fades in or out and the volume changes
+    /// on every update, until paused when fading out.
+    #[derivative(Hash = "ignore", PartialEq = "ignore")]
+    fade: Option<Fade>,
+    /// Measures the progress of fades.
+    #[derivative(Hash = "ignore", PartialEq = "ignore", Debug = "ignore")]
+    clock: Rc<dyn Clock>,
+}
+
+/// Fade in after activating or out before cancelling.
+#[derive(Debug)]
+struct Fade {
+    start: Instant,
+    duration: Duration,
+    /// Volume scale at the start of the fade.
+    from: f32,
+    /// Volume scale at the end, `0.0` when fading out.
+    to: f32,
+}
+
+impl Fade {
+    fn new(from: f32, to: f32, duration: Duration, start: Instant) -> Self {
+        Fade {
+            start,
+            duration,
+            from,
+            to,
+        }
+    }
+
+    /// Volume scale at the given time, from `from` at the start
+    /// of the fade to `to` when done.
+    fn scale(&self, now: Instant) -> f32 {
+        let elapsed = now.saturating_duration_since(self.start);
+        if elapsed >= self.duration {
+            self.to
+        } else {
+            let progress = elapsed.as_secs_f32() / self.duration.as_secs_f32();
+            self.from + (self.to - self.from) * progress
+        }
+    }
+
+    fn fading_out(&self) -> bool {
+        self.to == 0.0
+    }
+
+    fn done(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.start) >= self.duration
+    }
 }
 
 impl Sound {
-    fn new(player: Player, spec: &SoundSpec) -> Result<Self, Error> {
+    pub(crate) fn new(mut player: Player, spec: &SoundSpec) -> Result<Self, Error> {
+        if spec.is_normalized() {
+            debug!("Playing {:?} with normalized loudness", spec.source());
+        }
+        player.set_volume(spec.volume());
+        player.set_rate(spec.rate());
         let sound = Self {
             player,
             spec: spec.clone(),
             activated: false,
             never_activated: true,
+            volume: 1.0,
+            fade: None,
+            clock: Rc::new(SystemClock),
         };
 
         Ok(sound)
@@ -38,6 +100,9 @@ impl Sound {
         Self::new(player, spec)
     }
 
+    /// Loads the source file of the spec, ready to be activated.
+    ///
+    /// The context can be shared with other sounds.
     pub fn from_spec_with_ctx(spec: &SoundSpec, ctx: &PlayerContext) -> Result<Self, Error> {
         let player = Player::new_with_ctx(spec.source(), ctx)?;
         Self::new(player, spec)
@@ -45,15 +110,108 @@ impl Sound {
 
     /// Sets everything back to the initial state.
     ///
-    /// Stops and rewinds the player.
+    /// Stops the player and rewinds it to the start offset, keeping
+    /// the loaded media.
     pub fn reset(&mut self) -> Result<(), Error> {
-        self.player.rewind();
-        self.player.pause()?;
-        self.activated = false;
+        self.stop()?;
+        self.player.seek(self.spec.start_offset());
         self.never_activated = true;
         Ok(())
     }
 
+    /// Scales the playback volume, from `0.0` for silence to `1.0`
+    /// for the volume set in the spec.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume;
+        self.apply_volume();
+    }
+
+    /// Measures fades with the given clock instead of the system
+    /// time.
+    #[cfg(test)]
+    pub fn set_clock(&mut self, clock: Rc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Volume of the player, combining the volume of the spec with
+    /// the scale set from outside and any fade.
+    #[cfg(test)]
+    pub fn player_volume(&self) -> f32 {
+        self.player.volume()
+    }
+
+    fn apply_volume(&mut self) {
+        let fade = self.fade_scale();
+        self.player
+            .set_volume(self.volume * fade * self.spec.volume());
+    }
+
+    /// Changes the volume of a fading sound and stops it when
+    /// fading out is over.
+    fn update_fade(&mut self) -> Result<(), Error> {
+        let (done, fading_out) = match self.fade.as_ref() {
+            Some(fade) => (fade.done(self.clock.now()), fade.fading_out()),
+            None => return Ok(()),
+        };
+
+        if done && fading_out {
+            self.stop()
+        } else {
+            if done {
+                self.fade = None;
+            }
+            self.apply_volume();
+            Ok(())
+        }
+    }
+
+    /// Pauses right away, even if the spec has a fade out.
+    pub fn stop(&mut self) -> Result<(), Error> {
+        self.activated = false;
+        let paused = self.player.pause();
+        if self.fade.take().is_some() {
+            self.apply_volume();
+        }
+        paused
+    }
+
+    /// Activates the sound, fading in over the given duration if it
+    /// was not active, or over the fade in of the spec if it is
+    /// longer.
+    pub fn activate_with_fade(&mut self, fade: Duration) -> Result<(), Error> {
+        let was_active = self.activated;
+        self.activated = true;
+
+        let fade_in = fade.max(self.spec.fade_in());
+        let fading_out = self.fade.as_ref().map(Fade::fading_out).unwrap_or(false);
+        if fade_in > Duration::from_secs(0) && (!was_active || fading_out) {
+            let from = if was_active { self.fade_scale() } else { 0.0 };
+            self.fade = Some(Fade::new(from, 1.0, fade_in, self.clock.now()));
+            self.apply_volume();
+        } else if fading_out {
+            self.fade = None;
+            self.apply_volume();
+        }
+
+        self.seek_on_enter(was_active);
+        self.player.play()?; // Need to start playing first to make seeking possible
+        Ok(())
+    }
+
+    /// `true` while fading in or out.
+    pub fn fading(&self) -> bool {
+        self.fade.is_some()
+    }
+
+    /// Volume scale of an ongoing fade, `1.0` without one.
+    fn fade_scale(&self) -> f32 {
+        let now = self.clock.now();
+        self.fade
+            .as_ref()
+            .map(|fade| fade.scale(now))
+            .unwrap_or(1.0)
+    }
+
     fn loop_or_deactivate_on_finish(&mut self) {
         if let Ok(false) = self.player.playing() {
             if self.spec.is_loop() && self.activated {
@@ -65,6 +223,22 @@ impl Sound {
         }
     }
 
+    /// Stops the sound when the end marker or trailing silence is
+    /// reached, as if the file had ended, or starts over for loops.
+    fn stop_at_end_position(&mut self) -> Result<(), Error> {
+        if let Some(end) = self.spec.end_position() {
+            if self.activated && self.player.played() >= end {
+                if self.spec.is_loop() {
+                    self.player.rewind();
+                } else {
+                    self.stop()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     fn seek_on_enter(&mut self, was_active: bool) {
         if was_active {
             // Activating while already active, keep playing
@@ -173,32 +347,55 @@ fn duration_mod(duration: Duration, max_duration: Duration) -> Duration {
 }
 
 impl Act for Sound {
+    /// Starts playing, fading in over the fade in time of the spec,
+    /// or at full volume if it has none.
+    ///
+    /// A sound that is still fading out fades back in from its
+    /// current volume.
     fn activate(&mut self) -> Result<(), Error> {
-        let was_active = self.activated;
-        self.activated = true;
-        self.seek_on_enter(was_active);
-        self.player.play()?; // Need to start playing first to make seeking possible
-        Ok(())
+        self.activate_with_fade(Duration::from_secs(0))
     }
 
     fn update(&mut self) -> Result<(), Error> {
         self.loop_or_deactivate_on_finish();
-        Ok(())
+        self.stop_at_end_position()?;
+        self.update_fade()
     }
 
+    /// Not done while fading out.
     fn done(&self) -> Result<bool, Error> {
         Ok(!self.activated)
     }
 
+    /// Fades out over the fade out time of the spec, or pauses
+    /// right away if it has none.
     fn cancel(&mut self) -> Result<(), Error> {
-        self.activated = false;
-        self.player.pause()
+        self.cancel_with_fade(Duration::from_secs(0))
+    }
+
+    /// Ramps the volume down to silence over the given duration
+    /// before pausing, or over the fade out of the spec if it is
+    /// longer.
+    ///
+    /// An ongoing fade out is not restarted.
+    fn cancel_with_fade(&mut self, fade: Duration) -> Result<(), Error> {
+        let fade = fade.max(self.spec.fade_out());
+        if !self.activated || fade == Duration::from_secs(0) {
+            return self.stop();
+        }
+
+        let fading_out = self.fade.as_ref().map(Fade::fading_out).unwrap_or(false);
+        if !fading_out {
+            self.fade = Some(Fade::new(self.fade_scale(), 0.0, fade, self.clock.now()));
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::states::MockClock;
     use std::thread::sleep;
     use std::time::Instant;
 
@@ -479,6 +676,39 @@ mod test {
         assert!(play_start_time.elapsed() > Duration::from_millis(50))
     }
 
+    #[test]
+    fn end_at_finishes_before_end_of_file() {
+        // given
+        let mut sound = Sound::from_spec(
+            &SoundSpec::builder()
+                .source("test/A Good Bass for Gambling.mp3")
+                .end_at(1)
+                .unwrap()
+                .build(),
+        )
+        .expect("Could not make sound");
+
+        // when
+        let play_start_time = Instant::now();
+        sound.activate().unwrap();
+        sound.update().unwrap();
+        let done_after_activate = sound.done().unwrap();
+        while !sound.done().unwrap() {
+            sleep(Duration::from_millis(10));
+            sound.update().unwrap();
+        }
+        let play_time = play_start_time.elapsed();
+
+        // then
+        assert!(!done_after_activate);
+        assert!(
+            play_time >= Duration::from_millis(900) && play_time < Duration::from_millis(1500),
+            "Expected sound to finish after about one second, but took {:?}",
+            play_time
+        );
+        assert!(!sound.playing());
+    }
+
     #[test]
     fn elevator_music_loop_then_cancel() {
         let mut sound = Sound::from_spec(
@@ -504,6 +734,128 @@ mod test {
         assert!(sound.done().unwrap());
     }
 
+    #[test]
+    fn cancel_with_fade_takes_fade_duration() {
+        // given
+        let fade = Duration::from_millis(200);
+        let clock = MockClock::new();
+        let mut sound = Sound::from_spec(
+            &SoundSpec::builder()
+                .source("test/A Good Bass for Gambling.mp3")
+                .looping(true)
+                .build(),
+        )
+        .expect("Could not make sound");
+        sound.set_clock(Rc::new(clock.clone()));
+        sound.activate().unwrap();
+        sound.update().unwrap();
+
+        // when
+        sound.cancel_with_fade(fade).unwrap();
+        let done_after_cancel = sound.done().unwrap();
+        clock.advance(fade - Duration::from_millis(1));
+        sound.update().unwrap();
+        let done_before_end = sound.done().unwrap();
+        clock.advance(Duration::from_millis(1));
+        sound.update().unwrap();
+
+        // then
+        assert!(!done_after_cancel, "Expected sound to still fade out");
+        assert!(!done_before_end, "Expected fade to take {:?}", fade);
+        assert!(sound.done().unwrap());
+        assert!(!sound.playing());
+    }
+
+    #[test]
+    fn fade_out_of_spec_delays_done() {
+        // given
+        let clock = MockClock::new();
+        let mut sound = Sound::from_spec(
+            &SoundSpec::builder()
+                .source("test/A Good Bass for Gambling.mp3")
+                .fade_out(0.2)
+                .unwrap()
+                .build(),
+        )
+        .expect("Could not make sound");
+        sound.set_clock(Rc::new(clock.clone()));
+        sound.activate().unwrap();
+        sound.update().unwrap();
+
+        // when
+        sound.cancel().unwrap();
+        let done_after_cancel = sound.done().unwrap();
+        clock.advance(Duration::from_millis(100));
+        sound.update().unwrap();
+        let volume_halfway = sound.player_volume();
+        clock.advance(Duration::from_millis(100));
+        sound.update().unwrap();
+
+        // then
+        assert!(!done_after_cancel, "Expected sound to still fade out");
+        assert!(
+            (volume_halfway - 0.5).abs() < 0.01,
+            "was {}",
+            volume_halfway
+        );
+        assert!(sound.done().unwrap());
+        assert!(!sound.playing());
+    }
+
+    #[test]
+    fn fade_in_of_spec_ramps_up_volume() {
+        // given
+        let clock = MockClock::new();
+        let mut sound = Sound::from_spec(
+            &SoundSpec::builder()
+                .source("test/A Good Bass for Gambling.mp3")
+                .volume(0.5)
+                .unwrap()
+                .fade_in(0.2)
+                .unwrap()
+                .build(),
+        )
+        .expect("Could not make sound");
+        sound.set_clock(Rc::new(clock.clone()));
+
+        // when
+        sound.activate().unwrap();
+        let volume_at_start = sound.player_volume();
+        clock.advance(Duration::from_millis(200));
+        sound.update().unwrap();
+
+        // then
+        assert_eq!(volume_at_start, 0.0);
+        assert_eq!(sound.player_volume(), 0.5);
+        assert!(sound.playing());
+    }
+
+    #[test]
+    fn fade_interpolates_scale() {
+        // given
+        let start = Instant::now();
+        let duration = Duration::from_millis(100);
+        let fade_in = Fade::new(0.0, 1.0, duration, start);
+        let fade_out = Fade::new(0.5, 0.0, duration, start);
+
+        // when
+        let at_start = (fade_in.scale(start), fade_out.scale(start));
+        let halfway = (
+            fade_in.scale(start + duration / 2),
+            fade_out.scale(start + duration / 2),
+        );
+        let end = start + duration;
+
+        // then
+        assert_eq!(at_start, (0.0, 0.5));
+        assert_eq!(halfway, (0.5, 0.25));
+        assert!(!fade_in.fading_out());
+        assert!(fade_out.fading_out());
+        assert!(!fade_in.done(end - Duration::from_millis(1)));
+        assert!(fade_in.done(end) && fade_out.done(end));
+        assert_eq!((fade_in.scale(end), fade_out.scale(end)), (1.0, 0.0));
+    }
+
     fn delta(duration1: Duration, duration2: Duration) -> Duration {
         if duration1 > duration2 {
             duration1 - duration2
@@ -1,14 +1,53 @@
 pub use builder::*;
 
+use log::debug;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::{metadata, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+
+/// Content hashes of files that were already read, so comparing
+/// many specs does not read the same files again.
+static CONTENT_HASHES: Mutex<Option<HashMap<PathBuf, CachedHash>>> = Mutex::new(None);
+
+/// A content hash that is valid as long as the file keeps its
+/// modification time and length.
+struct CachedHash {
+    modified: SystemTime,
+    len: u64,
+    hash: u64,
+}
 
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub struct SoundSpec {
     source: PathBuf,
     start_offset: Duration,
+    /// Playback position where the sound ends, even if the file
+    /// goes on.
+    end_at: Option<Duration>,
     end: EndBehavior,
     reenter: ReenterBehavior,
+    /// Playback position where trailing silence starts, if it
+    /// should be skipped.
+    trailing_silence: Option<Duration>,
+    /// Linear gain, kept exact so that quiet volumes in decibels
+    /// are not rounded to silence.
+    volume: Gain,
+    /// Playback speed in percent, `100` for the original speed.
+    rate_percent: u32,
+    /// Time to ramp up the volume from silence when activated.
+    fade_in: Duration,
+    /// Time to ramp down the volume to silence before pausing
+    /// when cancelled.
+    fade_out: Duration,
+    /// The source has been normalized to a common loudness.
+    normalized: bool,
+    /// Length of the source file, if it was read when compiling.
+    duration: Option<Duration>,
 }
 
 impl SoundSpec {
@@ -35,6 +74,177 @@ impl SoundSpec {
     pub fn reenter_behavior(&self) -> ReenterBehavior {
         self.reenter
     }
+
+    /// If set, the sound is considered finished when playback
+    /// reaches this position, even if the file goes on.
+    pub fn end_at(&self) -> Option<Duration> {
+        self.end_at
+    }
+
+    /// If set, the sound is considered finished when playback
+    /// reaches this position.
+    pub fn trailing_silence(&self) -> Option<Duration> {
+        self.trailing_silence
+    }
+
+    /// Position where playback ends before the file does, the
+    /// earlier of the end marker and the trailing silence.
+    pub fn end_position(&self) -> Option<Duration> {
+        match (self.end_at, self.trailing_silence) {
+            (Some(end_at), Some(silence)) => Some(end_at.min(silence)),
+            (end_at, silence) => end_at.or(silence),
+        }
+    }
+
+    /// Linear gain of the sound, `1.0` for the original volume.
+    pub fn volume(&self) -> f32 {
+        self.volume.0
+    }
+
+    /// Playback speed, `1.0` for the original speed and `2.0` to
+    /// play twice as fast.
+    pub fn rate(&self) -> f32 {
+        self.rate_percent as f32 / 100.0
+    }
+
+    /// Time to ramp up the volume from silence when activated.
+    pub fn fade_in(&self) -> Duration {
+        self.fade_in
+    }
+
+    /// Time to ramp down the volume to silence when cancelled,
+    /// before the sound is done.
+    pub fn fade_out(&self) -> Duration {
+        self.fade_out
+    }
+
+    /// `true` if the source file has been normalized to -23 LUFS.
+    pub fn is_normalized(&self) -> bool {
+        self.normalized
+    }
+
+    /// Length of the whole source file, if it was read when
+    /// compiling the phonebook.
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    /// Sets the duration when it is read after compiling.
+    pub(crate) fn set_duration(&mut self, duration: Duration) {
+        self.duration = Some(duration);
+    }
+
+    /// Time from the start offset until the sound is finished
+    /// or its end marker or trailing silence is reached, if the
+    /// duration of the source is known.
+    ///
+    /// This is the time it takes to play, so it is shorter when
+    /// the rate is faster than the original speed.
+    pub fn playback_duration(&self) -> Option<Duration> {
+        self.duration.map(|duration| {
+            self.end_position()
+                .unwrap_or(duration)
+                .min(duration)
+                .checked_sub(self.start_offset)
+                .unwrap_or_default()
+                .div_f32(self.rate())
+        })
+    }
+
+    /// Hashes the content of the source file.
+    ///
+    /// The file is only read again if its modification time or
+    /// length changed since the last hash.
+    ///
+    /// If the file cannot be read, the path is hashed instead,
+    /// so unreadable files only match themselves.
+    pub fn content_hash(&self) -> u64 {
+        cached_content_hash(&self.source).unwrap_or_else(|err| {
+            debug!(
+                "Could not hash content of {:?}, using path: {}",
+                self.source, err
+            );
+            let mut hash = DefaultHasher::new();
+            self.source.hash(&mut hash);
+            hash.finish()
+        })
+    }
+
+    /// Checks if both specs play the same content the same way,
+    /// even if the source files are at different paths.
+    pub fn semantically_equal(&self, other: &SoundSpec) -> bool {
+        self.start_offset == other.start_offset
+            && self.end_at == other.end_at
+            && self.end == other.end
+            && self.reenter == other.reenter
+            && self.trailing_silence == other.trailing_silence
+            && self.volume == other.volume
+            && self.rate_percent == other.rate_percent
+            && self.fade_in == other.fade_in
+            && self.fade_out == other.fade_out
+            && (self.source == other.source || self.content_hash() == other.content_hash())
+    }
+}
+
+/// A linear gain that can be compared and hashed, `1.0` for the
+/// original volume.
+#[derive(Clone, Copy, Debug)]
+struct Gain(f32);
+
+/// Compares the exact bits, which is fine since gains are never
+/// `NaN`.
+impl PartialEq for Gain {
+    fn eq(&self, other: &Gain) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for Gain {}
+
+impl Hash for Gain {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state)
+    }
+}
+
+fn cached_content_hash(path: &Path) -> io::Result<u64> {
+    let meta = metadata(path)?;
+    let modified = meta.modified()?;
+    let len = meta.len();
+
+    let mut hashes = CONTENT_HASHES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    let hashes = hashes.get_or_insert_with(HashMap::new);
+    if let Some(cached) = hashes.get(path) {
+        if cached.modified == modified && cached.len == len {
+            return Ok(cached.hash);
+        }
+    }
+
+    let mut hash = DefaultHasher::new();
+    hash_file(path, &mut hash)?;
+    let hash = hash.finish();
+    hashes.insert(
+        path.to_path_buf(),
+        CachedHash {
+            modified,
+            len,
+            hash,
+        },
+    );
+    Ok(hash)
+}
+
+fn hash_file(path: &Path, hash: &mut impl Hasher) -> std::io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut buf = [0_u8; 8192];
+    loop {
+        match file.read(&mut buf)? {
+            0 => return Ok(()),
+            len => hash.write(&buf[0..len]),
+        }
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
@@ -72,10 +282,16 @@ impl Default for ReenterBehavior {
 mod builder {
     use super::*;
 
+    use crate::acts::sounds::play::MAX_VOLUME;
+    use crate::acts::sounds::silence;
     use crate::result::Result;
 
     use failure::bail;
 
+    /// Allowed range of playback speed.
+    const MIN_RATE: f64 = 0.25;
+    const MAX_RATE: f64 = 4.0;
+
     pub struct SoundSpecBuilder {
         spec: SoundSpec,
     }
@@ -88,15 +304,34 @@ mod builder {
 
     impl SoundSpecBuilderNeedingSource {
         pub fn source(&self, source: impl Into<PathBuf>) -> SoundSpecBuilder {
+            self.source_path(source.into())
+        }
+
+        /// Uses the sound file at the given path.
+        pub fn source_path(&self, path: PathBuf) -> SoundSpecBuilder {
             SoundSpecBuilder {
                 spec: SoundSpec {
-                    source: source.into(),
+                    source: path,
                     start_offset: Duration::from_millis(0),
+                    end_at: None,
                     end: Default::default(),
                     reenter: Default::default(),
+                    trailing_silence: None,
+                    volume: Gain(1.0),
+                    rate_percent: 100,
+                    fade_in: Duration::from_millis(0),
+                    fade_out: Duration::from_millis(0),
+                    normalized: false,
+                    duration: None,
                 },
             }
         }
+
+        /// Shortcut for `source_path` with a path in a string,
+        /// e.g. `SoundSpec::builder().source_str("intro.wav")`.
+        pub fn source_str(&self, path: &str) -> SoundSpecBuilder {
+            self.source_path(PathBuf::from(path))
+        }
     }
 
     impl SoundSpecBuilder {
@@ -110,6 +345,117 @@ mod builder {
             Ok(self)
         }
 
+        /// Ends the sound when playback reaches the given position
+        /// in seconds, e.g. to skip the rest of the file.
+        ///
+        /// Must be after the start offset, so set that first.
+        pub fn end_at(&mut self, end_at: impl Into<f64>) -> Result<&mut Self> {
+            let end_at = f64_to_duration(end_at, "end marker")?;
+            if end_at <= self.spec.start_offset {
+                bail!(
+                    "Encountered end marker at {end_at:?}, but it must be after \
+                     the start offset at {offset:?}.",
+                    end_at = end_at,
+                    offset = self.spec.start_offset
+                )
+            }
+
+            self.spec.end_at = Some(end_at);
+            Ok(self)
+        }
+
+        /// Analyzes the source file and ends the sound early when the
+        /// rest of it stays below the given RMS for at least the given
+        /// duration, e.g. `0.01` for about -40dB.
+        ///
+        /// Only WAV files are supported.
+        pub fn trim_silence(
+            &mut self,
+            threshold_rms: f64,
+            min_duration: Duration,
+        ) -> Result<&mut Self> {
+            if threshold_rms < 0.0 {
+                bail!(
+                    "Encountered negative silence threshold: {val}. \
+                     Positive was expected.",
+                    val = threshold_rms
+                )
+            }
+
+            self.spec.trailing_silence =
+                silence::trailing_silence(&self.spec.source, threshold_rms, min_duration)?;
+            Ok(self)
+        }
+
+        /// Sets a linear gain, e.g. `0.5` for half the volume.
+        ///
+        /// Values above `1.0` amplify the sound, up to twice the
+        /// original volume.
+        pub fn volume(&mut self, volume: impl Into<f64>) -> Result<&mut Self> {
+            let volume = volume.into();
+            if volume < 0.0 || volume.is_nan() {
+                bail!(
+                    "Encountered negative volume: {val}. \
+                     Positive was expected.",
+                    val = volume
+                )
+            }
+            if volume > f64::from(MAX_VOLUME) {
+                bail!(
+                    "Encountered volume {val}, but at most {max} is supported.",
+                    val = volume,
+                    max = MAX_VOLUME
+                )
+            }
+
+            self.spec.volume = Gain(volume as f32);
+            Ok(self)
+        }
+
+        /// Sets the playback speed, e.g. `2.0` to play twice as fast,
+        /// from `0.25` to `4.0`.
+        pub fn rate(&mut self, rate: impl Into<f64>) -> Result<&mut Self> {
+            let rate = rate.into();
+            if !(MIN_RATE..=MAX_RATE).contains(&rate) {
+                bail!(
+                    "Encountered rate {val}, but it must be between {min} and {max}.",
+                    val = rate,
+                    min = MIN_RATE,
+                    max = MAX_RATE
+                )
+            }
+
+            self.spec.rate_percent = (rate * 100.0).round() as u32;
+            Ok(self)
+        }
+
+        /// Ramps up the volume from silence over the given time in
+        /// seconds when the sound is activated.
+        pub fn fade_in(&mut self, fade_in: impl Into<f64>) -> Result<&mut Self> {
+            self.spec.fade_in = f64_to_duration(fade_in, "fade in")?;
+            Ok(self)
+        }
+
+        /// Ramps down the volume to silence over the given time in
+        /// seconds when the sound is cancelled, before pausing it.
+        pub fn fade_out(&mut self, fade_out: impl Into<f64>) -> Result<&mut Self> {
+            self.spec.fade_out = f64_to_duration(fade_out, "fade out")?;
+            Ok(self)
+        }
+
+        /// Remembers the length of the source file, e.g. after
+        /// reading it to validate the file.
+        pub fn duration(&mut self, duration: Duration) -> &mut Self {
+            self.spec.duration = Some(duration);
+            self
+        }
+
+        /// Marks the source as already normalized in loudness.
+        pub fn normalized(&mut self, normalized: bool) -> &mut Self {
+            self.spec.normalized = normalized;
+            self
+        }
+
         pub fn looping(&mut self, looping: bool) -> &mut Self {
             self.spec.end = if looping {
                 EndBehavior::Loop
@@ -160,6 +506,93 @@ mod builder {
             );
         }
 
+        #[test]
+        fn source_str_and_source_path_agree() {
+            let from_str = SoundSpecBuilderNeedingSource
+                .source_str("intro.wav")
+                .build();
+            let from_path = SoundSpecBuilderNeedingSource
+                .source_path(PathBuf::from("intro.wav"))
+                .build();
+
+            assert_eq!(from_str.source(), from_path.source());
+        }
+
+        #[test]
+        fn volume_range() {
+            let mut builder = SoundSpecBuilderNeedingSource.source("/dev/null");
+
+            assert!(builder.volume(-0.1).is_err());
+            assert!(builder.volume(2.5).is_err());
+            assert_eq!(builder.volume(0.4).unwrap().build().volume(), 0.4);
+        }
+
+        #[test]
+        fn quiet_volume_is_not_rounded_to_silence() {
+            // given
+            let minus_60_db = 0.001;
+
+            // when
+            let spec = SoundSpecBuilderNeedingSource
+                .source("/dev/null")
+                .volume(minus_60_db)
+                .unwrap()
+                .build();
+
+            // then
+            assert_eq!(spec.volume(), 0.001);
+        }
+
+        #[test]
+        fn rate_range() {
+            let mut builder = SoundSpecBuilderNeedingSource.source("/dev/null");
+
+            assert!(builder.rate(0.2).is_err());
+            assert!(builder.rate(4.5).is_err());
+            assert_eq!(builder.rate(2).unwrap().build().rate(), 2.0);
+        }
+
+        #[test]
+        fn faster_rate_plays_shorter() {
+            let spec = SoundSpecBuilderNeedingSource
+                .source("/dev/null")
+                .duration(Duration::from_secs(10))
+                .start_offset(2)
+                .unwrap()
+                .rate(2)
+                .unwrap()
+                .build();
+
+            assert_eq!(spec.playback_duration(), Some(Duration::from_secs(4)));
+        }
+
+        #[test]
+        fn end_at_after_start_offset() {
+            let mut builder = SoundSpecBuilderNeedingSource.source("/dev/null");
+            builder.start_offset(2).unwrap();
+
+            assert!(builder.end_at(2).is_err());
+            assert!(builder.end_at(-1).is_err());
+            assert_eq!(
+                builder.end_at(2.5).unwrap().build().end_at(),
+                Some(Duration::from_millis(2500))
+            );
+        }
+
+        #[test]
+        fn end_at_shortens_playback() {
+            let spec = SoundSpecBuilderNeedingSource
+                .source("/dev/null")
+                .duration(Duration::from_secs(10))
+                .start_offset(2)
+                .unwrap()
+                .end_at(5)
+                .unwrap()
+                .build();
+
+            assert_eq!(spec.playback_duration(), Some(Duration::from_secs(3)));
+        }
+
         #[test]
         fn negative_start_offset() {
             let error = SoundSpecBuilderNeedingSource
@@ -174,3 +607,65 @@ mod builder {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{copy, write};
+    use tempfile::tempdir;
+
+    const WAV: &str = "test/482381__erokia__msfxp3-15-thunky-bass.wav";
+
+    #[test]
+    fn same_content_at_different_paths_is_semantically_equal() {
+        // given
+        let dir = tempdir().unwrap();
+        let copied = dir.path().join("copy.wav");
+        copy(WAV, &copied).unwrap();
+        let original = SoundSpec::builder().source(WAV).build();
+        let copy = SoundSpec::builder().source(&copied).build();
+
+        // when
+        let equal = original.semantically_equal(&copy);
+
+        // then
+        assert_ne!(original, copy);
+        assert_eq!(original.content_hash(), copy.content_hash());
+        assert!(equal, "expected copies of the same file to be equal");
+    }
+
+    #[test]
+    fn changed_content_is_hashed_again() {
+        // given
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("changing.wav");
+        write(&path, b"first").unwrap();
+        let spec = SoundSpec::builder().source(&path).build();
+        let first_hash = spec.content_hash();
+
+        // when
+        write(&path, b"second, longer").unwrap();
+        let second_hash = spec.content_hash();
+
+        // then
+        assert_ne!(first_hash, second_hash);
+        assert_eq!(second_hash, spec.content_hash());
+    }
+
+    #[test]
+    fn different_offset_is_not_semantically_equal() {
+        // given
+        let original = SoundSpec::builder().source(WAV).build();
+        let offset = SoundSpec::builder()
+            .source(WAV)
+            .start_offset(0.5)
+            .unwrap()
+            .build();
+
+        // when
+        let equal = original.semantically_equal(&offset);
+
+        // then
+        assert!(!equal, "expected different start offsets to differ");
+    }
+}
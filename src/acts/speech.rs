@@ -1,6 +1,31 @@
 use crate::acts::Act;
 use failure::Error;
-use tavla::Speech;
+use std::sync::Arc;
+use tavla::{any_voice, Speech, Voice as TavlaVoice};
+
+/// Speaks the text of states.
+///
+/// Unlike the voices of `tavla`, can be used as a trait object,
+/// e.g. to select a voice at startup or to record speech in tests.
+pub trait Voice {
+    /// Starts speaking the given text and returns an act that is
+    /// done when finished speaking.
+    fn speak(&self, text: &str) -> Result<Box<dyn Act>, Error>;
+}
+
+/// A voice that can be shared between the actuators of all books.
+pub type SharedVoice = Arc<dyn Voice + Send + Sync>;
+
+/// Uses any voice available on the system, looking it up again
+/// for every text.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemVoice;
+
+impl Voice for SystemVoice {
+    fn speak(&self, text: &str) -> Result<Box<dyn Act>, Error> {
+        Ok(Box::new(any_voice()?.speak(text)?))
+    }
+}
 
 /// Speech errors are `Fail`, we can convert them
 /// to failure errors.
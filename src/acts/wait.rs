@@ -6,15 +6,81 @@ use std::time::{Duration, Instant};
 pub struct Wait {
     start: Instant,
     duration: Duration,
+    /// Minimum time between two checks of the elapsed time.
+    resolution: Duration,
+    last_check: Instant,
+    /// If `false`, cancelling has no effect and the wait
+    /// runs to completion.
+    cancel_immediately: bool,
+    cancelled: bool,
     done: bool,
 }
 
 impl Wait {
     pub fn new(duration: Duration) -> Self {
-        Wait {
-            start: Instant::now(),
+        Self::builder(duration).build()
+    }
+
+    /// Starts configuring a wait for the given duration.
+    ///
+    /// The wait starts counting when it is built.
+    pub fn builder(duration: Duration) -> WaitBuilder {
+        WaitBuilder {
             duration,
-            done: duration == Duration::from_millis(0),
+            resolution: Duration::from_millis(0),
+            cancel_immediately: true,
+        }
+    }
+
+    /// Defers the finish time by the given duration.
+    ///
+    /// A wait that already finished by itself is running again
+    /// if the extended finish time lies in the future. Cancelled
+    /// waits stay done.
+    pub fn extend(&mut self, by: Duration) {
+        self.duration += by;
+        if self.done && !self.cancelled {
+            self.done = self.start.elapsed() > self.duration;
+        }
+    }
+}
+
+/// Configures a wait, see `Wait::builder`.
+pub struct WaitBuilder {
+    duration: Duration,
+    resolution: Duration,
+    cancel_immediately: bool,
+}
+
+impl WaitBuilder {
+    /// Sets the minimum time between two checks of the
+    /// elapsed time on update.
+    ///
+    /// Defaults to zero, checking on every update.
+    pub fn resolution(&mut self, resolution: Duration) -> &mut Self {
+        self.resolution = resolution;
+        self
+    }
+
+    /// If `false`, the wait ignores cancellation and runs to
+    /// completion, e.g. to represent a mandatory silence.
+    ///
+    /// Defaults to `true`.
+    pub fn cancel_immediately(&mut self, cancel_immediately: bool) -> &mut Self {
+        self.cancel_immediately = cancel_immediately;
+        self
+    }
+
+    pub fn build(&self) -> Wait {
+        let now = Instant::now();
+        Wait {
+            start: now,
+            duration: self.duration,
+            resolution: self.resolution,
+            last_check: now,
+            cancel_immediately: self.cancel_immediately,
+            cancelled: false,
+            done: self.duration == Duration::from_millis(0),
         }
     }
 }
@@ -25,8 +91,11 @@ impl Act for Wait {
     }
 
     fn update(&mut self) -> Result<(), Error> {
-        if !self.done && self.start.elapsed().gt(&self.duration) {
-            self.done = true;
+        if !self.done && self.last_check.elapsed() >= self.resolution {
+            self.last_check = Instant::now();
+            if self.start.elapsed().gt(&self.duration) {
+                self.done = true;
+            }
         }
         Ok(())
     }
@@ -35,7 +104,19 @@ impl Act for Wait {
         Ok(self.done)
     }
 
+    fn remaining(&self) -> Option<Duration> {
+        if self.done {
+            None
+        } else {
+            Some(self.duration.saturating_sub(self.start.elapsed()))
+        }
+    }
+
     fn cancel(&mut self) -> Result<(), Error> {
+        if self.cancel_immediately {
+            self.cancelled = true;
+            self.done = true;
+        }
         Ok(())
     }
 }
@@ -86,4 +167,109 @@ mod test {
         wait.update().unwrap();
         assert!(wait.done().unwrap())
     }
+
+    #[test]
+    fn coarse_resolution_defers_check() {
+        let duration = Duration::from_millis(10);
+        let resolution = Duration::from_millis(300);
+        let mut wait = Wait::builder(duration).resolution(resolution).build();
+
+        sleep(duration * 5);
+        wait.update().unwrap();
+        let done_before_resolution = wait.done().unwrap();
+        sleep(resolution);
+        wait.update().unwrap();
+        let done_after_resolution = wait.done().unwrap();
+
+        assert!(!done_before_resolution);
+        assert!(done_after_resolution);
+    }
+
+    #[test]
+    fn cancel_immediately_finishes() {
+        let mut wait = Wait::new(Duration::from_secs(10));
+
+        wait.cancel().unwrap();
+
+        assert!(wait.done().unwrap());
+    }
+
+    #[test]
+    fn cancel_not_immediately_runs_to_completion() {
+        let duration = Duration::from_millis(200);
+        let mut wait = Wait::builder(duration).cancel_immediately(false).build();
+
+        wait.cancel().unwrap();
+        wait.update().unwrap();
+        let done_after_cancel = wait.done().unwrap();
+        sleep(duration);
+        wait.update().unwrap();
+        let done_after_duration = wait.done().unwrap();
+
+        assert!(!done_after_cancel);
+        assert!(done_after_duration);
+    }
+
+    #[test]
+    fn extend_defers_finish() {
+        let duration = Duration::from_millis(200);
+        let mut wait = Wait::new(duration);
+
+        wait.extend(duration);
+        sleep(duration);
+        wait.update().unwrap();
+        let done_after_original_duration = wait.done().unwrap();
+        sleep(duration);
+        wait.update().unwrap();
+        let done_after_extended_duration = wait.done().unwrap();
+
+        assert!(!done_after_original_duration);
+        assert!(done_after_extended_duration);
+    }
+
+    #[test]
+    fn extend_after_finish_runs_again() {
+        let duration = Duration::from_millis(100);
+        let mut wait = Wait::new(duration);
+
+        sleep(duration);
+        wait.update().unwrap();
+        let done_before_extend = wait.done().unwrap();
+        wait.extend(Duration::from_secs(10));
+        let done_after_extend = wait.done().unwrap();
+
+        assert!(done_before_extend);
+        assert!(!done_after_extend);
+    }
+
+    #[test]
+    fn extend_after_cancel_stays_done() {
+        let mut wait = Wait::new(Duration::from_secs(10));
+
+        wait.cancel().unwrap();
+        wait.extend(Duration::from_secs(10));
+
+        assert!(wait.done().unwrap());
+    }
+
+    #[test]
+    fn cancel_not_immediately_with_coarse_resolution() {
+        let duration = Duration::from_millis(10);
+        let resolution = Duration::from_millis(200);
+        let mut wait = Wait::builder(duration)
+            .resolution(resolution)
+            .cancel_immediately(false)
+            .build();
+
+        wait.cancel().unwrap();
+        sleep(duration * 5);
+        wait.update().unwrap();
+        let done_before_resolution = wait.done().unwrap();
+        sleep(resolution);
+        wait.update().unwrap();
+        let done_after_resolution = wait.done().unwrap();
+
+        assert!(!done_before_resolution);
+        assert!(done_after_resolution);
+    }
 }
@@ -1,35 +1,110 @@
-use super::{App, Run, TerminalStateBehavior};
+use super::config::Config;
+use super::panic::terminate_on_background_panic;
+use super::simulate::Simulator;
+use super::validate::validate;
+use super::{App, Run, SensorConfig, TerminalStateBehavior, Warning};
 
-use crate::books::Book;
+use crate::acts::{SharedPlayerContext, SharedVoice, SystemVoice};
+use crate::books::{self, Book, FileWatcher, Overrides};
 use crate::phone::Phone;
 use crate::result::Result;
-use crate::serve::Server;
+use crate::senses::StdinSignals;
+use crate::serve::{FernspielEvent, Format, Server};
+use crate::states::DEFAULT_HISTORY_SIZE;
 
-use log::error;
+use failure::bail;
+use log::{error, info, warn};
 
+use std::collections::HashMap;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+/// I2C slave address of the phone if no other address is
+/// configured.
+const DEFAULT_I2C_ADDRESS: u16 = 4;
+
+/// Configures an app before it is built.
+///
+/// Methods that can fail, e.g. because a file cannot be read or
+/// a port cannot be bound, return `Result<&mut Self>`. All other
+/// methods return `&mut Self`. Use `App::builder_try` to chain
+/// both kinds with `?`.
 pub struct Builder {
     /// If `None`, starts with an idle run, otherwise
     /// starts with the phonebook.
     startup_book: Option<Book>,
-    server: Option<Server>,
+    /// File the startup book was loaded from, if any.
+    startup_book_path: Option<PathBuf>,
+    /// If `true`, the startup book is compiled again when its file
+    /// changes.
+    reload_on_change: bool,
+    /// Variables and other settings that take precedence over the
+    /// ones in the startup book.
+    overrides: Overrides,
+    /// If `true`, starting without a startup book is intended,
+    /// a phonebook will be sent through remote control.
+    wait_for_remote_phonebook: bool,
+    /// If `Some`, this book advances silently alongside the
+    /// startup book.
+    parallel_book: Option<Book>,
+    /// Hostname and port to host the remote control server on,
+    /// the server is spawned when building.
+    serve: Option<String>,
+    /// Format of events sent to clients of the server.
+    ws_event_format: Format,
+    /// Pending connections of the server, or the OS default.
+    ws_backlog: Option<u32>,
     phone: Option<Arc<Mutex<Phone>>>,
+    /// If `true`, the keyboard is used instead of a phone.
+    simulate_phone: bool,
+    sensor_config: SensorConfig,
+    /// Speaks the text of states, any system voice if `None`.
+    tts_voice: Option<SharedVoice>,
     terminal_state_behavior: TerminalStateBehavior,
     termination_flag: Arc<AtomicBool>,
+    /// If `true`, the termination flag is set when a background
+    /// thread panics.
+    terminate_on_background_panic: bool,
+    tick_interval: Duration,
+    tick_overhead_limit: Option<Duration>,
+    progress_interval: Option<Duration>,
+    history_size: usize,
+    /// Volume scale of sounds while speaking or ringing.
+    duck_level: Option<f32>,
+    /// Time for sounds to fade out when leaving a state.
+    fade_out: Duration,
 }
 
 impl Default for Builder {
     fn default() -> Self {
         Builder {
             startup_book: None,
-            server: None,
+            startup_book_path: None,
+            reload_on_change: false,
+            overrides: Overrides::default(),
+            wait_for_remote_phonebook: false,
+            parallel_book: None,
+            serve: None,
+            ws_event_format: Format::default(),
+            ws_backlog: None,
             phone: None,
+            simulate_phone: false,
+            sensor_config: SensorConfig::default(),
+            tts_voice: None,
             terminal_state_behavior: TerminalStateBehavior::Rewind,
             // if never set up, termination flag never changes to true
             termination_flag: Arc::new(AtomicBool::new(false)),
+            terminate_on_background_panic: false,
+            tick_interval: Duration::from_millis(10),
+            tick_overhead_limit: None,
+            progress_interval: None,
+            history_size: DEFAULT_HISTORY_SIZE,
+            duck_level: None,
+            fade_out: Duration::from_secs(0),
         }
     }
 }
@@ -39,8 +114,129 @@ impl Builder {
         Default::default()
     }
 
+    /// Makes a builder configured by the YAML configuration file
+    /// at the given path, see `yaml_config`.
+    pub fn from_yaml_config(path: impl AsRef<Path>) -> Result<Builder> {
+        let mut builder = Builder::new();
+        builder.yaml_config(path)?;
+        Ok(builder)
+    }
+
+    /// Applies the YAML configuration file at the given path.
+    ///
+    /// Supported keys are `phonebook`, `serve`, `i2c_device`,
+    /// `i2c_address`, `tick_interval_ms`, `exit_on_terminal` and
+    /// `duck_level`.
+    /// The phonebook is loaded with the variables and other
+    /// overrides set so far. The builder can be further configured
+    /// afterwards, e.g. to override configuration values with
+    /// command line arguments.
+    ///
+    /// Fails if the file cannot be read, contains unknown keys,
+    /// if the phonebook fails to load or if the server address
+    /// cannot be resolved. A phone that cannot be connected to is
+    /// only logged.
+    pub fn yaml_config(&mut self, path: impl AsRef<Path>) -> Result<&mut Self> {
+        let config = Config::load(path.as_ref())?;
+
+        if let Some(phonebook) = config.phonebook {
+            self.startup_phonebook_path(&phonebook)?;
+        }
+
+        if let Some(bind_to) = config.serve {
+            self.serve(&bind_to)?;
+        }
+
+        if let Some(i2c_device) = config.i2c_device {
+            let address = config.i2c_address.unwrap_or(DEFAULT_I2C_ADDRESS);
+            match self.phone(&i2c_device, address) {
+                Ok(_) => info!("phone connected on {}, address {}.", i2c_device, address),
+                Err(e) => warn!("no phone available, error: {}", e),
+            }
+        }
+
+        if let Some(tick_interval_ms) = config.tick_interval_ms {
+            self.tick_interval(Duration::from_millis(tick_interval_ms));
+        }
+
+        if let Some(duck_level) = config.duck_level {
+            self.duck_level(duck_level)?;
+        }
+
+        match config.exit_on_terminal {
+            Some(true) => self.exit_on_terminal_state(),
+            Some(false) => self.rewind_on_terminal_state(),
+            None => self,
+        };
+
+        Ok(self)
+    }
+
     pub fn startup_phonebook(&mut self, book: Book) -> &mut Self {
         self.startup_book = Some(book);
+        self.startup_book_path = None;
+        self
+    }
+
+    /// Marks the app as intentionally starting without a startup
+    /// phonebook, e.g. when a phonebook will be sent through the
+    /// remote control server.
+    ///
+    /// Suppresses the warning about a silent runtime. Has no
+    /// effect if a startup phonebook is set.
+    pub fn set_wait_for_remote_phonebook(&mut self) -> &mut Self {
+        self.wait_for_remote_phonebook = true;
+        self
+    }
+
+    /// Loads and compiles the phonebook at the given path right
+    /// away and runs it at startup.
+    pub fn startup_phonebook_path(&mut self, path: &Path) -> Result<&mut Self> {
+        let book = books::from_path_with_overrides(path, &self.overrides)?;
+        self.startup_phonebook(book);
+        self.startup_book_path = Some(path.to_path_buf());
+        Ok(self)
+    }
+
+    /// Fills in placeholders like `{name}` in the speech of the
+    /// phonebook loaded with `startup_phonebook_path`, so call it
+    /// before loading. The values take precedence over the ones in
+    /// the phonebook.
+    pub fn variables(&mut self, variables: HashMap<String, String>) -> &mut Self {
+        self.overrides.variables = variables;
+        self
+    }
+
+    /// If `true`, states of the phonebook loaded with
+    /// `startup_phonebook_path` that have no transitions are only
+    /// warned about instead of failing compilation, as if the
+    /// phonebook set `allow_dead_ends: true`. Call it before loading.
+    pub fn allow_dead_ends(&mut self, allow: bool) -> &mut Self {
+        self.overrides.allow_dead_ends = allow;
+        self
+    }
+
+    /// Compiles the startup phonebook again when its file changes
+    /// and switches to it, like a phonebook sent through remote
+    /// control.
+    ///
+    /// Only has an effect if the startup phonebook was loaded with
+    /// `startup_phonebook_path`. If the changed file does not
+    /// compile, the current phonebook keeps running.
+    pub fn reload_on_change(&mut self) -> &mut Self {
+        self.reload_on_change = true;
+        self
+    }
+
+    /// Runs the given book alongside the startup book, e.g. for
+    /// A/B testing of two versions of a phonebook.
+    ///
+    /// The parallel book receives the same input as the startup
+    /// book, but it does not have any actuators and does not
+    /// publish events. It always starts over when reaching a
+    /// terminal state.
+    pub fn parallel_phonebook(&mut self, book: Book) -> &mut Self {
+        self.parallel_book = Some(book);
         self
     }
 
@@ -52,6 +248,37 @@ impl Builder {
         Ok(self)
     }
 
+    /// Selects the sensors to read input from. By default, stdin
+    /// and the phone dial are used.
+    pub fn configure_sensors(&mut self, config: SensorConfig) -> &mut Self {
+        self.sensor_config = config;
+        self
+    }
+
+    /// Reads whole lines from stdin as commands, so that numbers
+    /// with multiple digits can be dialled and the phonebook can be
+    /// reset (`r`) or the app quit (`q`) from the keyboard.
+    pub fn stdin_line_mode(&mut self) -> &mut Self {
+        self.sensor_config.stdin_line_mode = true;
+        self
+    }
+
+    /// Speaks the text of states with the given voice, instead
+    /// of any voice of the system, e.g. for a specific language.
+    pub fn tts_voice(&mut self, voice: SharedVoice) -> &mut Self {
+        self.tts_voice = Some(voice);
+        self
+    }
+
+    /// Simulates the phone with the keyboard instead of reading
+    /// input from stdin, showing the name of the current state.
+    ///
+    /// Cannot be combined with a phone on an I2C device.
+    pub fn simulate_phone(&mut self) -> &mut Self {
+        self.simulate_phone = true;
+        self
+    }
+
     /// Sets  a custom termination flag.
     pub fn termination_flag(&mut self, flag: &Arc<AtomicBool>) -> &mut Self {
         self.termination_flag = Arc::clone(flag);
@@ -80,8 +307,43 @@ impl Builder {
         }
     }
 
+    /// Sets the termination flag when a background thread of the
+    /// app panics, e.g. a sensor, so the app exits instead of running
+    /// on without it.
+    ///
+    /// The process-wide panic hook for this is only installed once,
+    /// apps that do not call this keep running.
+    pub fn terminate_on_background_panic(&mut self) -> &mut Self {
+        self.terminate_on_background_panic = true;
+        self
+    }
+
+    /// Sets the format of events sent over WebSockets, unless
+    /// clients ask for another format. Defaults to YAML.
+    pub fn ws_event_format(&mut self, format: Format) -> &mut Self {
+        self.ws_event_format = format;
+        self
+    }
+
+    /// Sets how many connections may wait for being accepted by
+    /// the WebSockets server. Defaults to the OS default.
+    pub fn ws_backlog(&mut self, backlog: u32) -> &mut Self {
+        self.ws_backlog = Some(backlog);
+        self
+    }
+
+    /// Hosts the remote control server on the given hostname and
+    /// port when building the app.
+    ///
+    /// Calling it again replaces the address, e.g. to override the
+    /// address of a configuration file on the command line, so at
+    /// most one server is spawned.
+    ///
+    /// Fails if the address cannot be resolved. If the port cannot
+    /// be bound, building fails.
     pub fn serve(&mut self, on_hostname_and_port: &str) -> Result<&mut Self> {
-        self.server = Server::spawn(on_hostname_and_port).map(Some)?;
+        on_hostname_and_port.to_socket_addrs()?;
+        self.serve = Some(on_hostname_and_port.to_string());
         Ok(self)
     }
 
@@ -95,31 +357,195 @@ impl Builder {
         self
     }
 
+    /// Sets the time to sleep between two evaluations of the
+    /// phonebook, 10ms by default.
+    pub fn tick_interval(&mut self, interval: Duration) -> &mut Self {
+        self.tick_interval = interval;
+        self
+    }
+
+    /// Logs a warning when running if a single tick takes longer
+    /// than the given duration, e.g. on an overloaded system.
+    pub fn warn_if_tick_overhead_exceeds(&mut self, limit: Duration) -> &mut Self {
+        self.tick_overhead_limit = Some(limit);
+        self
+    }
+
+    /// Publishes a progress event for the current state with the
+    /// given interval, never by default.
+    pub fn progress_interval(&mut self, interval: Duration) -> &mut Self {
+        self.progress_interval = Some(interval);
+        self
+    }
+
+    /// Sets how many of the last transitions are remembered for
+    /// introspection, 32 by default.
+    pub fn history_size(&mut self, size: usize) -> &mut Self {
+        self.history_size = size;
+        self
+    }
+
+    /// Lowers the volume of sounds while a state speaks or rings,
+    /// e.g. `0.3` to play music at 30 % so speech is easier to hear.
+    ///
+    /// Sounds keep their volume by default.
+    pub fn duck_level(&mut self, level: f32) -> Result<&mut Self> {
+        if !(0.0..=1.0).contains(&level) {
+            bail!(
+                "Encountered duck level {}, but it must be between 0 and 1.",
+                level
+            )
+        }
+
+        self.duck_level = Some(level);
+        Ok(self)
+    }
+
+    /// Fades out the sounds of a state over the given duration when
+    /// leaving it, e.g. when hanging up, instead of stopping them
+    /// right away.
+    pub fn fade_out(&mut self, duration: Duration) -> &mut Self {
+        self.fade_out = duration;
+        self
+    }
+
+    /// Checks the current configuration without building the app.
+    ///
+    /// Returns an error for problems that would make the app fail,
+    /// e.g. when the startup book references a sound file that is
+    /// not present on the file system. Otherwise, returns warnings
+    /// about configuration that is probably unintended.
+    pub fn validate(&self) -> Result<Vec<Warning>> {
+        if self.simulate_phone && self.phone.is_some() {
+            bail!("Phone simulation cannot be used while a phone is connected")
+        }
+
+        validate(
+            self.startup_book.as_ref(),
+            self.serve.is_some() || self.wait_for_remote_phonebook,
+            self.phone.is_some() || self.simulate_phone,
+            &self.terminal_state_behavior,
+        )
+    }
+
     /// Consumes the builder and tries to create an app from it.
     ///
     /// This may fail, e.g. when the book references a sound file
     /// that is not present on the file system.
+    ///
+    /// Validates the configuration first and logs any warnings.
     pub fn build(self) -> Result<App> {
+        for warning in self.validate()? {
+            warn!("{}", warning);
+        }
+
         let Builder {
             startup_book,
-            server,
+            startup_book_path,
+            reload_on_change,
+            overrides,
+            wait_for_remote_phonebook: _,
+            parallel_book,
+            serve,
+            ws_event_format,
+            ws_backlog,
             phone,
+            simulate_phone,
+            sensor_config,
+            tts_voice,
             terminal_state_behavior,
             termination_flag,
+            terminate_on_background_panic: on_background_panic,
+            tick_interval,
+            tick_overhead_limit,
+            progress_interval,
+            history_size,
+            duck_level,
+            fade_out,
         } = self;
-        let server = server.map(Rc::new);
+        if on_background_panic {
+            terminate_on_background_panic(&termination_flag);
+        }
+
+        let server = match (serve, ws_backlog) {
+            (Some(bind_to), Some(backlog)) => Some(Server::spawn_with_backlog(
+                &bind_to,
+                ws_event_format,
+                backlog,
+            )?),
+            (Some(bind_to), None) => Some(Server::spawn(&bind_to, ws_event_format)?),
+            (None, _) => None,
+        }
+        .map(Rc::new);
+        // set by reset and quit commands typed in line mode
+        let reset_flag = Arc::new(AtomicBool::new(false));
+        let quit_flag = Arc::new(AtomicBool::new(false));
+        // VLC is initialized once and used for every book
+        let player_ctx = SharedPlayerContext::new()?;
+
+        let (mut run, control) = Run::new_with_queue(
+            startup_book,
+            phone,
+            server.as_ref().map(Rc::clone),
+            &SensorConfig {
+                // the simulator reads the keyboard instead
+                use_stdin: sensor_config.use_stdin && !simulate_phone,
+                ..sensor_config
+            },
+            &StdinSignals::new(&reset_flag, &quit_flag),
+            player_ctx,
+            tts_voice.unwrap_or_else(|| Arc::new(SystemVoice)),
+        )?;
+        run.set_history_size(history_size);
+        run.set_duck_level(duck_level);
+        run.set_fade_out(fade_out);
 
-        let (run, control) =
-            Run::new_with_queue(startup_book, phone, server.as_ref().map(Rc::clone))?;
+        let watcher = match startup_book_path {
+            Some(path) if reload_on_change => {
+                Some(FileWatcher::spawn_with_overrides(path, overrides)?)
+            }
+            _ => None,
+        };
+
+        let simulator = if simulate_phone {
+            Some(Simulator::spawn(control.clone(), &termination_flag)?)
+        } else {
+            None
+        };
+
+        let secondary = parallel_book.map(|book| {
+            let (secondary, secondary_control) = Run::new_silent(book);
+            run.mirror_input(&secondary_control);
+            secondary
+        });
 
         let app = App {
             run,
+            secondary,
             control,
             server,
+            simulator,
+            compilation: None,
+            watcher,
             terminal_state_behavior,
             termination_flag,
+            reset_flag,
+            quit_flag,
+            tick_interval,
+            tick_overhead_limit,
+            tick_overhead_exceeded: false,
+            progress_interval,
+            last_progress: Instant::now(),
         };
 
+        if app.is_idle() {
+            info!("waiting for phonebook from remote control");
+        }
+        if let Some(server) = app.server.as_ref() {
+            // clients connect later, retain so each one learns if idle
+            server.publish_retained(FernspielEvent::idle(app.is_idle()));
+        }
+
         Ok(app)
     }
 }
@@ -127,7 +553,12 @@ impl Builder {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::app::TerminationCause;
+    use crate::books::spec::Sound as SoundSpec;
+    use crate::states::State;
+    use std::fs::{copy, remove_file, write};
     use std::sync::atomic::Ordering::SeqCst;
+    use tempfile::tempdir;
 
     #[test]
     fn build_with_default_settings() {
@@ -142,4 +573,269 @@ mod test {
         assert_eq!(app.terminal_state_behavior, TerminalStateBehavior::Rewind);
         assert_eq!(app.termination_flag.load(SeqCst), false);
     }
+
+    #[test]
+    fn dead_ends_can_be_allowed_when_loading() {
+        // given
+        let dir = tempdir().unwrap();
+        let book_path = dir.path().join("book.yaml");
+        write(
+            &book_path,
+            "initial: ring\nstates:\n  ring:\n  stuck:\ntransitions:\n  ring:\n    pick_up: stuck\n",
+        )
+        .unwrap();
+
+        // when
+        let rejected = App::builder().startup_phonebook_path(&book_path).is_err();
+        let allowed = App::builder()
+            .allow_dead_ends(true)
+            .startup_phonebook_path(&book_path)
+            .is_ok();
+
+        // then
+        assert!(rejected, "expected dead end to be an error by default");
+        assert!(allowed, "expected dead end to be allowed");
+    }
+
+    #[test]
+    fn builder_try_chains_fallible_and_infallible_methods() {
+        // given
+        let dir = tempdir().unwrap();
+        let book_path = dir.path().join("book.yaml");
+        write(&book_path, "initial: a\nstates:\n  a: { terminal: true }\n").unwrap();
+
+        // when
+        let builder = App::builder_try(|app| {
+            Ok(app
+                .exit_on_terminal_state()
+                .startup_phonebook_path(&book_path)?
+                .tick_interval(Duration::from_millis(5)))
+        })
+        .unwrap();
+        let app = builder.build().unwrap();
+
+        // then
+        assert!(!app.is_idle());
+        assert_eq!(app.terminal_state_behavior, TerminalStateBehavior::Exit);
+        assert_eq!(app.tick_interval, Duration::from_millis(5));
+    }
+
+    #[test]
+    fn builder_try_fails_with_configuration() {
+        // when
+        let result =
+            App::builder_try(|app| app.startup_phonebook_path(Path::new("does/not/exist.yaml")));
+
+        // then
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn idle_until_book_is_loaded() {
+        // given
+        let mut book = Book::builder();
+        book.state(State::builder().id("1").name("1").build());
+        let mut with_book = App::builder();
+        with_book.startup_phonebook(book.build());
+
+        // when
+        let without_book = App::builder().build().unwrap();
+        let with_book = with_book.build().unwrap();
+
+        // then
+        assert!(without_book.is_idle());
+        assert!(!with_book.is_idle());
+    }
+
+    #[test]
+    fn run_without_book_when_waiting_for_remote_phonebook() {
+        // given
+        let mut builder = App::builder();
+        builder.set_wait_for_remote_phonebook();
+        let termination_flag = Arc::new(AtomicBool::new(true));
+        builder.termination_flag(&termination_flag);
+
+        // when
+        let warnings = builder.validate().unwrap();
+        let cause = builder.build().unwrap().run();
+
+        // then
+        assert!(warnings
+            .iter()
+            .all(|w| !w.to_string().contains("remain silent")));
+        assert_eq!(cause.unwrap(), TerminationCause::Signal);
+    }
+
+    #[test]
+    fn build_from_yaml_config() {
+        // given
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("fernspielapparat.yaml");
+        write(
+            dir.path().join("book.yaml"),
+            "initial: one\nstates:\n  one: { terminal: true }\n",
+        )
+        .unwrap();
+        write(
+            &config_path,
+            "phonebook: book.yaml\n\
+             tick_interval_ms: 25\n\
+             exit_on_terminal: true\n\
+             duck_level: 0.3\n",
+        )
+        .unwrap();
+
+        // when
+        let builder = Builder::from_yaml_config(&config_path).unwrap();
+
+        // then
+        assert!(builder.startup_book.is_some());
+        assert!(builder.serve.is_none());
+        assert!(builder.phone.is_none());
+        assert_eq!(builder.tick_interval, Duration::from_millis(25));
+        assert_eq!(builder.terminal_state_behavior, TerminalStateBehavior::Exit);
+        assert_eq!(builder.duck_level, Some(0.3));
+    }
+
+    #[test]
+    fn overrides_apply_to_phonebook_of_config() {
+        // given
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("fernspielapparat.yaml");
+        write(
+            dir.path().join("book.yaml"),
+            "initial: one\nstates:\n  one: { terminal: false }\n",
+        )
+        .unwrap();
+        write(&config_path, "phonebook: book.yaml\n").unwrap();
+        let mut builder = Builder::new();
+        builder.allow_dead_ends(true);
+
+        // when
+        let result = builder.yaml_config(&config_path);
+
+        // then
+        assert!(result.is_ok(), "expected overrides to apply to phonebook");
+    }
+
+    #[test]
+    fn serve_replaces_address_of_config() {
+        // given
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("fernspielapparat.yaml");
+        write(&config_path, "serve: 127.0.0.1:38397\n").unwrap();
+        let mut builder = Builder::from_yaml_config(&config_path).unwrap();
+
+        // when
+        builder.serve("127.0.0.1:38398").unwrap();
+
+        // then
+        assert_eq!(builder.serve, Some("127.0.0.1:38398".to_string()));
+    }
+
+    #[test]
+    fn duck_level_out_of_range_fails() {
+        assert!(App::builder().duck_level(1.5).is_err());
+        assert!(App::builder().duck_level(-0.1).is_err());
+    }
+
+    #[test]
+    fn unknown_config_key_fails() {
+        // given
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("fernspielapparat.yaml");
+        write(&config_path, "tick_interval: 25\n").unwrap();
+
+        // when
+        let result = Builder::from_yaml_config(&config_path);
+
+        // then
+        assert!(result.is_err(), "expected typo in key to be an error");
+    }
+
+    #[test]
+    fn missing_startup_phonebook_fails() {
+        // given
+        let dir = tempdir().unwrap();
+        let mut builder = App::builder();
+
+        // when
+        let result = builder.startup_phonebook_path(&dir.path().join("missing.yaml"));
+
+        // then
+        assert!(result.is_err(), "expected missing phonebook to be an error");
+    }
+
+    #[test]
+    fn validate_missing_sound_file() {
+        // given
+        let dir = tempdir().unwrap();
+        let sound_path = dir.path().join("deleted.wav");
+        copy(
+            "test/482381__erokia__msfxp3-15-thunky-bass.wav",
+            &sound_path,
+        )
+        .unwrap();
+        let mut book = Book::builder();
+        book.sound(SoundSpec {
+            file: sound_path.to_str().unwrap().into(),
+            ..Default::default()
+        })
+        .unwrap()
+        .state(State::builder().id("1").name("1").sounds(vec![0]).build());
+        let mut builder = App::builder();
+        builder.startup_phonebook(book.build());
+        // sound files must exist when compiling, but could be deleted later
+        remove_file(&sound_path).unwrap();
+
+        // when
+        let validation = builder.validate();
+
+        // then
+        assert!(
+            validation.is_err(),
+            "expected missing sound file to be an error"
+        );
+    }
+
+    #[test]
+    fn validate_warns_about_exit_without_terminal_state() {
+        // given
+        let mut book = Book::builder();
+        book.state(State::builder().id("1").name("1").build());
+        let mut builder = App::builder();
+        builder
+            .startup_phonebook(book.build())
+            .exit_on_terminal_state();
+
+        // when
+        let warnings = builder.validate().unwrap();
+
+        // then
+        assert!(
+            warnings
+                .iter()
+                .any(|w| w.to_string().contains("no terminal state")),
+            "expected warning about missing terminal state, got: {:?}",
+            warnings
+        );
+    }
+
+    #[test]
+    fn validate_warns_about_silent_runtime() {
+        // given
+        let builder = App::builder();
+
+        // when
+        let warnings = builder.validate().unwrap();
+
+        // then
+        assert!(
+            warnings
+                .iter()
+                .any(|w| w.to_string().contains("remain silent")),
+            "expected warning about silent runtime, got: {:?}",
+            warnings
+        );
+    }
 }
@@ -0,0 +1,81 @@
+use crate::books::{compile_with_progress, spec::Book as BookSpec, Book};
+use crate::result::Result;
+
+use failure::format_err;
+use log::debug;
+
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+/// A phonebook that is being compiled on a background thread,
+/// e.g. while speech for its sounds is synthesized.
+pub struct Compilation {
+    progress: Receiver<Progress>,
+    /// Crossfade when switching to the book after compilation.
+    crossfade: Option<Duration>,
+}
+
+/// What happened with a compilation since it was last polled.
+#[derive(Debug)]
+pub enum Progress {
+    /// The given number of sounds still need to be prepared.
+    Compiling { sounds_remaining: usize },
+    /// Compilation finished, successfully or not.
+    Done(Result<Box<Book>>),
+}
+
+impl Compilation {
+    /// Starts compiling the given phonebook in the background.
+    ///
+    /// Sound durations are not read on the background thread, so it
+    /// needs no libvlc of its own, see `Run::read_durations`.
+    pub fn spawn(spec: BookSpec, crossfade: Option<Duration>) -> Result<Self> {
+        let (tx, progress) = channel();
+        thread::Builder::new()
+            .name("compile-book".to_string())
+            .spawn(move || {
+                let result = compile_with_progress(spec, |sounds_remaining| {
+                    tx.send(Progress::Compiling { sounds_remaining }).ok();
+                });
+                if tx.send(Progress::Done(result.map(Box::new))).is_err() {
+                    debug!("compiled phonebook is not needed anymore");
+                }
+            })?;
+
+        Ok(Compilation {
+            progress,
+            crossfade,
+        })
+    }
+
+    /// Crossfade duration requested for switching to the book.
+    pub fn crossfade(&self) -> Option<Duration> {
+        self.crossfade
+    }
+
+    /// Gets the progress reported since the last call, without
+    /// blocking.
+    ///
+    /// If the compilation thread stopped without a result, e.g.
+    /// because it panicked, this is reported as a failure.
+    pub fn poll(&self) -> Vec<Progress> {
+        let mut progress = vec![];
+        loop {
+            match self.progress.try_recv() {
+                Ok(next) => progress.push(next),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    let done = progress.iter().any(|p| matches!(p, Progress::Done(_)));
+                    if !done {
+                        progress.push(Progress::Done(Err(format_err!(
+                            "Compilation stopped unexpectedly"
+                        ))));
+                    }
+                    break;
+                }
+            }
+        }
+        progress
+    }
+}
@@ -0,0 +1,49 @@
+use crate::result::Result;
+
+use serde::Deserialize;
+use serde_yaml::from_reader;
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Settings for an app, read from a YAML configuration file.
+///
+/// Keys mirror the methods of the app builder, every key is
+/// optional.
+#[derive(Deserialize, Default, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Path to a phonebook to run at startup. Relative paths
+    /// are relative to the directory of the configuration file.
+    pub phonebook: Option<PathBuf>,
+    /// Hostname and port to host the remote control server on.
+    pub serve: Option<String>,
+    /// I2C device file of the phone, e.g. `/dev/i2c-1`.
+    pub i2c_device: Option<String>,
+    /// I2C slave address of the phone, `4` if unspecified.
+    pub i2c_address: Option<u16>,
+    /// Time to sleep between ticks in milliseconds.
+    pub tick_interval_ms: Option<u64>,
+    /// If `true`, exits when reaching a terminal state instead
+    /// of starting over.
+    pub exit_on_terminal: Option<bool>,
+    /// Volume of sounds while speaking or ringing, e.g. `0.3` for
+    /// 30 %. Sounds keep their volume if unspecified.
+    pub duck_level: Option<f32>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        let mut config: Config = from_reader(file)?;
+
+        if let Some(phonebook) = config.phonebook.take() {
+            config.phonebook = Some(match path.parent() {
+                Some(config_dir) => config_dir.join(phonebook),
+                None => phonebook,
+            });
+        }
+
+        Ok(config)
+    }
+}
@@ -1,21 +1,36 @@
 mod builder;
+mod compilation;
+mod config;
+mod panic;
 mod run;
+mod simulate;
+mod validate;
 
+use crate::books::{spec, Book, FileWatcher};
+#[cfg(feature = "tokio-runtime")]
+use crate::evt::{AsyncResponder, AsyncResponderAdapter};
+use crate::evt::{Responder, ResponderId};
 use crate::result::Result;
 use crate::senses::QueueInput;
-use crate::serve::Request;
 use crate::serve::Server;
+use crate::serve::{ConnectionHandle, FernspielEvent, Query, Request};
+use crate::states::State;
 
-use log::debug;
+use compilation::{Compilation, Progress};
+use log::{debug, info, warn};
 use run::Run;
+use simulate::Simulator;
 
 use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
 use std::sync::Arc;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+pub use crate::serve::Format;
 pub use builder::Builder;
+pub use run::SensorConfig;
+pub use validate::Warning;
 
 /// Controls the main loop, invoking the run for ticks
 /// and controlling termination through the termination
@@ -29,11 +44,34 @@ pub struct App {
     ///
     /// Can be modified by remote control messages.
     run: Run,
+    /// A silent run advancing on the same input as `run`,
+    /// for A/B testing of phonebooks.
+    secondary: Option<Run>,
     server: Option<Rc<Server>>,
+    /// Shows the state and reads keys when simulating the phone.
+    simulator: Option<Simulator>,
+    /// A phonebook sent by a client that is still being compiled.
+    compilation: Option<Compilation>,
+    /// Compiles the startup phonebook again when its file changes.
+    watcher: Option<FileWatcher>,
     /// Behavior when phonebook reaches a terminal state.
     terminal_state_behavior: TerminalStateBehavior,
     termination_flag: Arc<AtomicBool>,
+    /// Set when a reset is requested from the keyboard.
+    reset_flag: Arc<AtomicBool>,
+    /// Set when quitting is requested from the keyboard.
+    quit_flag: Arc<AtomicBool>,
     control: QueueInput,
+    /// Time to sleep between two ticks.
+    tick_interval: Duration,
+    /// Warn if a single tick takes longer than this.
+    tick_overhead_limit: Option<Duration>,
+    /// If `true`, the last tick exceeded the limit and a warning
+    /// has already been logged.
+    tick_overhead_exceeded: bool,
+    /// If set, progress events are published this often.
+    progress_interval: Option<Duration>,
+    last_progress: Instant,
 }
 
 #[derive(Debug, PartialEq)]
@@ -46,42 +84,283 @@ pub enum TerminalStateBehavior {
     Rewind,
 }
 
+/// Why `App::run` returned without an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminationCause {
+    /// The termination flag was set, e.g. by Ctrl-C.
+    Signal,
+    /// A terminal state was reached with `TerminalStateBehavior::Exit`.
+    TerminalState,
+    /// Quitting was requested with a command typed in line mode.
+    ShutdownCommand,
+}
+
 impl App {
     pub fn builder() -> Builder {
         Builder::new()
     }
 
+    /// Makes a builder and configures it with the given function,
+    /// so that fallible and infallible builder methods can be
+    /// chained with `?`.
+    ///
+    /// Fails with the first error of the configuration.
+    pub fn builder_try<F>(configure: F) -> Result<Builder>
+    where
+        F: FnOnce(&mut Builder) -> Result<&mut Builder>,
+    {
+        let mut builder = Builder::new();
+        configure(&mut builder)?;
+        Ok(builder)
+    }
+
     /// Keeps the application running, including phonebook evaluation
     /// and the remote control server, depending on configuration.
     /// Terminates when requested with termination flag or when reaching
     /// a fatal error, e.g. a malformed startup phonebook.
     ///
     /// Consumes the startup book.
-    pub fn run(&mut self) -> Result<()> {
-        while !self.should_terminate() {
-            self.poll_remote_control()?;
-
-            let running = self.run.tick();
-            if !running {
-                match self.terminal_state_behavior {
-                    TerminalStateBehavior::Exit => {
-                        debug!("reached terminal state, exiting");
-                        break;
-                    }
-                    TerminalStateBehavior::Rewind => self.run.reset(),
+    ///
+    /// The remote control server is shut down when exiting, even
+    /// after an error.
+    ///
+    /// Returns why the app stopped running, unless it stopped with
+    /// an error.
+    pub fn run(&mut self) -> Result<TerminationCause> {
+        let result = self.run_until_exit();
+        self.shutdown_server();
+        result
+    }
+
+    /// Like `run`, but sleeps between ticks with `tokio::time::sleep`
+    /// so the thread is free for other tasks in the meantime.
+    ///
+    /// The app holds sounds, sensors and the remote control server
+    /// in types that are not `Send`, so the returned future cannot
+    /// be moved to another thread.
+    ///
+    /// # Migrating from `run`
+    /// Instead of calling `app.run()` on a dedicated thread, create
+    /// the app on a thread that drives a current-thread runtime or
+    /// a `tokio::task::LocalSet` and await it there, e.g. with
+    /// `runtime.block_on(app.run_async())` or
+    /// `local_set.spawn_local(async move { app.run_async().await })`.
+    /// Do not use `tokio::spawn`, it requires a `Send` future.
+    #[cfg(feature = "tokio-runtime")]
+    pub async fn run_async(&mut self) -> Result<TerminationCause> {
+        let result = self.run_until_exit_async().await;
+        self.shutdown_server();
+        result
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    async fn run_until_exit_async(&mut self) -> Result<TerminationCause> {
+        loop {
+            if let Some(cause) = self.requested_termination() {
+                return Ok(cause);
+            }
+
+            if !self.timed_tick()? {
+                return Ok(TerminationCause::TerminalState);
+            }
+
+            tokio::time::sleep(self.tick_interval).await;
+        }
+    }
+
+    fn shutdown_server(&self) {
+        if let Some(server) = self.server.as_ref() {
+            server.shutdown();
+        }
+    }
+
+    fn run_until_exit(&mut self) -> Result<TerminationCause> {
+        loop {
+            if let Some(cause) = self.requested_termination() {
+                return Ok(cause);
+            }
+
+            if !self.timed_tick()? {
+                return Ok(TerminationCause::TerminalState);
+            }
+
+            sleep(self.tick_interval);
+        }
+    }
+
+    /// Ticks and warns if the tick took longer than configured.
+    ///
+    /// Only warns once until ticks are fast enough again.
+    fn timed_tick(&mut self) -> Result<bool> {
+        let start = Instant::now();
+        let running = self.tick()?;
+
+        if let Some(limit) = self.tick_overhead_limit {
+            let took = start.elapsed();
+            if took <= limit {
+                self.tick_overhead_exceeded = false;
+            } else if !self.tick_overhead_exceeded {
+                warn!(
+                    "tick took {:?}, exceeding the limit of {:?}, the system may be overloaded",
+                    took, limit
+                );
+                self.tick_overhead_exceeded = true;
+            }
+        }
+
+        Ok(running)
+    }
+
+    /// Current state of the primary phonebook, that is, the
+    /// phonebook with active actuators.
+    pub fn primary_state(&self) -> &State {
+        self.run.current_state()
+    }
+
+    /// Name of the current state of the primary phonebook.
+    pub fn current_state_name(&self) -> &str {
+        self.run.current_state_name()
+    }
+
+    /// The spec the primary phonebook was compiled from, e.g. to
+    /// export it again. `None` if the phonebook was built in code.
+    pub fn book_spec(&self) -> Option<&spec::Book> {
+        self.run.book_spec()
+    }
+
+    /// All states of the primary phonebook, the initial state first,
+    /// e.g. to show the whole state graph.
+    pub fn states(&self) -> &[State] {
+        self.run.states()
+    }
+
+    /// Finds the state of the primary phonebook with the given ID.
+    pub fn state_by_id(&self, id: &str) -> Option<&State> {
+        self.run.state_by_id(id)
+    }
+
+    /// Finds the first state of the primary phonebook with the
+    /// given name.
+    pub fn state_by_name(&self, name: &str) -> Option<&State> {
+        self.run.state_by_name(name)
+    }
+
+    /// Forces a transition of the primary phonebook to the state at
+    /// the index returned by the predicate for the current state, e.g.
+    /// when a button in a GUI should always go to a help state.
+    ///
+    /// Returns `true` if a transition was performed, `false` if the
+    /// predicate returned `None` or an index without a state.
+    pub fn transition_if(&mut self, predicate: impl Fn(&State) -> Option<usize>) -> bool {
+        self.run.transition_if(predicate)
+    }
+
+    /// The last transitions of the primary phonebook, oldest first,
+    /// as pairs of the cause and the name of the target state.
+    pub fn transition_history(&self) -> Vec<(String, String)> {
+        self.run.transition_history()
+    }
+
+    /// Sends the events of the primary phonebook to the given
+    /// responder, e.g. to monitor a running app. The responder
+    /// keeps receiving events after switching phonebooks.
+    ///
+    /// Its `update` is called on every tick.
+    pub fn add_responder(&mut self, responder: Box<dyn Responder<State>>) -> ResponderId {
+        self.run.add_responder(responder)
+    }
+
+    /// Like `add_responder`, but runs the responder on a tokio
+    /// runtime in a background thread, so that e.g. posting events
+    /// to a webhook does not block the main loop.
+    ///
+    /// Removing the responder waits for its queued events.
+    #[cfg(feature = "tokio-runtime")]
+    pub fn add_async_responder<R>(&mut self, responder: R) -> Result<ResponderId>
+    where
+        R: AsyncResponder<State> + 'static,
+    {
+        let adapter = AsyncResponderAdapter::spawn(responder)?;
+        Ok(self.add_responder(Box::new(adapter)))
+    }
+
+    /// Stops sending events to a responder added with
+    /// `add_responder`, returning it if it was still there.
+    pub fn remove_responder(&mut self, id: ResponderId) -> Option<Box<dyn Responder<State>>> {
+        self.run.remove_responder(id)
+    }
+
+    /// Current state of the parallel phonebook that silently
+    /// advances on the same input, if any.
+    pub fn secondary_state(&self) -> Option<&State> {
+        self.secondary.as_ref().map(Run::current_state)
+    }
+
+    /// Handles remote control and advances the runs.
+    ///
+    /// Returns `false` if the application should exit because
+    /// a terminal state has been reached.
+    fn tick(&mut self) -> Result<bool> {
+        self.poll_remote_control()?;
+        self.poll_compilation()?;
+        self.poll_watcher()?;
+
+        if self.reset_flag.swap(false, SeqCst) {
+            debug!("reset requested from the keyboard");
+            self.handle_request(Request::Reset, None)?;
+        }
+
+        let running = self.run.tick();
+
+        if let Some(progress) = self.progress() {
+            if let Some(server) = self.server.as_ref() {
+                server.publish(progress);
+            }
+        }
+
+        if let Some(simulator) = self.simulator.as_mut() {
+            simulator.show(self.run.current_state());
+        }
+
+        // the secondary run always starts over, it cannot cause an exit
+        if let Some(secondary) = self.secondary.as_mut() {
+            if !secondary.tick() {
+                secondary.reset();
+            }
+        }
+
+        if !running {
+            match self.terminal_state_behavior {
+                TerminalStateBehavior::Exit => {
+                    debug!("reached terminal state, exiting");
+                    return Ok(false);
                 }
+                TerminalStateBehavior::Rewind => self.run.reset(),
             }
+        }
 
-            sleep(Duration::from_millis(10));
+        Ok(true)
+    }
+
+    /// Makes a progress event if the configured interval has
+    /// passed since the last one.
+    fn progress(&mut self) -> Option<FernspielEvent> {
+        let interval = self.progress_interval?;
+        if self.last_progress.elapsed() < interval {
+            return None;
         }
 
-        Ok(())
+        self.last_progress = Instant::now();
+        Some(FernspielEvent::progress(
+            self.run.current_state(),
+            self.run.actuator_progress(),
+        ))
     }
 
     fn poll_remote_control(&mut self) -> Result<()> {
         if let Some(server) = self.server.as_mut() {
-            if let Some(request) = server.poll() {
-                self.handle_request(request)?;
+            if let Some((source, request)) = server.poll() {
+                self.handle_request(request, Some(source))?;
             }
         }
 
@@ -90,16 +369,85 @@ impl App {
 
     /// Handles a websocket request, which may overwrite the current phonebook
     /// run.
-    fn handle_request(&mut self, request: Request) -> Result<()> {
+    ///
+    /// Transitions caused by a dial request are attributed to the
+    /// source connection, if any.
+    fn handle_request(&mut self, request: Request, source: Option<ConnectionHandle>) -> Result<()> {
         match request {
             // reset request, start over with last phonebook
-            Request::Reset => self.run.reset(),
-            // stop current phonebook and launch the sent one
-            Request::Run(new_book) => self.run.switch(new_book)?,
+            Request::Reset => {
+                self.run.reset();
+                if let Some(secondary) = self.secondary.as_mut() {
+                    secondary.reset();
+                }
+            }
+            // jump to a state of the current phonebook
+            Request::Goto(state_id) => {
+                if let Err(err) = self.run.reset_to(&state_id) {
+                    warn!("ignoring goto request: {}", err);
+                }
+            }
+            // silence everything, but stay in the current state
+            Request::Stop => {
+                if let Err(err) = self.run.stop() {
+                    warn!("failed to stop all sounds: {}", err);
+                }
+            }
+            // compile the sent phonebook in the background, then
+            // stop the current phonebook and launch the sent one
+            Request::Run { book, crossfade } => {
+                // a newer book replaces one that is still compiling
+                self.compilation = Some(Compilation::spawn(*book, crossfade)?);
+            }
+            Request::Query(Query::History) => {
+                if let Some(server) = self.server.as_ref() {
+                    let history = FernspielEvent::history(self.transition_history());
+                    match source {
+                        Some(source) => server.send_to(source, history),
+                        None => server.publish(history),
+                    }
+                }
+            }
+            Request::Query(Query::Idle) => {
+                if let Some(server) = self.server.as_ref() {
+                    let idle = FernspielEvent::idle(self.is_idle());
+                    match source {
+                        Some(source) => server.send_to(source, idle),
+                        None => server.publish(idle),
+                    }
+                }
+            }
+            Request::Query(Query::Book) => {
+                if let Some(server) = self.server.as_ref() {
+                    let book = FernspielEvent::book(self.run.book());
+                    match source {
+                        Some(source) => server.send_to(source, book),
+                        None => server.publish(book),
+                    }
+                }
+            }
+            Request::Query(Query::Peers) => {
+                if let Some(server) = self.server.as_ref() {
+                    let peers = FernspielEvent::peers(&server.connected_peers());
+                    match source {
+                        Some(source) => server.send_to(source, peers),
+                        None => server.publish(peers),
+                    }
+                }
+            }
+            Request::Query(Query::Status) => {
+                if let Some(server) = self.server.as_ref() {
+                    let status = self.status();
+                    match source {
+                        Some(source) => server.send_to(source, status),
+                        None => server.publish(status),
+                    }
+                }
+            }
             Request::Dial(input) => {
-                debug!("remote dial: {:?}", input);
+                debug!("remote dial: {:?} from {:?}", input, source);
                 input.into_iter().for_each(|i| {
-                    self.control.send(i).ok();
+                    self.control.send_from(i, source).ok();
                 })
             }
         };
@@ -107,7 +455,634 @@ impl App {
         Ok(())
     }
 
-    fn should_terminate(&self) -> bool {
-        self.termination_flag.load(SeqCst)
+    /// Publishes the progress of a phonebook that is compiling in
+    /// the background and switches to it when done.
+    ///
+    /// If compilation fails, the current phonebook keeps running.
+    fn poll_compilation(&mut self) -> Result<()> {
+        let progress = match self.compilation.as_ref() {
+            Some(compilation) => compilation.poll(),
+            None => return Ok(()),
+        };
+
+        for progress in progress {
+            match progress {
+                Progress::Compiling { sounds_remaining } => {
+                    self.publish(FernspielEvent::Compiling { sounds_remaining })
+                }
+                Progress::Done(result) => {
+                    // unwrap is safe, only polled when compiling
+                    let crossfade = self.compilation.take().unwrap().crossfade();
+                    match result.and_then(|book| self.run.read_durations(*book)) {
+                        Ok(book) => self.switch(book, crossfade)?,
+                        Err(err) => {
+                            warn!("phonebook could not be compiled: {}", err);
+                            self.publish(FernspielEvent::CompilationFailed {
+                                error: err.to_string(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Switches to the startup phonebook again if its file changed.
+    ///
+    /// If the changed file cannot be compiled, the current phonebook
+    /// keeps running.
+    fn poll_watcher(&mut self) -> Result<()> {
+        let reloaded = match self.watcher.as_ref() {
+            Some(watcher) => watcher.poll(),
+            None => return Ok(()),
+        };
+
+        for book in reloaded {
+            match book {
+                Ok(book) => {
+                    info!("phonebook changed on disk, reloading");
+                    self.switch(book, None)?;
+                }
+                Err(err) => warn!(
+                    "changed phonebook could not be compiled, keeping the current one: {}",
+                    err
+                ),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn publish(&self, event: FernspielEvent) {
+        if let Some(server) = self.server.as_ref() {
+            server.publish(event);
+        }
+    }
+
+    /// Checks if no phonebook has been loaded yet, neither at
+    /// startup nor from a remote control client.
+    pub fn is_idle(&self) -> bool {
+        self.run.is_passive()
+    }
+
+    /// Switches to the given book, replaying any input that arrived
+    /// before the switch but was not yet processed, e.g. when the
+    /// user dials while the old book is in a terminal state.
+    ///
+    /// With a crossfade duration, sounds of the old book fade out
+    /// instead of stopping right away.
+    fn switch(&mut self, book: Book, crossfade: Option<Duration>) -> Result<()> {
+        let was_idle = self.is_idle();
+        let buffered = self.run.drain_input();
+        let switched = match crossfade {
+            Some(duration) => self.run.switch_with_crossfade(book, duration),
+            None => self.run.switch(book),
+        };
+
+        if was_idle != self.is_idle() {
+            if let Some(server) = self.server.as_ref() {
+                server.publish_retained(FernspielEvent::idle(self.is_idle()));
+            }
+        }
+
+        if !buffered.is_empty() {
+            debug!("replaying input after switch: {:?}", buffered);
+        }
+        for (input, source) in buffered {
+            self.control.send_from(input, source).ok();
+        }
+
+        switched
+    }
+
+    /// Describes the current state of the primary phonebook and
+    /// how long it has been active.
+    fn status(&self) -> FernspielEvent {
+        FernspielEvent::status(self.primary_state(), self.run.time_in_current_state())
+            .in_book(self.run.book().book_id())
+    }
+
+    /// Checks if the app was asked to stop running, and how.
+    fn requested_termination(&self) -> Option<TerminationCause> {
+        if self.termination_flag.load(SeqCst) {
+            Some(TerminationCause::Signal)
+        } else if self.quit_flag.load(SeqCst) {
+            Some(TerminationCause::ShutdownCommand)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::evt::Event;
+    use crate::senses::Input;
+    use std::cell::RefCell;
+
+    #[cfg(feature = "tokio-runtime")]
+    #[tokio::test]
+    async fn run_async_until_terminal_state() {
+        // given
+        let mut book = Book::builder();
+        book.state(
+            State::builder()
+                .id("waiting")
+                .name("waiting")
+                .timeout(Duration::from_millis(50), 1)
+                .build(),
+        )
+        .state(
+            State::builder()
+                .id("done")
+                .name("done")
+                .terminal(true)
+                .build(),
+        );
+        let mut builder = App::builder();
+        builder
+            .startup_phonebook(book.build())
+            .exit_on_terminal_state();
+        let mut app = builder.build().unwrap();
+
+        // when
+        let cause = app.run_async().await.unwrap();
+
+        // then
+        assert_eq!(cause, TerminationCause::TerminalState);
+        assert_eq!(app.primary_state().id(), "done");
+    }
+
+    #[test]
+    fn transition_history_names_target_states() {
+        // given
+        let mut book = Book::builder();
+        book.state(
+            State::builder()
+                .id("1")
+                .name("down")
+                .input(Input::pick_up(), 1)
+                .build(),
+        )
+        .state(
+            State::builder()
+                .id("2")
+                .name("up")
+                .input(Input::hang_up(), 0)
+                .build(),
+        );
+        let mut builder = App::builder();
+        builder.startup_phonebook(book.build());
+        let mut app = builder.build().unwrap();
+
+        // when
+        app.control.send(Input::pick_up()).unwrap();
+        app.tick().unwrap();
+        app.control.send(Input::hang_up()).unwrap();
+        app.tick().unwrap();
+
+        // then
+        assert_eq!(
+            app.transition_history(),
+            vec![
+                ("picked up".to_string(), "up".to_string()),
+                ("hung up".to_string(), "down".to_string()),
+            ]
+        );
+    }
+
+    /// Records the IDs of the states entered through transitions.
+    struct TransitionRecorder(Rc<RefCell<Vec<String>>>);
+
+    impl Responder<State> for TransitionRecorder {
+        fn respond(&mut self, event: &Event<State>) -> Result<()> {
+            if let Event::Transition { to, .. } = event {
+                self.0.borrow_mut().push(to.id().to_string());
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn added_responder_receives_events_until_removed() {
+        // given
+        let mut book = Book::builder();
+        book.state(
+            State::builder()
+                .id("down")
+                .input(Input::pick_up(), 1)
+                .build(),
+        )
+        .state(State::builder().id("up").input(Input::hang_up(), 0).build());
+        let mut builder = App::builder();
+        builder.startup_phonebook(book.build());
+        let mut app = builder.build().unwrap();
+        let recorded = Rc::new(RefCell::new(vec![]));
+
+        // when
+        app.control.send(Input::pick_up()).unwrap();
+        app.tick().unwrap();
+        let id = app.add_responder(Box::new(TransitionRecorder(Rc::clone(&recorded))));
+        app.control.send(Input::hang_up()).unwrap();
+        app.tick().unwrap();
+        let removed = app.remove_responder(id);
+        app.control.send(Input::pick_up()).unwrap();
+        app.tick().unwrap();
+
+        // then
+        assert!(removed.is_some());
+        assert_eq!(*recorded.borrow(), vec!["down".to_string()]);
+    }
+
+    /// Like `TransitionRecorder`, but async.
+    #[cfg(feature = "tokio-runtime")]
+    struct AsyncTransitionRecorder(Arc<std::sync::Mutex<Vec<String>>>);
+
+    #[cfg(feature = "tokio-runtime")]
+    impl AsyncResponder<State> for AsyncTransitionRecorder {
+        async fn respond(&mut self, event: &Event<'_, State>) -> Result<()> {
+            if let Event::Transition { to, .. } = event {
+                self.0.lock().unwrap().push(to.id().to_string());
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "tokio-runtime")]
+    #[test]
+    fn async_responder_receives_events() {
+        // given
+        let mut book = Book::builder();
+        book.state(
+            State::builder()
+                .id("down")
+                .input(Input::pick_up(), 1)
+                .build(),
+        )
+        .state(State::builder().id("up").build());
+        let mut builder = App::builder();
+        builder.startup_phonebook(book.build());
+        let mut app = builder.build().unwrap();
+        let recorded = Arc::new(std::sync::Mutex::new(vec![]));
+
+        // when
+        let id = app
+            .add_async_responder(AsyncTransitionRecorder(Arc::clone(&recorded)))
+            .unwrap();
+        app.control.send(Input::pick_up()).unwrap();
+        app.tick().unwrap();
+        // waits for the queued events
+        drop(app.remove_responder(id));
+
+        // then
+        assert_eq!(*recorded.lock().unwrap(), vec!["up".to_string()]);
+    }
+
+    #[test]
+    fn app_exposes_spec_of_primary_book() {
+        // given
+        let book = crate::books::from_str(
+            "initial: greeting\nstates:\n  greeting:\n    name: Hello\n    terminal: true\n",
+        )
+        .unwrap();
+        let mut builder = App::builder();
+        builder.startup_phonebook(book);
+
+        // when
+        let app = builder.build().unwrap();
+        let spec = app.book_spec().unwrap();
+
+        // then
+        let greeting = spec::Id::new("greeting");
+        assert_eq!(spec.initial, greeting);
+        assert_eq!(
+            spec.states[&greeting].as_ref().map(|s| s.name.as_str()),
+            Some("Hello")
+        );
+    }
+
+    #[test]
+    fn app_exposes_all_states_of_primary_book() {
+        // given
+        let mut book = Book::builder();
+        book.state(State::builder().id("1").name("down").build())
+            .state(State::builder().id("2").name("up").build());
+        let mut builder = App::builder();
+        builder.startup_phonebook(book.build());
+        let app = builder.build().unwrap();
+
+        // when
+        let names: Vec<&str> = app.states().iter().map(State::name).collect();
+        let up = app.state_by_id("2").map(State::name);
+        let down = app.state_by_name("down").map(State::id);
+
+        // then
+        assert_eq!(names, vec!["down", "up"]);
+        assert_eq!(up, Some("up"));
+        assert_eq!(down, Some("1"));
+    }
+
+    #[test]
+    fn parallel_phonebook_advances_on_same_input() {
+        // given
+        let mut primary = Book::builder();
+        primary
+            .state(
+                State::builder()
+                    .id("primary 1")
+                    .name("primary 1")
+                    .input(Input::pick_up(), 1)
+                    .build(),
+            )
+            .state(State::builder().id("primary 2").name("primary 2").build());
+        let mut secondary = Book::builder();
+        secondary
+            .state(
+                State::builder()
+                    .id("secondary 1")
+                    .name("secondary 1")
+                    .input(Input::pick_up(), 1)
+                    .build(),
+            )
+            .state(
+                State::builder()
+                    .id("secondary 2")
+                    .name("secondary 2")
+                    .build(),
+            );
+        let mut builder = App::builder();
+        builder
+            .startup_phonebook(primary.build())
+            .parallel_phonebook(secondary.build());
+
+        // when
+        let mut app = builder.build().unwrap();
+        app.tick().unwrap();
+        let primary_before = app.primary_state().id().to_string();
+        let secondary_before = app.secondary_state().unwrap().id().to_string();
+        app.control.send(Input::pick_up()).unwrap();
+        app.tick().unwrap();
+        let primary_after = app.primary_state().id().to_string();
+        let secondary_after = app.secondary_state().unwrap().id().to_string();
+
+        // then
+        assert_eq!(primary_before, "primary 1");
+        assert_eq!(secondary_before, "secondary 1");
+        assert_eq!(primary_after, "primary 2");
+        assert_eq!(secondary_after, "secondary 2");
+    }
+
+    #[test]
+    fn input_during_terminal_state_is_replayed_after_switch() {
+        // given
+        let mut finished = Book::builder();
+        finished.state(
+            State::builder()
+                .id("finished")
+                .name("finished")
+                .terminal(true)
+                .build(),
+        );
+        let mut next = Book::builder();
+        next.state(
+            State::builder()
+                .id("next 1")
+                .name("next 1")
+                .input(Input::pick_up(), 1)
+                .build(),
+        )
+        .state(State::builder().id("next 2").name("next 2").build());
+        let mut builder = App::builder();
+        builder.startup_phonebook(finished.build());
+
+        // when
+        let mut app = builder.build().unwrap();
+        app.tick().unwrap();
+        app.control.send(Input::pick_up()).unwrap();
+        app.switch(next.build(), None).unwrap();
+        let state_after_switch = app.primary_state().id().to_string();
+        app.tick().unwrap();
+        let state_after_replay = app.primary_state().id().to_string();
+
+        // then
+        assert_eq!(state_after_switch, "next 1");
+        assert_eq!(state_after_replay, "next 2");
+    }
+
+    #[test]
+    fn run_request_switches_after_background_compilation() {
+        // given
+        let mut app = App::builder().build().unwrap();
+        let request = Request::decode(
+            "{ invoke: run, with: { initial: compiled, states: { compiled: { terminal: true } } } }",
+        )
+        .unwrap();
+
+        // when
+        app.handle_request(request, None).unwrap();
+        let start = Instant::now();
+        while app.is_idle() && start.elapsed() < Duration::from_secs(5) {
+            app.tick().unwrap();
+            sleep(Duration::from_millis(10));
+        }
+
+        // then
+        assert_eq!(app.primary_state().id(), "compiled");
+        assert!(app.compilation.is_none());
+    }
+
+    #[test]
+    fn stop_request_keeps_running_in_current_state() {
+        // given
+        let mut book = Book::builder();
+        book.state(State::builder().id("current").name("current").build());
+        let mut builder = App::builder();
+        builder.startup_phonebook(book.build());
+        let mut app = builder.build().unwrap();
+        app.tick().unwrap();
+
+        // when
+        app.handle_request(Request::Stop, None).unwrap();
+        let running = app.tick().unwrap();
+
+        // then
+        assert!(running, "expected app to keep running after stop");
+        assert_eq!(app.primary_state().id(), "current");
+    }
+
+    #[test]
+    fn failed_compilation_keeps_current_book() {
+        // given
+        let mut book = Book::builder();
+        book.state(State::builder().id("current").name("current").build());
+        let mut builder = App::builder();
+        builder.startup_phonebook(book.build());
+        let mut app = builder.build().unwrap();
+        let request =
+            Request::decode("{ invoke: run, with: { initial: missing, states: { other: {} } } }")
+                .unwrap();
+
+        // when
+        app.handle_request(request, None).unwrap();
+        let start = Instant::now();
+        while app.compilation.is_some() && start.elapsed() < Duration::from_secs(5) {
+            app.tick().unwrap();
+            sleep(Duration::from_millis(10));
+        }
+
+        // then
+        assert!(app.compilation.is_none());
+        assert_eq!(app.primary_state().id(), "current");
+    }
+
+    #[test]
+    fn changed_phonebook_file_is_reloaded() {
+        // given
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.yaml");
+        std::fs::write(
+            &path,
+            "initial: first\nstates:\n  first: { terminal: true }\n",
+        )
+        .unwrap();
+        let mut builder = App::builder();
+        builder
+            .startup_phonebook_path(&path)
+            .unwrap()
+            .reload_on_change();
+        let mut app = builder.build().unwrap();
+
+        // when
+        std::fs::write(
+            &path,
+            "initial: reloaded\nstates:\n  reloaded: { terminal: true }\n",
+        )
+        .unwrap();
+        let start = Instant::now();
+        while app.primary_state().id() == "first" && start.elapsed() < Duration::from_secs(5) {
+            app.tick().unwrap();
+            sleep(Duration::from_millis(10));
+        }
+
+        // then
+        assert_eq!(app.primary_state().id(), "reloaded");
+    }
+
+    #[test]
+    fn broken_phonebook_file_keeps_current_book() {
+        // given
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.yaml");
+        std::fs::write(
+            &path,
+            "initial: current\nstates:\n  current: { terminal: true }\n",
+        )
+        .unwrap();
+        let mut builder = App::builder();
+        builder
+            .startup_phonebook_path(&path)
+            .unwrap()
+            .reload_on_change();
+        let mut app = builder.build().unwrap();
+
+        // when
+        std::fs::write(&path, "initial: [current\n").unwrap();
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(500) {
+            app.tick().unwrap();
+            sleep(Duration::from_millis(10));
+        }
+
+        // then
+        assert_eq!(app.primary_state().id(), "current");
+    }
+
+    #[cfg_attr(not(feature = "expensive_tests"), ignore)]
+    #[test]
+    fn progress_events_at_interval() {
+        // given
+        let ring_time = Duration::from_secs(1);
+        let mut book = Book::builder();
+        book.state(
+            State::builder()
+                .id("ringing")
+                .name("ringing")
+                .ring_for(ring_time)
+                .build(),
+        );
+        let mut builder = App::builder();
+        builder
+            .startup_phonebook(book.build())
+            .progress_interval(Duration::from_millis(100));
+        let mut app = builder.build().unwrap();
+
+        // when
+        let start = Instant::now();
+        let mut events = vec![];
+        while start.elapsed() < ring_time {
+            app.run.tick();
+            events.extend(app.progress());
+            sleep(Duration::from_millis(10));
+        }
+
+        // then
+        assert!(
+            events.len() >= 9 && events.len() <= 11,
+            "expected about 10 progress events, got {}",
+            events.len()
+        );
+        let remaining: Vec<u64> = events
+            .iter()
+            .filter_map(|evt| match evt {
+                FernspielEvent::Progress { actuator, .. } => actuator.ring_remaining_ms,
+                _ => None,
+            })
+            .collect();
+        assert!(!remaining.is_empty());
+        assert!(remaining.windows(2).all(|pair| pair[0] > pair[1]));
+    }
+
+    #[test]
+    fn quit_command_is_reported_as_cause() {
+        // given
+        let mut book = Book::builder();
+        book.state(State::builder().id("forever").name("forever").build());
+        let mut builder = App::builder();
+        builder.startup_phonebook(book.build());
+        let mut app = builder.build().unwrap();
+
+        // when
+        app.quit_flag.store(true, SeqCst);
+        let cause = app.run().unwrap();
+
+        // then
+        assert_eq!(cause, TerminationCause::ShutdownCommand);
+    }
+
+    #[test]
+    fn background_panic_terminates_run() {
+        // given
+        let mut book = Book::builder();
+        book.state(State::builder().id("forever").name("forever").build());
+        let mut builder = App::builder();
+        builder
+            .startup_phonebook(book.build())
+            .terminate_on_background_panic();
+        let mut app = builder.build().unwrap();
+
+        // when
+        let sensor_thread = std::thread::Builder::new()
+            .name("sense-test".into())
+            .spawn(|| panic!("sensor failure"))
+            .unwrap();
+        let panicked = sensor_thread.join().is_err();
+        let cause = app.run().unwrap();
+
+        // then
+        assert!(panicked);
+        assert_eq!(cause, TerminationCause::Signal);
     }
 }
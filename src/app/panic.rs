@@ -0,0 +1,87 @@
+use log::error;
+
+use std::panic::{set_hook, take_hook};
+use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+use std::sync::{Arc, Mutex, MutexGuard, Once, Weak};
+use std::thread;
+
+/// Name prefixes of the threads that the app spawns in the
+/// background, e.g. for sensors or the remote control server.
+const BACKGROUND_THREADS: &[&str] = &["ctl-", "sense-", "simulate-", "async-responder"];
+
+static INSTALL_HOOK: Once = Once::new();
+
+/// Flags of the apps that are still alive, set when a
+/// background thread panics.
+static TERMINATION_FLAGS: Mutex<Vec<Weak<AtomicBool>>> = Mutex::new(Vec::new());
+
+/// Sets the termination flag when a background thread of the app
+/// panics, so the main loop exits instead of running on without
+/// its sensors or server.
+///
+/// The panic hook is installed only once per process, later calls
+/// just register their flag with it. Any previously installed hook
+/// is still called afterwards.
+pub fn terminate_on_background_panic(termination_flag: &Arc<AtomicBool>) {
+    INSTALL_HOOK.call_once(|| {
+        let previous = take_hook();
+        set_hook(Box::new(move |info| {
+            if let Some(name) = thread::current().name().filter(|n| is_background(n)) {
+                error!("background thread {} panicked, terminating: {}", name, info);
+                terminate_all();
+            }
+            previous(info)
+        }));
+    });
+
+    let mut flags = lock_flags();
+    // forget the flags of apps that have been dropped in the meantime
+    flags.retain(|flag| flag.strong_count() > 0);
+    flags.push(Arc::downgrade(termination_flag));
+}
+
+fn terminate_all() {
+    for flag in lock_flags().iter().filter_map(Weak::upgrade) {
+        flag.store(true, SeqCst);
+    }
+}
+
+/// Locks the registered flags, even if another thread panicked
+/// while holding the lock.
+fn lock_flags() -> MutexGuard<'static, Vec<Weak<AtomicBool>>> {
+    TERMINATION_FLAGS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn is_background(thread_name: &str) -> bool {
+    BACKGROUND_THREADS
+        .iter()
+        .any(|prefix| thread_name.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hook_is_installed_once() {
+        // given
+        let first = Arc::new(AtomicBool::new(false));
+        let second = Arc::new(AtomicBool::new(false));
+
+        // when
+        terminate_on_background_panic(&first);
+        terminate_on_background_panic(&second);
+        let panicked = thread::Builder::new()
+            .name("sense-test".into())
+            .spawn(|| panic!("sensor failed"))
+            .unwrap()
+            .join();
+
+        // then
+        assert!(panicked.is_err());
+        assert!(first.load(SeqCst));
+        assert!(second.load(SeqCst));
+    }
+}
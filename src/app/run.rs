@@ -1,15 +1,19 @@
-use crate::acts::Actuators;
-use crate::books::Book;
-use crate::evt::Responder;
+use crate::acts::{Actuators, Ensemble, SharedPlayerContext, SharedVoice, SystemVoice};
+use crate::books::{spec, Book};
+use crate::evt::{Responder, ResponderId};
 use crate::phone::Phone;
 use crate::result::Result;
-use crate::senses::QueueInput;
-use crate::senses::{Sensors, SensorsBuilder};
-use crate::serve::{EventPublisher, Server};
+use crate::senses::{QueueInput, QueuedInput};
+use crate::senses::{Sensors, SensorsBuilder, StdinSignals};
+use crate::serve::{ActuatorProgress, EventPublisher, Server};
 use crate::states::State;
 
+use log::{debug, error};
+
+use std::cell::RefCell;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 type CompositeResponder = crate::evt::CompositeResponder<State>;
 type Machine = crate::states::Machine<CompositeResponder>;
@@ -20,17 +24,73 @@ pub struct Run {
     machine: Machine,
     phone: Option<Arc<Mutex<Phone>>>,
     server: Option<Rc<Server>>,
+    /// Used for the sounds of every book, `None` if the run
+    /// advances without any actuators and without publishing
+    /// events.
+    player_ctx: Option<SharedPlayerContext>,
+    /// Sounds of the current book, `None` for silent runs.
+    ensemble: Option<Rc<RefCell<Ensemble>>>,
+    /// Also used by the machine, kept to stop them remotely.
+    actuators: Option<Rc<RefCell<Actuators>>>,
+    /// Speaks the text of states in this and all later books.
+    voice: SharedVoice,
+    /// How sounds are ducked and faded out in this and all later
+    /// books.
+    mixing: Mixing,
+    /// Sounds of the previous book that are still fading out.
+    crossfade: Option<Crossfade>,
+    /// Responders added by embedding code, kept across book switches.
+    observers: Rc<RefCell<CompositeResponder>>,
+}
+
+/// Settings for the actuators of this and all later books.
+#[derive(Clone, Copy, Default)]
+struct Mixing {
+    /// Volume scale of sounds while speaking or ringing.
+    duck_level: Option<f32>,
+    /// Time for sounds to fade out when leaving a state.
+    fade_out: Duration,
+}
+
+/// Sounds of a previous book that fade out while the sounds of
+/// the current book fade in.
+struct Crossfade {
+    fading_out: Rc<RefCell<Ensemble>>,
+    start: Instant,
+    duration: Duration,
+}
+
+impl Crossfade {
+    /// Progress of the fade from `0.0` to `1.0`.
+    fn progress(&self) -> f32 {
+        let elapsed = self.start.elapsed().as_secs_f32();
+        let duration = self.duration.as_secs_f32();
+        if elapsed >= duration {
+            1.0
+        } else {
+            elapsed / duration
+        }
+    }
 }
 
 impl Run {
+    /// Makes the initial run with input from the returned queue and
+    /// the configured sensors.
+    ///
+    /// The sounds of this and all later books are played with the
+    /// given player context, and text is spoken with the given voice.
     pub fn new_with_queue(
         book: Option<Book>,
         phone: Option<Arc<Mutex<Phone>>>,
         server: Option<Rc<Server>>,
+        sensor_config: &SensorConfig,
+        stdin_signals: &StdinSignals,
+        player_ctx: SharedPlayerContext,
+        voice: SharedVoice,
     ) -> Result<(Self, QueueInput)> {
-        let mut sensors = init_sensors(&phone);
+        let mut sensors = init_sensors(&phone, sensor_config, stdin_signals);
         let (_, queue) = sensors.queue();
-        Self::new_with_sensors(book, phone, server, sensors).map(|r| (r, queue))
+        Self::new_with_sensors(book, phone, server, sensors, player_ctx, voice).map(|r| (r, queue))
     }
 
     fn new_with_sensors(
@@ -38,10 +98,22 @@ impl Run {
         phone: Option<Arc<Mutex<Phone>>>,
         server: Option<Rc<Server>>,
         sensors: SensorsBuilder,
+        player_ctx: SharedPlayerContext,
+        voice: SharedVoice,
     ) -> Result<Self> {
         let book = book.unwrap_or_else(Book::passive);
         let sensors = sensors.build();
-        let responder = make_responder(&phone, &server, &book)?;
+        let ensemble = make_ensemble(&book, &player_ctx)?;
+        let observers = Rc::new(RefCell::new(silent_responder()));
+        let (responder, actuators) = make_responder(
+            &phone,
+            &server,
+            &ensemble,
+            &voice,
+            Mixing::default(),
+            &observers,
+            &book,
+        );
         let machine = Machine::new(sensors, responder, book.states());
 
         let run = Run {
@@ -49,17 +121,205 @@ impl Run {
             machine,
             phone,
             server: server.clone(),
+            player_ctx: Some(player_ctx),
+            ensemble: Some(ensemble),
+            actuators: Some(actuators),
+            voice,
+            mixing: Mixing::default(),
+            crossfade: None,
+            observers,
         };
 
         Ok(run)
     }
 
+    /// Makes a run that evaluates the given book without any
+    /// actuators and without publishing events to the server.
+    ///
+    /// The only input comes from the returned queue, e.g. when
+    /// mirroring the input of another run.
+    pub fn new_silent(book: Book) -> (Self, QueueInput) {
+        let mut sensors = Sensors::builder();
+        let (_, queue) = sensors.queue();
+        let machine = Machine::new(sensors.build(), silent_responder(), book.states());
+
+        let run = Run {
+            book,
+            machine,
+            phone: None,
+            server: None,
+            player_ctx: None,
+            ensemble: None,
+            actuators: None,
+            voice: Arc::new(SystemVoice),
+            mixing: Mixing::default(),
+            crossfade: None,
+            observers: Rc::new(RefCell::new(silent_responder())),
+        };
+
+        (run, queue)
+    }
+
+    /// The running book.
+    pub fn book(&self) -> &Book {
+        &self.book
+    }
+
+    /// The spec of the running book, if it was compiled from one.
+    pub fn book_spec(&self) -> Option<&spec::Book> {
+        self.book.source_spec()
+    }
+
+    /// Checks if the run has no phonebook loaded and runs a
+    /// passive book instead.
+    pub fn is_passive(&self) -> bool {
+        self.book.is_passive()
+    }
+
+    /// The state the run is currently in.
+    pub fn current_state(&self) -> &State {
+        self.machine.current_state()
+    }
+
+    /// Name of the state the run is currently in.
+    pub fn current_state_name(&self) -> &str {
+        self.machine.current_state_name()
+    }
+
+    /// How long ago the current state was entered.
+    pub fn time_in_current_state(&self) -> Duration {
+        self.machine.time_in_current_state()
+    }
+
+    /// All states of the current book, the initial state first.
+    pub fn states(&self) -> &[State] {
+        self.machine.states()
+    }
+
+    /// Finds the state of the current book with the given ID.
+    pub fn state_by_id(&self, id: &str) -> Option<&State> {
+        self.machine.state_by_id(id)
+    }
+
+    /// Finds the first state of the current book with the given name.
+    pub fn state_by_name(&self, name: &str) -> Option<&State> {
+        self.machine.state_by_name(name)
+    }
+
+    /// Transitions to the state at the index returned by the
+    /// predicate for the current state, if any.
+    pub fn transition_if(&mut self, predicate: impl Fn(&State) -> Option<usize>) -> bool {
+        self.machine.transition_if(predicate)
+    }
+
+    /// The last transitions of the current book, oldest first,
+    /// as pairs of cause and the name of the target state.
+    pub fn transition_history(&self) -> Vec<(String, String)> {
+        let states = self.book.states();
+        self.machine
+            .history()
+            .iter()
+            .map(|(cause, idx)| (cause.to_string(), states[*idx].name().to_string()))
+            .collect()
+    }
+
+    /// Sends the events of this and all later books to the given
+    /// responder, in addition to the actuators.
+    ///
+    /// Silent runs do not send events to added responders.
+    pub fn add_responder(&mut self, responder: Box<dyn Responder<State>>) -> ResponderId {
+        self.observers.borrow_mut().add_responder(responder)
+    }
+
+    /// Stops sending events to a responder added with `add_responder`,
+    /// returning it if it was still there.
+    pub fn remove_responder(&mut self, id: ResponderId) -> Option<Box<dyn Responder<State>>> {
+        self.observers.borrow_mut().remove_responder(id)
+    }
+
+    /// Sets the maximum number of transitions to keep in the history.
+    pub fn set_history_size(&mut self, size: usize) {
+        self.machine.set_history_size(size)
+    }
+
+    /// Lowers the volume of sounds to the given scale while speaking
+    /// or ringing, or keeps it with `None`.
+    pub fn set_duck_level(&mut self, duck_level: Option<f32>) {
+        self.mixing.duck_level = duck_level;
+        if let Some(actuators) = self.actuators.as_ref() {
+            actuators.borrow_mut().set_duck_level(duck_level);
+        }
+    }
+
+    /// Fades out the sounds of a state over the given duration when
+    /// leaving it, e.g. when hanging up, instead of stopping them
+    /// right away.
+    pub fn set_fade_out(&mut self, fade_out: Duration) {
+        self.mixing.fade_out = fade_out;
+        if let Some(actuators) = self.actuators.as_ref() {
+            actuators.borrow_mut().set_fade_out(fade_out);
+        }
+    }
+
+    /// Takes all input that has been received but not yet
+    /// processed by the current book.
+    pub fn drain_input(&mut self) -> Vec<QueuedInput> {
+        self.machine.drain_input()
+    }
+
+    /// Sends a copy of all input this run receives from now on
+    /// to the given queue.
+    pub fn mirror_input(&mut self, to: &QueueInput) {
+        self.machine.mirror_input(to)
+    }
+
     /// Keeps the current book open, but resets all actuators and
     /// starts over with the initial state.
     pub fn reset(&mut self) {
         self.machine.reset();
     }
 
+    /// Jumps to the state with the given ID, failing if there is
+    /// no such state.
+    pub fn reset_to(&mut self, state_id: &str) -> Result<()> {
+        self.machine.reset_to(state_id)
+    }
+
+    /// How far the actuators are with the current state.
+    ///
+    /// Silent runs have nothing to do and are always done.
+    pub fn actuator_progress(&self) -> ActuatorProgress {
+        match self.actuators.as_ref() {
+            Some(actuators) => {
+                let actuators = actuators.borrow();
+                ActuatorProgress {
+                    sounds_done: actuators.sounds_done(),
+                    ring_remaining_ms: actuators
+                        .ring_remaining()
+                        .map(|remaining| remaining.as_millis() as u64),
+                    speech_done: actuators.speech_done(),
+                }
+            }
+            None => ActuatorProgress {
+                sounds_done: true,
+                ring_remaining_ms: None,
+                speech_done: true,
+            },
+        }
+    }
+
+    /// Silences all sounds, speech and ringing right away, including
+    /// sounds of the previous book that are still fading out.
+    ///
+    /// The current state is kept.
+    pub fn stop(&mut self) -> Result<()> {
+        self.crossfade = None;
+        match self.actuators.as_ref() {
+            Some(actuators) => actuators.borrow_mut().cancel_all_sounds(),
+            None => Ok(()),
+        }
+    }
+
     /// Continues evaluating the book.
     ///
     /// Returns `false` when a terminal state is current, otherwise
@@ -70,6 +330,7 @@ impl Run {
     /// takes effect on the next tick, even if the conditions
     /// are met right away.
     pub fn tick(&mut self) -> bool {
+        self.update_crossfade();
         self.machine.update()
     }
 
@@ -77,19 +338,120 @@ impl Run {
     /// beginning, resetting any remaining actuator state.
     ///
     /// Any previously consumed book is dropped after the switch.
+    /// Sounds of the new book that play the same content the same
+    /// way as a sound of the previous book take over its player, so
+    /// they keep playing without interruption.
     ///
     /// If any error occurs, e.g. when the book references non-existing
     /// files, then the previous book remains in place.
     pub fn switch(&mut self, book: Book) -> Result<()> {
         // overwrite and reset the machine
-        let responders = make_responder(&self.phone, &self.server, &book)?;
+        let responders = match self.player_ctx.as_ref() {
+            None => silent_responder(),
+            Some(player_ctx) => {
+                let ensemble = make_ensemble(&book, player_ctx)?;
+                if let Some(previous) = self.ensemble.as_ref() {
+                    let reused = ensemble
+                        .borrow_mut()
+                        .reuse_matching(&mut previous.borrow_mut());
+                    debug!("Reusing {} sounds of the previous book", reused);
+                }
+                let (responders, actuators) = make_responder(
+                    &self.phone,
+                    &self.server,
+                    &ensemble,
+                    &self.voice,
+                    self.mixing,
+                    &self.observers,
+                    &book,
+                );
+                self.ensemble = Some(ensemble);
+                self.actuators = Some(actuators);
+                responders
+            }
+        };
         self.machine.load(responders, book.states());
 
+        // stop sounds that may still be fading out
+        self.crossfade = None;
+
         // and keep the book as it may contain temp dirs
         self.book = book;
 
         Ok(())
     }
+
+    /// Reads the durations of sounds that were compiled on another
+    /// thread, with the context used for playback.
+    ///
+    /// Runs without sounds leave them unknown.
+    pub fn read_durations(&self, mut book: Book) -> Result<Book> {
+        if let Some(player_ctx) = self.player_ctx.as_ref() {
+            book.read_durations(player_ctx)?;
+        }
+        Ok(book)
+    }
+
+    /// Like `switch`, but instead of stopping the sounds of the
+    /// previous book right away, fades them out over the given
+    /// duration while fading in the sounds of the new book.
+    ///
+    /// A crossfade that is still in progress is cut short.
+    pub fn switch_with_crossfade(&mut self, book: Book, duration: Duration) -> Result<()> {
+        let player_ctx = match self.player_ctx.as_ref() {
+            Some(player_ctx) => player_ctx,
+            None => return self.switch(book),
+        };
+
+        let ensemble = make_ensemble(&book, player_ctx)?;
+        ensemble.borrow_mut().set_volume(0.0);
+        let (responders, actuators) = make_responder(
+            &self.phone,
+            &self.server,
+            &ensemble,
+            &self.voice,
+            self.mixing,
+            &self.observers,
+            &book,
+        );
+        self.machine.load(responders, book.states());
+        self.actuators = Some(actuators);
+
+        let fading_out = self.ensemble.replace(ensemble);
+        self.crossfade = fading_out.map(|fading_out| Crossfade {
+            fading_out,
+            start: Instant::now(),
+            duration,
+        });
+
+        self.book = book;
+
+        Ok(())
+    }
+
+    /// Adjusts volumes of an ongoing crossfade and drops the old
+    /// sounds when it is finished.
+    fn update_crossfade(&mut self) {
+        let crossfade = match self.crossfade.as_ref() {
+            Some(crossfade) => crossfade,
+            None => return,
+        };
+
+        let progress = crossfade.progress();
+        if let Some(ensemble) = self.ensemble.as_ref() {
+            ensemble.borrow_mut().set_volume(progress);
+        }
+
+        if progress >= 1.0 {
+            self.crossfade = None;
+        } else {
+            let mut fading_out = crossfade.fading_out.borrow_mut();
+            fading_out.set_volume(1.0 - progress);
+            if let Err(err) = fading_out.update() {
+                error!("Sound update failures during crossfade: {:?}", err);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -104,35 +466,103 @@ impl Run {
         phone: Option<Arc<Mutex<Phone>>>,
         server: Option<Rc<Server>>,
     ) -> Result<Self> {
-        let sensors = init_sensors(&phone);
-        Self::new_with_sensors(book, phone, server, sensors)
+        let sensors = init_sensors(&phone, &SensorConfig::default(), &StdinSignals::default());
+        Self::new_with_sensors(
+            book,
+            phone,
+            server,
+            sensors,
+            SharedPlayerContext::new()?,
+            Arc::new(SystemVoice),
+        )
     }
 }
 
+fn make_ensemble(book: &Book, player_ctx: &SharedPlayerContext) -> Result<Rc<RefCell<Ensemble>>> {
+    let mut ensemble = Ensemble::from_specs_with_shared_ctx(book.sounds(), player_ctx)?;
+    ensemble.set_crossfade(book.crossfade());
+    Ok(Rc::new(RefCell::new(ensemble)))
+}
+
+/// Makes the responders for a book, along with a handle to the
+/// actuators among them.
+///
+/// The observers receive events after the actuators and the server.
 fn make_responder(
     phone: &Option<Arc<Mutex<Phone>>>,
     server: &Option<Rc<Server>>,
+    ensemble: &Rc<RefCell<Ensemble>>,
+    voice: &SharedVoice,
+    mixing: Mixing,
+    observers: &Rc<RefCell<CompositeResponder>>,
     book: &Book,
-) -> Result<CompositeResponder> {
-    let mut responders: Vec<Box<dyn Responder<State>>> = Vec::with_capacity(2);
+) -> (CompositeResponder, Rc<RefCell<Actuators>>) {
+    let mut responders: Vec<Box<dyn Responder<State>>> = Vec::with_capacity(3);
 
-    let actuators = Actuators::new(phone, book.sounds())?;
-    responders.push(Box::new(actuators));
+    let mut actuators = Actuators::with_ensemble(phone, ensemble).with_voice(voice);
+    actuators.set_duck_level(mixing.duck_level);
+    actuators.set_fade_out(mixing.fade_out);
+    let actuators = Rc::new(RefCell::new(actuators));
+    responders.push(Box::new(Rc::clone(&actuators)));
 
     if let Some(server) = server.as_ref() {
-        let publisher = EventPublisher::through(server);
+        let publisher = EventPublisher::through(server, book.book_id());
         responders.push(Box::new(publisher));
     }
 
-    Ok(CompositeResponder::from(responders))
+    responders.push(Box::new(Rc::clone(observers)));
+
+    (CompositeResponder::from(responders), actuators)
+}
+
+/// A responder without any actuators that is always idle.
+fn silent_responder() -> CompositeResponder {
+    CompositeResponder::from(vec![])
 }
 
-pub fn init_sensors(phone: &Option<Arc<Mutex<Phone>>>) -> SensorsBuilder {
+/// Selects the sensors that provide input to runs, in addition
+/// to the queue for remote control.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorConfig {
+    /// Read input from the keyboard.
+    pub use_stdin: bool,
+    /// Read whole lines from the keyboard as commands, instead of
+    /// single characters.
+    pub stdin_line_mode: bool,
+    /// Read the dial of the phone, if one is connected.
+    pub use_i2c: bool,
+}
+
+impl Default for SensorConfig {
+    fn default() -> Self {
+        SensorConfig {
+            use_stdin: true,
+            stdin_line_mode: false,
+            use_i2c: true,
+        }
+    }
+}
+
+/// Makes sensors for the given configuration.
+///
+/// In line mode, reset and quit commands typed on the keyboard set
+/// the flags of the given signals.
+pub fn init_sensors(
+    phone: &Option<Arc<Mutex<Phone>>>,
+    config: &SensorConfig,
+    signals: &StdinSignals,
+) -> SensorsBuilder {
     let mut sensors = Sensors::builder();
-    sensors.stdin();
+    if config.use_stdin && config.stdin_line_mode {
+        sensors.stdin_lines(signals);
+    } else if config.use_stdin {
+        sensors.stdin();
+    }
 
-    if let Some(phone) = phone.as_ref() {
-        sensors.i2c_dial(phone);
+    if config.use_i2c {
+        if let Some(phone) = phone.as_ref() {
+            sensors.i2c_dial(phone);
+        }
     }
     sensors
 }
@@ -146,8 +576,7 @@ mod test {
     use crate::testutil::{
         actual_speech_time, assert_duration, MediaInfo, TEST_MUSIC, WILHELM_SCREAM,
     };
-    use std::thread::yield_now;
-    use std::time::Instant;
+    use std::thread::{sleep, yield_now};
 
     #[cfg_attr(not(feature = "expensive_tests"), ignore)]
     #[test]
@@ -314,6 +743,42 @@ mod test {
         );
     }
 
+    #[cfg_attr(not(feature = "expensive_tests"), ignore)]
+    #[test]
+    fn switch_keeps_playing_sound_with_same_content() {
+        // given
+        init_test_logging();
+        let dir = tempfile::tempdir().unwrap();
+        let copied_music = dir.path().join("copy.mp3");
+        std::fs::copy(TEST_MUSIC, &copied_music).unwrap();
+        let mut first_book = Book::builder();
+        first_book
+            .sound(music_non_looping(TEST_MUSIC))
+            .unwrap()
+            .state(State::builder().id("1").name("1").sounds(vec![0]).build());
+        let mut second_book = Book::builder();
+        second_book
+            .sound(music_non_looping(&copied_music.to_string_lossy()))
+            .unwrap()
+            .state(State::builder().id("1").name("1").sounds(vec![0]).build());
+        let mut run = Run::new(Some(first_book.build()), None, None).unwrap();
+        run.tick();
+        sleep(Duration::from_millis(500));
+
+        // when
+        run.switch(second_book.build()).unwrap();
+        run.tick();
+
+        // then
+        let ensemble = run.ensemble.as_ref().unwrap().borrow();
+        let played = ensemble.sound(0).played();
+        assert!(
+            played >= Duration::from_millis(400),
+            "expected reused sound to keep its position, but played only {:?}",
+            played
+        );
+    }
+
     #[test]
     fn switch_from_queue_dial() {
         // given
@@ -329,7 +794,16 @@ mod test {
         let book = book.build();
 
         // when
-        let (mut run, input) = Run::new_with_queue(Some(book), None, None).unwrap();
+        let (mut run, input) = Run::new_with_queue(
+            Some(book),
+            None,
+            None,
+            &SensorConfig::default(),
+            &StdinSignals::default(),
+            shared_ctx(),
+            system_voice(),
+        )
+        .unwrap();
         let initially_running = run.tick();
         input.send(Input::pick_up()).ok();
         let running_after_pick_up = run.tick();
@@ -345,25 +819,297 @@ mod test {
         )
     }
 
+    #[test]
+    fn run_with_only_queue_input() {
+        // given
+        let mut book = Book::builder();
+        book.state(
+            State::builder()
+                .id("1")
+                .name("1")
+                .input(Input::pick_up(), 1)
+                .build(),
+        )
+        .state(State::builder().id("2").name("2").terminal(true).build());
+        let config = SensorConfig {
+            use_stdin: false,
+            stdin_line_mode: false,
+            use_i2c: false,
+        };
+
+        // when
+        let (mut run, input) = Run::new_with_queue(
+            Some(book.build()),
+            None,
+            None,
+            &config,
+            &StdinSignals::default(),
+            shared_ctx(),
+            system_voice(),
+        )
+        .unwrap();
+        let running_without_input = run.tick();
+        input.send(Input::pick_up()).ok();
+        let running_after_pick_up = run.tick();
+
+        // then
+        assert!(running_without_input);
+        assert!(!running_after_pick_up);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "expensive_tests"), ignore)]
+    fn estimated_duration_close_to_actual_runtime() {
+        // given
+        let mut book = Book::builder();
+        book.state(
+            State::builder()
+                .id("ring")
+                .name("ring")
+                .ring_for(Duration::from_millis(300))
+                .timeout(Duration::from_millis(200), 1)
+                .build(),
+        )
+        .state(
+            State::builder()
+                .id("wait")
+                .name("wait")
+                .timeout(Duration::from_millis(300), 2)
+                .build(),
+        )
+        .state(
+            State::builder()
+                .id("end")
+                .name("end")
+                .terminal(true)
+                .build(),
+        );
+        let book = book.build();
+        let estimate = book.estimated_duration().unwrap();
+        let config = SensorConfig {
+            use_stdin: false,
+            stdin_line_mode: false,
+            use_i2c: false,
+        };
+        let (mut run, _) = Run::new_with_queue(
+            Some(book),
+            None,
+            None,
+            &config,
+            &StdinSignals::default(),
+            shared_ctx(),
+            system_voice(),
+        )
+        .unwrap();
+
+        // when
+        let start = Instant::now();
+        while run.tick() {
+            sleep(Duration::from_millis(5));
+        }
+        let actual = start.elapsed();
+
+        // then
+        assert_eq!(estimate, Duration::from_millis(800));
+        let deviation = actual.abs_diff(estimate);
+        assert!(
+            deviation <= estimate / 10,
+            "Expected estimate {:?} within 10% of actual runtime {:?}",
+            estimate,
+            actual
+        );
+    }
+
+    #[test]
+    fn crossfade_keeps_old_sounds_until_finished() {
+        // given
+        let crossfade_duration = Duration::from_millis(200);
+        let (mut run, _) = Run::new_with_queue(
+            None,
+            None,
+            None,
+            &SensorConfig::default(),
+            &StdinSignals::default(),
+            shared_ctx(),
+            system_voice(),
+        )
+        .unwrap();
+
+        // when
+        run.switch_with_crossfade(Book::passive(), crossfade_duration)
+            .unwrap();
+        run.tick();
+        let fading_after_switch = run.crossfade.is_some();
+        sleep(crossfade_duration);
+        run.tick();
+        let fading_after_duration = run.crossfade.is_some();
+
+        // then
+        assert!(
+            fading_after_switch,
+            "Expected old sounds to be fading out right after switch"
+        );
+        assert!(
+            !fading_after_duration,
+            "Expected old sounds to be dropped after crossfade duration"
+        );
+    }
+
+    #[test]
+    fn switch_cuts_crossfade_short() {
+        // given
+        let (mut run, _) = Run::new_with_queue(
+            None,
+            None,
+            None,
+            &SensorConfig::default(),
+            &StdinSignals::default(),
+            shared_ctx(),
+            system_voice(),
+        )
+        .unwrap();
+
+        // when
+        run.switch_with_crossfade(Book::passive(), Duration::from_secs(10))
+            .unwrap();
+        run.switch(Book::passive()).unwrap();
+
+        // then
+        assert!(run.crossfade.is_none());
+    }
+
+    #[cfg_attr(not(feature = "expensive_tests"), ignore)]
+    #[test]
+    fn crossfade_without_audio_gap() {
+        // given
+        init_test_logging();
+        let max_gap = Duration::from_millis(5);
+        let crossfade_duration = Duration::from_millis(800);
+        let looping_music = || {
+            let mut book = Book::builder();
+            book.sound(SoundSpec {
+                looping: true,
+                ..music_non_looping(TEST_MUSIC)
+            })
+            .unwrap()
+            .state(State::builder().id("1").name("1").sounds(vec![0]).build());
+            book.build()
+        };
+        let mut run = Run::new(Some(looping_music()), None, None).unwrap();
+        let warmup_start = Instant::now();
+        while warmup_start.elapsed() < Duration::from_millis(300) {
+            run.tick();
+            yield_now();
+        }
+
+        // when
+        run.switch_with_crossfade(looping_music(), crossfade_duration)
+            .unwrap();
+        let mut last_audible = Instant::now();
+        let mut longest_gap = Duration::from_millis(0);
+        while run.crossfade.is_some() {
+            run.tick();
+            let old_playing = run
+                .crossfade
+                .as_ref()
+                .map(|c| c.fading_out.borrow().any_playing())
+                .unwrap_or(false);
+            let new_playing = run.ensemble.as_ref().unwrap().borrow().any_playing();
+            if old_playing || new_playing {
+                longest_gap = longest_gap.max(last_audible.elapsed());
+                last_audible = Instant::now();
+            }
+            yield_now();
+        }
+
+        // then
+        assert!(
+            longest_gap <= max_gap,
+            "Expected no gap in audio longer than {:?}, but found {:?}",
+            max_gap,
+            longest_gap
+        );
+    }
+
+    #[cfg_attr(not(feature = "expensive_tests"), ignore)]
+    #[test]
+    fn stop_silences_sounds() {
+        // given
+        init_test_logging();
+        let mut book = Book::builder();
+        book.sound(SoundSpec {
+            looping: true,
+            ..music_non_looping(TEST_MUSIC)
+        })
+        .unwrap()
+        .state(State::builder().id("1").name("1").sounds(vec![0]).build());
+        let mut run = Run::new(Some(book.build()), None, None).unwrap();
+        let warmup_start = Instant::now();
+        while warmup_start.elapsed() < Duration::from_millis(300) {
+            run.tick();
+            yield_now();
+        }
+        let playing_before = run.ensemble.as_ref().unwrap().borrow().any_playing();
+
+        // when
+        run.stop().unwrap();
+        sleep(Duration::from_millis(100));
+        run.tick();
+
+        // then
+        let playing_after = run.ensemble.as_ref().unwrap().borrow().any_playing();
+        assert!(playing_before, "Expected music to play before stopping");
+        assert!(!playing_after, "Expected silence after stopping");
+        assert_eq!(run.current_state().id(), "1");
+    }
+
+    fn shared_ctx() -> SharedPlayerContext {
+        SharedPlayerContext::new().unwrap()
+    }
+
+    fn system_voice() -> SharedVoice {
+        Arc::new(SystemVoice)
+    }
+
     fn speech(speech: &str) -> SoundSpec {
         SoundSpec {
             speech: Some(speech.into()),
+            voice: None,
+            tts_rate: None,
+            tts_pitch: None,
             file: String::new(),
-            volume: 1.0,
+            volume: None,
+            volume_db: None,
             backoff: None,
             looping: false,
             start_offset: None,
+            end_at: None,
+            trim_silence: None,
+            rate: None,
+            fade_in: None,
+            fade_out: None,
+            normalize: false,
         }
     }
 
     fn music_non_looping(music_file: &str) -> SoundSpec {
         SoundSpec {
             speech: None,
+            voice: None,
+            tts_rate: None,
+            tts_pitch: None,
             file: music_file.to_string(),
-            volume: 1.0,
+            volume: None,
+            volume_db: None,
             backoff: None,
             looping: false,
             start_offset: None,
+            end_at: None,
+            trim_silence: None,
+            rate: None,
+            fade_in: None,
+            fade_out: None,
+            normalize: false,
         }
     }
 }
@@ -0,0 +1,171 @@
+use crate::result::Result;
+use crate::senses::{Input, QueueInput};
+use crate::states::State;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::execute;
+use crossterm::style::Print;
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+use log::{debug, error};
+
+use std::io::{stdout, Write};
+use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Maximum time the key thread waits for input before checking
+/// if it should stop.
+const KEY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+const HELP: &str = "simulating phone: 0-9 dial, p pick up, h hang up, r recall, ctrl+c quit";
+
+/// Simulates a phone with the keyboard for development on
+/// machines without the I2C phone.
+///
+/// Puts the terminal into raw mode so keys take effect
+/// without pressing enter, and restores it when dropped.
+pub struct Simulator {
+    /// ID of the state that was last displayed.
+    displayed: Option<String>,
+    stop: Arc<AtomicBool>,
+    keys: Option<JoinHandle<()>>,
+}
+
+/// What a key press means for the simulation.
+#[derive(Debug, PartialEq)]
+enum Command {
+    Dial(Vec<Input>),
+    Quit,
+    Ignore,
+}
+
+impl Simulator {
+    /// Starts reading keys in the background, sending the inputs
+    /// to the given queue.
+    ///
+    /// Since raw mode disables ctrl+c signals, the termination flag
+    /// is set instead when ctrl+c is pressed.
+    pub fn spawn(input: QueueInput, termination_flag: &Arc<AtomicBool>) -> Result<Self> {
+        enable_raw_mode()?;
+        execute!(stdout(), Print(HELP), Print("\r\n"))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let keys = {
+            let stop = Arc::clone(&stop);
+            let termination_flag = Arc::clone(termination_flag);
+            thread::Builder::new()
+                .name("simulate-keys".to_string())
+                .spawn(move || read_keys(&input, &termination_flag, &stop))?
+        };
+
+        Ok(Simulator {
+            displayed: None,
+            stop,
+            keys: Some(keys),
+        })
+    }
+
+    /// Prints the name of the state if it changed since the last call.
+    pub fn show(&mut self, state: &State) {
+        if self.displayed.as_deref() == Some(state.id()) {
+            return;
+        }
+
+        let line = format!("state: {}\r\n", state.name());
+        if let Err(err) = execute!(stdout(), Print(line)) {
+            debug!("could not display simulated phone state: {}", err);
+        }
+        self.displayed = Some(state.id().to_string());
+    }
+}
+
+impl Drop for Simulator {
+    fn drop(&mut self) {
+        self.stop.store(true, SeqCst);
+        if let Some(keys) = self.keys.take() {
+            keys.join().ok();
+        }
+
+        if let Err(err) = disable_raw_mode() {
+            error!("failed to restore terminal after phone simulation: {}", err);
+        }
+    }
+}
+
+fn read_keys(input: &QueueInput, termination_flag: &AtomicBool, stop: &AtomicBool) {
+    while !stop.load(SeqCst) {
+        let key = match event::poll(KEY_POLL_INTERVAL).and_then(|ready| {
+            if ready {
+                event::read().map(Some)
+            } else {
+                Ok(None)
+            }
+        }) {
+            Ok(Some(Event::Key(key))) => key,
+            Ok(_) => continue,
+            Err(err) => {
+                error!("stopping phone simulation, could not read keys: {}", err);
+                break;
+            }
+        };
+
+        match command(key) {
+            Command::Dial(inputs) => {
+                for i in inputs {
+                    if input.send(i).is_err() {
+                        debug!("stopping phone simulation, input queue disconnected");
+                        return;
+                    }
+                }
+            }
+            Command::Quit => termination_flag.store(true, SeqCst),
+            Command::Ignore => (),
+        }
+    }
+}
+
+fn command(key: KeyEvent) -> Command {
+    match key.code {
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Command::Quit,
+        KeyCode::Char(digit @ '0'..='9') => {
+            // always in range [0,9], unwrap is safe
+            Command::Dial(vec![Input::digit(digit as i32 - '0' as i32).unwrap()])
+        }
+        KeyCode::Char('p') => Command::Dial(vec![Input::pick_up()]),
+        KeyCode::Char('h') => Command::Dial(vec![Input::hang_up()]),
+        // keys have no release events, so release right away
+        KeyCode::Char('r') => Command::Dial(vec![Input::recall_press(), Input::recall_release()]),
+        _ => Command::Ignore,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn map_keys_to_commands() {
+        // given
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+
+        // when
+        let digit = command(key(KeyCode::Char('7')));
+        let pick_up = command(key(KeyCode::Char('p')));
+        let hang_up = command(key(KeyCode::Char('h')));
+        let recall = command(key(KeyCode::Char('r')));
+        let quit = command(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL));
+        let other = command(key(KeyCode::Char('x')));
+
+        // then
+        assert_eq!(digit, Command::Dial(vec![Input::digit(7).unwrap()]));
+        assert_eq!(pick_up, Command::Dial(vec![Input::pick_up()]));
+        assert_eq!(hang_up, Command::Dial(vec![Input::hang_up()]));
+        assert_eq!(
+            recall,
+            Command::Dial(vec![Input::recall_press(), Input::recall_release()])
+        );
+        assert_eq!(quit, Command::Quit);
+        assert_eq!(other, Command::Ignore);
+    }
+}
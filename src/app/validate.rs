@@ -0,0 +1,77 @@
+use super::TerminalStateBehavior;
+
+use crate::books::Book;
+use crate::result::Result;
+
+use failure::bail;
+
+use std::fmt;
+
+/// A problem with the configuration of an app that does not
+/// prevent it from running, but is probably unintended.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning(String);
+
+impl Warning {
+    fn new(message: impl Into<String>) -> Self {
+        Warning(message.into())
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Checks the startup configuration of an app.
+///
+/// Returns an error for problems that would make the app fail,
+/// e.g. missing sound files, otherwise the list of warnings.
+pub fn validate(
+    startup_book: Option<&Book>,
+    expects_remote_book: bool,
+    has_phone: bool,
+    terminal_state_behavior: &TerminalStateBehavior,
+) -> Result<Vec<Warning>> {
+    let mut warnings = Vec::new();
+
+    if !has_phone {
+        warnings.push(Warning::new(
+            "no phone connected, input is only accepted from the keyboard",
+        ));
+    }
+
+    match startup_book {
+        None if !expects_remote_book => warnings.push(Warning::new(
+            "neither a startup phonebook nor a remote control server, \
+             the runtime will remain silent",
+        )),
+        None => (),
+        Some(book) => {
+            validate_book(book, terminal_state_behavior, &mut warnings)?;
+        }
+    }
+
+    Ok(warnings)
+}
+
+fn validate_book(
+    book: &Book,
+    terminal_state_behavior: &TerminalStateBehavior,
+    warnings: &mut Vec<Warning>,
+) -> Result<()> {
+    if let Some(missing) = book.sounds().iter().find(|s| !s.source().exists()) {
+        bail!("Sound file does not exist: {:?}", missing.source());
+    }
+
+    let has_terminal = book.states().iter().any(|s| s.is_terminal());
+    if *terminal_state_behavior == TerminalStateBehavior::Exit && !has_terminal {
+        warnings.push(Warning::new(
+            "configured to exit on terminal state, but the startup \
+             phonebook has no terminal state and will never exit",
+        ));
+    }
+
+    Ok(())
+}
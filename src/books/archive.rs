@@ -0,0 +1,213 @@
+use super::{compile_relative_to, file, include, Book, Overrides};
+
+use failure::{bail, Error};
+use std::fs::{read_to_string, File};
+use std::path::Path;
+use tempfile::tempdir;
+use zip::ZipArchive;
+
+/// Name of the phonebook inside a ZIP archive, must be at the root.
+const PHONEBOOK_NAME: &str = "phonebook.yaml";
+
+/// Extension of compressed phonebooks, which are ZIP archives.
+pub const FPZ_EXTENSION: &str = "fpz";
+
+/// Extracts a ZIP archive with a `phonebook.yaml` at the root and
+/// the sound files it references, then compiles the phonebook.
+///
+/// Relative sound file paths are relative to the root of the
+/// archive. The extracted files are deleted when the book is
+/// dropped.
+pub fn from_zip(path: impl AsRef<Path>) -> Result<Book, Error> {
+    from_zip_with_overrides(path, &Overrides::default())
+}
+
+/// Like `from_zip`, with settings that take precedence over the
+/// ones in the phonebook.
+pub(crate) fn from_zip_with_overrides(
+    path: impl AsRef<Path>,
+    overrides: &Overrides,
+) -> Result<Book, Error> {
+    extract_and_compile(path.as_ref(), overrides, true)
+}
+
+/// Like `from_zip_with_overrides`, but for archives from untrusted
+/// sources, e.g. downloads. The phonebook must not use `!include`,
+/// which could read arbitrary local files.
+pub(crate) fn from_remote_zip(path: &Path, overrides: &Overrides) -> Result<Book, Error> {
+    extract_and_compile(path, overrides, false)
+}
+
+fn extract_and_compile(
+    path: &Path,
+    overrides: &Overrides,
+    allow_includes: bool,
+) -> Result<Book, Error> {
+    let mut archive = ZipArchive::new(File::open(path)?)?;
+    let assets_dir = tempdir()?;
+    archive.extract(assets_dir.path())?;
+
+    let phonebook_path = assets_dir.path().join(PHONEBOOK_NAME);
+    if !phonebook_path.is_file() {
+        bail!(
+            "Archive {:?} contains no {} at the root",
+            path,
+            PHONEBOOK_NAME
+        );
+    }
+
+    if !allow_includes && include::has_includes(&read_to_string(&phonebook_path)?) {
+        bail!(
+            "{} in archive {:?} cannot use !include",
+            PHONEBOOK_NAME,
+            path
+        );
+    }
+
+    let mut spec = file::load(&phonebook_path)?;
+    overrides.apply(&mut spec);
+    let mut book = compile_relative_to(spec, assets_dir.path())?;
+    book.keep_assets(assets_dir);
+    Ok(book)
+}
+
+/// Loads a compressed `.fpz` phonebook, a ZIP archive with a
+/// `phonebook.yaml` at the root and its sound files alongside.
+///
+/// Like with `from_zip`, the extracted files live as long as the
+/// book.
+pub fn from_fpz_path(path: impl AsRef<Path>) -> Result<Book, Error> {
+    from_zip(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::books::from_path;
+    use hound::{SampleFormat, WavSpec, WavWriter};
+    use std::fs::{read, write};
+    use std::io::Write;
+    use zip::write::{FileOptions, ZipWriter};
+
+    #[test]
+    fn compile_from_zip_with_wav() {
+        // given
+        let dir = tempdir().unwrap();
+        let wav_path = dir.path().join("beep.wav");
+        write_silent_wav(&wav_path);
+        let zip_path = dir.path().join("book.zip");
+        let mut zip = ZipWriter::new(File::create(&zip_path).unwrap());
+        zip.start_file(PHONEBOOK_NAME, FileOptions::default())
+            .unwrap();
+        zip.write_all(
+            b"initial: beeping
+states:
+  beeping:
+    terminal: true
+    sounds:
+      - beep
+sounds:
+  beep:
+    file: sounds/beep.wav
+",
+        )
+        .unwrap();
+        zip.start_file("sounds/beep.wav", FileOptions::default())
+            .unwrap();
+        zip.write_all(&read(&wav_path).unwrap()).unwrap();
+        zip.finish().unwrap();
+
+        // when
+        let book = from_zip(&zip_path).unwrap();
+
+        // then
+        let source = book.sounds()[0].source();
+        assert!(source.is_absolute(), "Expected absolute path: {:?}", source);
+        assert!(source.ends_with("sounds/beep.wav"));
+        assert_eq!(read(source).unwrap(), read(&wav_path).unwrap());
+    }
+
+    #[test]
+    fn zip_without_phonebook() {
+        // given
+        let dir = tempdir().unwrap();
+        let zip_path = dir.path().join("empty.zip");
+        let mut zip = ZipWriter::new(File::create(&zip_path).unwrap());
+        zip.start_file("readme.txt", FileOptions::default())
+            .unwrap();
+        zip.finish().unwrap();
+
+        // when
+        let result = from_zip(&zip_path);
+
+        // then
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn compile_fpz_fixture() {
+        // when
+        let book = from_fpz_path("test/testbook.fpz").unwrap();
+
+        // then
+        let source = book.sounds()[0].source().to_path_buf();
+        assert!(source.is_absolute(), "Expected absolute path: {:?}", source);
+        assert!(source.ends_with("sounds/beep.wav"));
+        assert!(source.is_file());
+        drop(book);
+        assert!(!source.exists(), "Expected extracted files to be deleted");
+    }
+
+    #[test]
+    fn fpz_round_trip() {
+        // given
+        let dir = tempdir().unwrap();
+        let wav_path = dir.path().join("beep.wav");
+        write_silent_wav(&wav_path);
+        let yaml = "initial: beeping
+states:
+  beeping:
+    terminal: true
+    sounds:
+      - beep
+sounds:
+  beep:
+    file: beep.wav
+";
+        write(dir.path().join(PHONEBOOK_NAME), yaml).unwrap();
+        let fpz_path = dir.path().join("book.fpz");
+        let mut fpz = ZipWriter::new(File::create(&fpz_path).unwrap());
+        for name in &[PHONEBOOK_NAME, "beep.wav"] {
+            fpz.start_file(*name, FileOptions::default()).unwrap();
+            fpz.write_all(&read(dir.path().join(name)).unwrap())
+                .unwrap();
+        }
+        fpz.finish().unwrap();
+
+        // when
+        let from_dir = from_path(dir.path().join(PHONEBOOK_NAME)).unwrap();
+        let from_fpz = from_path(&fpz_path).unwrap();
+
+        // then
+        assert_eq!(from_fpz.states()[0].id(), from_dir.states()[0].id());
+        assert_eq!(
+            read(from_fpz.sounds()[0].source()).unwrap(),
+            read(from_dir.sounds()[0].source()).unwrap()
+        );
+        assert_ne!(from_fpz.sounds()[0].source(), from_dir.sounds()[0].source());
+    }
+
+    fn write_silent_wav(path: &Path) {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(path, spec).unwrap();
+        for _ in 0..800 {
+            writer.write_sample(0_i16).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+}
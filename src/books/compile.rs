@@ -1,48 +1,111 @@
+use crate::books::graph::{dead_ends, reachable_from};
 use crate::books::spec;
 use crate::senses::Input;
-use crate::states::{State, StateBuilder};
+use crate::states::{State, StateBuilder, TransitionTarget};
 use crate::util::time::to_duration;
+pub(crate) use book::expand_path;
 pub use book::Book;
-use failure::{bail, format_err, Error};
+use failure::{bail, format_err, Error, Fail};
 use log::warn;
-use spec::{Id, Transitions};
+use spec::{Id, Location, Locations, Transitions};
 use std::collections::HashMap;
+use std::path::Path;
 use std::time::Duration;
 
+/// Reserved state ID for transitions back to the state that was
+/// current before the most recent transition.
+const HISTORY_ID: &str = "$history";
+
+/// Dial pattern for digits without a transition of their own.
+const ANY_DIGIT_PATTERN: &str = "*";
+
+/// Problems with the structure of a phonebook that are found
+/// after compiling all of its states.
+#[derive(Debug, Fail, PartialEq)]
+pub enum CompileError {
+    /// A state that is not terminal has no transitions, so the
+    /// phonebook would get stuck in it.
+    #[fail(
+        display = "State {:?} is not terminal but has no transitions and would never be left, \
+                   make it terminal, add a transition or set allow_dead_ends: true",
+        state_name
+    )]
+    DeadEnd { state_name: String },
+}
+
 mod book {
-    use crate::acts::SoundSpec;
+    use crate::acts::{Player, PlayerContext, SoundSpec};
+    use crate::books::convert::{ensure_wav, normalize_loudness};
+    use crate::books::graph::estimated_duration;
     use crate::books::spec;
+    use crate::books::tts_cache;
+    use crate::books::variables::Variables;
+    use crate::books::voices::EspeakVoice;
     use crate::states::State;
-    use failure::{format_err, Error};
+    use crate::util::time::to_duration;
+    use failure::{bail, format_err, Error};
     use log::{debug, warn};
     use std::cmp::min;
     use std::collections::hash_map::DefaultHasher;
-    use std::fs::write;
+    use std::collections::HashMap;
+    use std::env::var;
+    use std::fs::{create_dir_all, rename, write};
     use std::hash::Hasher;
     use std::path::{Path, PathBuf};
+    use std::rc::Rc;
+    use std::time::Duration;
     use tavla::{any_voice, Speech, Voice};
     use tempfile::{tempdir, TempDir};
 
     const KIB: usize = 1024;
+    /// Sounds shorter than this are probably not intended.
+    const MIN_EXPECTED_DURATION: Duration = Duration::from_millis(100);
+    /// Allowed range for `volume_db`.
+    const MIN_VOLUME_DB: f64 = -60.0;
+    const MAX_VOLUME_DB: f64 = 6.0;
 
     #[derive(Debug)]
     pub struct Book {
+        book_id: Option<String>,
         pub(crate) states: Vec<State>,
         sounds: Vec<SoundSpec>,
-        /// Directory for generated sounds, e.g. from espeak.
+        /// Directory for generated sounds, e.g. conversions, or
+        /// speech if there is no TTS cache.
         /// Gets deleted when book is destroyed.
         /// Only created when sound is generated, otherwise `None`.
         compiled_speech_dir: Option<TempDir>,
+        /// The spec the book was compiled from, if any.
+        source_spec: Option<spec::Book>,
+        /// Files extracted from a phonebook archive, deleted
+        /// when the book is destroyed.
+        assets_dir: Option<TempDir>,
+        /// `true` for the placeholder book used when no phonebook
+        /// is loaded.
+        passive: bool,
+        /// Time to fade between the sounds of states.
+        crossfade: Duration,
     }
 
     impl Book {
         pub fn builder() -> BookBuilder {
             BookBuilder {
                 book: Book {
+                    book_id: None,
                     states: vec![],
                     sounds: vec![],
                     compiled_speech_dir: None,
+                    source_spec: None,
+                    assets_dir: None,
+                    passive: false,
+                    crossfade: Duration::from_secs(0),
                 },
+                player_ctx: None,
+                base_dir: None,
+                variables: Variables::default(),
+                tts_cache: default_tts_cache(),
+                converted_cache: default_converted_cache(),
+                prepare_sounds: true,
+                read_durations: true,
             }
         }
 
@@ -53,6 +116,7 @@ mod book {
         /// finishes.
         pub fn passive() -> Self {
             Book {
+                book_id: None,
                 states: vec![State::builder()
                     .id("passive")
                     .name("passive")
@@ -60,9 +124,39 @@ mod book {
                     .build()],
                 sounds: vec![],
                 compiled_speech_dir: None,
+                source_spec: None,
+                assets_dir: None,
+                passive: true,
+                crossfade: Duration::from_secs(0),
             }
         }
 
+        /// Keeps the given directory until the book is dropped.
+        pub(crate) fn keep_assets(&mut self, assets_dir: TempDir) {
+            self.assets_dir = Some(assets_dir);
+        }
+
+        /// Checks if this is a book made with `passive`.
+        pub fn is_passive(&self) -> bool {
+            self.passive
+        }
+
+        /// Time to fade between the sounds of states on transitions.
+        pub fn crossfade(&self) -> Duration {
+            self.crossfade
+        }
+
+        /// Identifier of the book from the spec, if any.
+        pub fn book_id(&self) -> Option<&str> {
+            self.book_id.as_deref()
+        }
+
+        /// The spec the book was compiled from, or `None` if the
+        /// book was built in code.
+        pub fn source_spec(&self) -> Option<&spec::Book> {
+            self.source_spec.as_ref()
+        }
+
         pub fn states(&self) -> &[State] {
             &self.states
         }
@@ -70,10 +164,55 @@ mod book {
         pub fn sounds(&self) -> &[SoundSpec] {
             &self.sounds
         }
+
+        /// Reads the durations of sounds that were compiled with
+        /// `defer_durations`, using the given context.
+        pub fn read_durations(&mut self, ctx: &PlayerContext) -> Result<(), Error> {
+            for spec in self.sounds.iter_mut() {
+                if spec.duration().is_none() && !spec.source().as_os_str().is_empty() {
+                    let duration = read_duration(spec, ctx)?;
+                    spec.set_duration(duration);
+                }
+            }
+
+            Ok(())
+        }
+
+        /// Best-effort estimate of the time from the initial state
+        /// to a terminal state without any input, taking the longest
+        /// branch where there are multiple transitions.
+        ///
+        /// `None` if the book has loops, speech in states or sounds
+        /// of unknown length.
+        pub fn estimated_duration(&self) -> Option<Duration> {
+            if self.passive {
+                None
+            } else {
+                estimated_duration(self)
+            }
+        }
     }
 
     pub struct BookBuilder {
         book: Book,
+        /// Context for reading sound durations, only obtained
+        /// when the first sound is added and shared with other
+        /// compilations on the same thread.
+        player_ctx: Option<Rc<PlayerContext>>,
+        /// Directory that relative sound file paths are relative
+        /// to, or the working directory if `None`.
+        base_dir: Option<PathBuf>,
+        /// Values for placeholders in speech.
+        variables: Variables,
+        /// Keeps synthesized speech across restarts, if `Some`.
+        tts_cache: Option<PathBuf>,
+        /// Keeps sounds converted with ffmpeg across restarts, if `Some`.
+        converted_cache: Option<PathBuf>,
+        /// If `false`, sounds are neither synthesized, converted
+        /// nor read, see `skip_sound_preparation`.
+        prepare_sounds: bool,
+        /// If `false`, durations are left for `Book::read_durations`.
+        read_durations: bool,
     }
 
     impl BookBuilder {
@@ -83,45 +222,120 @@ mod book {
         /// generated filenames.
         const MAX_SUMMARY_LEN: usize = 60;
 
+        pub fn book_id(&mut self, book_id: impl Into<String>) -> &mut Self {
+            self.book.book_id = Some(book_id.into());
+            self
+        }
+
+        /// Fades between the sounds of states over the given time
+        /// on transitions.
+        pub fn crossfade(&mut self, crossfade: Duration) -> &mut Self {
+            self.book.crossfade = crossfade;
+            self
+        }
+
+        pub fn source_spec(&mut self, spec: spec::Book) -> &mut Self {
+            self.book.source_spec = Some(spec);
+            self
+        }
+
+        /// Resolves relative sound file paths of sounds added after
+        /// this call against the given directory, rather than the
+        /// working directory.
+        pub fn base_dir(&mut self, base_dir: impl Into<PathBuf>) -> &mut Self {
+            self.base_dir = Some(base_dir.into());
+            self
+        }
+
+        /// Fills in placeholders like `{name}` in the speech of sounds
+        /// added after this call.
+        ///
+        /// If `strict`, adding a sound with a placeholder that has
+        /// no value fails, otherwise the placeholder is spoken as is.
+        pub fn variables(&mut self, values: HashMap<String, String>, strict: bool) -> &mut Self {
+            self.variables = Variables::new(values, strict);
+            self
+        }
+
+        /// Keeps speech of sounds added after this call in the given
+        /// directory, or in the temporary directory of the book
+        /// with `None`. Uses `tts_cache::dir` by default.
+        pub fn tts_cache(&mut self, dir: Option<PathBuf>) -> &mut Self {
+            self.tts_cache = dir;
+            self
+        }
+
+        /// Keeps converted and normalized copies of sounds added after
+        /// this call in the given directory, or in the temporary
+        /// directory of the book with `None`. Uses
+        /// `tts_cache::converted_dir` by default.
+        pub fn converted_cache(&mut self, dir: Option<PathBuf>) -> &mut Self {
+            self.converted_cache = dir;
+            self
+        }
+
+        /// Checks sounds added after this call without synthesizing
+        /// speech, converting files or reading their duration, so
+        /// espeak and ffmpeg are not needed and nothing is cached.
+        ///
+        /// The sounds of the resulting book cannot be played.
+        pub fn skip_sound_preparation(&mut self) -> &mut Self {
+            self.prepare_sounds = false;
+            self
+        }
+
+        /// Leaves the durations of sounds added after this call unknown
+        /// until `Book::read_durations`, e.g. for compiling on a thread
+        /// that should not initialize its own libvlc.
+        pub fn defer_durations(&mut self) -> &mut Self {
+            self.read_durations = false;
+            self
+        }
+
         pub fn state(&mut self, state: State) -> &mut Self {
             self.book.states.push(state);
             self
         }
 
         /// If the given sound spec describes text-to-speech, adds a
-        /// temporary file to the books temporary directory with the
-        /// speech content.
+        /// file to the speech directory with the speech content,
+        /// unless it is already there.
         ///
         /// The content file is then set to the given spec and its
-        /// speech text is removed.spec
-        fn prepare_sound(sound: &mut spec::Sound, cache_directory: &Path) -> Result<(), Error> {
+        /// speech text is removed. Placeholders in the speech are
+        /// filled in before synthesis.
+        fn prepare_sound(
+            sound: &mut spec::Sound,
+            cache_directory: &Path,
+            speech_directory: &Path,
+            variables: &Variables,
+        ) -> Result<(), Error> {
             // Files take precedence over speech
             if sound.file.is_empty() && sound.speech.is_some() {
-                let mut text = sound.speech.take().unwrap(); // Checked if some, unwrap is safe
+                let text = sound.speech.take().unwrap(); // Checked if some, unwrap is safe
+                let mut text = variables.substitute(&text)?;
 
                 if text.len() > Self::MAX_TEXT_LEN {
                     shrink_to_max(&mut text, Self::MAX_TEXT_LEN);
                 }
 
-                let mut hash = DefaultHasher::new();
-                hash.write(text.as_bytes());
-                let hash = hash.finish();
-
-                // work on a slice of the maximum summary length
-                // in case there are no whitespaces.
-                let summary = summarize(&text, Self::MAX_SUMMARY_LEN);
-
-                let mut filename = PathBuf::from(cache_directory);
-                filename.push(format!(
-                    "{hash}-{summary}.wav",
-                    hash = hash,
-                    summary = summary
-                ));
+                let voice = EspeakVoice::of(sound)?;
+                let filename = speech_directory.join(speech_file_name(&text, &voice));
 
-                debug!("Preparing speech {:?}...", &filename);
-                debug!("Text: {:?}", text);
-                let voice = any_voice()?;
-                voice.speak_to_file(text, &filename)?.await_done()?;
+                if tts_cache::lookup(&filename).is_some() {
+                    debug!("Using cached speech {:?}", &filename);
+                } else {
+                    debug!("Preparing speech {:?}...", &filename);
+                    debug!("Text: {:?}", text);
+                    // write to a temporary name first, so interrupted
+                    // synthesis does not count as cached
+                    let partial = filename.with_extension("part.wav");
+                    match voice {
+                        Some(voice) => voice.speak_to_file(&text, &partial)?,
+                        None => any_voice()?.speak_to_file(text, &partial)?.await_done()?,
+                    }
+                    rename(&partial, &filename)?;
+                }
 
                 sound.file = filename.to_str().unwrap().into();
             }
@@ -130,7 +344,13 @@ mod book {
                 Ok(Some(persisted_data_uri_path)) => {
                     sound.file = persisted_data_uri_path.to_str().unwrap().into()
                 }
-                Ok(None) => (),
+                Ok(None) => {
+                    let expanded = expand_path(&sound.file);
+                    if expanded != sound.file {
+                        debug!("Expanded {:?} to {:?}", sound.file, expanded);
+                        sound.file = expanded;
+                    }
+                }
                 Err(err) => return Err(err),
             };
 
@@ -153,6 +373,7 @@ mod book {
 
                 let mut hash = DefaultHasher::new();
                 hash.write(&content);
+                let hash = tts_cache::hex(&hash);
                 let extension = match mime {
                     "audio/mpeg" | "audio/mp3" | "audio/mpeg3" | "audio/x-mpeg-3"
                     | "video/mpeg" | "video/x-mpeg" => "mp3",
@@ -161,7 +382,7 @@ mod book {
                 let mut path = PathBuf::from(cache_directory);
                 path.push(format!(
                     "{name}.{extension}",
-                    name = hash.finish(),
+                    name = hash,
                     extension = extension
                 ));
                 debug!("Writing base64 encoded {:?}", path);
@@ -174,28 +395,168 @@ mod book {
         }
 
         pub fn sound(&mut self, mut sound: spec::Sound) -> Result<&mut Self, Error> {
-            let cache_directory = self.compiled_speech_dir()?;
-
-            Self::prepare_sound(&mut sound, cache_directory)?;
-            let path = sound.file.clone();
+            let volume = volume(&sound)?;
+            let (path, normalized) = if self.prepare_sounds {
+                self.prepared_path(&mut sound)?
+            } else {
+                (self.unprepared_path(&sound)?, false)
+            };
 
-            self.book.sounds.push({
-                let mut builder = SoundSpec::builder().source(path);
+            let mut builder = {
+                let mut builder = SoundSpec::builder().source_path(path);
+                builder.normalized(normalized);
 
                 if let Some(offset) = sound.start_offset {
                     builder.start_offset(offset)?;
                 }
 
+                if let Some(end_at) = sound.end_at {
+                    builder.end_at(end_at)?;
+                }
+
                 if let Some(backoff) = sound.backoff {
                     builder.backoff(backoff)?;
                 }
 
-                builder.looping(sound.looping).build()
-            });
+                if let Some(trim) = sound.trim_silence.as_ref() {
+                    builder.trim_silence(trim.threshold_rms, to_duration(trim.min_duration)?)?;
+                }
+
+                if let Some(volume) = volume {
+                    builder.volume(volume)?;
+                }
+
+                if let Some(rate) = sound.rate {
+                    builder.rate(rate)?;
+                }
+
+                if let Some(fade_in) = sound.fade_in {
+                    builder.fade_in(fade_in)?;
+                }
+
+                if let Some(fade_out) = sound.fade_out {
+                    builder.fade_out(fade_out)?;
+                }
+
+                builder.looping(sound.looping);
+                builder
+            };
+
+            let spec = builder.build();
+            if self.prepare_sounds && self.read_durations && !spec.source().as_os_str().is_empty() {
+                let duration = read_duration(&spec, self.player_ctx()?)?;
+                builder.duration(duration);
+            }
 
+            self.book.sounds.push(builder.build());
             Ok(self)
         }
 
+        /// Synthesizes, converts or normalizes the sound as needed and
+        /// returns the file to play and whether it was normalized.
+        fn prepared_path(&mut self, sound: &mut spec::Sound) -> Result<(PathBuf, bool), Error> {
+            let base_dir = self.base_dir.clone();
+            let variables = self.variables.clone();
+            let speech_directory = self.speech_dir()?;
+            let cache_directory = self.compiled_speech_dir()?;
+
+            Self::prepare_sound(sound, cache_directory, &speech_directory, &variables)?;
+            if let Some(base_dir) = base_dir {
+                if !sound.file.is_empty() && Path::new(&sound.file).is_relative() {
+                    sound.file = base_dir.join(&sound.file).to_string_lossy().into_owned();
+                }
+            }
+
+            if !sound.file.is_empty() && !Path::new(&sound.file).is_file() {
+                bail!("Sound file {:?} does not exist", sound.file);
+            }
+
+            // cached, so other books can reuse the result
+            let converted_directory = self.converted_dir()?;
+            let normalized = if sound.normalize && !sound.file.is_empty() {
+                normalize_loudness(Path::new(&sound.file), &converted_directory)?
+            } else {
+                None
+            };
+            let path = match normalized.as_ref() {
+                Some(normalized) => normalized.clone(),
+                None if sound.file.is_empty() => PathBuf::new(),
+                None => ensure_wav(Path::new(&sound.file), &converted_directory)?,
+            };
+
+            Ok((path, normalized.is_some()))
+        }
+
+        /// Resolves the sound file like `prepared_path`, but only checks
+        /// speech for missing placeholders and invalid voices instead of
+        /// synthesizing it and leaves files as they are.
+        fn unprepared_path(&self, sound: &spec::Sound) -> Result<PathBuf, Error> {
+            if sound.file.is_empty() {
+                if let Some(speech) = sound.speech.as_ref() {
+                    self.variables.substitute(speech)?;
+                    EspeakVoice::of(sound)?;
+                }
+                return Ok(PathBuf::new());
+            }
+
+            if sound.file.starts_with("data:") {
+                return Ok(PathBuf::new());
+            }
+
+            let mut file = PathBuf::from(expand_path(&sound.file));
+            if let Some(base_dir) = self.base_dir.as_ref() {
+                if file.is_relative() {
+                    file = base_dir.join(file);
+                }
+            }
+
+            if !file.is_file() {
+                bail!("Sound file {:?} does not exist", file);
+            }
+
+            Ok(file)
+        }
+
+        fn player_ctx(&mut self) -> Result<&PlayerContext, Error> {
+            if self.player_ctx.is_none() {
+                self.player_ctx = Some(PlayerContext::for_current_thread()?);
+            }
+
+            Ok(self.player_ctx.as_ref().unwrap()) // safe: either was already there or just created
+        }
+
+        /// The TTS cache, or the temporary directory of the book if
+        /// there is no cache or it cannot be created.
+        fn speech_dir(&mut self) -> Result<PathBuf, Error> {
+            if let Some(cache) = self.tts_cache.as_ref() {
+                match create_dir_all(cache) {
+                    Ok(()) => return Ok(cache.clone()),
+                    Err(e) => {
+                        warn!("Cannot cache speech in {:?}: {}", cache, e);
+                        self.tts_cache = None;
+                    }
+                }
+            }
+
+            Ok(self.compiled_speech_dir()?.to_path_buf())
+        }
+
+        /// The cache for converted sounds, or the temporary directory
+        /// of the book if there is no cache or it cannot be created.
+        fn converted_dir(&mut self) -> Result<PathBuf, Error> {
+            if let Some(cache) = self.converted_cache.as_ref() {
+                match create_dir_all(cache) {
+                    Ok(()) => return Ok(cache.clone()),
+                    Err(e) => {
+                        warn!("Cannot cache converted sounds in {:?}: {}", cache, e);
+                        self.converted_cache = None;
+                    }
+                }
+            }
+
+            Ok(self.compiled_speech_dir()?.to_path_buf())
+        }
+
         fn compiled_speech_dir(&mut self) -> Result<&Path, Error> {
             if self.book.compiled_speech_dir.is_none() {
                 // temp dir is need but not yet created, do it
@@ -215,6 +576,121 @@ mod book {
         }
     }
 
+    /// Reads the duration of the sound file, so unreadable files
+    /// are an error at compile time rather than when playing.
+    fn read_duration(spec: &SoundSpec, ctx: &PlayerContext) -> Result<Duration, Error> {
+        let source = spec.source();
+        if !source.is_file() {
+            bail!("Sound file {:?} does not exist", source);
+        }
+
+        let duration = Player::read_duration(source, ctx)?;
+
+        if spec.start_offset() >= duration {
+            bail!(
+                "Start offset {offset:?} of {file:?} is not before the end \
+                 of the sound at {duration:?}",
+                offset = spec.start_offset(),
+                file = source,
+                duration = duration
+            );
+        }
+
+        if duration < MIN_EXPECTED_DURATION {
+            warn!(
+                "Sound file {file:?} is only {duration:?} long",
+                file = source,
+                duration = duration
+            );
+        }
+
+        Ok(duration)
+    }
+
+    /// Speech is cached across restarts in `tts_cache::dir`, except
+    /// in unit tests, which inject a temporary cache with `tts_cache`
+    /// when they need one.
+    fn default_tts_cache() -> Option<PathBuf> {
+        if cfg!(test) {
+            None
+        } else {
+            tts_cache::dir()
+        }
+    }
+
+    /// Like `default_tts_cache`, for sounds converted with ffmpeg.
+    fn default_converted_cache() -> Option<PathBuf> {
+        if cfg!(test) {
+            None
+        } else {
+            tts_cache::converted_dir()
+        }
+    }
+
+    /// Linear gain of the sound, set either directly with `volume`
+    /// from `0.0` to `1.0` or in decibels with `volume_db`.
+    fn volume(sound: &spec::Sound) -> Result<Option<f64>, Error> {
+        match (sound.volume, sound.volume_db) {
+            (Some(_), Some(_)) => bail!(
+                "Sound {:?} specifies both volume and volume_db, only one is allowed",
+                sound.file
+            ),
+            (None, Some(db)) => db_to_linear(db).map(Some),
+            (Some(volume), None) => {
+                if !(0.0..=1.0).contains(&volume) {
+                    bail!(
+                        "volume must be between 0.0 and 1.0, but was {volume}, \
+                         use volume_db to amplify",
+                        volume = volume
+                    );
+                }
+                Ok(Some(f64::from(volume)))
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Name of the file for the text spoken with the voice, the
+    /// same across restarts.
+    fn speech_file_name(text: &str, voice: &Option<EspeakVoice>) -> String {
+        let mut hash = DefaultHasher::new();
+        hash.write(text.as_bytes());
+        if let Some(voice) = voice {
+            // separates the voice from the text
+            hash.write(&[0]);
+            hash.write(voice.name.as_ref().map_or(&[][..], |name| name.as_bytes()));
+            hash.write(&[0]);
+            hash.write(&voice.speed.map_or([0; 3], |speed| {
+                let [high, low] = speed.to_be_bytes();
+                [1, high, low]
+            }));
+            hash.write(&voice.pitch.map_or([0; 2], |pitch| [1, pitch]));
+        }
+
+        // work on a slice of the maximum summary length
+        // in case there are no whitespaces.
+        let summary = summarize(text, BookBuilder::MAX_SUMMARY_LEN);
+
+        format!(
+            "{hash}-{summary}.wav",
+            hash = tts_cache::hex(&hash),
+            summary = summary
+        )
+    }
+
+    fn db_to_linear(db: f64) -> Result<f64, Error> {
+        if !(MIN_VOLUME_DB..=MAX_VOLUME_DB).contains(&db) {
+            bail!(
+                "volume_db must be between {min} and {max}, but was {db}",
+                min = MIN_VOLUME_DB,
+                max = MAX_VOLUME_DB,
+                db = db
+            );
+        }
+
+        Ok(10.0f64.powf(db / 20.0))
+    }
+
     fn shrink_to_max(text: &mut String, max: usize) {
         warn!(
             "Sound text has a size of {actual}KiB, \
@@ -263,14 +739,367 @@ mod book {
             .join("-")
     }
 
+    /// Replaces a leading `~/` with the home directory and `$VAR`
+    /// with the value of the environment variable `VAR`.
+    ///
+    /// Variables that are not set are left as they are.
+    pub(crate) fn expand_path(path: &str) -> String {
+        match (path.strip_prefix("~/"), var("HOME")) {
+            (Some(rest), Ok(home)) => format!("{}/{}", home, expand_vars(rest)),
+            _ => expand_vars(path),
+        }
+    }
+
+    fn expand_vars(path: &str) -> String {
+        let mut expanded = String::with_capacity(path.len());
+        let mut rest = path;
+        while let Some(dollar) = rest.find('$') {
+            expanded.push_str(&rest[..dollar]);
+            let name_len = rest[dollar + 1..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len() - dollar - 1);
+            let name = &rest[dollar + 1..dollar + 1 + name_len];
+            match var(name) {
+                Ok(value) if !name.is_empty() => expanded.push_str(&value),
+                _ => expanded.push_str(&rest[dollar..dollar + 1 + name_len]),
+            }
+            rest = &rest[dollar + 1 + name_len..];
+        }
+        expanded.push_str(rest);
+        expanded
+    }
+
     #[cfg(test)]
     mod test {
         use super::*;
+        use crate::acts::Ensemble;
         use crate::books::file::load;
         use crate::books::spec::Id;
-        use std::fs::read_dir;
+        use std::fs::{copy, read_dir, File};
         use tempfile::tempdir;
 
+        const VALID_WAV: &str = "test/482381__erokia__msfxp3-15-thunky-bass.wav";
+
+        #[test]
+        fn valid_sound_file_compiles() {
+            // given
+            let mut builder = Book::builder();
+
+            // when
+            let result = builder.sound(sound_file(VALID_WAV));
+
+            // then
+            assert!(result.is_ok(), "expected valid WAV to compile");
+            assert_eq!(builder.build().sounds().len(), 1);
+        }
+
+        #[test]
+        fn empty_sound_file_is_error() {
+            // given
+            let dir = tempdir().unwrap();
+            let empty = dir.path().join("empty.wav");
+            File::create(&empty).unwrap();
+            let mut builder = Book::builder();
+
+            // when
+            let result = builder.sound(sound_file(empty.to_str().unwrap()));
+
+            // then
+            assert!(result.is_err(), "expected empty file to be an error");
+        }
+
+        #[test]
+        fn tilde_is_expanded_in_error() {
+            // given
+            let home = var("HOME").expect("expected HOME to be set");
+            let mut builder = Book::builder();
+
+            // when
+            let err = builder
+                .sound(sound_file("~/nonexistent.mp3"))
+                .err()
+                .expect("expected missing file to be an error")
+                .to_string();
+
+            // then
+            let expanded = format!("{}/nonexistent.mp3", home);
+            assert!(err.contains(&expanded), "unexpected error: {}", err);
+            assert!(!err.contains('~'), "unexpected error: {}", err);
+        }
+
+        #[test]
+        fn env_vars_are_expanded() {
+            let home = var("HOME").expect("expected HOME to be set");
+
+            assert_eq!(expand_path("$HOME/a.wav"), format!("{}/a.wav", home));
+            assert_eq!(
+                expand_path("/x/$FERNSPIEL_UNSET_VAR/a.wav"),
+                "/x/$FERNSPIEL_UNSET_VAR/a.wav"
+            );
+            assert_eq!(expand_path("/x/$/a.wav"), "/x/$/a.wav");
+        }
+
+        #[test]
+        fn start_offset_after_end_is_error() {
+            // given
+            let mut builder = Book::builder();
+
+            // when
+            let result = builder.sound(spec::Sound {
+                start_offset: Some(3600.0),
+                ..sound_file(VALID_WAV)
+            });
+
+            // then
+            assert!(result.is_err(), "expected offset after end to be an error");
+        }
+
+        #[test]
+        fn end_at_before_start_offset_is_error() {
+            // given
+            let mut builder = Book::builder();
+
+            // when
+            let result = builder.sound(spec::Sound {
+                start_offset: Some(1.0),
+                end_at: Some(0.5),
+                ..sound_file(VALID_WAV)
+            });
+
+            // then
+            assert!(result.is_err(), "expected end before offset to be an error");
+        }
+
+        #[test]
+        fn minus_six_db_is_half_volume() {
+            let linear = db_to_linear(-6.0).unwrap();
+
+            assert!(
+                (linear - 0.5).abs() < 0.01,
+                "expected about 0.5, got {}",
+                linear
+            );
+        }
+
+        #[test]
+        fn volume_db_out_of_range_is_error() {
+            // given
+            let mut builder = Book::builder();
+
+            // when
+            let too_loud = builder
+                .sound(spec::Sound {
+                    volume_db: Some(6.5),
+                    ..sound_file(VALID_WAV)
+                })
+                .is_err();
+            let too_quiet = builder
+                .sound(spec::Sound {
+                    volume_db: Some(-61.0),
+                    ..sound_file(VALID_WAV)
+                })
+                .is_err();
+
+            // then
+            assert!(too_loud, "expected +6.5 dB to be an error");
+            assert!(too_quiet, "expected -61 dB to be an error");
+        }
+
+        #[test]
+        fn linear_volume_is_applied_to_player() {
+            // given
+            let mut builder = Book::builder();
+            builder
+                .sound(spec::Sound {
+                    volume: Some(0.25),
+                    ..sound_file(VALID_WAV)
+                })
+                .unwrap();
+            let book = builder.build();
+
+            // when
+            let mut ensemble = Ensemble::from_specs(book.sounds()).unwrap();
+            ensemble.transition_to(&[0]).unwrap();
+            ensemble.update().unwrap();
+
+            // then
+            assert_eq!(book.sounds()[0].volume(), 0.25);
+            assert_eq!(ensemble.sound(0).player_volume(), 0.25);
+        }
+
+        #[test]
+        fn linear_volume_out_of_range_is_error() {
+            // given
+            let mut builder = Book::builder();
+
+            // when
+            let too_loud = builder
+                .sound(spec::Sound {
+                    volume: Some(1.5),
+                    ..sound_file(VALID_WAV)
+                })
+                .is_err();
+            let negative = builder
+                .sound(spec::Sound {
+                    volume: Some(-0.1),
+                    ..sound_file(VALID_WAV)
+                })
+                .is_err();
+
+            // then
+            assert!(too_loud, "expected volume 1.5 to be an error");
+            assert!(negative, "expected negative volume to be an error");
+        }
+
+        #[test]
+        fn volume_and_volume_db_is_error() {
+            // given
+            let mut builder = Book::builder();
+
+            // when
+            let result = builder.sound(spec::Sound {
+                volume: Some(0.5),
+                volume_db: Some(-6.0),
+                ..sound_file(VALID_WAV)
+            });
+
+            // then
+            assert!(
+                result.is_err(),
+                "expected conflicting volumes to be an error"
+            );
+        }
+
+        #[test]
+        fn cached_speech_is_not_synthesized_again() {
+            // given
+            let cache = tempdir().unwrap();
+            let cached = cache.path().join(speech_file_name("Hello", &None));
+            copy(VALID_WAV, &cached).unwrap();
+            let mut builder = Book::builder();
+            builder.tts_cache(Some(cache.path().to_path_buf()));
+
+            // when
+            builder
+                .sound(spec::Sound {
+                    speech: Some("Hello".to_string()),
+                    ..Default::default()
+                })
+                .unwrap();
+
+            // then
+            let book = builder.build();
+            assert_eq!(book.sounds()[0].source(), cached.as_path());
+        }
+
+        #[test]
+        fn deferred_durations_are_read_later() {
+            // given
+            let mut builder = Book::builder();
+            builder.defer_durations();
+            builder.sound(sound_file(VALID_WAV)).unwrap();
+            let mut book = builder.build();
+            let unread = book.sounds()[0].duration();
+
+            // when
+            book.read_durations(&PlayerContext::new().unwrap()).unwrap();
+
+            // then
+            assert_eq!(unread, None);
+            assert!(book.sounds()[0].duration().is_some());
+        }
+
+        #[test]
+        fn skipped_preparation_does_not_synthesize_speech() {
+            // given
+            let cache = tempdir().unwrap();
+            let mut builder = Book::builder();
+            builder
+                .tts_cache(Some(cache.path().to_path_buf()))
+                .skip_sound_preparation();
+
+            // when
+            builder
+                .sound(spec::Sound {
+                    speech: Some("Hello".to_string()),
+                    ..Default::default()
+                })
+                .unwrap();
+
+            // then
+            let book = builder.build();
+            assert_eq!(book.sounds().len(), 1);
+            assert_eq!(read_dir(cache.path()).unwrap().count(), 0);
+        }
+
+        #[test]
+        fn skipped_preparation_checks_placeholders() {
+            // given
+            let mut builder = Book::builder();
+            builder
+                .variables(HashMap::new(), true)
+                .skip_sound_preparation();
+
+            // when
+            let result = builder.sound(spec::Sound {
+                speech: Some("Welcome to {name}".to_string()),
+                ..Default::default()
+            });
+
+            // then
+            assert!(result.is_err());
+        }
+
+        #[test]
+        #[cfg_attr(not(feature = "expensive_tests"), ignore)]
+        fn normalized_sound_plays_normalized_copy() {
+            // given
+            let cache = tempdir().unwrap();
+            let mut builder = Book::builder();
+            builder.converted_cache(Some(cache.path().to_path_buf()));
+
+            // when
+            builder
+                .sound(spec::Sound {
+                    normalize: true,
+                    ..sound_file(VALID_WAV)
+                })
+                .unwrap();
+
+            // then
+            let book = builder.build();
+            let spec = &book.sounds()[0];
+            assert!(spec.is_normalized(), "expected ffmpeg to normalize");
+            assert!(spec.source().starts_with(cache.path()));
+        }
+
+        #[test]
+        fn speech_is_named_after_hash_of_text_and_voice() {
+            // given
+            let slow = Some(EspeakVoice {
+                speed: Some(80),
+                ..Default::default()
+            });
+
+            // when
+            let any_voice = speech_file_name("Hello", &None);
+            let slow_voice = speech_file_name("Hello", &slow);
+
+            // then
+            assert_eq!(any_voice, speech_file_name("Hello", &None));
+            assert_eq!(any_voice.len(), "0123456789abcdef-hello.wav".len());
+            assert!(any_voice.ends_with("-hello.wav"), "{}", any_voice);
+            assert_ne!(any_voice, slow_voice);
+            assert!(slow_voice.ends_with("-hello.wav"), "{}", slow_voice);
+        }
+
+        fn sound_file(file: &str) -> spec::Sound {
+            spec::Sound {
+                file: file.to_string(),
+                ..Default::default()
+            }
+        }
+
         #[test]
         fn prepare_wav_files_from_default_yaml() {
             // given
@@ -282,7 +1111,13 @@ mod book {
             match missiles_launched_opt {
                 Some(sound_spec) => {
                     assert!(sound_spec.speech.is_some());
-                    BookBuilder::prepare_sound(sound_spec, tempdir.path()).unwrap();
+                    BookBuilder::prepare_sound(
+                        sound_spec,
+                        tempdir.path(),
+                        tempdir.path(),
+                        &Variables::default(),
+                    )
+                    .unwrap();
                 }
                 _ => panic!("Could not load demo file"),
             }
@@ -303,24 +1138,115 @@ mod book {
 /// This also prepares espeak speech into WAV files
 /// in a temporary directory.
 pub fn compile(book: spec::Book) -> Result<Book, Error> {
+    compile_book(book, None, Preparation::Full, |_| ())
+}
+
+/// Like `compile`, but reports the number of sounds that still
+/// need to be prepared before preparing each sound, and `0` when
+/// all sounds are done.
+///
+/// This is meant for compiling on a background thread, so sound
+/// durations are not read. Read them with `Book::read_durations`
+/// on the thread that plays the book.
+pub fn compile_with_progress(
+    book: spec::Book,
+    on_progress: impl FnMut(usize),
+) -> Result<Book, Error> {
+    compile_book(book, None, Preparation::WithoutDurations, on_progress)
+}
+
+/// Like `compile`, but relative sound file paths are relative to
+/// the given directory instead of the working directory.
+pub fn compile_relative_to(book: spec::Book, base_dir: &Path) -> Result<Book, Error> {
+    compile_book(book, Some(base_dir), Preparation::Full, |_| ())
+}
+
+/// Like `compile_relative_to`, but only checks the sounds instead
+/// of preparing them, so the structure of phonebooks can be checked
+/// without espeak or ffmpeg. The sounds of the book cannot be played.
+pub fn compile_unprepared_relative_to(book: spec::Book, base_dir: &Path) -> Result<Book, Error> {
+    compile_book(book, Some(base_dir), Preparation::None, |_| ())
+}
+
+/// How much of the sounds `compile_book` prepares.
+enum Preparation {
+    /// Synthesizes speech, converts files and reads durations.
+    Full,
+    /// Like `Full`, but leaves durations for `Book::read_durations`.
+    WithoutDurations,
+    /// Only checks sounds, see `BookBuilder::skip_sound_preparation`.
+    None,
+}
+
+fn compile_book(
+    book: spec::Book,
+    base_dir: Option<&Path>,
+    preparation: Preparation,
+    mut on_progress: impl FnMut(usize),
+) -> Result<Book, Error> {
     let mut builder = Book::builder();
+    builder.source_spec(book.clone());
+    if let Some(base_dir) = base_dir {
+        builder.base_dir(base_dir);
+    }
+    match preparation {
+        Preparation::Full => (),
+        Preparation::WithoutDurations => {
+            builder.defer_durations();
+        }
+        Preparation::None => {
+            builder.skip_sound_preparation();
+        }
+    }
 
     let spec::Book {
+        book_id,
         states,
         sounds,
         initial,
-        mut transitions,
+        transitions,
+        any_transitions,
+        groups,
+        group_transitions,
+        variables,
+        strict,
+        allow_dead_ends,
+        crossfade,
+        locations,
     } = book;
 
+    if let Some(book_id) = book_id {
+        builder.book_id(book_id);
+    }
+    if let Some(crossfade) = crossfade {
+        builder.crossfade(to_duration(crossfade)?);
+    }
+    builder.variables(variables, strict.unwrap_or(true));
+
+    let sound_count = sounds.len();
     let sounds: HashMap<Id, usize> = sounds
         .into_iter()
         .enumerate()
-        .map(|(idx, (id, s))| builder.sound(s).map(|_| (id, idx)))
+        .map(|(idx, (id, s))| {
+            on_progress(sound_count - idx);
+            builder
+                .sound(s)
+                .map_err(|e| located(e, locations.sound(&id)))
+                .map(|_| (id, idx))
+        })
         .collect::<Result<_, Error>>()?;
+    on_progress(0);
 
     let defined_states = {
         let mut states: Vec<Id> = states.keys().map(Clone::clone).collect();
 
+        if states.contains(&Id::new(HISTORY_ID)) {
+            bail!(
+                "{:?} is reserved for going back and cannot be a state",
+                HISTORY_ID
+            );
+        }
+
         let initial_idx = states
             .iter()
             .position(|s| *s == initial)
@@ -333,9 +1259,31 @@ pub fn compile(book: spec::Book) -> Result<Book, Error> {
         states
     };
 
-    let any_transition = transitions.remove(&Id::new("any"));
+    for name in groups.values().flatten() {
+        if name == "any" || states.contains_key(&Id::new(name.as_str())) {
+            bail!(
+                "Group {:?} cannot have the same name as a state or \"any\"",
+                name
+            );
+        }
+    }
+
+    for (id, names) in groups.iter() {
+        if let Some(name) = names.iter().find(|n| !group_transitions.contains_key(*n)) {
+            return Err(located(
+                format_err!(
+                    "State {:?} is in group {:?}, but no transitions are defined for it",
+                    id,
+                    name
+                ),
+                locations.state(id),
+            ));
+        }
+    }
+
     let default_transition = Transitions::default();
     let default_state = spec::State::default();
+    let no_groups = vec![];
 
     defined_states
         .iter()
@@ -347,18 +1295,75 @@ pub fn compile(book: spec::Book) -> Result<Book, Error> {
                 .as_ref()
                 .unwrap_or(&default_state);
 
+            let state_groups = groups.get(id).unwrap_or(&no_groups);
             let transitions = with_any(
                 transitions.get(id).unwrap_or(&default_transition),
-                any_transition.as_ref().unwrap_or(&default_transition),
+                state_groups
+                    .iter()
+                    .filter_map(|name| group_transitions.get(name)),
+                any_transitions.as_ref().unwrap_or(&default_transition),
             );
 
-            let state = compile_state(&defined_states, id, state, &transitions, &sounds)?;
+            let state = compile_state(
+                &defined_states,
+                id,
+                state,
+                &transitions,
+                state_groups,
+                &sounds,
+                &locations,
+            )?;
             builder.state(state);
             Ok(())
         })
         .collect::<Result<Vec<()>, Error>>()?;
 
-    Ok(builder.build())
+    let book = builder.build();
+    for (id1, id2, name) in duplicate_names(book.states()) {
+        warn!(
+            "States {:?} and {:?} have the same name {:?}",
+            id1, id2, name
+        );
+    }
+
+    let reachable = reachable_from(&book, 0);
+    for (idx, state) in book.states().iter().enumerate() {
+        if !reachable.contains(&idx) {
+            warn!(
+                "State {:?} cannot be reached from the initial state",
+                state.id()
+            );
+        }
+    }
+
+    for idx in dead_ends(&book) {
+        let dead_end = CompileError::DeadEnd {
+            state_name: book.states()[idx].name().to_string(),
+        };
+        if allow_dead_ends.unwrap_or(false) {
+            warn!("{}", dead_end);
+        } else {
+            return Err(dead_end.into());
+        }
+    }
+
+    Ok(book)
+}
+
+/// Finds pairs of states with different IDs but the same name,
+/// as `(id1, id2, name)`.
+fn duplicate_names(states: &[State]) -> Vec<(&str, &str, &str)> {
+    let mut first_with_name: HashMap<&str, &str> = HashMap::new();
+    let mut duplicates = vec![];
+    for state in states {
+        match first_with_name.get(state.name()) {
+            Some(first_id) => duplicates.push((*first_id, state.id(), state.name())),
+            None => {
+                first_with_name.insert(state.name(), state.id());
+            }
+        }
+    }
+    duplicates
 }
 
 fn compile_state(
@@ -366,8 +1371,30 @@ fn compile_state(
     state_id: &Id,
     spec: &spec::State,
     transitions: &Transitions,
+    groups: &[String],
     sounds: &HashMap<Id, usize>,
+    locations: &Locations,
 ) -> Result<State, Error> {
+    let state_location = locations.state(state_id);
+    let transitions_location = locations.transitions(state_id).or(state_location);
+    // transitions not defined on the state itself come from its
+    // groups or `any`
+    let fallbacks: Vec<Id> = groups
+        .iter()
+        .map(|name| Id::new(name.as_str()))
+        .chain(Some(Id::new("any")))
+        .collect();
+    let transition_location = |key: &str| {
+        locations
+            .transition(state_id, key)
+            .or_else(|| {
+                fallbacks
+                    .iter()
+                    .find_map(|fallback| locations.transition(fallback, key))
+            })
+            .or(transitions_location)
+    };
+
     let id_str = format!("{}", state_id);
     let mut state = State::builder()
         .id(&id_str)
@@ -388,7 +1415,8 @@ fn compile_state(
                     bail!("State {:?} uses undefined Sound ID {:?}", state_id, id)
                 }
             })
-            .collect::<Result<Vec<usize>, Error>>()?,
+            .collect::<Result<Vec<usize>, Error>>()
+            .map_err(|e| located(e, state_location))?,
     );
 
     if !spec.speech.is_empty() {
@@ -397,54 +1425,110 @@ fn compile_state(
     }
 
     state = compile_ring(state, spec.ring);
+    state = compile_transitions(state, defined_states, transitions, transition_location)?;
 
-    if let Some(ref timeout) = transitions.timeout {
-        state = lookup_state(defined_states, &timeout.to)
-            .and_then(|idx| compile_timeout(state, timeout.after, idx))?
+    Ok(state.build())
+}
+
+/// Prefixes the error message with the location, if known,
+/// e.g. `phonebook.yaml:14:5: Transition to unknown state "foo"`.
+fn located(error: Error, location: Option<&Location>) -> Error {
+    match location {
+        Some(location) => format_err!("{}: {}", location, error),
+        None => error,
+    }
+}
+
+/// Adds the transitions to the state, locating errors with the
+/// given function that looks up keys like `pick_up` or `dial.1`.
+fn compile_transitions<'a>(
+    mut state: StateBuilder,
+    defined_states: &[Id],
+    transitions: &Transitions,
+    location: impl Fn(&str) -> Option<&'a Location>,
+) -> Result<StateBuilder, Error> {
+    let lookup = |target_id: &Id, key: &str| {
+        lookup_state(defined_states, target_id).map_err(|e| located(e, location(key)))
+    };
+
+    for timeout in transitions
+        .timeout
+        .iter()
+        .flat_map(|timeouts| timeouts.iter())
+    {
+        state = lookup(&timeout.to, "timeout")
+            .and_then(|target| compile_timeout(state, timeout.after, target))?
     }
 
     for (dial_pattern, target_id) in transitions.dial.iter() {
+        let key = format!("dial.{}", dial_pattern);
+        if dial_pattern.trim() == ANY_DIGIT_PATTERN {
+            state = state.any_digit(lookup(target_id, &key)?);
+            continue;
+        }
+
         let mut pattern_digits = dial_pattern.chars().filter(|c| *c >= '0' && *c <= '9');
         let input = pattern_digits
             .next()
             .ok_or_else(|| format_err!("Pattern contained no digits: \"{}\"", dial_pattern))
-            .map(|c| (c as i32) - ('0' as i32))?;
+            .map(|c| (c as i32) - ('0' as i32))
+            .map_err(|e| located(e, location(&key)))?;
 
         if pattern_digits.next().is_some() {
-            bail!(
-                "Pattern can currently only consist of a single digit, but got: \"{}\"",
-                dial_pattern
-            );
+            return Err(located(
+                format_err!(
+                    "Pattern can currently only consist of a single digit, but got: \"{}\"",
+                    dial_pattern
+                ),
+                location(&key),
+            ));
         }
 
-        let target_idx = lookup_state(defined_states, target_id)?;
+        let target = lookup(target_id, &key)?;
 
-        state = state.input(Input::digit(input)?, target_idx);
+        state = state.input(Input::digit(input)?, target);
     }
 
     if let Some(ref target_id) = transitions.hang_up {
-        let target_idx = lookup_state(defined_states, target_id)?;
-        state = state.input(Input::hang_up(), target_idx);
+        let target = lookup(target_id, "hang_up")?;
+        state = state.input(Input::hang_up(), target);
     }
 
     if let Some(ref target_id) = transitions.pick_up {
-        let target_idx = lookup_state(defined_states, target_id)?;
-        state = state.input(Input::pick_up(), target_idx);
+        let target = lookup(target_id, "pick_up")?;
+        state = state.input(Input::pick_up(), target);
+    }
+
+    if let Some(ref target_id) = transitions.recall_press {
+        let target = lookup(target_id, "recall_press")?;
+        state = state.input(Input::recall_press(), target);
+    }
+
+    if let Some(ref target_id) = transitions.recall_release {
+        let target = lookup(target_id, "recall_release")?;
+        state = state.input(Input::recall_release(), target);
     }
 
     if let Some(ref target_id) = transitions.end {
-        let target_idx = lookup_state(defined_states, target_id)?;
-        state = state.end(target_idx);
+        let target = lookup(target_id, "end")?;
+        state = state.end(target);
     }
 
-    Ok(state.build())
+    Ok(state)
 }
 
-fn lookup_state(defined_states: &[Id], search_id: &Id) -> Result<usize, Error> {
+/// Finds the target state by ID, or history for the reserved ID
+/// `$history`.
+fn lookup_state(defined_states: &[Id], search_id: &Id) -> Result<TransitionTarget, Error> {
+    if *search_id == Id::new(HISTORY_ID) {
+        return Ok(TransitionTarget::History);
+    }
+
     defined_states
         .iter()
         .position(|id| id == search_id)
-        .ok_or_else(|| format_err!("Transition mentions unknown state: {}", search_id))
+        .map(TransitionTarget::State)
+        .ok_or_else(|| format_err!("Transition to unknown state \"{}\"", search_id))
 }
 
 fn compile_ring(state: StateBuilder, ring: f64) -> StateBuilder {
@@ -456,44 +1540,581 @@ fn compile_ring(state: StateBuilder, ring: f64) -> StateBuilder {
     }
 }
 
-fn compile_timeout(state: StateBuilder, after: f64, to: usize) -> Result<StateBuilder, Error> {
+fn compile_timeout(
+    state: StateBuilder,
+    after: f64,
+    to: TransitionTarget,
+) -> Result<StateBuilder, Error> {
     to_duration(after).map(|dur| state.timeout(dur, to))
 }
 
-fn with_any(base: &Transitions, any: &Transitions) -> Transitions {
-    let dial = base
+/// Adds the transitions of the groups of a state, highest priority
+/// first, and then those for any state to the transitions of the
+/// state, where it does not already have a transition for the same
+/// input.
+fn with_any<'a>(
+    base: &Transitions,
+    groups: impl Iterator<Item = &'a Transitions>,
+    any: &'a Transitions,
+) -> Transitions {
+    groups
+        .chain(Some(any))
+        .fold(base.clone(), |merged, fallback| {
+            with_fallback(&merged, fallback)
+        })
+}
+
+/// Adds the transitions of the fallback where the base does not
+/// already define a transition for the same input.
+fn with_fallback(base: &Transitions, fallback: &Transitions) -> Transitions {
+    // later entries win when collecting, so the state goes last
+    let dial = fallback
         .dial
         .iter()
-        .chain(any.dial.iter())
+        .chain(base.dial.iter())
         .map(|(input, id)| (input.clone(), id.clone()))
         .collect();
 
     let pick_up = base
         .pick_up
         .as_ref()
-        .or_else(|| any.pick_up.as_ref())
+        .or_else(|| fallback.pick_up.as_ref())
         .map(Clone::clone);
     let hang_up = base
         .hang_up
         .as_ref()
-        .or_else(|| any.hang_up.as_ref())
+        .or_else(|| fallback.hang_up.as_ref())
+        .map(Clone::clone);
+    let recall_press = base
+        .recall_press
+        .as_ref()
+        .or_else(|| fallback.recall_press.as_ref())
+        .map(Clone::clone);
+    let recall_release = base
+        .recall_release
+        .as_ref()
+        .or_else(|| fallback.recall_release.as_ref())
         .map(Clone::clone);
     let end = base
         .end
         .as_ref()
-        .or_else(|| any.end.as_ref())
+        .or_else(|| fallback.end.as_ref())
         .map(Clone::clone);
     let timeout = base
         .timeout
         .as_ref()
-        .or_else(|| any.timeout.as_ref())
+        .or_else(|| fallback.timeout.as_ref())
         .map(Clone::clone);
 
     Transitions {
         dial,
         pick_up,
         hang_up,
+        recall_press,
+        recall_release,
         end,
         timeout,
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::books::from_str;
+    use crate::log::capture_logs;
+
+    #[test]
+    fn speech_with_unknown_variable_fails() {
+        // given
+        let yaml = "
+initial: greeting
+states:
+  greeting:
+    terminal: true
+    sounds: [welcome]
+sounds:
+  welcome:
+    speech: Welcome to exhibit {name}
+";
+
+        // when
+        let error = from_str(yaml).unwrap_err().to_string();
+
+        // then
+        assert!(error.contains("\"name\""), "{}", error);
+    }
+
+    #[test]
+    fn finds_states_with_duplicate_names() {
+        // given
+        let yaml = "
+initial: a
+states:
+  a:
+    name: Announcement
+    terminal: true
+  b:
+    name: Announcement
+    terminal: true
+  c:
+    name: Goodbye
+    terminal: true
+";
+
+        // when
+        let book = from_str(yaml).unwrap();
+        let duplicates = duplicate_names(book.states());
+
+        // then
+        assert_eq!(duplicates.len(), 1);
+        let (id1, id2, name) = duplicates[0];
+        let mut ids = vec![id1, id2];
+        ids.sort();
+        assert_eq!(ids, vec!["a", "b"]);
+        assert_eq!(name, "Announcement");
+    }
+
+    #[test]
+    fn warns_about_duplicate_names_when_compiling() {
+        // given
+        let yaml = "
+initial: a
+states:
+  a:
+    name: Announcement
+    terminal: true
+  b:
+    name: Announcement
+    terminal: true
+";
+
+        // when
+        let (book, logged) = capture_logs(|| from_str(yaml));
+
+        // then
+        assert!(book.is_ok());
+        assert!(
+            logged
+                .iter()
+                .any(|msg| msg.contains("have the same name \"Announcement\"")),
+            "expected warning about duplicate names, got: {:?}",
+            logged
+        );
+    }
+
+    #[test]
+    fn history_id_goes_back() {
+        // given
+        let yaml = "initial: ring
+states:
+  ring:
+  talk:
+transitions:
+  ring:
+    pick_up: talk
+  talk:
+    hang_up: $history
+";
+
+        // when
+        let book = from_str(yaml).unwrap();
+
+        // then
+        let talk = &book.states()[1];
+        assert_eq!(
+            talk.target_for_input(Input::hang_up()),
+            Some(TransitionTarget::History)
+        );
+        assert_eq!(talk.transition_for_input(Input::hang_up()), None);
+    }
+
+    #[test]
+    fn star_dials_any_other_digit() {
+        // given
+        let yaml = "initial: menu
+states:
+  menu:
+  help:
+    terminal: true
+  other:
+    terminal: true
+transitions:
+  menu:
+    dial:
+      1: help
+      \"*\": other
+";
+
+        // when
+        let book = from_str(yaml).unwrap();
+
+        // then
+        let menu = &book.states()[0];
+        let target_id = |digit: i32| {
+            menu.transition_for_input(Input::digit(digit).unwrap())
+                .map(|idx| book.states()[idx].id().to_string())
+        };
+        assert_eq!(target_id(1), Some("help".to_string()));
+        for digit in 2..=9 {
+            assert_eq!(target_id(digit), Some("other".to_string()));
+        }
+    }
+
+    const PHONEBOOK_WITH_GROUPS: &str = "initial: menu
+states:
+  menu:
+  settings:
+    group: navigation
+  volume:
+    groups: [audio, navigation]
+  help:
+transitions:
+  any:
+    hang_up: help
+    pick_up: help
+  navigation:
+    hang_up: menu
+    dial:
+      0: menu
+  audio:
+    dial:
+      0: settings
+  volume:
+    pick_up: volume
+";
+
+    /// ID of the target of the transition on the input from the
+    /// state with the given ID.
+    fn target_id(book: &Book, id: &str, input: Input) -> Option<String> {
+        book.states()
+            .iter()
+            .find(|state| state.id() == id)
+            .and_then(|state| state.transition_for_input(input))
+            .map(|idx| book.states()[idx].id().to_string())
+    }
+
+    #[test]
+    fn group_transitions_apply_to_group_members_only() {
+        // when
+        let book = from_str(PHONEBOOK_WITH_GROUPS).unwrap();
+
+        // then
+        let hang_up = |id| target_id(&book, id, Input::hang_up());
+        assert_eq!(hang_up("settings"), Some("menu".to_string()));
+        assert_eq!(hang_up("volume"), Some("menu".to_string()));
+        assert_eq!(hang_up("menu"), Some("help".to_string()));
+        assert_eq!(hang_up("help"), Some("help".to_string()));
+    }
+
+    #[test]
+    fn state_before_groups_before_any() {
+        // when
+        let book = from_str(PHONEBOOK_WITH_GROUPS).unwrap();
+
+        // then
+        let zero = Input::digit(0).unwrap();
+        let pick_up = Input::pick_up();
+        assert_eq!(target_id(&book, "volume", zero), Some("settings".into()));
+        assert_eq!(target_id(&book, "settings", zero), Some("menu".into()));
+        assert_eq!(target_id(&book, "volume", pick_up), Some("volume".into()));
+        assert_eq!(target_id(&book, "settings", pick_up), Some("help".into()));
+    }
+
+    #[test]
+    fn group_with_name_of_state_is_error() {
+        // given
+        let yaml = "initial: menu
+states:
+  menu:
+    group: menu
+";
+
+        // when
+        let error = from_str(yaml).err();
+
+        // then
+        assert!(error.is_some(), "expected group named like a state to fail");
+    }
+
+    #[test]
+    fn group_without_transitions_is_error() {
+        // given
+        let yaml = "initial: menu
+states:
+  menu:
+    group: navigaton
+transitions:
+  navigation:
+    dial:
+      0: menu
+";
+
+        // when
+        let error = from_str(yaml).err();
+
+        // then
+        let error = error
+            .expect("expected misspelled group to fail")
+            .to_string();
+        assert!(error.contains("\"navigaton\""), "was: {}", error);
+    }
+
+    #[test]
+    fn history_id_cannot_be_a_state() {
+        // given
+        let yaml = "initial: ring
+states:
+  ring:
+  $history:
+";
+
+        // when
+        let error = from_str(yaml).err();
+
+        // then
+        assert!(error.is_some(), "expected reserved ID to be an error");
+    }
+
+    #[test]
+    fn dead_end_is_error() {
+        // given
+        let yaml = "initial: ring
+states:
+  ring:
+  stuck:
+    name: Stuck
+  bye:
+    terminal: true
+transitions:
+  ring:
+    pick_up: stuck
+    hang_up: bye
+";
+
+        // when
+        let error = from_str(yaml).unwrap_err();
+
+        // then
+        assert_eq!(
+            error.downcast_ref::<CompileError>(),
+            Some(&CompileError::DeadEnd {
+                state_name: "Stuck".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn allowed_dead_end_compiles() {
+        // given
+        let yaml = "initial: ring
+allow_dead_ends: true
+states:
+  ring:
+  stuck:
+transitions:
+  ring:
+    pick_up: stuck
+";
+
+        // when
+        let book = from_str(yaml);
+
+        // then
+        assert!(book.is_ok(), "{:?}", book.err());
+    }
+
+    #[test]
+    fn unreachable_state_compiles() {
+        // given
+        let yaml = "initial: ring
+states:
+  ring:
+    terminal: true
+  forgotten:
+    terminal: true
+";
+
+        // when
+        let book = from_str(yaml).unwrap();
+
+        // then
+        assert_eq!(book.states().len(), 2);
+    }
+
+    #[test]
+    fn error_contains_line_of_transition() {
+        // given
+        let yaml = "initial: ring
+states:
+  ring:
+  talk:
+transitions:
+  ring:
+    pick_up: talk
+  talk:
+    hang_up: nowhere
+";
+
+        // when
+        let error = from_str(yaml).unwrap_err();
+
+        // then
+        let message = error.to_string();
+        assert!(
+            message.starts_with("line 9, column 5: Transition to unknown state \"nowhere\""),
+            "expected line of transitions in error message, got: {}",
+            message
+        );
+    }
+
+    #[test]
+    fn recall_transitions() {
+        // given
+        let yaml = "
+initial: idle
+states:
+  idle:
+  flashing:
+transitions:
+  idle:
+    recall_press: flashing
+  flashing:
+    recall_release: idle
+";
+
+        // when
+        let book = from_str(yaml).unwrap();
+        let idle = book.states().iter().position(|s| s.id() == "idle").unwrap();
+        let flashing = book
+            .states()
+            .iter()
+            .position(|s| s.id() == "flashing")
+            .unwrap();
+
+        // then
+        assert_eq!(
+            book.states()[idle].transition_for_input(Input::recall_press()),
+            Some(flashing)
+        );
+        assert_eq!(
+            book.states()[flashing].transition_for_input(Input::recall_release()),
+            Some(idle)
+        );
+        assert_eq!(
+            book.states()[idle].transition_for_input(Input::recall_release()),
+            None
+        );
+    }
+
+    #[test]
+    fn state_dial_overrides_any_dial() {
+        // given
+        let yaml = "
+initial: idle
+states:
+  idle:
+  menu:
+  help:
+transitions:
+  any:
+    dial:
+      0: help
+      1: help
+  idle:
+    dial:
+      1: menu
+";
+
+        // when
+        let book = from_str(yaml).unwrap();
+        let position = |id| book.states().iter().position(|s| s.id() == id).unwrap();
+        let idle = &book.states()[position("idle")];
+
+        // then
+        assert_eq!(
+            idle.transition_for_input(Input::digit(1).unwrap()),
+            Some(position("menu"))
+        );
+        assert_eq!(
+            idle.transition_for_input(Input::digit(0).unwrap()),
+            Some(position("help"))
+        );
+    }
+
+    #[test]
+    fn source_spec_round_trip() {
+        // given
+        let book = from_str(include_str!("../../test/testbook_full.yaml")).unwrap();
+
+        // when
+        let spec = book
+            .source_spec()
+            .expect("Expected compiled book to keep its spec");
+
+        // then
+        let mut compiled_names: Vec<&str> = book.states().iter().map(State::name).collect();
+        let mut spec_names: Vec<String> = spec
+            .states
+            .iter()
+            .map(|(id, state)| match state {
+                Some(state) if !state.name.is_empty() => state.name.clone(),
+                _ => id.to_string(),
+            })
+            .collect();
+        compiled_names.sort();
+        spec_names.sort();
+        assert_eq!(compiled_names, spec_names);
+    }
+
+    #[test]
+    fn crossfade_is_passed_through() {
+        // given
+        let yaml = "
+crossfade: 1.5
+initial: a
+states:
+  a:
+    terminal: true
+";
+
+        // when
+        let book = from_str(yaml).unwrap();
+
+        // then
+        assert_eq!(book.crossfade(), Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn book_id_is_passed_through() {
+        // given
+        let yaml = "
+book_id: intro
+initial: a
+states:
+  a:
+    terminal: true
+";
+
+        // when
+        let book = from_str(yaml).unwrap();
+
+        // then
+        assert_eq!(book.book_id(), Some("intro"));
+    }
+
+    #[test]
+    fn no_duplicates_for_distinct_names() {
+        // given
+        let book = from_str(include_str!("../../test/testbook_full.yaml")).unwrap();
+
+        // when
+        let duplicates = duplicate_names(book.states());
+
+        // then
+        assert!(
+            duplicates.is_empty(),
+            "unexpected duplicates: {:?}",
+            duplicates
+        );
+    }
+}
@@ -0,0 +1,300 @@
+use crate::books::tts_cache;
+use failure::{bail, format_err, Error, Fail};
+use log::{debug, warn};
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{rename, File};
+use std::hash::Hasher;
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Extensions of formats that VLC can reliably read durations
+/// from and seek in.
+const PASSTHROUGH_EXTENSIONS: &[&str] = &["wav", "mp3"];
+
+/// Filter for `normalize_loudness`, aiming for -23 LUFS as in
+/// EBU R 128.
+const LOUDNORM_FILTER: &str = "loudnorm=I=-23:TP=-2:LRA=7";
+
+/// Raised when `ffmpeg` could not be found.
+#[derive(Debug, Fail)]
+#[fail(display = "ffmpeg is not installed")]
+struct FfmpegMissing;
+
+/// Transcodes the given sound file to WAV with `ffmpeg`, unless
+/// it already is a WAV or MP3 file, and returns the path of the
+/// file to use.
+///
+/// Converted files are put into the given cache directory and
+/// are named after the file contents, so the same sound is only
+/// converted once, even if it is used by multiple phonebooks.
+///
+/// If `ffmpeg` is not installed, the original file is used.
+pub fn ensure_wav(input_path: &Path, cache_dir: &Path) -> Result<PathBuf, Error> {
+    if !needs_conversion(input_path) {
+        return Ok(input_path.to_path_buf());
+    }
+
+    let output_path = cache_dir.join(converted_name(input_path)?);
+    if tts_cache::lookup(&output_path).is_some() {
+        debug!("Using cached conversion {:?}", output_path);
+        return Ok(output_path);
+    }
+
+    debug!("Converting {:?} to WAV...", input_path);
+    match transcode(input_path, &[], &output_path, "convert") {
+        Ok(()) => Ok(output_path),
+        Err(e) if e.downcast_ref::<FfmpegMissing>().is_some() => {
+            warn!("Playing {:?} without conversion to WAV: {}", input_path, e);
+            Ok(input_path.to_path_buf())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Normalizes the perceived loudness of the given sound file to
+/// -23 LUFS with `ffmpeg` and returns the path of the normalized
+/// WAV file.
+///
+/// Like conversions, normalized files are cached in the given
+/// directory.
+///
+/// If `ffmpeg` is not installed, returns `None` so the sound can
+/// be played without normalization.
+pub fn normalize_loudness(input_path: &Path, cache_dir: &Path) -> Result<Option<PathBuf>, Error> {
+    let output_path = cache_dir.join(normalized_name(input_path)?);
+    if tts_cache::lookup(&output_path).is_some() {
+        debug!("Using cached normalization {:?}", output_path);
+        return Ok(Some(output_path));
+    }
+
+    debug!("Normalizing loudness of {:?}...", input_path);
+    // loudnorm upsamples to 192kHz, go back to a common rate
+    let normalized = transcode(
+        input_path,
+        &["-af", LOUDNORM_FILTER, "-ar", "48000"],
+        &output_path,
+        "normalize",
+    );
+    match normalized {
+        Ok(()) => Ok(Some(output_path)),
+        Err(e) if e.downcast_ref::<FfmpegMissing>().is_some() => {
+            warn!("Playing {:?} without normalization: {}", input_path, e);
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Runs `ffmpeg` with the given extra arguments and writes a WAV
+/// file to the output path.
+///
+/// The verb describes the operation in error messages.
+fn transcode(
+    input_path: &Path,
+    extra_args: &[&str],
+    output_path: &Path,
+    verb: &str,
+) -> Result<(), Error> {
+    // write to a temporary name first, so interrupted conversions
+    // do not count as cache hits
+    let partial_path = output_path.with_extension("wav.part");
+    let output = Command::new("ffmpeg")
+        .arg("-nostdin")
+        .args(["-loglevel", "error", "-y", "-i"])
+        .arg(input_path)
+        .args(extra_args)
+        .args(["-f", "wav"])
+        .arg(&partial_path)
+        .output()
+        .map_err(|e| match e.kind() {
+            ErrorKind::NotFound => FfmpegMissing.into(),
+            _ => format_err!(
+                "Could not run ffmpeg to {} {:?} to WAV: {}",
+                verb,
+                input_path,
+                e
+            ),
+        })?;
+
+    if !output.status.success() {
+        bail!(
+            "ffmpeg failed to {} {:?} to WAV: {}",
+            verb,
+            input_path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    rename(&partial_path, output_path)?;
+    Ok(())
+}
+
+fn needs_conversion(path: &Path) -> bool {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) => !PASSTHROUGH_EXTENSIONS
+            .iter()
+            .any(|passthrough| ext.eq_ignore_ascii_case(passthrough)),
+        None => true,
+    }
+}
+
+/// Name of the converted file, unique for the contents of the
+/// input file.
+fn converted_name(input_path: &Path) -> Result<String, Error> {
+    cached_name(input_path, "")
+}
+
+/// Name of the normalized file, distinct from the converted name.
+fn normalized_name(input_path: &Path) -> Result<String, Error> {
+    cached_name(input_path, "-normalized")
+}
+
+fn cached_name(input_path: &Path, suffix: &str) -> Result<String, Error> {
+    let mut file = File::open(input_path)
+        .map_err(|e| format_err!("Could not read sound file {:?}: {}", input_path, e))?;
+    let mut hash = DefaultHasher::new();
+    let mut buf = [0; 8192];
+    loop {
+        let len = file.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        hash.write(&buf[..len]);
+    }
+
+    Ok(format!(
+        "{stem}-{hash}{suffix}.wav",
+        stem = input_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or_default(),
+        hash = tts_cache::hex(&hash),
+        suffix = suffix
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+    use std::f64::consts::PI;
+    use std::fs::{copy as copy_file, write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn wav_and_mp3_are_not_converted() {
+        // given
+        let cache = tempdir().unwrap();
+        let wav = Path::new("test/482381__erokia__msfxp3-15-thunky-bass.wav");
+        let mp3 = Path::new("test/A Good Bass for Gambling.mp3");
+
+        // when
+        let wav_result = ensure_wav(wav, cache.path()).unwrap();
+        let mp3_result = ensure_wav(mp3, cache.path()).unwrap();
+
+        // then
+        assert_eq!(wav_result, wav);
+        assert_eq!(mp3_result, mp3);
+    }
+
+    #[test]
+    fn cached_conversion_is_reused() {
+        // given
+        let cache = tempdir().unwrap();
+        let ogg = Path::new("test/Wilhelm_Scream.ogx");
+        let cached = cache.path().join(converted_name(ogg).unwrap());
+        write(&cached, b"already converted").unwrap();
+
+        // when
+        let result = ensure_wav(ogg, cache.path()).unwrap();
+
+        // then
+        assert_eq!(result, cached);
+    }
+
+    #[test]
+    fn conversions_are_named_after_contents() {
+        // given
+        let dir = tempdir().unwrap();
+        let original = Path::new("test/Wilhelm_Scream.ogx");
+        let copy = dir.path().join("Wilhelm_Scream.ogx");
+        let other = dir.path().join("other.ogx");
+        copy_file(original, &copy).unwrap();
+        write(&other, b"not a scream").unwrap();
+
+        // when
+        let original_name = converted_name(original).unwrap();
+        let copy_name = converted_name(&copy).unwrap();
+        let other_name = converted_name(&other).unwrap();
+
+        // then
+        assert_eq!(original_name, copy_name);
+        assert_ne!(original_name, other_name);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "expensive_tests"), ignore)]
+    fn normalization_evens_out_peaks() {
+        // given
+        let dir = tempdir().unwrap();
+        let quiet = dir.path().join("quiet.wav");
+        let loud = dir.path().join("loud.wav");
+        write_tone(&quiet, 0.05);
+        write_tone(&loud, 0.8);
+
+        // when
+        let quiet_peak = peak(&normalize_loudness(&quiet, dir.path()).unwrap().unwrap());
+        let loud_peak = peak(&normalize_loudness(&loud, dir.path()).unwrap().unwrap());
+
+        // then
+        let ratio = loud_peak / quiet_peak;
+        assert!(
+            ratio > 0.9 && ratio < 1.1,
+            "Expected about equal peaks after normalization, but ratio was {}",
+            ratio
+        );
+    }
+
+    #[test]
+    fn cached_normalization_is_reused() {
+        // given
+        let cache = tempdir().unwrap();
+        let wav = Path::new("test/482381__erokia__msfxp3-15-thunky-bass.wav");
+        let cached = cache.path().join(normalized_name(wav).unwrap());
+        write(&cached, b"already normalized").unwrap();
+
+        // when
+        let result = normalize_loudness(wav, cache.path()).unwrap();
+
+        // then
+        assert_eq!(result, Some(cached));
+    }
+
+    fn write_tone(path: &Path, amplitude: f64) {
+        const SAMPLE_RATE: u32 = 44100;
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(path, spec).unwrap();
+        for frame in 0..(3 * SAMPLE_RATE) {
+            let t = f64::from(frame) / f64::from(SAMPLE_RATE);
+            let sample = (2.0 * PI * 440.0 * t).sin() * amplitude * f64::from(i16::MAX);
+            writer.write_sample(sample as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    fn peak(path: &Path) -> f64 {
+        let mut reader = WavReader::open(path).unwrap();
+        let max = reader
+            .samples::<i16>()
+            .map(|s| i32::from(s.unwrap()).abs())
+            .max()
+            .unwrap_or(0);
+        f64::from(max) / f64::from(i16::MAX)
+    }
+}
@@ -0,0 +1,185 @@
+//! Draws the states and transitions of a phonebook as a graph in
+//! the DOT language of Graphviz, e.g. to render with
+//! `dot -Tsvg -o phonebook.svg`.
+use super::Book;
+use crate::states::{State, TransitionTarget};
+
+use std::fmt::Write;
+
+/// Node for transitions to `$history`.
+const HISTORY_NODE: &str = "$history";
+
+/// Makes a directed graph with a node for every state and an edge
+/// for every transition, labelled with what triggers it.
+///
+/// Nodes are labelled with the state name and whether the state is
+/// initial, terminal or rings.
+pub fn to_dot(book: &Book) -> String {
+    let states = book.states();
+    let mut dot = String::from("digraph phonebook {\n    node [shape=box];\n");
+
+    for (idx, state) in states.iter().enumerate() {
+        let peripheries = if state.is_terminal() { 2 } else { 1 };
+        writeln!(
+            dot,
+            "    {} [label={}, peripheries={}];",
+            quote(state.id()),
+            quote(&node_label(state, idx == 0)),
+            peripheries
+        )
+        .unwrap();
+    }
+
+    if states.iter().any(State::has_history_transition) {
+        writeln!(
+            dot,
+            "    {} [label=\"H\", shape=circle];",
+            quote(HISTORY_NODE)
+        )
+        .unwrap();
+    }
+
+    for state in states {
+        for (trigger, target) in state.transitions() {
+            let target = match target {
+                TransitionTarget::State(idx) => states[idx].id(),
+                TransitionTarget::History => HISTORY_NODE,
+            };
+            writeln!(
+                dot,
+                "    {} -> {} [label={}];",
+                quote(state.id()),
+                quote(target),
+                quote(&trigger.to_string())
+            )
+            .unwrap();
+        }
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+/// The name, and a second line with notes like `initial, rings 2s`.
+fn node_label(state: &State, initial: bool) -> String {
+    let mut notes = vec![];
+    if initial {
+        notes.push("initial".to_string());
+    }
+    if state.is_terminal() {
+        notes.push("terminal".to_string());
+    }
+    if let Some(ring) = state.ring_time() {
+        notes.push(format!("rings {:?}", ring));
+    }
+
+    if notes.is_empty() {
+        state.name().to_string()
+    } else {
+        format!("{}\n{}", state.name(), notes.join(", "))
+    }
+}
+
+/// Quotes as a DOT string, escaping quotes, backslashes and
+/// line breaks.
+fn quote(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::books::{from_path, from_str};
+    use regex::Regex;
+
+    fn edge_count(dot: &str) -> usize {
+        Regex::new(r#"(?m)^\s*"(?:[^"\\]|\\.)*" -> "(?:[^"\\]|\\.)*""#)
+            .unwrap()
+            .find_iter(dot)
+            .count()
+    }
+
+    #[test]
+    fn an_edge_for_every_transition() {
+        for path in &["test/testbook_full.yaml", "test/testbook_generated.yaml"] {
+            // given
+            let book = from_path(path).unwrap();
+            let transitions: usize = book
+                .states()
+                .iter()
+                .map(|state| state.transitions().len())
+                .sum();
+
+            // when
+            let dot = to_dot(&book);
+
+            // then
+            assert!(transitions > 0, "expected transitions in {}", path);
+            assert_eq!(edge_count(&dot), transitions, "in {}:\n{}", path, dot);
+        }
+    }
+
+    #[test]
+    fn labels_triggers_and_states() {
+        // given
+        let book = from_str(
+            "initial: idle
+states:
+  idle:
+    name: Waiting \"idle\"
+    ring: 2
+  talk:
+  bye:
+    terminal: true
+transitions:
+  idle:
+    pick_up: talk
+    timeout:
+      after: 5
+      to: bye
+  talk:
+    dial:
+      \"*\": $history
+    end: bye
+",
+        )
+        .unwrap();
+
+        // when
+        let dot = to_dot(&book);
+
+        // then
+        assert!(dot.starts_with("digraph phonebook {"), "{}", dot);
+        assert!(
+            dot.contains(r#""idle" [label="Waiting \"idle\"\ninitial, rings 2s", peripheries=1];"#),
+            "{}",
+            dot
+        );
+        assert!(
+            dot.contains(r#""bye" [label="bye\nterminal", peripheries=2];"#),
+            "{}",
+            dot
+        );
+        assert!(
+            dot.contains(r#""idle" -> "talk" [label="pick up"];"#),
+            "{}",
+            dot
+        );
+        assert!(
+            dot.contains(r#""idle" -> "bye" [label="timeout 5s"];"#),
+            "{}",
+            dot
+        );
+        assert!(
+            dot.contains(r#""talk" -> "$history" [label="type any digit"];"#),
+            "{}",
+            dot
+        );
+        assert!(dot.contains(r#""talk" -> "bye" [label="end"];"#), "{}", dot);
+        assert_eq!(edge_count(&dot), 4);
+    }
+}
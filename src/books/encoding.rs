@@ -0,0 +1,77 @@
+use failure::{bail, Error};
+use std::borrow::Cow;
+use std::str::from_utf8;
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Decodes phonebook source text from UTF-8 with or without BOM,
+/// or from Latin-1 if it is not valid UTF-8.
+///
+/// Latin-1 is only assumed if all non-ASCII bytes are printable
+/// Latin-1 characters, so that broken UTF-8 is an error rather than
+/// garbled text.
+pub fn decode(bytes: &[u8]) -> Result<Cow<'_, str>, Error> {
+    let bytes = if bytes.starts_with(UTF8_BOM) {
+        &bytes[UTF8_BOM.len()..]
+    } else {
+        bytes
+    };
+
+    match from_utf8(bytes) {
+        Ok(text) => Ok(Cow::Borrowed(text)),
+        Err(utf8_err) => {
+            if looks_like_latin1(bytes) {
+                Ok(Cow::Owned(bytes.iter().map(|&b| char::from(b)).collect()))
+            } else {
+                bail!("Phonebook is neither UTF-8 nor Latin-1: {}", utf8_err)
+            }
+        }
+    }
+}
+
+/// `true` if the bytes contain no C1 control characters, which
+/// are unlikely in Latin-1 text but common in UTF-8 sequences.
+fn looks_like_latin1(bytes: &[u8]) -> bool {
+    !bytes.iter().any(|b| (0x80..0xA0).contains(b))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn utf8_with_bom() {
+        // given
+        let bytes = b"\xEF\xBB\xBFinitial: gr\xC3\xBC\xC3\x9F";
+
+        // when
+        let text = decode(bytes).unwrap();
+
+        // then
+        assert_eq!(text, "initial: grüß");
+    }
+
+    #[test]
+    fn latin1() {
+        // given
+        let bytes = b"speech: \xE0 bient\xF4t, \xFCber";
+
+        // when
+        let text = decode(bytes).unwrap();
+
+        // then
+        assert_eq!(text, "speech: à bientôt, über");
+    }
+
+    #[test]
+    fn invalid_utf8_is_error() {
+        // given
+        let truncated_euro_sign = b"speech: 5 \xE2\x82";
+
+        // when
+        let result = decode(truncated_euro_sign);
+
+        // then
+        assert!(result.is_err(), "expected broken UTF-8 to be an error");
+    }
+}
@@ -0,0 +1,259 @@
+use super::Book;
+use crate::states::State;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+/// Makes a matrix where `[i][j]` is `true` if state `i` has a
+/// direct transition to state `j`.
+///
+/// Indexes are the indexes of the states in the book.
+pub fn adjacency_matrix(book: &Book) -> Vec<Vec<bool>> {
+    let states = book.states();
+    states
+        .iter()
+        .map(|state| {
+            let mut row = vec![false; states.len()];
+            for target in state.transition_targets() {
+                row[target] = true;
+            }
+            row
+        })
+        .collect()
+}
+
+/// Finds the indexes of all states that can be reached from the
+/// state at the given index, including that state itself.
+pub fn reachable_from(book: &Book, start_idx: usize) -> HashSet<usize> {
+    let states = book.states();
+    let mut reachable = HashSet::new();
+    let mut queue = VecDeque::new();
+
+    if start_idx < states.len() {
+        reachable.insert(start_idx);
+        queue.push_back(start_idx);
+    }
+
+    while let Some(idx) = queue.pop_front() {
+        for target in states[idx].transition_targets() {
+            if reachable.insert(target) {
+                queue.push_back(target);
+            }
+        }
+    }
+
+    reachable
+}
+
+/// Finds the indexes of all states that are not terminal but have
+/// no transitions, so they could never be left.
+pub fn dead_ends(book: &Book) -> Vec<usize> {
+    book.states()
+        .iter()
+        .enumerate()
+        .filter(|(_, state)| !state.is_terminal() && state.transitions().is_empty())
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+/// Estimates the time from the initial state to a terminal state
+/// without any input, following the longest branch.
+///
+/// `None` if a loop is reachable or the time in a state cannot be
+/// estimated.
+pub(crate) fn estimated_duration(book: &Book) -> Option<Duration> {
+    if book.states().is_empty() {
+        return None;
+    }
+
+    let mut visiting = vec![false; book.states().len()];
+    longest_path(book, 0, &mut visiting, &mut HashMap::new())
+}
+
+fn longest_path(
+    book: &Book,
+    idx: usize,
+    visiting: &mut [bool],
+    known: &mut HashMap<usize, Duration>,
+) -> Option<Duration> {
+    if let Some(known) = known.get(&idx) {
+        return Some(*known);
+    }
+
+    if visiting[idx] {
+        // loops may run forever
+        return None;
+    }
+
+    let state = &book.states()[idx];
+    let active = active_duration(book, state)?;
+    if state.is_terminal() {
+        return Some(active);
+    }
+
+    if state.has_history_transition() {
+        // going back may loop
+        return None;
+    }
+
+    visiting[idx] = true;
+    let branches = state
+        .transition_targets()
+        .map(|target| (Duration::from_secs(0), target))
+        .chain(state.timeout_transitions());
+    let mut longest: Option<Duration> = None;
+    for (wait, target) in branches {
+        let rest = longest_path(book, target, visiting, known)?;
+        longest = longest.max(Some(wait + rest));
+    }
+    visiting[idx] = false;
+
+    // a non-terminal state without transitions never finishes
+    let total = active + state.min_silence() + longest?;
+    known.insert(idx, total);
+    Some(total)
+}
+
+/// Time until the ringing and all non-looping sounds of a state
+/// are done, `None` if it cannot be known before speaking.
+fn active_duration(book: &Book, state: &State) -> Option<Duration> {
+    if !state.speech().is_empty() {
+        return None;
+    }
+
+    let mut active = state.ring_time().unwrap_or_default();
+    for spec in state.sounds().iter().map(|&idx| &book.sounds()[idx]) {
+        if !spec.is_loop() {
+            active = active.max(spec.playback_duration()?);
+        }
+    }
+    Some(active)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::books::from_str;
+
+    const BOOK: &str = "
+initial: ring
+states:
+  ring:
+  greeting:
+  goodbye:
+    terminal: true
+  unreachable:
+transitions:
+  ring:
+    pick_up: greeting
+  greeting:
+    dial:
+      1: goodbye
+    timeout:
+      after: 5
+      to: ring
+  unreachable:
+    pick_up: ring
+";
+
+    #[test]
+    fn matrix_of_known_book() {
+        // given
+        let book = from_str(BOOK).unwrap();
+        let idx = |id: &str| book.states().iter().position(|s| s.id() == id).unwrap();
+        let (ring, greeting, goodbye, unreachable) = (
+            idx("ring"),
+            idx("greeting"),
+            idx("goodbye"),
+            idx("unreachable"),
+        );
+
+        // when
+        let matrix = adjacency_matrix(&book);
+
+        // then
+        let edges: Vec<(usize, usize)> = (0..matrix.len())
+            .flat_map(|i| (0..matrix.len()).map(move |j| (i, j)))
+            .filter(|&(i, j)| matrix[i][j])
+            .collect();
+        let mut expected = vec![
+            (ring, greeting),
+            (greeting, goodbye),
+            (greeting, ring),
+            (unreachable, ring),
+        ];
+        expected.sort();
+        assert_eq!(edges, expected);
+    }
+
+    #[test]
+    fn reachable_from_initial() {
+        // given
+        let book = from_str(BOOK).unwrap();
+        let ids = |idxs: HashSet<usize>| {
+            let mut ids: Vec<&str> = idxs.into_iter().map(|i| book.states()[i].id()).collect();
+            ids.sort();
+            ids
+        };
+
+        // when
+        let from_ring = reachable_from(&book, 0);
+        let from_goodbye = reachable_from(
+            &book,
+            book.states()
+                .iter()
+                .position(|s| s.id() == "goodbye")
+                .unwrap(),
+        );
+
+        // then
+        assert_eq!(ids(from_ring), vec!["goodbye", "greeting", "ring"]);
+        assert_eq!(ids(from_goodbye), vec!["goodbye"]);
+    }
+
+    #[test]
+    fn no_estimate_with_loops() {
+        // given
+        let book = from_str(BOOK).unwrap();
+
+        // when
+        let estimate = book.estimated_duration();
+
+        // then
+        assert_eq!(estimate, None);
+    }
+
+    #[test]
+    fn estimate_takes_longest_branch() {
+        // given
+        let book = from_str(
+            "
+initial: ring
+states:
+  ring:
+    ring: 1
+  short:
+    terminal: true
+  long:
+    ring: 2
+  goodbye:
+    terminal: true
+transitions:
+  ring:
+    pick_up: short
+    timeout:
+      after: 1
+      to: long
+  long:
+    end: goodbye
+",
+        )
+        .unwrap();
+
+        // when
+        let estimate = book.estimated_duration();
+
+        // then
+        assert_eq!(estimate, Some(Duration::from_secs(4)));
+    }
+}
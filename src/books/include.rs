@@ -0,0 +1,366 @@
+//! Resolves `!include` tags in YAML phonebooks, so large
+//! phonebooks can be split across multiple files.
+//!
+//! `sounds: !include sounds.yaml` replaces the value with the
+//! contents of the file. `<<: !include more.yaml` merges the keys
+//! of the file into the enclosing mapping, and at the top level of
+//! a book also combines `states`, `transitions` and `sounds`. Every
+//! key, e.g. a state ID, may only be defined once when merging.
+use failure::{bail, format_err, Error};
+use serde_yaml::{Mapping, Value};
+use yaml_rust::parser::{Event, EventReceiver, Parser};
+use yaml_rust::scanner::TokenType;
+
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+/// Key that merges an included mapping into the enclosing one.
+const MERGE_KEY: &str = "<<";
+
+/// Sections of a book that are combined when merging at the top
+/// level, instead of being an error.
+const SECTIONS: [&str; 3] = ["states", "transitions", "sounds"];
+
+/// Resolves the `!include` tags in the given YAML source, with
+/// paths relative to `base_dir`.
+///
+/// Returns `None` if there is nothing to include, so the source
+/// can be used as is.
+pub fn resolve(source: &str, base_dir: &Path) -> Result<Option<String>, Error> {
+    let includes = find_includes(source);
+    if includes.is_empty() {
+        return Ok(None);
+    }
+
+    let document = include_all(source, includes, base_dir, &mut vec![])?;
+    Ok(Some(serde_yaml::to_string(&document)?))
+}
+
+/// `true` if the given YAML source has any `!include` tags.
+pub fn has_includes(source: &str) -> bool {
+    !find_includes(source).is_empty()
+}
+
+/// Loads a file with its includes resolved.
+///
+/// `stack` holds the files that are currently being included, to
+/// detect cycles.
+fn load(path: &Path, stack: &mut Vec<PathBuf>) -> Result<Value, Error> {
+    let canonical = path
+        .canonicalize()
+        .map_err(|e| format_err!("Could not include {:?}: {}", path, e))?;
+    if stack.contains(&canonical) {
+        bail!("{:?} includes itself", path);
+    }
+
+    let source = read_to_string(path)?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    stack.push(canonical);
+    let document = include_all(&source, find_includes(&source), base_dir, stack);
+    stack.pop();
+    document
+}
+
+fn include_all(
+    source: &str,
+    includes: Vec<Include>,
+    base_dir: &Path,
+    stack: &mut Vec<PathBuf>,
+) -> Result<Value, Error> {
+    let mut document: Value = serde_yaml::from_str(source)?;
+    let mut merges = vec![];
+
+    for Include { path, file } in includes {
+        let included = load(&base_dir.join(&file), stack)?;
+        let node = node_mut(&mut document, &path)
+            .ok_or_else(|| format_err!("Could not include {:?}", file))?;
+        *node = included;
+
+        if let Some(parent) = merge_parent(&path) {
+            if !merges.contains(&parent) {
+                merges.push(parent);
+            }
+        }
+    }
+
+    for parent in merges {
+        let top_level = parent.is_empty();
+        let mapping = match node_mut(&mut document, &parent) {
+            Some(Value::Mapping(mapping)) => mapping,
+            _ => bail!("Included files can only be merged into mappings"),
+        };
+        let merged = mapping
+            .remove(&Value::String(MERGE_KEY.to_string()))
+            .unwrap_or(Value::Null);
+        let merged = match merged {
+            Value::Sequence(merged) => merged,
+            merged => vec![merged],
+        };
+        for source in merged {
+            merge(mapping, source, top_level)?;
+        }
+    }
+
+    Ok(document)
+}
+
+/// Adds the entries of `source` to `target`, failing if an entry
+/// is already there.
+fn merge(target: &mut Mapping, source: Value, top_level: bool) -> Result<(), Error> {
+    let source = match source {
+        Value::Mapping(source) => source,
+        _ => bail!("Only mappings can be merged with {}", MERGE_KEY),
+    };
+
+    for (key, value) in source {
+        let section = scalar(&key).filter(|key| top_level && SECTIONS.contains(&key.as_str()));
+        match (target.get_mut(&key), value) {
+            (None, value) => {
+                target.insert(key, value);
+            }
+            (Some(Value::Mapping(existing)), Value::Mapping(entries)) if section.is_some() => {
+                for (id, entry) in entries {
+                    if existing.contains_key(&id) {
+                        bail!(
+                            "{} is defined more than once in {}",
+                            describe(&id),
+                            section.unwrap_or_default()
+                        );
+                    }
+                    existing.insert(id, entry);
+                }
+            }
+            (Some(_), _) => bail!("{} is defined more than once", describe(&key)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Path to the mapping that an include is merged into, if it is
+/// under the merge key, alone or in a list.
+fn merge_parent(path: &[Segment]) -> Option<Vec<Segment>> {
+    let merge_key = Segment::Key(MERGE_KEY.to_string());
+    match path {
+        [parent @ .., last] if *last == merge_key => Some(parent.to_vec()),
+        [parent @ .., key, Segment::Index(_)] if *key == merge_key => Some(parent.to_vec()),
+        _ => None,
+    }
+}
+
+fn node_mut<'a>(mut node: &'a mut Value, path: &[Segment]) -> Option<&'a mut Value> {
+    for segment in path {
+        node = match (node, segment) {
+            (Value::Mapping(mapping), Segment::Key(key)) => mapping
+                .iter_mut()
+                .find(|(k, _)| scalar(k).as_ref() == Some(key))
+                .map(|(_, value)| value)?,
+            (Value::Sequence(sequence), Segment::Index(idx)) => sequence.get_mut(*idx)?,
+            _ => return None,
+        };
+    }
+    Some(node)
+}
+
+/// Text of a scalar as written in the source, e.g. `1` for a
+/// dial pattern.
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(string) => Some(string.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(boolean) => Some(boolean.to_string()),
+        _ => None,
+    }
+}
+
+fn describe(key: &Value) -> String {
+    scalar(key)
+        .map(|key| format!("{:?}", key))
+        .unwrap_or_else(|| "Key".to_string())
+}
+
+/// A value tagged with `!include` and where it is in the document.
+struct Include {
+    path: Vec<Segment>,
+    /// Path of the file to include, relative to the including file.
+    file: String,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Finds all values tagged with `!include`.
+///
+/// Malformed YAML yields no includes, so that deserialization can
+/// report the error.
+fn find_includes(source: &str) -> Vec<Include> {
+    let mut finder = Finder {
+        stack: vec![],
+        includes: vec![],
+    };
+    match Parser::new(source.chars()).load(&mut finder, false) {
+        Ok(()) => finder.includes,
+        Err(_) => vec![],
+    }
+}
+
+struct Finder {
+    stack: Vec<Frame>,
+    includes: Vec<Include>,
+}
+
+enum Frame {
+    /// A mapping with the key of the value currently being
+    /// read, or `None` if the next scalar is a key.
+    Mapping(Option<String>),
+    /// A sequence with the index of the current item.
+    Sequence(usize),
+}
+
+impl Finder {
+    fn path(&self) -> Vec<Segment> {
+        self.stack
+            .iter()
+            .filter_map(|frame| match frame {
+                Frame::Mapping(key) => key.clone().map(Segment::Key),
+                Frame::Sequence(idx) => Some(Segment::Index(*idx)),
+            })
+            .collect()
+    }
+
+    /// The current value of a mapping or sequence is done.
+    fn value_done(&mut self) {
+        match self.stack.last_mut() {
+            Some(Frame::Mapping(key)) => *key = None,
+            Some(Frame::Sequence(idx)) => *idx += 1,
+            None => (),
+        }
+    }
+}
+
+impl EventReceiver for Finder {
+    fn on_event(&mut self, event: Event) {
+        match event {
+            Event::MappingStart(_) => self.stack.push(Frame::Mapping(None)),
+            Event::SequenceStart(_) => self.stack.push(Frame::Sequence(0)),
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+                self.value_done();
+            }
+            Event::Scalar(value, _, _, tag) => {
+                if let Some(Frame::Mapping(key @ None)) = self.stack.last_mut() {
+                    *key = Some(value);
+                    return;
+                }
+
+                if let Some(TokenType::Tag(handle, suffix)) = tag {
+                    if handle == "!" && suffix == "include" {
+                        self.includes.push(Include {
+                            path: self.path(),
+                            file: value,
+                        });
+                    }
+                }
+                self.value_done();
+            }
+            Event::Alias(_) => self.value_done(),
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::write;
+    use tempfile::tempdir;
+
+    const DEMO: &str = include_str!("../../resources/demo.yaml");
+
+    /// Splits the demo into the part before the sounds and the
+    /// sounds section, starting with `sounds:`.
+    fn split_demo() -> (&'static str, &'static str) {
+        let sounds_start = DEMO.find("\nsounds:\n").unwrap() + 1;
+        DEMO.split_at(sounds_start)
+    }
+
+    #[test]
+    fn included_sounds_are_like_inline_sounds() {
+        // given
+        let dir = tempdir().unwrap();
+        let (main, sounds) = split_demo();
+        // without the `sounds:` line and one level of indentation
+        let sounds: String = sounds
+            .lines()
+            .skip(1)
+            .map(|line| format!("{}\n", line.get(2..).unwrap_or("")))
+            .collect();
+        write(dir.path().join("sounds.yaml"), sounds).unwrap();
+        let main = format!("{}sounds: !include sounds.yaml\n", main);
+
+        // when
+        let resolved = resolve(&main, dir.path()).unwrap().unwrap();
+
+        // then
+        let resolved: Value = serde_yaml::from_str(&resolved).unwrap();
+        let original: Value = serde_yaml::from_str(DEMO).unwrap();
+        assert_eq!(resolved, original);
+    }
+
+    #[test]
+    fn merged_book_is_like_single_file() {
+        // given
+        let dir = tempdir().unwrap();
+        let (main, sounds) = split_demo();
+        write(dir.path().join("part.yaml"), sounds).unwrap();
+        let main = format!("<<: !include part.yaml\n{}", main);
+
+        // when
+        let resolved = resolve(&main, dir.path()).unwrap().unwrap();
+
+        // then
+        let resolved: Value = serde_yaml::from_str(&resolved).unwrap();
+        let original: Value = serde_yaml::from_str(DEMO).unwrap();
+        assert_eq!(resolved, original);
+    }
+
+    #[test]
+    fn duplicate_state_is_error() {
+        // given
+        let dir = tempdir().unwrap();
+        write(
+            dir.path().join("more.yaml"),
+            "states:\n  intro:\n    name: Other\n",
+        )
+        .unwrap();
+        let main = "<<: !include more.yaml\ninitial: intro\nstates:\n  intro:\n";
+
+        // when
+        let error = resolve(main, dir.path()).unwrap_err().to_string();
+
+        // then
+        assert_eq!(error, "\"intro\" is defined more than once in states");
+    }
+
+    #[test]
+    fn cyclic_include_is_error() {
+        // given
+        let dir = tempdir().unwrap();
+        write(dir.path().join("a.yaml"), "b: !include b.yaml\n").unwrap();
+        write(dir.path().join("b.yaml"), "a: !include a.yaml\n").unwrap();
+
+        // when
+        let error = resolve("a: !include a.yaml\n", dir.path()).unwrap_err();
+
+        // then
+        assert!(error.to_string().contains("includes itself"), "{}", error);
+    }
+
+    #[test]
+    fn nothing_to_include() {
+        assert!(resolve(DEMO, Path::new("")).unwrap().is_none());
+    }
+}
@@ -0,0 +1,171 @@
+use super::spec::{Id, Location, Locations};
+
+use std::path::Path;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+
+/// Finds where states and transitions are defined in the given
+/// YAML source of a phonebook.
+///
+/// Malformed YAML yields the locations found up to the error.
+pub fn locate(source: &str, file: Option<&Path>) -> Locations {
+    let mut locator = Locator {
+        stack: vec![],
+        file,
+        locations: Locations::default(),
+    };
+    Parser::new(source.chars()).load(&mut locator, false).ok();
+    locator.locations
+}
+
+struct Locator<'a> {
+    stack: Vec<Frame>,
+    file: Option<&'a Path>,
+    locations: Locations,
+}
+
+enum Frame {
+    /// A mapping with the key of the value currently being
+    /// read, or `None` if the next scalar is a key.
+    Mapping(Option<String>),
+    Sequence,
+}
+
+impl<'a> Locator<'a> {
+    /// Records the location of a key in the second level of
+    /// the document, e.g. a state ID under `states`, and of
+    /// single transitions below `transitions`.
+    fn found_key(&mut self, key: &str, mark: Marker) {
+        let location = Location {
+            file: self.file.map(Path::to_path_buf),
+            line: mark.line(),
+            column: mark.col() + 1,
+        };
+
+        // keys of the enclosing mappings, without the new key
+        let path: Vec<&str> = self
+            .stack
+            .iter()
+            .take(self.stack.len() - 1)
+            .map(|frame| match frame {
+                Frame::Mapping(Some(key)) => Some(key.as_str()),
+                _ => None,
+            })
+            .collect::<Option<_>>()
+            .unwrap_or_default();
+
+        match path.as_slice() {
+            ["states"] => {
+                if self.locations.states.contains_key(&Id::new(key)) {
+                    self.locations
+                        .duplicate_states
+                        .push((Id::new(key), location.clone()));
+                }
+                self.locations.states.insert(Id::new(key), location);
+            }
+            ["transitions"] => {
+                self.locations.transitions.insert(Id::new(key), location);
+            }
+            ["sounds"] => {
+                self.locations.sounds.insert(Id::new(key), location);
+            }
+            ["transitions", state] => {
+                let state = Id::new(*state);
+                self.locations
+                    .transition_keys
+                    .insert((state, snake_case(key)), location);
+            }
+            ["transitions", state, "dial"] => {
+                let state = Id::new(*state);
+                self.locations
+                    .transition_keys
+                    .insert((state, format!("dial.{}", key)), location);
+            }
+            _ => (),
+        }
+    }
+
+    /// The value of the current mapping key is done.
+    fn value_done(&mut self) {
+        if let Some(Frame::Mapping(key)) = self.stack.last_mut() {
+            *key = None;
+        }
+    }
+}
+
+impl<'a> MarkedEventReceiver for Locator<'a> {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::MappingStart(_) => self.stack.push(Frame::Mapping(None)),
+            Event::SequenceStart(_) => self.stack.push(Frame::Sequence),
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+                self.value_done();
+            }
+            Event::Scalar(value, ..) => match self.stack.last() {
+                Some(Frame::Mapping(None)) => {
+                    self.found_key(&value, mark);
+                    self.stack.pop();
+                    self.stack.push(Frame::Mapping(Some(value)));
+                }
+                _ => self.value_done(),
+            },
+            Event::Alias(_) => self.value_done(),
+            _ => (),
+        }
+    }
+}
+
+/// Converts camel case keys like `pickUp` to snake case.
+fn snake_case(key: &str) -> String {
+    let mut snake = String::with_capacity(key.len() + 1);
+    for c in key.chars() {
+        if c.is_ascii_uppercase() {
+            snake.push('_');
+            snake.push(c.to_ascii_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn locate_states_and_transitions() {
+        // given
+        let source = "initial: ring
+states:
+  ring:
+    ring: 1
+  talk:
+    sounds:
+      - hello
+transitions:
+  ring:
+    pickUp: talk
+    dial:
+      1: ring
+sounds:
+  hello:
+    speech: Hello
+";
+
+        // when
+        let locations = locate(source, None);
+
+        // then
+        let line = |loc: Option<&Location>| loc.map(|l| (l.line, l.column));
+        assert_eq!(line(locations.state(&Id::new("ring"))), Some((3, 3)));
+        assert_eq!(line(locations.state(&Id::new("talk"))), Some((5, 3)));
+        assert_eq!(line(locations.transitions(&Id::new("ring"))), Some((9, 3)));
+        assert_eq!(locations.state(&Id::new("pick_up")), None);
+        let ring = Id::new("ring");
+        assert_eq!(line(locations.transition(&ring, "pick_up")), Some((10, 5)));
+        assert_eq!(line(locations.transition(&ring, "dial.1")), Some((12, 7)));
+        assert_eq!(line(locations.sound(&Id::new("hello"))), Some((14, 3)));
+    }
+}
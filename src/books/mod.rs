@@ -1,31 +1,239 @@
+mod archive;
 mod compile;
+mod convert;
+mod dot;
+mod encoding;
+mod graph;
+mod include;
+mod locate;
+mod remote;
 pub(crate) mod spec;
-pub use compile::{compile, Book};
+pub mod tts_cache;
+mod variables;
+mod voices;
+mod watcher;
+pub use archive::{from_fpz_path, from_zip};
+pub(crate) use compile::expand_path;
+pub use compile::{
+    compile, compile_relative_to, compile_unprepared_relative_to, compile_with_progress, Book,
+    CompileError,
+};
+pub use dot::to_dot;
 use failure::Error;
+pub use graph::{adjacency_matrix, dead_ends, reachable_from};
+pub use remote::{from_url, from_url_with_overrides};
+use serde_json;
 use serde_yaml;
+pub use spec::Location;
+use std::collections::HashMap;
+use std::io::Read;
 use std::path::Path;
+use toml;
+pub use variables::load_variables;
+pub(crate) use voices::voice_exists;
+pub use watcher::FileWatcher;
 
+/// Settings from outside a phonebook that take precedence over
+/// the ones in the phonebook when loading it.
+#[derive(Clone, Debug, Default)]
+pub struct Overrides {
+    /// Values for placeholders like `{name}` in speech.
+    pub variables: HashMap<String, String>,
+    /// If `true`, states without transitions are only warned
+    /// about, as if the phonebook set `allow_dead_ends: true`.
+    pub allow_dead_ends: bool,
+}
+
+impl Overrides {
+    fn apply(&self, spec: &mut spec::Book) {
+        spec.variables.extend(self.variables.clone());
+        if self.allow_dead_ends {
+            spec.allow_dead_ends = Some(true);
+        }
+    }
+}
+
+/// Loads and compiles a phonebook from a YAML file, or from
+/// a ZIP archive if the file has the `.zip` or `.fpz` extension.
+///
+/// Relative sound file paths are relative to the directory
+/// containing the file.
 pub fn from_path(source_file: impl AsRef<Path>) -> Result<Book, Error> {
-    file::load(source_file).and_then(compile)
+    from_path_with_overrides(source_file, &Overrides::default())
+}
+
+/// Like `from_path`, with values for placeholders like `{name}` in
+/// speech. The values take precedence over the `variables` in the
+/// phonebook.
+pub fn from_path_with_variables(
+    source_file: impl AsRef<Path>,
+    variables: HashMap<String, String>,
+) -> Result<Book, Error> {
+    from_path_with_overrides(
+        source_file,
+        &Overrides {
+            variables,
+            ..Overrides::default()
+        },
+    )
+}
+
+/// Like `from_path`, with settings that take precedence over the
+/// ones in the phonebook.
+pub fn from_path_with_overrides(
+    source_file: impl AsRef<Path>,
+    overrides: &Overrides,
+) -> Result<Book, Error> {
+    let source_file = source_file.as_ref();
+    let is_zip = source_file
+        .extension()
+        .map(|ext| {
+            ext.eq_ignore_ascii_case("zip") || ext.eq_ignore_ascii_case(archive::FPZ_EXTENSION)
+        })
+        .unwrap_or(false);
+
+    if is_zip {
+        archive::from_zip_with_overrides(source_file, overrides)
+    } else {
+        let base_dir = source_file.parent().unwrap_or_else(|| Path::new(""));
+        file::load(source_file).and_then(|mut spec| {
+            overrides.apply(&mut spec);
+            compile_relative_to(spec, base_dir)
+        })
+    }
+}
+
+/// Loads and compiles a phonebook from a YAML file, resolving
+/// relative sound file paths against the given directory.
+pub fn from_path_with_base(yaml_path: &Path, base_dir: &Path) -> Result<Book, Error> {
+    file::load(yaml_path).and_then(|spec| compile_relative_to(spec, base_dir))
 }
 
 pub fn from_str(source_string: impl AsRef<str>) -> Result<Book, Error> {
-    let book = serde_yaml::from_str(source_string.as_ref())?;
-    compile(book)
+    from_bytes(source_string.as_ref().as_bytes())
+}
+
+/// Compiles YAML source in UTF-8, optionally with a byte order
+/// mark, or in Latin-1.
+pub fn from_bytes(source: &[u8]) -> Result<Book, Error> {
+    from_bytes_with_overrides(source, &Overrides::default())
+}
+
+/// Like `from_bytes`, with settings that take precedence over the
+/// ones in the phonebook.
+pub fn from_bytes_with_overrides(source: &[u8], overrides: &Overrides) -> Result<Book, Error> {
+    parse(&encoding::decode(source)?, None).and_then(|mut spec| {
+        overrides.apply(&mut spec);
+        compile(spec)
+    })
+}
+
+/// Compiles a phonebook from JSON source.
+///
+/// Keys are the same as in YAML, including the camel case
+/// aliases, e.g. `{"initial": "a", "states": {"a": null}}`.
+/// Dial patterns are string keys in JSON, e.g. `{"dial": {"1": "b"}}`
+/// for `dial: { 1: b }` in YAML.
+pub fn from_json_str(source: impl AsRef<str>) -> Result<Book, Error> {
+    parse_json(source.as_ref(), None).and_then(compile)
+}
+
+/// Loads and compiles a phonebook from a JSON file.
+///
+/// Relative sound file paths are relative to the directory
+/// containing the file.
+pub fn from_json_path(source_file: impl AsRef<Path>) -> Result<Book, Error> {
+    let source_file = source_file.as_ref();
+    let base_dir = source_file.parent().unwrap_or_else(|| Path::new(""));
+    file::load_json(source_file).and_then(|spec| compile_relative_to(spec, base_dir))
+}
+
+/// Compiles a phonebook from TOML source.
+///
+/// Keys are the same as in YAML, states, transitions and sounds
+/// are tables keyed by ID, e.g. `[states.intro]`. Since TOML has no
+/// null, states without configuration are empty tables. Errors in
+/// transitions are not located in the source.
+pub fn from_toml_str(source: impl AsRef<str>) -> Result<Book, Error> {
+    parse_toml(source.as_ref()).and_then(compile)
+}
+
+/// Loads and compiles a phonebook from a TOML file.
+///
+/// Relative sound file paths are relative to the directory
+/// containing the file.
+pub fn from_toml_path(source_file: impl AsRef<Path>) -> Result<Book, Error> {
+    let source_file = source_file.as_ref();
+    let base_dir = source_file.parent().unwrap_or_else(|| Path::new(""));
+    file::load_toml(source_file).and_then(|spec| compile_relative_to(spec, base_dir))
+}
+
+/// Reads YAML until the end of the given reader, e.g. stdin,
+/// and compiles it. Like with `from_bytes`, the source does not
+/// need to be UTF-8.
+pub fn from_reader(reader: impl Read) -> Result<Book, Error> {
+    from_reader_with_overrides(reader, &Overrides::default())
 }
 
-/// pub(crate) for testing, loads YAML files
+/// Like `from_reader`, with settings that take precedence over the
+/// ones in the phonebook.
+pub fn from_reader_with_overrides(
+    mut reader: impl Read,
+    overrides: &Overrides,
+) -> Result<Book, Error> {
+    let mut source = Vec::new();
+    reader.read_to_end(&mut source)?;
+    from_bytes_with_overrides(&source, overrides)
+}
+
+/// Deserializes the YAML source and remembers where states and
+/// transitions are defined, for error messages.
+///
+/// Values tagged with `!include` are replaced with the contents
+/// of the file, relative to the file or the working directory.
+fn parse(source: &str, file: Option<&Path>) -> Result<spec::Book, Error> {
+    let base_dir = file.and_then(Path::parent).unwrap_or_else(|| Path::new(""));
+    let mut book: spec::Book = match include::resolve(source, base_dir)? {
+        Some(resolved) => serde_yaml::from_str(&resolved)?,
+        None => serde_yaml::from_str(source)?,
+    };
+    book.locations = locate::locate(source, file);
+    Ok(book)
+}
+
+/// Deserializes JSON source and remembers where states and
+/// transitions are defined, for error messages.
+fn parse_json(source: &str, file: Option<&Path>) -> Result<spec::Book, Error> {
+    let mut book: spec::Book = serde_json::from_str(source)?;
+    // JSON is also valid YAML, so it can be located the same way
+    book.locations = locate::locate(source, file);
+    Ok(book)
+}
+
+/// Deserializes TOML source, without locations.
+fn parse_toml(source: &str) -> Result<spec::Book, Error> {
+    Ok(toml::from_str(source)?)
+}
+
+/// pub(crate) for testing, loads YAML, JSON and TOML files
 pub(crate) mod file {
-    use super::spec;
+    use super::{parse, parse_json, parse_toml, spec};
     use failure::Error;
-    use serde_yaml::from_reader;
-    use std::fs::File;
+    use std::fs::read_to_string;
     use std::path::Path;
 
     pub fn load<P: AsRef<Path>>(source_file: P) -> Result<spec::Book, Error> {
-        let mut source_file = File::open(source_file.as_ref())?;
-        let book = from_reader(&mut source_file)?;
-        Ok(book)
+        let source = read_to_string(source_file.as_ref())?;
+        parse(&source, Some(source_file.as_ref()))
+    }
+
+    pub fn load_json<P: AsRef<Path>>(source_file: P) -> Result<spec::Book, Error> {
+        let source = read_to_string(source_file.as_ref())?;
+        parse_json(&source, Some(source_file.as_ref()))
+    }
+
+    pub fn load_toml<P: AsRef<Path>>(source_file: P) -> Result<spec::Book, Error> {
+        parse_toml(&read_to_string(source_file.as_ref())?)
     }
 }
 
@@ -42,6 +250,49 @@ mod test {
         assert_eq!(states[0].name(), "ring");
     }
 
+    #[test]
+    fn can_compile_from_reader() {
+        let yaml: &[u8] = include_bytes!("../../test/testbook_only_states.yaml");
+        let book = from_reader(yaml).unwrap();
+        let states = book.states();
+
+        assert_eq!(states[0].name(), "announcement");
+    }
+
+    #[test]
+    fn can_compile_from_latin1_bytes() {
+        let yaml = b"initial: start\nstates:\n  start:\n    name: \xC4rger\n    terminal: true\n";
+
+        let book = from_bytes(yaml).unwrap();
+
+        assert_eq!(book.states()[0].name(), "Ärger");
+    }
+
+    #[test]
+    fn can_compile_from_latin1_reader() {
+        let yaml: &[u8] =
+            b"initial: start\nstates:\n  start:\n    name: \xC4rger\n    terminal: true\n";
+
+        let book = from_reader(yaml).unwrap();
+
+        assert_eq!(book.states()[0].name(), "Ärger");
+    }
+
+    #[test]
+    fn can_compile_camel_case() {
+        let yaml = "bookId: camels\n\
+                    initial: down\n\
+                    states:\n  down:\n  up:\n\
+                    transitions:\n  down:\n    pickUp: up\n  up:\n    hangUp: down\n";
+
+        let book = from_str(yaml).unwrap();
+        let states = book.states();
+
+        assert_eq!(book.book_id(), Some("camels"));
+        assert!(states[0].transition_for_input(Input::pick_up()).is_some());
+        assert!(states[1].transition_for_input(Input::hang_up()).is_some());
+    }
+
     #[test]
     fn can_compile_example() {
         let book = from_path("test/testbook_full.yaml").unwrap();
@@ -50,6 +301,189 @@ mod test {
         assert_eq!(states[0].name(), "announcement");
     }
 
+    #[test]
+    fn sound_paths_are_relative_to_phonebook() {
+        // given
+        let dir = tempfile::tempdir().unwrap();
+        let pb = dir.path().join("pb");
+        std::fs::create_dir(&pb).unwrap();
+        let yaml = pb.join("phonebook.yaml");
+        std::fs::write(
+            &yaml,
+            "initial: ring\nstates:\n  ring:\n    terminal: true\n    sounds:\n      - ring\n\
+             sounds:\n  ring:\n    file: assets/ring.mp3\n",
+        )
+        .unwrap();
+
+        // when
+        let err = from_path(&yaml).unwrap_err().to_string();
+
+        // then
+        let expected = pb.join("assets/ring.mp3");
+        assert!(
+            err.contains(expected.to_str().unwrap()),
+            "Expected error for {:?}, got: {}",
+            expected,
+            err
+        );
+    }
+
+    #[test]
+    fn json_compiles_like_yaml() {
+        for (json, yaml) in &[
+            (
+                include_str!("../../test/testbook_full.json"),
+                include_str!("../../test/testbook_full.yaml"),
+            ),
+            (
+                include_str!("../../test/testbook_only_states.json"),
+                include_str!("../../test/testbook_only_states.yaml"),
+            ),
+        ] {
+            let from_json = from_json_str(json).unwrap();
+            let from_yaml = from_str(yaml).unwrap();
+
+            assert_eq!(describe(&from_json), describe(&from_yaml));
+        }
+    }
+
+    #[test]
+    fn split_book_compiles_like_single_file() {
+        // given
+        let dir = tempfile::tempdir().unwrap();
+        let yaml = include_str!("../../test/testbook_full.yaml");
+        let transitions_start = yaml.find("\ntransitions:\n").unwrap() + 1;
+        let (main, transitions) = yaml.split_at(transitions_start);
+        let main_path = dir.path().join("main.yaml");
+        std::fs::write(
+            &main_path,
+            format!("<<: !include transitions.yaml\n{}", main),
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("transitions.yaml"), transitions).unwrap();
+
+        // when
+        let split = from_path(&main_path).unwrap();
+        let single = from_str(yaml).unwrap();
+
+        // then
+        assert_eq!(describe(&split), describe(&single));
+    }
+
+    #[test]
+    fn toml_round_trip_compiles_like_yaml() {
+        // given
+        let yaml = include_str!("../../test/testbook_full.yaml");
+        // TOML only has string keys, so convert the JSON equivalent
+        // that has quoted digits for dial patterns
+        let json = include_str!("../../test/testbook_full.json");
+        let value: toml::Value = serde_json::from_str(json).unwrap();
+        let toml = toml::to_string(&value).unwrap();
+
+        // when
+        let from_toml = from_toml_str(&toml).unwrap();
+        let from_yaml = from_str(yaml).unwrap();
+
+        // then
+        assert_eq!(describe(&from_toml), describe(&from_yaml));
+    }
+
+    #[test]
+    fn can_compile_toml_with_empty_states() {
+        // given
+        let toml = "initial = \"down\"\n\
+                    [states.down]\n\
+                    [states.up]\n\
+                    name = \"Up\"\n\
+                    [transitions.down]\n\
+                    pickUp = \"up\"\n\
+                    [transitions.up.dial]\n\
+                    1 = \"down\"\n";
+
+        // when
+        let book = from_toml_str(toml).unwrap();
+
+        // then
+        let states = book.states();
+        assert_eq!(states[0].id(), "down");
+        assert_eq!(states[1].name(), "Up");
+        assert_eq!(states[0].transition_for_input(Input::pick_up()), Some(1));
+        assert_eq!(
+            states[1].transition_for_input(Input::digit(1).unwrap()),
+            Some(0)
+        );
+    }
+
+    /// Describes states with transition targets by ID, since the
+    /// order of states is not stable across compilations.
+    fn describe(book: &Book) -> Vec<String> {
+        let id = |idx: Option<usize>| idx.map(|idx| book.states()[idx].id());
+        let mut states: Vec<String> = book
+            .states()
+            .iter()
+            .map(|s| {
+                let dial: Vec<_> = (0..10)
+                    .map(|d| id(s.transition_for_input(Input::digit(d).unwrap())))
+                    .collect();
+                format!(
+                    "{} {:?} {:?} {:?} {:?}",
+                    s.id(),
+                    s.name(),
+                    s.speech(),
+                    dial,
+                    id(s.transition_end())
+                )
+            })
+            .collect();
+        states.sort();
+        states
+    }
+
+    #[test]
+    fn can_compile_json_example() {
+        let book = from_json_path("test/testbook_full.json").unwrap();
+        let states = book.states();
+
+        assert_eq!(states[0].name(), "announcement");
+        assert!(states[0]
+            .transition_for_input(Input::digit(1).unwrap())
+            .is_some());
+    }
+
+    #[test]
+    fn json_error_contains_line_of_transition() {
+        // given
+        let json = "{
+  \"initial\": \"ring\",
+  \"states\": { \"ring\": null, \"talk\": null },
+  \"transitions\": {
+    \"ring\": { \"pick_up\": \"talk\" },
+    \"talk\": { \"hang_up\": \"nowhere\" }
+  }
+}";
+
+        // when
+        let message = from_json_str(json).unwrap_err().to_string();
+
+        // then
+        assert!(
+            message.starts_with("line 6, column 15: "),
+            "expected line of transitions in error message, got: {}",
+            message
+        );
+    }
+
+    #[test]
+    fn json_syntax_error_has_line() {
+        // when
+        let message = from_json_str("{\n  \"initial\": \"a\",\n  \"states\": {\n}")
+            .unwrap_err()
+            .to_string();
+
+        // then
+        assert!(message.contains("line 4"), "got: {}", message);
+    }
+
     #[test]
     fn can_compile_generated() {
         let book = from_path("test/testbook_generated.yaml").unwrap();
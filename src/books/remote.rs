@@ -0,0 +1,331 @@
+//! Loads phonebooks from HTTP and HTTPS URLs, e.g. for networked
+//! installations that get their phonebook from a server.
+use super::archive::from_remote_zip;
+use super::{compile, encoding, include, parse, spec, Book, Overrides};
+
+use failure::{bail, Error};
+use log::debug;
+use ureq::{AgentBuilder, ErrorKind, Response, Transport};
+
+use std::error::Error as _;
+use std::io::{self, copy, BufReader, Read};
+use std::path::Path;
+use std::time::Duration;
+
+/// Redirects to follow before giving up.
+const MAX_REDIRECTS: u32 = 5;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// YAML is read into memory before parsing, so larger phonebooks
+/// are rejected rather than risking to run out of memory.
+const MAX_YAML_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Downloads and compiles the phonebook at the given URL.
+///
+/// The format is chosen by the `Content-Type` of the response,
+/// falling back to the extension in the URL for generic types like
+/// `application/octet-stream`. YAML is assumed if neither is known.
+/// JSON and ZIP archives are streamed rather than downloaded into
+/// memory first, YAML must not be larger than 16 MiB.
+///
+/// Relative sound file paths are relative to the working directory,
+/// or to the root of the archive for ZIP archives. Since the
+/// phonebook comes from another machine, it cannot use `!include`
+/// to read local files.
+pub fn from_url(url: &str) -> Result<Book, Error> {
+    from_url_with_overrides(url, &Overrides::default())
+}
+
+/// Like `from_url`, with settings that take precedence over the
+/// ones in the phonebook.
+pub fn from_url_with_overrides(url: &str, overrides: &Overrides) -> Result<Book, Error> {
+    let response = get(url)?;
+    let format = Format::of(response.header("Content-Type"), url);
+    debug!("loading {:?} phonebook from {}", format, url);
+
+    let mut body = response.into_reader();
+    match format {
+        Format::Yaml => {
+            let source = read_limited(body, MAX_YAML_SIZE)?;
+            let source = encoding::decode(&source)?;
+            if include::has_includes(&source) {
+                bail!("Phonebook from {} cannot use !include", url);
+            }
+            let mut book = parse(&source, Some(Path::new(url)))?;
+            overrides.apply(&mut book);
+            compile(book)
+        }
+        Format::Json => {
+            let mut book: spec::Book = serde_json::from_reader(BufReader::new(body))?;
+            overrides.apply(&mut book);
+            compile(book)
+        }
+        Format::Zip => {
+            // extracting needs to seek, so the archive goes to a file
+            let mut archive = tempfile::Builder::new().suffix(".zip").tempfile()?;
+            copy(&mut body, &mut archive)?;
+            from_remote_zip(archive.path(), overrides)
+        }
+    }
+}
+
+/// Reads everything, failing if there are more than `limit` bytes.
+fn read_limited(reader: impl Read, limit: u64) -> Result<Vec<u8>, Error> {
+    let mut data = vec![];
+    reader.take(limit + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > limit {
+        bail!("Phonebook is larger than the limit of {} bytes", limit);
+    }
+    Ok(data)
+}
+
+fn get(url: &str) -> Result<Response, Error> {
+    let agent = AgentBuilder::new()
+        .redirects(MAX_REDIRECTS)
+        .timeout_connect(CONNECT_TIMEOUT)
+        .build();
+
+    let response = match agent.get(url).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(status, response)) => bail!(
+            "Server responded with status {} {} for {}",
+            status,
+            response.status_text(),
+            url
+        ),
+        Err(ureq::Error::Transport(transport)) => match transport.kind() {
+            ErrorKind::TooManyRedirects => bail!(
+                "Gave up on {} after more than {} redirects",
+                url,
+                MAX_REDIRECTS
+            ),
+            _ if is_tls_failure(&transport) => {
+                bail!("TLS validation failed for {}: {}", url, transport)
+            }
+            _ => bail!("Could not fetch {}: {}", url, transport),
+        },
+    };
+
+    if response.status() != 200 {
+        bail!(
+            "Expected status 200 for {}, but got {} {}",
+            url,
+            response.status(),
+            response.status_text()
+        );
+    }
+
+    Ok(response)
+}
+
+/// TLS errors, e.g. untrusted certificates, fail the connection
+/// with invalid data.
+fn is_tls_failure(transport: &Transport) -> bool {
+    transport.kind() == ErrorKind::ConnectionFailed
+        && transport
+            .source()
+            .and_then(|source| source.downcast_ref::<io::Error>())
+            .map(|source| source.kind() == io::ErrorKind::InvalidData)
+            .unwrap_or(false)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Yaml,
+    Json,
+    Zip,
+}
+
+impl Format {
+    fn of(content_type: Option<&str>, url: &str) -> Self {
+        let mime = content_type
+            .and_then(|content_type| content_type.split(';').next())
+            .map(|mime| mime.trim().to_ascii_lowercase());
+
+        match mime.as_deref() {
+            Some("application/json") | Some("text/json") => Format::Json,
+            Some("application/zip") | Some("application/x-zip-compressed") => Format::Zip,
+            Some(mime) if mime.ends_with("+json") => Format::Json,
+            Some(mime) if mime.ends_with("+zip") => Format::Zip,
+            Some("application/octet-stream") | Some("text/plain") | None => Self::of_extension(url),
+            Some(_) => Format::Yaml,
+        }
+    }
+
+    fn of_extension(url: &str) -> Self {
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+        let extension = Path::new(path)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("json") => Format::Json,
+            Some("zip") | Some("fpz") => Format::Zip,
+            _ => Format::Yaml,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Answers every request on a local port with the given
+    /// response and returns the address.
+    fn serve(response: String) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                // requests are small enough to arrive at once
+                let _ = stream.read(&mut [0; 4096]);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        addr
+    }
+
+    fn respond(status: &str, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            headers,
+            body.len(),
+            body
+        )
+    }
+
+    #[test]
+    fn yaml_from_url() {
+        // given
+        let addr = serve(respond(
+            "200 OK",
+            "Content-Type: application/yaml\r\n",
+            "initial: ring\nstates:\n  ring:\n    ring: 0.5\n    terminal: true\n",
+        ));
+
+        // when
+        let book = from_url(&format!("http://{}/exhibit.yaml", addr)).unwrap();
+
+        // then
+        assert_eq!(book.states()[0].id(), "ring");
+    }
+
+    #[test]
+    fn reading_more_than_limit_is_error() {
+        // given
+        let data = b"initial: ring";
+
+        // when
+        let at_limit = read_limited(&data[..], data.len() as u64);
+        let over_limit = read_limited(&data[..], data.len() as u64 - 1);
+
+        // then
+        assert_eq!(at_limit.unwrap(), data);
+        assert!(over_limit.is_err(), "expected data over limit to fail");
+    }
+
+    #[test]
+    fn overrides_apply_to_book_from_url() {
+        // given
+        let addr = serve(respond(
+            "200 OK",
+            "Content-Type: application/yaml\r\n",
+            "initial: stuck\nstates:\n  stuck:\n",
+        ));
+        let url = format!("http://{}/exhibit.yaml", addr);
+        let overrides = Overrides {
+            allow_dead_ends: true,
+            ..Overrides::default()
+        };
+
+        // when
+        let without_overrides = from_url(&url);
+        let with_overrides = from_url_with_overrides(&url, &overrides);
+
+        // then
+        assert!(without_overrides.is_err(), "expected dead end to fail");
+        assert!(with_overrides.is_ok(), "expected dead end to be allowed");
+    }
+
+    #[test]
+    fn include_from_url_is_error() {
+        // given
+        let addr = serve(respond(
+            "200 OK",
+            "Content-Type: application/yaml\r\n",
+            "initial: ring\nstates: !include /etc/hostname\n",
+        ));
+
+        // when
+        let error = from_url(&format!("http://{}/exhibit.yaml", addr)).unwrap_err();
+
+        // then
+        assert!(error.to_string().contains("!include"), "{}", error);
+    }
+
+    #[test]
+    fn format_by_content_type_then_extension() {
+        assert_eq!(
+            Format::of(Some("application/json; charset=utf-8"), "x.yaml"),
+            Format::Json
+        );
+        assert_eq!(Format::of(Some("text/yaml"), "x.json"), Format::Yaml);
+        assert_eq!(Format::of(Some("application/zip"), "x"), Format::Zip);
+        assert_eq!(
+            Format::of(Some("application/octet-stream"), "https://a/b.fpz?v=2"),
+            Format::Zip
+        );
+        assert_eq!(Format::of(None, "https://a/b.json"), Format::Json);
+        assert_eq!(Format::of(None, "https://a/b"), Format::Yaml);
+    }
+
+    #[test]
+    fn status_other_than_ok_is_error() {
+        // given
+        let addr = serve(respond("404 Not Found", "", "nope"));
+
+        // when
+        let error = from_url(&format!("http://{}/missing.yaml", addr)).unwrap_err();
+
+        // then
+        assert!(
+            error.to_string().contains("status 404 Not Found"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn redirect_loop_is_error() {
+        // given
+        let addr = serve(respond("302 Found", "Location: /again\r\n", ""));
+
+        // when
+        let error = from_url(&format!("http://{}/", addr)).unwrap_err();
+
+        // then
+        assert!(error.to_string().contains("redirects"), "{}", error);
+    }
+
+    #[test]
+    fn tls_failure_is_error() {
+        // given, a server that does not speak TLS
+        let addr = serve(respond("200 OK", "", "initial: a\nstates:\n  a:\n"));
+
+        // when
+        let error = from_url(&format!("https://{}/", addr)).unwrap_err();
+
+        // then
+        assert!(
+            error.to_string().starts_with("TLS validation failed"),
+            "{}",
+            error
+        );
+    }
+}
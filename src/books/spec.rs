@@ -1,6 +1,7 @@
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fmt;
+use std::path::PathBuf;
 
 #[derive(PartialEq, Eq, Hash, Clone, Debug, Deserialize)]
 #[serde(transparent)]
@@ -18,17 +19,192 @@ impl fmt::Display for Id {
     }
 }
 
-#[derive(Deserialize, Debug)]
+/// Keys of multiple words are in snake case, but camel case is
+/// accepted too, e.g. `startOffset` for `start_offset`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(from = "BookSource")]
 pub struct Book {
+    /// Optional identifier of the phonebook, included in events
+    /// so clients can tell phonebooks apart.
+    pub book_id: Option<String>,
     pub initial: Id,
     pub states: HashMap<Id, Option<State>>,
-    #[serde(default)]
+    /// Transitions of individual states, by state ID.
     pub transitions: HashMap<Id, Transitions>,
-    #[serde(default)]
+    /// Transitions defined under the `any` key in `transitions`,
+    /// which apply to every state.
+    ///
+    /// Where a state defines a transition for the same input,
+    /// e.g. the same digit or `end`, the one of the state is used.
+    /// Because of this key, a state with the ID `any` cannot have
+    /// transitions of its own.
+    ///
+    /// ```yaml
+    /// transitions:
+    ///   any:
+    ///     hang_up: idle
+    ///   idle:
+    ///     pick_up: announcement
+    /// ```
+    pub any_transitions: Option<Transitions>,
+    /// Names of the transition groups of each state that is in
+    /// any, by state ID, highest priority first.
+    pub groups: HashMap<Id, Vec<String>>,
+    /// Transitions shared by the states in a group, by group name.
+    ///
+    /// They are defined in `transitions` like the transitions of a
+    /// state, but under the name of the group. Transitions of the
+    /// state take priority over those of its groups, which take
+    /// priority over `any`.
+    ///
+    /// ```yaml
+    /// states:
+    ///   settings:
+    ///     group: navigation
+    /// transitions:
+    ///   navigation:
+    ///     hang_up: menu
+    /// ```
+    pub group_transitions: HashMap<String, Transitions>,
     pub sounds: HashMap<Id, Sound>,
+    /// Values for placeholders like `{name}` in the speech of
+    /// sounds.
+    pub variables: HashMap<String, String>,
+    /// If `Some(false)`, placeholders without a value are spoken
+    /// as they are, otherwise compilation fails.
+    pub strict: Option<bool>,
+    /// If `Some(true)`, states that are not terminal but have no
+    /// transitions only cause a warning, otherwise compilation
+    /// fails.
+    pub allow_dead_ends: Option<bool>,
+    /// Time in seconds to fade out the sounds of the previous
+    /// state while fading in the sounds of the next one.
+    pub crossfade: Option<f64>,
+    /// Where states and transitions are defined in the source,
+    /// if loaded from YAML.
+    pub locations: Locations,
+}
+
+/// Book as written in YAML, with `any` and groups still mixed in
+/// with the transitions of states.
+#[derive(Deserialize)]
+struct BookSource {
+    #[serde(default, alias = "bookId")]
+    book_id: Option<String>,
+    initial: Id,
+    states: HashMap<Id, Option<State>>,
+    #[serde(default)]
+    transitions: HashMap<Id, Transitions>,
+    #[serde(default)]
+    sounds: HashMap<Id, Sound>,
+    #[serde(default)]
+    variables: HashMap<String, String>,
+    #[serde(default)]
+    strict: Option<bool>,
+    #[serde(default, alias = "allowDeadEnds")]
+    allow_dead_ends: Option<bool>,
+    #[serde(default)]
+    crossfade: Option<f64>,
+}
+
+impl From<BookSource> for Book {
+    fn from(mut source: BookSource) -> Self {
+        let any_transitions = source.transitions.remove(&Id::new("any"));
+
+        let groups: HashMap<Id, Vec<String>> = source
+            .states
+            .iter()
+            .filter_map(|(id, state)| {
+                let group = state.as_ref()?.group.as_ref()?;
+                Some((id.clone(), group.names()))
+            })
+            .collect();
+        let mut group_transitions = HashMap::new();
+        for name in groups.values().flatten() {
+            let id = Id::new(name.as_str());
+            // a state with the same name keeps its transitions,
+            // compiling reports the conflict
+            if !source.states.contains_key(&id) {
+                if let Some(transitions) = source.transitions.remove(&id) {
+                    group_transitions.insert(name.clone(), transitions);
+                }
+            }
+        }
+
+        Book {
+            book_id: source.book_id,
+            initial: source.initial,
+            states: source.states,
+            transitions: source.transitions,
+            any_transitions,
+            groups,
+            group_transitions,
+            sounds: source.sounds,
+            variables: source.variables,
+            strict: source.strict,
+            allow_dead_ends: source.allow_dead_ends,
+            crossfade: source.crossfade,
+            locations: Locations::default(),
+        }
+    }
+}
+
+/// Positions of definitions in the YAML source of a book.
+#[derive(Default, Clone, Debug)]
+pub struct Locations {
+    pub states: HashMap<Id, Location>,
+    pub transitions: HashMap<Id, Location>,
+    /// Single transitions by state ID and key in snake case,
+    /// e.g. `hang_up`, or `dial.1` for dial patterns.
+    pub transition_keys: HashMap<(Id, String), Location>,
+    pub sounds: HashMap<Id, Location>,
+    /// State IDs that are defined more than once, at the location
+    /// of each repeated definition.
+    pub duplicate_states: Vec<(Id, Location)>,
+}
+
+impl Locations {
+    pub fn state(&self, id: &Id) -> Option<&Location> {
+        self.states.get(id)
+    }
+
+    pub fn transitions(&self, id: &Id) -> Option<&Location> {
+        self.transitions.get(id)
+    }
+
+    /// Location of a single transition of a state, e.g. with
+    /// key `pick_up` or `dial.1`.
+    pub fn transition(&self, id: &Id, key: &str) -> Option<&Location> {
+        self.transition_keys.get(&(id.clone(), key.to_string()))
+    }
+
+    pub fn sound(&self, id: &Id) -> Option<&Location> {
+        self.sounds.get(id)
+    }
+}
+
+/// A position in a YAML file, with lines and columns
+/// starting at 1.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Location {
+    /// `None` if not read from a file, e.g. from stdin.
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Formats as `file:line:column` like compilers do, or as
+/// `line 1, column 2` if there is no file.
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.file.as_ref() {
+            Some(file) => write!(f, "{}:{}:{}", file.display(), self.line, self.column),
+            None => write!(f, "line {}, column {}", self.line, self.column),
+        }
+    }
 }
 
-#[derive(Deserialize, Default, Debug)]
+#[derive(Deserialize, Default, Clone, Debug)]
 pub struct State {
     /// Name of the state, does not have to be unique.
     #[serde(default)]
@@ -44,16 +220,56 @@ pub struct State {
     pub terminal: bool,
     #[serde(default)]
     pub sounds: Vec<Id>,
+    /// Groups whose transitions the state shares, a single name
+    /// or a list with the highest priority first.
+    #[serde(default, alias = "groups")]
+    pub group: Option<Groups>,
+}
+
+/// A single group name or a list of them.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Groups {
+    One(String),
+    Many(Vec<String>),
 }
 
-#[derive(Deserialize, Default, Debug)]
+impl Groups {
+    /// The group names, highest priority first.
+    pub fn names(&self) -> Vec<String> {
+        match self {
+            Groups::One(name) => vec![name.clone()],
+            Groups::Many(names) => names.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Debug)]
 pub struct Sound {
     #[serde(default)]
     pub speech: Option<String>,
+    /// Name of the espeak voice for the speech, e.g. `en-us`,
+    /// or `None` for any voice.
+    #[serde(default)]
+    pub voice: Option<String>,
+    /// Speed of the speech in words per minute, from 1 to 500.
+    #[serde(default, alias = "ttsRate")]
+    pub tts_rate: Option<u16>,
+    /// Pitch of the speech, from 0 to 99.
+    #[serde(default, alias = "ttsPitch")]
+    pub tts_pitch: Option<u8>,
+    /// Path or data URI of the sound file. A leading `~/` and
+    /// `$VAR` in paths are expanded when compiling.
     #[serde(default)]
     pub file: String,
+    /// Linear gain, from `0.0` for silence to `1.0` for the
+    /// original volume.
     #[serde(default)]
-    pub volume: f32,
+    pub volume: Option<f32>,
+    /// Gain in decibels, from `-60.0` to `6.0`. Cannot be
+    /// combined with `volume`.
+    #[serde(default, alias = "volumeDb")]
+    pub volume_db: Option<f64>,
     /// When the sound is played again after being
     /// interrupted, do not start over but play from
     /// the last playback position minus the specified
@@ -67,10 +283,46 @@ pub struct Sound {
     #[serde(default, rename = "loop")]
     pub looping: bool,
     /// Offset on first playback in seconds.
+    #[serde(alias = "startOffset")]
     pub start_offset: Option<f64>,
+    /// Position in seconds where the sound ends, even if the file
+    /// goes on. Must be after the start offset.
+    #[serde(default, alias = "endAt")]
+    pub end_at: Option<f64>,
+    /// Ends the sound early when it has trailing silence.
+    #[serde(default, alias = "trimSilence")]
+    pub trim_silence: Option<TrimSilence>,
+    /// Playback speed, e.g. `1.5` to play one and a half times
+    /// as fast, from `0.25` to `4.0`.
+    #[serde(default)]
+    pub rate: Option<f64>,
+    /// Time in seconds to ramp up the volume from silence when
+    /// the sound starts.
+    #[serde(default, alias = "fadeIn")]
+    pub fade_in: Option<f64>,
+    /// Time in seconds to ramp down the volume to silence when
+    /// the sound is stopped, e.g. when leaving the state.
+    #[serde(default, alias = "fadeOut")]
+    pub fade_out: Option<f64>,
+    /// Normalizes the loudness of the file to -23 LUFS, so
+    /// sounds from different sources are about equally loud.
+    #[serde(default)]
+    pub normalize: bool,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct TrimSilence {
+    /// Audio with an RMS below this value, from `0.0` to
+    /// `1.0`, is considered silent.
+    #[serde(alias = "thresholdRms")]
+    pub threshold_rms: f64,
+    /// Minimum length of trailing silence in seconds for it
+    /// to be skipped.
+    #[serde(alias = "minDuration")]
+    pub min_duration: f64,
 }
 
-#[derive(Deserialize, Default, Debug)]
+#[derive(Deserialize, Default, Clone, Debug)]
 pub struct Lighting {
     #[serde(default)]
     pub power: i8,
@@ -80,16 +332,41 @@ pub struct Lighting {
     pub mood: i8,
 }
 
-#[derive(Deserialize, Default, Debug)]
+#[derive(Deserialize, Default, Clone, Debug)]
 pub struct Transitions {
     /// When input in some format was received.
     #[serde(default)]
     pub dial: HashMap<String, Id>,
+    #[serde(alias = "pickUp")]
     pub pick_up: Option<Id>,
+    #[serde(alias = "hangUp")]
     pub hang_up: Option<Id>,
+    /// When the flash/recall button is pressed down.
+    #[serde(alias = "recallPress")]
+    pub recall_press: Option<Id>,
+    /// When the flash/recall button is let go.
+    #[serde(alias = "recallRelease")]
+    pub recall_release: Option<Id>,
     /// When all actuators are done.
     pub end: Option<Id>,
-    pub timeout: Option<Timeout>,
+    pub timeout: Option<Timeouts>,
+}
+
+/// A single timeout or a list of timeouts at different times.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Timeouts {
+    One(Timeout),
+    Many(Vec<Timeout>),
+}
+
+impl Timeouts {
+    pub fn iter(&self) -> impl Iterator<Item = &Timeout> {
+        match self {
+            Timeouts::One(timeout) => std::slice::from_ref(timeout).iter(),
+            Timeouts::Many(timeouts) => timeouts.iter(),
+        }
+    }
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -122,6 +399,74 @@ mod test {
             .expect("Could not deserialize");
     }
 
+    #[test]
+    fn timeout_or_list_of_timeouts() {
+        // given
+        let yaml = "initial: idle
+states:
+  idle:
+  ringing:
+transitions:
+  idle:
+    timeout:
+      after: 5
+      to: ringing
+  ringing:
+    timeout:
+      - after: 5
+        to: idle
+      - after: 30
+        to: ringing
+";
+
+        // when
+        let book: Book = from_str(yaml).expect("Could not deserialize");
+
+        // then
+        let count = |id: &str| {
+            book.transitions[&Id::new(id)]
+                .timeout
+                .as_ref()
+                .map(|timeout| timeout.iter().count())
+        };
+        assert_eq!(count("idle"), Some(1));
+        assert_eq!(count("ringing"), Some(2));
+    }
+
+    #[test]
+    fn any_transitions_are_separate() {
+        // given
+        let yaml = "initial: idle\n\
+                    states:\n  idle:\n  ringing:\n\
+                    transitions:\n  any:\n    hang_up: idle\n  idle:\n    end: ringing\n";
+
+        // when
+        let book: Book = from_str(yaml).expect("Could not deserialize");
+
+        // then
+        let any = book.any_transitions.expect("Expected any transitions");
+        assert_eq!(any.hang_up, Some(Id::new("idle")));
+        assert!(!book.transitions.contains_key(&Id::new("any")));
+        assert!(book.transitions.contains_key(&Id::new("idle")));
+    }
+
+    #[test]
+    fn deserialize_camel_case_sound() {
+        let sound: Sound = from_str(
+            "file: music.mp3\n\
+             startOffset: 2\n\
+             volumeDb: -6\n\
+             trimSilence:\n  thresholdRms: 0.01\n  minDuration: 1.5\n",
+        )
+        .expect("Could not deserialize");
+
+        assert_eq!(sound.start_offset, Some(2.0));
+        assert_eq!(sound.volume_db, Some(-6.0));
+        let trim = sound.trim_silence.unwrap();
+        assert_eq!(trim.threshold_rms, 0.01);
+        assert_eq!(trim.min_duration, 1.5);
+    }
+
     #[should_panic]
     #[test]
     fn deserialize_empty_should_fail() {
@@ -0,0 +1,167 @@
+//! Keeps speech synthesized from text across restarts, so the
+//! same text does not have to be synthesized again on every
+//! start. Sounds converted with `ffmpeg` are kept in a separate
+//! directory, see `converted_dir`.
+//!
+//! Files are named after a hash of text and voice. Using a file
+//! updates its modification time, so entries that have not been
+//! used for a while can be pruned.
+use failure::Error;
+use log::debug;
+use std::collections::hash_map::DefaultHasher;
+use std::env::var_os;
+use std::fs::{read_dir, remove_dir_all, remove_file, File};
+use std::hash::Hasher;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Entries that have not been used for this long are pruned.
+pub const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// The cache directory, `$XDG_CACHE_HOME/fernspielapparat/tts` or
+/// `~/.cache/fernspielapparat/tts`, or `None` if neither variable
+/// is set.
+pub fn dir() -> Option<PathBuf> {
+    app_cache_dir().map(|dir| dir.join("tts"))
+}
+
+/// Directory for sounds converted or normalized with `ffmpeg`,
+/// next to the speech cache in `fernspielapparat/converted`.
+///
+/// Unlike speech, conversions are not pruned or cleared.
+pub fn converted_dir() -> Option<PathBuf> {
+    app_cache_dir().map(|dir| dir.join("converted"))
+}
+
+fn app_cache_dir() -> Option<PathBuf> {
+    let cache_home = var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    Some(cache_home.join("fernspielapparat"))
+}
+
+/// Deletes all cached speech.
+pub fn clear() -> Result<(), Error> {
+    match dir() {
+        Some(dir) => clear_dir(&dir),
+        None => Ok(()),
+    }
+}
+
+/// Deletes cached speech that has not been used for longer than
+/// the given time and returns the number of deleted files.
+pub fn prune(max_age: Duration) -> Result<usize, Error> {
+    match dir() {
+        Some(dir) => prune_dir(&dir, max_age),
+        None => Ok(0),
+    }
+}
+
+/// Gets the cached file at the given path and marks it as used,
+/// or `None` if there is no such file.
+pub(crate) fn lookup(path: &Path) -> Option<&Path> {
+    if !path.is_file() {
+        return None;
+    }
+
+    let touched = File::options()
+        .append(true)
+        .open(path)
+        .and_then(|file| file.set_modified(SystemTime::now()));
+    if let Err(e) = touched {
+        debug!("Could not mark {:?} as used: {}", path, e);
+    }
+
+    Some(path)
+}
+
+/// Lowercase hex of the hash, for naming cached files.
+///
+/// The hash may change with the Rust version, which only means
+/// that entries are created again.
+pub(crate) fn hex(hash: &DefaultHasher) -> String {
+    format!("{:016x}", hash.finish())
+}
+
+fn clear_dir(dir: &Path) -> Result<(), Error> {
+    match remove_dir_all(dir) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn prune_dir(dir: &Path, max_age: Duration) -> Result<usize, Error> {
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+
+    let now = SystemTime::now();
+    let mut pruned = 0;
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .unwrap_or_default();
+        if metadata.is_file() && age > max_age {
+            debug!("Pruning cached speech {:?}", entry.path());
+            remove_file(entry.path())?;
+            pruned += 1;
+        }
+    }
+
+    Ok(pruned)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn prune_unused_entries() {
+        // given
+        let dir = tempdir().unwrap();
+        let old = dir.path().join("old.wav");
+        let new = dir.path().join("new.wav");
+        write(&old, "").unwrap();
+        write(&new, "").unwrap();
+        File::options()
+            .append(true)
+            .open(&old)
+            .unwrap()
+            .set_modified(SystemTime::now() - 2 * MAX_AGE)
+            .unwrap();
+
+        // when
+        let pruned = prune_dir(dir.path(), MAX_AGE).unwrap();
+
+        // then
+        assert_eq!(pruned, 1);
+        assert!(!old.exists());
+        assert!(new.exists());
+    }
+
+    #[test]
+    fn clear_removes_entries() {
+        // given
+        let dir = tempdir().unwrap();
+        let cache = dir.path().join("tts");
+        std::fs::create_dir(&cache).unwrap();
+        write(cache.join("speech.wav"), "").unwrap();
+
+        // when
+        clear_dir(&cache).unwrap();
+
+        // then
+        assert!(!cache.exists());
+        assert!(clear_dir(&cache).is_ok(), "clearing twice should be ok");
+    }
+}
@@ -0,0 +1,119 @@
+//! Placeholders like `{name}` in speech, filled in with values from
+//! the phonebook, a variables file or a run request.
+use failure::{bail, Error};
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::Path;
+
+/// Values for placeholders and what to do about placeholders
+/// without a value.
+#[derive(Debug, Clone)]
+pub struct Variables {
+    values: HashMap<String, String>,
+    /// If `true`, placeholders without a value are an error,
+    /// otherwise they are left as they are.
+    strict: bool,
+}
+
+impl Default for Variables {
+    fn default() -> Self {
+        Variables::new(HashMap::new(), true)
+    }
+}
+
+impl Variables {
+    pub fn new(values: HashMap<String, String>, strict: bool) -> Self {
+        Variables { values, strict }
+    }
+
+    /// Replaces `{key}` in the text with the value of `key`.
+    ///
+    /// Write `{{` and `}}` for literal braces.
+    pub fn substitute(&self, text: &str) -> Result<String, Error> {
+        let mut substituted = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(start) = rest.find(['{', '}']) {
+            substituted.push_str(&rest[..start]);
+            let from_brace = &rest[start..];
+
+            if from_brace.starts_with("{{") || from_brace.starts_with("}}") {
+                substituted.push_str(&from_brace[..1]);
+                rest = &from_brace[2..];
+                continue;
+            }
+
+            let placeholder = if from_brace.starts_with('{') {
+                from_brace.find('}').map(|end| &from_brace[..=end])
+            } else {
+                None
+            };
+            match placeholder {
+                Some(placeholder) => {
+                    let key = &placeholder[1..placeholder.len() - 1];
+                    match self.values.get(key) {
+                        Some(value) => substituted.push_str(value),
+                        None if self.strict => {
+                            bail!("No value for variable {:?} in speech {:?}", key, text)
+                        }
+                        None => substituted.push_str(placeholder),
+                    }
+                    rest = &from_brace[placeholder.len()..];
+                }
+                None => {
+                    // unmatched brace, keep it
+                    substituted.push_str(&from_brace[..1]);
+                    rest = &from_brace[1..];
+                }
+            }
+        }
+
+        substituted.push_str(rest);
+        Ok(substituted)
+    }
+}
+
+/// Loads variables from a JSON file with an object of strings, e.g.
+/// `{"name": "Harry the Hog"}`.
+pub fn load_variables(path: impl AsRef<Path>) -> Result<HashMap<String, String>, Error> {
+    let source = read_to_string(path)?;
+    Ok(serde_json::from_str(&source)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn variables(strict: bool) -> Variables {
+        let mut values = HashMap::new();
+        values.insert("name".to_string(), "Harry".to_string());
+        values.insert("date".to_string(), "Monday".to_string());
+        Variables::new(values, strict)
+    }
+
+    #[test]
+    fn substitute_placeholders() {
+        let text = variables(true)
+            .substitute("Welcome to exhibit {name}, today is {date}")
+            .unwrap();
+        assert_eq!(text, "Welcome to exhibit Harry, today is Monday");
+    }
+
+    #[test]
+    fn escaped_braces() {
+        let text = variables(true).substitute("{{name}} is {name}}}").unwrap();
+        assert_eq!(text, "{name} is Harry}");
+    }
+
+    #[test]
+    fn unknown_variable_is_error_when_strict() {
+        let error = variables(true).substitute("Hello {visitor}").unwrap_err();
+        assert!(error.to_string().contains("\"visitor\""), "{}", error);
+    }
+
+    #[test]
+    fn unknown_variable_is_kept_when_not_strict() {
+        let text = variables(false).substitute("Hello {visitor} {").unwrap();
+        assert_eq!(text, "Hello {visitor} {");
+    }
+}
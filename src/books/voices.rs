@@ -0,0 +1,202 @@
+//! Speech with a voice selected by name, e.g. `en-us` or `de`,
+//! or with other speed and pitch, synthesized with `espeak`.
+//!
+//! tavla only offers any voice with its default settings, so
+//! sounds that configure the voice call `espeak` directly.
+use crate::books::spec;
+use failure::{bail, format_err, Error};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+const ESPEAK: &str = "espeak";
+
+/// Allowed range for the speed in words per minute.
+const MIN_SPEED: u16 = 1;
+const MAX_SPEED: u16 = 500;
+/// Allowed range for the pitch.
+const MAX_PITCH: u8 = 99;
+
+/// An espeak voice, `None` fields use the defaults of espeak.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct EspeakVoice {
+    /// Language, name or file of the voice, e.g. `en-us`.
+    pub name: Option<String>,
+    /// Words per minute, from 1 to 500.
+    pub speed: Option<u16>,
+    /// Pitch from 0 to 99.
+    pub pitch: Option<u8>,
+}
+
+impl EspeakVoice {
+    /// The voice configured for the sound, or `None` if the sound
+    /// configures nothing and any voice will do.
+    pub fn of(sound: &spec::Sound) -> Result<Option<Self>, Error> {
+        if let Some(speed) = sound.tts_rate {
+            if !(MIN_SPEED..=MAX_SPEED).contains(&speed) {
+                bail!(
+                    "tts_rate must be between {min} and {max}, but was {speed}",
+                    min = MIN_SPEED,
+                    max = MAX_SPEED,
+                    speed = speed
+                );
+            }
+        }
+
+        if let Some(pitch) = sound.tts_pitch {
+            if pitch > MAX_PITCH {
+                bail!(
+                    "tts_pitch must be between 0 and {max}, but was {pitch}",
+                    max = MAX_PITCH,
+                    pitch = pitch
+                );
+            }
+        }
+
+        let voice = EspeakVoice {
+            name: sound.voice.clone(),
+            speed: sound.tts_rate,
+            pitch: sound.tts_pitch,
+        };
+        Ok(if voice == EspeakVoice::default() {
+            None
+        } else {
+            Some(voice)
+        })
+    }
+
+    /// Speaks the text into a WAV file at the given path and waits
+    /// until done.
+    pub fn speak_to_file(&self, text: &str, path: &Path) -> Result<(), Error> {
+        let mut espeak = Command::new(ESPEAK);
+        if let Some(name) = self.name.as_ref() {
+            if !voice_exists(name)? {
+                bail!("Voice {:?} is not installed", name);
+            }
+            espeak.args(["-v", name]);
+        }
+        if let Some(speed) = self.speed {
+            espeak.arg("-s").arg(speed.to_string());
+        }
+        if let Some(pitch) = self.pitch {
+            espeak.arg("-p").arg(pitch.to_string());
+        }
+
+        // text on stdin, so it cannot be mistaken for options
+        let mut espeak = espeak
+            .args(["--stdin", "-w"])
+            .arg(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format_err!("Could not run {} to speak: {}", ESPEAK, e))?;
+        espeak
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(text.as_bytes())?;
+
+        let output = espeak.wait_with_output()?;
+        if !output.status.success() {
+            bail!(
+                "{} failed to speak with {:?}: {}",
+                ESPEAK,
+                self,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks if a voice with the given name is installed.
+///
+/// Matches the language, name or file of installed voices,
+/// ignoring variants like `+f3`.
+pub fn voice_exists(name: &str) -> Result<bool, Error> {
+    let output = Command::new(ESPEAK)
+        .arg("--voices")
+        .output()
+        .map_err(|e| format_err!("Could not run {} to list voices: {}", ESPEAK, e))?;
+    if !output.status.success() {
+        bail!(
+            "{} failed to list voices: {}",
+            ESPEAK,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(voice_listed(&String::from_utf8_lossy(&output.stdout), name))
+}
+
+/// Looks for the voice in the output of `espeak --voices`, e.g.:
+///
+/// ```text
+/// Pty Language Age/Gender VoiceName          File          Other Languages
+///  5  de             M  german               de
+///  2  en-us          M  english-us           en/en-us     (en 3)
+/// ```
+fn voice_listed(voices: &str, name: &str) -> bool {
+    let name = name.split('+').next().unwrap_or(name);
+    voices.lines().skip(1).any(|line| {
+        line.split_whitespace()
+            .enumerate()
+            // language, voice name and file
+            .filter(|(idx, _)| [1, 3, 4].contains(idx))
+            .any(|(_, field)| field.eq_ignore_ascii_case(name))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const VOICES: &str = "Pty Language Age/Gender VoiceName          File          Other Languages
+ 5  de             M  german               de
+ 2  en-us          M  english-us           en/en-us     (en 3)
+";
+
+    #[test]
+    fn voice_only_if_configured() {
+        // given
+        let unconfigured = spec::Sound::default();
+        let slow = spec::Sound {
+            tts_rate: Some(80),
+            ..Default::default()
+        };
+
+        // when
+        let unconfigured = EspeakVoice::of(&unconfigured).unwrap();
+        let slow = EspeakVoice::of(&slow).unwrap();
+
+        // then
+        assert_eq!(unconfigured, None);
+        assert_eq!(slow.unwrap().speed, Some(80));
+    }
+
+    #[test]
+    fn speed_and_pitch_range() {
+        let sound = |tts_rate, tts_pitch| spec::Sound {
+            tts_rate,
+            tts_pitch,
+            ..Default::default()
+        };
+
+        assert!(EspeakVoice::of(&sound(Some(0), None)).is_err());
+        assert!(EspeakVoice::of(&sound(Some(501), None)).is_err());
+        assert!(EspeakVoice::of(&sound(None, Some(100))).is_err());
+        assert!(EspeakVoice::of(&sound(Some(500), Some(0))).is_ok());
+    }
+
+    #[test]
+    fn find_listed_voices() {
+        assert!(voice_listed(VOICES, "de"));
+        assert!(voice_listed(VOICES, "EN-US"));
+        assert!(voice_listed(VOICES, "english-us+f3"));
+        assert!(voice_listed(VOICES, "en/en-us"));
+        assert!(!voice_listed(VOICES, "fr"));
+        assert!(!voice_listed(VOICES, "M"));
+    }
+}
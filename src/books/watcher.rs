@@ -0,0 +1,305 @@
+//! Recompiles a phonebook when its file changes on disk, e.g.
+//! while iterating on the YAML of a running phonebook.
+use super::{from_path_with_overrides, Book, Overrides};
+
+use failure::Error;
+use log::{debug, warn};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::thread::{self, sleep};
+use std::time::{Duration, SystemTime};
+
+/// Maximum time to wait for changes before checking if the
+/// watcher has been dropped, also the interval when polling.
+const WAIT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Time to wait after a change before compiling, since editors
+/// often write files in multiple steps.
+const SETTLE_TIME: Duration = Duration::from_millis(50);
+
+/// Watches a phonebook file on a background thread and compiles
+/// it again when it has been modified.
+///
+/// Uses inotify on Linux and polls the modification time
+/// elsewhere. The thread stops when the watcher is dropped.
+pub struct FileWatcher {
+    path: PathBuf,
+    books: Receiver<Result<Book, Error>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl FileWatcher {
+    /// Starts watching the phonebook at the given path.
+    pub fn spawn(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        Self::spawn_with_variables(path, HashMap::new())
+    }
+
+    /// Like `spawn`, but compiles with values for placeholders in
+    /// speech, see `from_path_with_variables`.
+    pub fn spawn_with_variables(
+        path: impl Into<PathBuf>,
+        variables: HashMap<String, String>,
+    ) -> Result<Self, Error> {
+        Self::spawn_with_overrides(
+            path,
+            Overrides {
+                variables,
+                ..Overrides::default()
+            },
+        )
+    }
+
+    /// Like `spawn`, but compiles with settings that take precedence
+    /// over the ones in the phonebook, see `from_path_with_overrides`.
+    pub fn spawn_with_overrides(
+        path: impl Into<PathBuf>,
+        overrides: Overrides,
+    ) -> Result<Self, Error> {
+        let path = path.into();
+        let (tx, books) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // set up before spawning, so no change is missed
+        let mut last_modified = modified(&path);
+        let mut changes = Changes::new(&path);
+
+        let thread_stop = Arc::clone(&stop);
+        let thread_path = path.clone();
+        thread::Builder::new()
+            .name("watch-book".to_string())
+            .spawn(move || {
+                while !thread_stop.load(SeqCst) {
+                    if !changes.wait(WAIT_INTERVAL) {
+                        continue;
+                    }
+
+                    sleep(SETTLE_TIME);
+                    let modified = modified(&thread_path);
+                    // missing while an editor replaces the file
+                    if modified.is_none() || modified == last_modified {
+                        continue;
+                    }
+                    last_modified = modified;
+
+                    debug!("{:?} changed, compiling", thread_path);
+                    let book = from_path_with_overrides(&thread_path, &overrides);
+                    if tx.send(book).is_err() {
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(FileWatcher { path, books, stop })
+    }
+
+    /// The watched file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Gets the books compiled since the last call, without
+    /// blocking, or errors for changes that did not compile.
+    pub fn poll(&self) -> Vec<Result<Book, Error>> {
+        self.books.try_iter().collect()
+    }
+}
+
+impl Drop for FileWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, SeqCst);
+    }
+}
+
+/// Modification time and length of the file, the length helps
+/// with coarse timestamps.
+fn modified(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = path.metadata().ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Waits for changes in the directory of the watched file.
+enum Changes {
+    #[cfg(target_os = "linux")]
+    Inotify(inotify::DirectoryEvents),
+    Polling,
+}
+
+impl Changes {
+    #[cfg(target_os = "linux")]
+    fn new(path: &Path) -> Self {
+        // editors often replace files, so watch the directory
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        match inotify::DirectoryEvents::new(dir) {
+            Ok(events) => Changes::Inotify(events),
+            Err(e) => {
+                warn!("cannot watch {:?} with inotify, polling: {}", dir, e);
+                Changes::Polling
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn new(_path: &Path) -> Self {
+        Changes::Polling
+    }
+
+    /// Waits up to the given time and returns `true` if the file
+    /// may have changed.
+    fn wait(&mut self, timeout: Duration) -> bool {
+        match self {
+            #[cfg(target_os = "linux")]
+            Changes::Inotify(events) => match events.wait(timeout) {
+                Ok(changed) => changed,
+                Err(e) => {
+                    warn!("inotify failed, polling from now on: {}", e);
+                    *self = Changes::Polling;
+                    true
+                }
+            },
+            Changes::Polling => {
+                sleep(timeout);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::ffi::CString;
+    use std::io::{Error, ErrorKind, Result};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::time::Duration;
+
+    /// Events for files in a directory being written, created or
+    /// moved there.
+    pub struct DirectoryEvents {
+        fd: libc::c_int,
+    }
+
+    impl DirectoryEvents {
+        pub fn new(dir: &Path) -> Result<Self> {
+            let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(Error::last_os_error());
+            }
+            // closes the descriptor if adding the watch fails
+            let events = DirectoryEvents { fd };
+
+            let dir = CString::new(dir.as_os_str().as_bytes())?;
+            let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE;
+            if unsafe { libc::inotify_add_watch(fd, dir.as_ptr(), mask) } < 0 {
+                return Err(Error::last_os_error());
+            }
+
+            Ok(events)
+        }
+
+        /// Waits up to the given time for events and returns
+        /// `true` if there were any.
+        pub fn wait(&mut self, timeout: Duration) -> Result<bool> {
+            let mut pollfd = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let timeout_ms = timeout.as_millis() as libc::c_int;
+            match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+                0 => Ok(false),
+                ready if ready > 0 => {
+                    self.drain();
+                    Ok(true)
+                }
+                _ => {
+                    let error = Error::last_os_error();
+                    if error.kind() == ErrorKind::Interrupted {
+                        Ok(false)
+                    } else {
+                        Err(error)
+                    }
+                }
+            }
+        }
+
+        /// Reads all pending events, only the fact that there were
+        /// events is interesting.
+        fn drain(&mut self) {
+            let mut buf = [0_u8; 4096];
+            while unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) }
+                > 0
+            {}
+        }
+    }
+
+    impl Drop for DirectoryEvents {
+        fn drop(&mut self) {
+            unsafe { libc::close(self.fd) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::write;
+    use std::time::Instant;
+    use tempfile::tempdir;
+
+    fn next_book(watcher: &FileWatcher) -> Result<Book, Error> {
+        let start = Instant::now();
+        loop {
+            if let Some(book) = watcher.poll().pop() {
+                return book;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(5),
+                "Expected a book after the change"
+            );
+            sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn changed_file_is_compiled() {
+        // given
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("book.yaml");
+        write(&path, "initial: a\nstates:\n  a: { terminal: true }\n").unwrap();
+        let watcher = FileWatcher::spawn(&path).unwrap();
+
+        // when
+        write(
+            &path,
+            "initial: second\nstates:\n  second: { terminal: true }\n",
+        )
+        .unwrap();
+        let book = next_book(&watcher).unwrap();
+
+        // then
+        assert_eq!(book.states()[0].id(), "second");
+    }
+
+    #[test]
+    fn syntax_error_is_reported() {
+        // given
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("book.yaml");
+        write(&path, "initial: a\nstates:\n  a: { terminal: true }\n").unwrap();
+        let watcher = FileWatcher::spawn(&path).unwrap();
+
+        // when
+        write(&path, "initial: [a\n").unwrap();
+        let book = next_book(&watcher);
+
+        // then
+        assert!(book.is_err());
+    }
+}
@@ -1,28 +1,143 @@
-//! Functionality to perform hardware checks without running
-//! a phonebook.
+//! Functionality to perform hardware checks and to lint
+//! phonebooks without running a phonebook.
+use crate::acts::{Player, PlayerContext};
+use crate::books::spec::{Id, Locations};
+use crate::books::{
+    self, compile_unprepared_relative_to, dead_ends, expand_path, reachable_from, voice_exists,
+    Book, Location,
+};
+use crate::evt::CompositeResponder;
 use crate::phone::Phone;
 use crate::result::Result;
+use crate::senses::{Input, Sensors};
+use crate::states::{Machine, State};
 
+use failure::{bail, format_err};
+use hound::{SampleFormat, WavSpec, WavWriter};
 use log::{error, info};
 use tavla::{any_voice, Speech, Voice};
+use tempfile::tempdir;
 
+use std::f64::consts::PI;
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind};
+use std::path::Path;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-/// Checks I2C phone and speech synthesis.
+/// I2C device the hardware phone is checked on.
+const I2C_DEVICE: &str = "/dev/i2c-1";
+
+/// I2C address the hardware phone is checked on.
+const I2C_ADDRESS: u16 = 4;
+
+/// Number of ticks to average over when benchmarking.
+const BENCHMARK_TICKS: u32 = 1000;
+
+/// Length of the test tone for the audio output check.
+const TEST_TONE_DURATION: Duration = Duration::from_secs(1);
+
+/// Extra time for the test tone to finish, e.g. when the audio
+/// device is slow to open.
+const TEST_TONE_GRACE: Duration = Duration::from_secs(3);
+
+/// Outcome of a successful I2C round-trip to the phone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct I2cCheckResult {
+    /// Time it took to poll the phone, including retries.
+    pub latency_ms: u64,
+    /// Input the phone reported, `None` if it had nothing to send.
+    pub input: Option<Input>,
+}
+
+/// Checks I2C communication, phone, speech synthesis and audio
+/// output.
 ///
-/// If any of the two does not stand the check, then
-/// an error with more details is returned.
-pub fn check_system() -> Result<()> {
-    let check_result = check_phone().and(check_speech());
+/// All checks are performed and a summary is logged. If any of
+/// them does not stand the check, then the first error is
+/// returned. Audio is played on the ALSA device with the given
+/// name, or on the default device.
+pub fn check_system(audio_device: Option<&str>) -> Result<()> {
+    let results = vec![
+        (
+            "i2c",
+            check_i2c(I2C_DEVICE, I2C_ADDRESS).map(|i2c| format!("{} ms", i2c.latency_ms)),
+        ),
+        ("phone", check_phone().map(|_| String::new())),
+        ("speech", check_speech().map(|_| String::new())),
+        (
+            "audio output",
+            check_audio_output(audio_device).map(|_| String::new()),
+        ),
+    ];
 
-    if check_result.is_ok() {
-        info!("Systems check successful.");
-    } else {
-        error!("Systems check failure.");
+    for (name, result) in &results {
+        match result {
+            Ok(details) => info!("{:<12} ok {}", name, details),
+            Err(e) => error!("{:<12} failed: {}", name, e),
+        }
     }
 
-    check_result
+    match results.into_iter().find_map(|(_, r)| r.err()) {
+        None => {
+            info!("Systems check successful.");
+            Ok(())
+        }
+        Some(first_failure) => {
+            error!("Systems check failure.");
+            Err(first_failure)
+        }
+    }
+}
+
+/// Connects to the phone on the given I2C device and address and
+/// polls it once, measuring the round-trip latency.
+///
+/// If the phone has no input to report, communication still
+/// worked and the result has no input. Other errors are reported
+/// with the OS error code, if any, and hints for fixing them.
+pub fn check_i2c(device: &str, address: u16) -> Result<I2cCheckResult> {
+    info!(
+        "Testing I2C communication on {}, address {}...",
+        device, address
+    );
+
+    let start = Instant::now();
+    let test_result = Phone::connect(device, address).and_then(|mut phone| match phone.poll() {
+        Ok(input) => Ok(Some(input)),
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+        Err(e) => Err(e),
+    });
+    let latency_ms = start.elapsed().as_millis() as u64;
+
+    match test_result {
+        Ok(input) => {
+            info!("I2C communication ok, took {} ms.", latency_ms);
+            Ok(I2cCheckResult { latency_ms, input })
+        }
+        Err(e) => {
+            let e = i2c_failure(&e, device, address);
+            error!("{}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Describes a failed I2C check with the OS error code and
+/// suggestions what to try.
+fn i2c_failure(cause: &IoError, device: &str, address: u16) -> failure::Error {
+    let code = cause
+        .raw_os_error()
+        .map(|code| format!(" (OS error {})", code))
+        .unwrap_or_default();
+    format_err!(
+        "I2C communication on {}, address {} failed{}: {}. \
+         Check the cable to the phone and verify the address, e.g. with `i2cdetect -y 1`.",
+        device,
+        address,
+        code,
+        cause
+    )
 }
 
 /// Checks if the I2C phone can be connected to and then
@@ -33,7 +148,7 @@ pub fn check_system() -> Result<()> {
 pub fn check_phone() -> Result<()> {
     info!("Testing communication with hardware phone...");
 
-    let test_result = Phone::connect("/dev/i2c-1", 4).and_then(|mut phone| {
+    let test_result = Phone::connect(I2C_DEVICE, I2C_ADDRESS).and_then(|mut phone| {
         phone.ring()?;
         sleep(Duration::from_secs(1));
         phone.unring()?;
@@ -50,6 +165,33 @@ pub fn check_phone() -> Result<()> {
     Ok(test_result?)
 }
 
+/// Measures the average time a tick of the state machine takes
+/// without any input, sounds or other I/O.
+///
+/// This is the overhead of the runtime itself, the time between
+/// ticks should be considerably higher.
+pub fn benchmark_tick_rate() -> Duration {
+    let states = [
+        State::builder()
+            .id("waiting")
+            .name("waiting")
+            .timeout(Duration::from_secs(60 * 60), 1)
+            .build(),
+        State::builder().id("never").name("never").build(),
+    ];
+    let mut machine = Machine::new(
+        Sensors::blind(),
+        CompositeResponder::<State>::from(vec![]),
+        &states,
+    );
+
+    let start = Instant::now();
+    for _ in 0..BENCHMARK_TICKS {
+        machine.update();
+    }
+    start.elapsed() / BENCHMARK_TICKS
+}
+
 /// Checks if speech synthesis is working by speaking the
 /// sentence "This is fernspielapparat speaking.".
 pub fn check_speech() -> Result<()> {
@@ -71,3 +213,360 @@ pub fn check_speech() -> Result<()> {
         }
     }
 }
+
+/// Checks if the espeak voice with the given name, e.g. `en-us`,
+/// is installed and can be used for speech in phonebooks.
+pub fn check_tts_voice(name: &str) -> Result<()> {
+    info!("Testing voice {:?}...", name);
+
+    match voice_exists(name) {
+        Ok(true) => {
+            info!("Voice {:?} ok.", name);
+            Ok(())
+        }
+        Ok(false) => {
+            error!("Voice {:?} is not installed.", name);
+            bail!("Voice {:?} is not installed", name)
+        }
+        Err(e) => {
+            error!("Voice {:?} could not be checked: {}.", name, e);
+            Err(e)
+        }
+    }
+}
+
+/// Checks if audio can be played by playing a short 440 Hz
+/// test tone with VLC.
+///
+/// Audio is played on the ALSA device with the given name, or on
+/// the default device. Returns an error if VLC cannot be
+/// initialized, if the duration of the tone cannot be read or if
+/// playback does not finish in time.
+pub fn check_audio_output(device: Option<&str>) -> Result<()> {
+    info!("Testing audio output...");
+
+    let test_result = play_test_tone(device);
+    match test_result {
+        Ok(_) => info!("Audio output ok."),
+        Err(ref e) => error!("Audio output failed: {}.", e),
+    }
+
+    test_result
+}
+
+fn play_test_tone(device: Option<&str>) -> Result<()> {
+    let ctx = match device {
+        Some(device) => PlayerContext::with_audio_device(device)?,
+        None => PlayerContext::new()?,
+    };
+
+    let dir = tempdir()?;
+    let tone = dir.path().join("test-tone.wav");
+    write_test_tone(&tone, TEST_TONE_DURATION)?;
+
+    let mut player = Player::new_with_ctx(&tone, &ctx)
+        .map_err(|e| format_err!("Could not load test tone: {}", e))?;
+    player.play()?;
+
+    let deadline = Instant::now() + player.duration() + TEST_TONE_GRACE;
+    // give VLC a moment to start before checking the state
+    sleep(Duration::from_millis(100));
+    while player.playing().unwrap_or(true) {
+        if Instant::now() > deadline {
+            bail!(
+                "Test tone did not finish playing within {:?}",
+                player.duration() + TEST_TONE_GRACE
+            );
+        }
+        sleep(Duration::from_millis(50));
+    }
+
+    Ok(())
+}
+
+/// Writes a mono sine wave with 440 Hz at half volume.
+fn write_test_tone(path: &Path, duration: Duration) -> Result<()> {
+    const SAMPLE_RATE: u32 = 44100;
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut writer = WavWriter::create(path, spec)?;
+    let frames = duration.as_millis() as u32 * SAMPLE_RATE / 1000;
+    for frame in 0..frames {
+        let t = f64::from(frame) / f64::from(SAMPLE_RATE);
+        let sample = (2.0 * PI * 440.0 * t).sin() * 0.5 * f64::from(i16::MAX);
+        writer.write_sample(sample as i16)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+/// What kind of problem the lint of a phonebook found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintKind {
+    /// The phonebook cannot be compiled, e.g. because of a
+    /// transition to an undefined state.
+    Invalid,
+    /// A state ID is defined more than once, only the last
+    /// definition is used.
+    DuplicateState,
+    /// A state uses a sound ID that is not defined.
+    UndefinedSound,
+    /// A sound file does not exist.
+    MissingAudioFile,
+    /// A state is not terminal but has no transitions, so it
+    /// could never be left.
+    DeadEnd,
+    /// A state cannot be reached from the initial state.
+    UnreachableState,
+    /// A transition goes back to the state it starts from.
+    SelfTransition,
+}
+
+impl LintKind {
+    /// Errors keep the phonebook from running as written, the
+    /// other kinds are only probably unintended.
+    pub fn is_error(self) -> bool {
+        !matches!(self, LintKind::UnreachableState | LintKind::SelfTransition)
+    }
+}
+
+/// A single problem found when linting a phonebook.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintWarning {
+    pub kind: LintKind,
+    pub message: String,
+    /// Where the problem is in the phonebook file, if known.
+    pub location: Option<Location>,
+}
+
+/// Formats as `file:line:column: message` like compile errors.
+impl fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.location.as_ref() {
+            Some(location) => write!(f, "{}: {}", location, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// All problems found when linting a phonebook.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LintReport {
+    pub warnings: Vec<LintWarning>,
+}
+
+impl LintReport {
+    /// Checks if any of the warnings is an error.
+    pub fn has_errors(&self) -> bool {
+        self.warnings.iter().any(|w| w.kind.is_error())
+    }
+
+    /// The warnings of the given kind.
+    pub fn of_kind(&self, kind: LintKind) -> impl Iterator<Item = &LintWarning> {
+        self.warnings.iter().filter(move |w| w.kind == kind)
+    }
+
+    fn push(&mut self, kind: LintKind, message: String, location: Option<&Location>) {
+        self.warnings.push(LintWarning {
+            kind,
+            message,
+            location: location.cloned(),
+        });
+    }
+}
+
+/// Checks the phonebook YAML file at the given path for problems
+/// without running it.
+///
+/// Unlike loading the phonebook, this does not stop at the first
+/// error. Returns an error only if the file cannot be read or
+/// parsed at all. Sounds are only checked, not prepared, so
+/// neither espeak nor ffmpeg are needed.
+pub fn lint_phonebook(path: impl AsRef<Path>) -> Result<LintReport> {
+    let path = path.as_ref();
+    let spec = books::file::load(path)?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let locations = &spec.locations;
+    let mut report = LintReport::default();
+
+    for (id, location) in &locations.duplicate_states {
+        report.push(
+            LintKind::DuplicateState,
+            format!("State {:?} is defined more than once", id.to_string()),
+            Some(location),
+        );
+    }
+
+    let mut state_ids: Vec<_> = spec.states.keys().collect();
+    state_ids.sort_by_key(|id| id.to_string());
+    for id in state_ids {
+        let sounds = spec.states[id].iter().flat_map(|s| s.sounds.iter());
+        for sound in sounds.filter(|sound| !spec.sounds.contains_key(sound)) {
+            report.push(
+                LintKind::UndefinedSound,
+                format!(
+                    "State {:?} uses undefined sound ID {:?}",
+                    id.to_string(),
+                    sound.to_string()
+                ),
+                locations.state(id),
+            );
+        }
+    }
+
+    let mut missing_files = vec![];
+    let mut sound_ids: Vec<_> = spec.sounds.keys().collect();
+    sound_ids.sort_by_key(|id| id.to_string());
+    for id in sound_ids {
+        let file = &spec.sounds[id].file;
+        if file.is_empty() || file.starts_with("data:") {
+            continue;
+        }
+
+        let resolved = base_dir.join(expand_path(file));
+        if !resolved.is_file() {
+            report.push(
+                LintKind::MissingAudioFile,
+                format!("Sound file {:?} does not exist", resolved),
+                locations.sound(id),
+            );
+            missing_files.push(id.clone());
+        }
+    }
+
+    // compile with dead ends allowed and without the sounds reported
+    // above, so the problems in the structure are reported below
+    // even if some sounds are broken
+    let allow_dead_ends = spec.allow_dead_ends.unwrap_or(false);
+    let mut lenient = spec.clone();
+    lenient.allow_dead_ends = Some(true);
+    for id in &missing_files {
+        lenient.sounds.remove(id);
+    }
+    let defined_sounds = &lenient.sounds;
+    for state in lenient.states.values_mut().flatten() {
+        state
+            .sounds
+            .retain(|sound| defined_sounds.contains_key(sound));
+    }
+    match compile_unprepared_relative_to(lenient, base_dir) {
+        Ok(book) => lint_book(&book, locations, allow_dead_ends, &mut report),
+        // the problems found so far already explain why
+        Err(_) if report.has_errors() => (),
+        Err(err) => report.push(LintKind::Invalid, err.to_string(), None),
+    }
+
+    Ok(report)
+}
+
+/// Finds problems in the structure of a compiled phonebook.
+fn lint_book(book: &Book, locations: &Locations, allow_dead_ends: bool, report: &mut LintReport) {
+    let reachable = reachable_from(book, 0);
+    let dead_ends = dead_ends(book);
+    for (idx, state) in book.states().iter().enumerate() {
+        let id = Id::new(state.id());
+
+        if !allow_dead_ends && dead_ends.contains(&idx) {
+            report.push(
+                LintKind::DeadEnd,
+                format!(
+                    "State {:?} is not terminal but has no transitions",
+                    state.id()
+                ),
+                locations.state(&id),
+            );
+        }
+
+        if !reachable.contains(&idx) {
+            report.push(
+                LintKind::UnreachableState,
+                format!(
+                    "State {:?} cannot be reached from the initial state",
+                    state.id()
+                ),
+                locations.state(&id),
+            );
+        }
+
+        for (trigger, target) in state.transitions() {
+            if target.state() == Some(idx) {
+                report.push(
+                    LintKind::SelfTransition,
+                    format!(
+                        "State {:?} transitions to itself on {}",
+                        state.id(),
+                        trigger
+                    ),
+                    locations.transitions(&id).or_else(|| locations.state(&id)),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hound::WavReader;
+
+    #[test]
+    fn test_tone_has_requested_duration() {
+        // given
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("tone.wav");
+
+        // when
+        write_test_tone(&path, Duration::from_millis(500)).unwrap();
+
+        // then
+        let reader = WavReader::open(&path).unwrap();
+        assert_eq!(reader.duration(), 22050);
+    }
+
+    #[test]
+    fn i2c_failure_reports_os_error_code() {
+        // given
+        let no_ack = IoError::from_raw_os_error(121);
+
+        // when
+        let msg = i2c_failure(&no_ack, "/dev/i2c-1", 4).to_string();
+
+        // then
+        assert!(msg.contains("OS error 121"), "unexpected message: {}", msg);
+        assert!(msg.contains("Check the cable"));
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    #[test]
+    fn i2c_check_fails_without_linux() {
+        // when
+        let result = check_i2c("/dev/i2c-1", 4);
+
+        // then
+        let msg = result.unwrap_err().to_string();
+        assert!(
+            msg.contains("not supported on this platform"),
+            "unexpected message: {}",
+            msg
+        );
+        assert!(!msg.contains("OS error"));
+    }
+
+    #[cfg_attr(not(feature = "expensive_tests"), ignore)]
+    #[test]
+    fn benchmark_ticks() {
+        // when
+        let tick = benchmark_tick_rate();
+
+        // then
+        assert!(
+            tick < Duration::from_millis(1),
+            "Expected ticks without I/O to be fast, but took {:?}",
+            tick
+        );
+    }
+}
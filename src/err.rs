@@ -1,4 +1,25 @@
-use failure::{bail, Error};
+use failure::{Error, Fail};
+use std::fmt;
+
+/// Multiple errors that occurred together, e.g. when loading
+/// several sounds.
+#[derive(Debug, Fail)]
+pub struct CompoundError {
+    pub errors: Vec<Error>,
+}
+
+impl fmt::Display for CompoundError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Multiple errors: ")?;
+        for (idx, err) in self.errors.iter().enumerate() {
+            if idx > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", err)?;
+        }
+        Ok(())
+    }
+}
 
 /// Consumes the given iterator of results and returns
 /// `Ok(())` if no errors were encountered.
@@ -18,25 +39,18 @@ where
 /// Consumes the given iterator of fails or errors and
 /// returns `Ok(())` if no errors were encountered.
 ///
-/// Otherwise returns an error describing the whole of
-/// the found errors.
+/// Otherwise returns the only error, or a `CompoundError`
+/// holding all of the found errors.
 pub fn compound_error<I, E>(errors: I) -> Result<(), Error>
 where
     I: IntoIterator<Item = E>,
     E: Into<Error>,
 {
-    let mut errors = errors.into_iter().map(Into::into);
+    let mut errors: Vec<Error> = errors.into_iter().map(Into::into).collect();
 
-    match errors.next() {
-        None => Ok(()),
-        Some(first) => {
-            let mut tail: Vec<Error> = errors.collect();
-            if tail.is_empty() {
-                Err(first)
-            } else {
-                tail.insert(0, first);
-                bail!("Multiple errors: {:?}", tail)
-            }
-        }
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err(errors.remove(0)),
+        _ => Err(CompoundError { errors }.into()),
     }
 }
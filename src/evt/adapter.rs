@@ -0,0 +1,177 @@
+use super::*;
+
+use crate::states::Symbol;
+
+use failure::format_err;
+use log::{debug, error};
+use tokio::runtime;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+use std::future::Future;
+use std::thread::{self, JoinHandle};
+
+/// Like `Responder`, but for responders that perform I/O, e.g.
+/// posting events to a webhook, that should not block the main
+/// loop.
+///
+/// Use `AsyncResponderAdapter` to run it in the background.
+pub trait AsyncResponder<S>: Send {
+    /// Reacts to the event, e.g. with `async fn respond`.
+    fn respond(&mut self, event: &Event<'_, S>) -> impl Future<Output = Result<()>>;
+}
+
+/// Runs an async responder on a tokio runtime in a background
+/// thread, passing on events without waiting for them to be
+/// processed.
+///
+/// Dropping the adapter waits for queued events to be processed.
+pub struct AsyncResponderAdapter<S> {
+    events: Option<UnboundedSender<OwnedEvent<S>>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+/// An event that owns copies of its states so it can be sent to
+/// another thread.
+enum OwnedEvent<S> {
+    Start { initial: S },
+    Reset { initial: S, reason: ResetReason },
+    Finish { terminal: S },
+    Transition { cause: Symbol, from: S, to: S },
+}
+
+impl<S: Clone> OwnedEvent<S> {
+    fn from(event: &Event<S>) -> Self {
+        match *event {
+            Event::Start { initial } => OwnedEvent::Start {
+                initial: initial.clone(),
+            },
+            Event::Reset { initial, reason } => OwnedEvent::Reset {
+                initial: initial.clone(),
+                reason,
+            },
+            Event::Finish { terminal } => OwnedEvent::Finish {
+                terminal: terminal.clone(),
+            },
+            Event::Transition { cause, from, to } => OwnedEvent::Transition {
+                cause,
+                from: from.clone(),
+                to: to.clone(),
+            },
+        }
+    }
+
+    fn as_event(&self) -> Event<'_, S> {
+        match self {
+            OwnedEvent::Start { initial } => Event::Start { initial },
+            OwnedEvent::Reset { initial, reason } => Event::Reset {
+                initial,
+                reason: *reason,
+            },
+            OwnedEvent::Finish { terminal } => Event::Finish { terminal },
+            OwnedEvent::Transition { cause, from, to } => Event::Transition {
+                cause: *cause,
+                from,
+                to,
+            },
+        }
+    }
+}
+
+impl<S: Clone + Send + 'static> AsyncResponderAdapter<S> {
+    /// Spawns a thread with a single-threaded tokio runtime that
+    /// drives the given responder.
+    pub fn spawn<R: AsyncResponder<S> + 'static>(mut responder: R) -> Result<Self> {
+        let (events, mut received) = unbounded_channel::<OwnedEvent<S>>();
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let worker = thread::Builder::new()
+            .name("async-responder".to_string())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    while let Some(event) = received.recv().await {
+                        if let Err(err) = responder.respond(&event.as_event()).await {
+                            error!("async responder failed: {}", err);
+                        }
+                    }
+                });
+                debug!("async responder stopped, no more events");
+            })?;
+
+        Ok(AsyncResponderAdapter {
+            events: Some(events),
+            worker: Some(worker),
+        })
+    }
+}
+
+impl<S: Clone> Responder<S> for AsyncResponderAdapter<S> {
+    /// Queues the event for the async responder and returns
+    /// right away.
+    fn respond(&mut self, event: &Event<S>) -> Result<()> {
+        self.events
+            .as_ref()
+            .and_then(|events| events.send(OwnedEvent::from(event)).ok())
+            .ok_or_else(|| format_err!("async responder is no longer running"))
+    }
+}
+
+impl<S> Drop for AsyncResponderAdapter<S> {
+    fn drop(&mut self) {
+        // closing the channel ends the loop after the last event
+        self.events.take();
+        if let Some(worker) = self.worker.take() {
+            worker.join().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::senses::Input;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    /// Records the states of events after waiting a bit.
+    struct SlowRecordingResponder(Arc<Mutex<Vec<String>>>);
+
+    impl AsyncResponder<String> for SlowRecordingResponder {
+        async fn respond(&mut self, event: &Event<'_, String>) -> Result<()> {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            let state = match event {
+                Event::Start { initial } | Event::Reset { initial, .. } => initial,
+                Event::Finish { terminal } => terminal,
+                Event::Transition { to, .. } => to,
+            };
+            self.0.lock().unwrap().push(state.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn events_are_processed_in_background() {
+        // given
+        let recorded = Arc::new(Mutex::new(vec![]));
+        let mut adapter =
+            AsyncResponderAdapter::spawn(SlowRecordingResponder(Arc::clone(&recorded))).unwrap();
+        let (first, second) = ("first".to_string(), "second".to_string());
+
+        // when
+        adapter.respond(&Event::Start { initial: &first }).unwrap();
+        adapter
+            .respond(&Event::Transition {
+                cause: Symbol::Dial(Input::pick_up()),
+                from: &first,
+                to: &second,
+            })
+            .unwrap();
+        let recorded_right_away = recorded.lock().unwrap().len();
+        drop(adapter);
+
+        // then
+        assert_eq!(recorded_right_away, 0);
+        assert_eq!(*recorded.lock().unwrap(), vec!["first", "second"]);
+    }
+}
@@ -2,24 +2,56 @@ use super::*;
 
 use crate::err::{compound_error, compound_result};
 
-pub struct CompositeResponder<S>(Vec<Box<dyn Responder<S>>>);
+use std::collections::BTreeMap;
+
+/// Identifies a responder that was added to a composite responder.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct ResponderId(usize);
+
+/// Forwards events to multiple responders, in the order they were
+/// added.
+pub struct CompositeResponder<S> {
+    responders: BTreeMap<ResponderId, Box<dyn Responder<S>>>,
+    next_id: usize,
+}
 
 impl<S> CompositeResponder<S> {
     pub fn from(responders: Vec<Box<dyn Responder<S>>>) -> Self {
-        CompositeResponder(responders)
+        let mut composite = CompositeResponder {
+            responders: BTreeMap::new(),
+            next_id: 0,
+        };
+        for responder in responders {
+            composite.add_responder(responder);
+        }
+        composite
+    }
+
+    /// Adds a responder that receives all events from now on.
+    pub fn add_responder(&mut self, responder: Box<dyn Responder<S>>) -> ResponderId {
+        let id = ResponderId(self.next_id);
+        self.next_id += 1;
+        self.responders.insert(id, responder);
+        id
+    }
+
+    /// Removes a previously added responder, returning it if it
+    /// was still there.
+    pub fn remove_responder(&mut self, id: ResponderId) -> Option<Box<dyn Responder<S>>> {
+        self.responders.remove(&id)
     }
 }
 
 impl<S> Responder<S> for CompositeResponder<S> {
     fn respond(&mut self, event: &Event<S>) -> Result<()> {
-        compound_result(self.0.iter_mut().map(|r| r.respond(event)))
+        compound_result(self.responders.values_mut().map(|r| r.respond(event)))
     }
 
     fn update(&mut self) -> Result<ResponderState> {
         let mut compound_state = ResponderState::Idle;
         let mut errs = Vec::new();
 
-        for responder in &mut self.0 {
+        for responder in self.responders.values_mut() {
             match responder.update() {
                 Ok(ResponderState::Idle) => (),
                 Ok(ResponderState::Running) => compound_state = ResponderState::Running,
@@ -34,6 +66,23 @@ impl<S> Responder<S> for CompositeResponder<S> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Records the kinds of events it received.
+    struct RecordingResponder(Rc<RefCell<Vec<&'static str>>>);
+
+    impl Responder<()> for RecordingResponder {
+        fn respond(&mut self, event: &Event<()>) -> Result<()> {
+            self.0.borrow_mut().push(match event {
+                Event::Start { .. } => "start",
+                Event::Reset { .. } => "reset",
+                Event::Finish { .. } => "finish",
+                Event::Transition { .. } => "transition",
+            });
+            Ok(())
+        }
+    }
 
     #[derive(Clone)]
     struct ResponderWithState(ResponderState);
@@ -78,4 +127,23 @@ mod test {
         assert_eq!(running_and_idle, ResponderState::Running);
         assert_eq!(running_and_running, ResponderState::Running);
     }
+
+    #[test]
+    fn responder_added_mid_run_only_receives_later_events() {
+        // given
+        let recorded = Rc::new(RefCell::new(vec![]));
+        let mut composite =
+            CompositeResponder::from(vec![Box::new(ResponderWithState(ResponderState::Idle))]);
+
+        // when
+        composite.respond(&Event::Start { initial: &() }).unwrap();
+        let id = composite.add_responder(Box::new(RecordingResponder(Rc::clone(&recorded))));
+        composite.respond(&Event::Finish { terminal: &() }).unwrap();
+        let removed = composite.remove_responder(id);
+        composite.respond(&Event::Start { initial: &() }).unwrap();
+
+        // then
+        assert!(removed.is_some());
+        assert_eq!(*recorded.borrow(), vec!["finish"]);
+    }
 }
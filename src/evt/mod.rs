@@ -1,23 +1,37 @@
 //! Module for state machine events that a remote controlling
 //! application may be interested in.
 use crate::result::Result;
-use crate::states::Symbol;
 
+use serde::Serialize;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[cfg(feature = "tokio-runtime")]
+mod adapter;
 mod composite;
 
-pub use composite::CompositeResponder;
+pub use crate::states::{State, Symbol};
+#[cfg(feature = "tokio-runtime")]
+pub use adapter::{AsyncResponder, AsyncResponderAdapter};
+pub use composite::{CompositeResponder, ResponderId};
 
 /// State machine event for a machine state evaluates states
 /// of type `S`.
 #[derive(Copy, Clone)]
 pub enum Event<'a, S> {
-    /// A new phonebook has been loaded, the same phonebook has
-    /// been reset, or normal phonebook progression caused the
-    /// initial state to be reached again.
+    /// Normal phonebook progression caused the initial state to
+    /// be reached again.
     ///
     /// The specified initial state is now current.
     #[allow(unused)] // used through type aliases, but rustc does not pick it up
     Start { initial: &'a S },
+    /// A phonebook has been loaded or started over, for the
+    /// given reason.
+    ///
+    /// The specified initial state is now current.
+    #[allow(unused)] // used through type aliases, but rustc does not pick it up
+    Reset { initial: &'a S, reason: ResetReason },
     /// The phonebook has progressed to a terminal state.
     #[allow(unused)] // used through type aliases, but rustc does not pick it up
     Finish { terminal: &'a S },
@@ -34,6 +48,22 @@ pub enum Event<'a, S> {
     },
 }
 
+/// Why a machine started over with the initial state.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub enum ResetReason {
+    /// The first phonebook has been loaded at startup.
+    #[serde(rename = "initial_load")]
+    InitialLoad,
+    /// The same phonebook started over, e.g. after reaching a
+    /// terminal state or on request of a remote control client.
+    #[serde(rename = "rewind")]
+    PhonebookRewind,
+    /// Another phonebook has been loaded in place of the previous
+    /// one.
+    #[serde(rename = "switch")]
+    PhonebookSwitch,
+}
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum ResponderState {
     /// The responder finished its behavior or has never done anything.
@@ -65,3 +95,15 @@ pub trait Responder<S> {
         Ok(ResponderState::Idle)
     }
 }
+
+/// Shared responders, e.g. actuators that can also be stopped
+/// from outside the machine.
+impl<S, R: Responder<S>> Responder<S> for Rc<RefCell<R>> {
+    fn respond(&mut self, event: &Event<S>) -> Result<()> {
+        self.borrow_mut().respond(event)
+    }
+
+    fn update(&mut self) -> Result<ResponderState> {
+        self.borrow_mut().update()
+    }
+}
@@ -1,15 +1,17 @@
 //! Core functionality used by the runner in `main.js`
 //! and also for headless integration tests.
 //!
-//! Exports `AppBuilder`, `App` and `Phone` as the only
-//! interface to the core functionality for client code.
+//! Exports `AppBuilder`, `App` and `Phone` as the main
+//! interface to the core functionality for client code,
+//! sounds in `acts` for playing audio directly, and
+//! responders in `evt` for observing a running app.
+//! `Machine` evaluates the `State`s of a phonebook without an
+//! app, with input from `Sensors`.
 
 #[cfg(test)]
 mod testutil;
 
-mod acts;
 mod err;
-mod evt;
 mod phone;
 mod result;
 mod senses;
@@ -17,10 +19,14 @@ mod serve;
 mod states;
 mod util;
 
+pub mod acts;
 pub mod app;
 pub mod books;
 pub mod check;
+pub mod evt;
 pub mod log;
 
 pub use app::{App, Builder as AppBuilder};
-pub use phone::Phone;
+pub use phone::{Phone, RetryConfig};
+pub use senses::{Sensors, SensorsBuilder};
+pub use states::{Machine, State};
@@ -1,12 +1,22 @@
 use failure::Error;
 use log::{debug, error, LevelFilter};
 
+#[cfg(test)]
+use log::{Log, Metadata, Record};
+#[cfg(test)]
+use std::cell::RefCell;
 #[cfg(test)]
 use std::sync::Once;
 
 #[cfg(test)]
 static INIT_TEST_LOGGING: Once = Once::new();
 
+#[cfg(test)]
+thread_local! {
+    /// Messages logged on the current thread while capturing.
+    static CAPTURED: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
 /// Initializes logging for normal operation.
 ///
 /// If fails, prints a message once and then never logs anything.
@@ -32,13 +42,55 @@ pub fn init_logging(verbosity_level: Option<u64>) {
 #[cfg(test)]
 pub fn init_test_logging() {
     INIT_TEST_LOGGING.call_once(|| {
-        let _ = env_logger::builder()
+        let logger = env_logger::builder()
             .filter_level(LevelFilter::Debug)
             .is_test(true)
-            .init();
+            .build();
+        let max_level = logger.filter();
+        if log::set_boxed_logger(Box::new(CapturingLogger(logger))).is_ok() {
+            log::set_max_level(max_level);
+        }
     })
 }
 
+/// Calls the given function and returns its result together with
+/// the messages it logged on the current thread.
+#[cfg(test)]
+pub fn capture_logs<T>(f: impl FnOnce() -> T) -> (T, Vec<String>) {
+    init_test_logging();
+    CAPTURED.with(|captured| *captured.borrow_mut() = Some(vec![]));
+    let result = f();
+    let messages = CAPTURED.with(|captured| captured.borrow_mut().take());
+    (result, messages.unwrap_or_default())
+}
+
+/// Logs like `env_logger` and additionally remembers messages
+/// of threads that are capturing.
+#[cfg(test)]
+struct CapturingLogger(env_logger::Logger);
+
+#[cfg(test)]
+impl Log for CapturingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.0.matches(record) {
+            CAPTURED.with(|captured| {
+                if let Some(messages) = captured.borrow_mut().as_mut() {
+                    messages.push(format!("{}", record.args()));
+                }
+            });
+        }
+        self.0.log(record)
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
 /// Logs that the given error is fatal and leads to termination
 /// of the application.
 ///
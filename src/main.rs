@@ -10,19 +10,27 @@
 //!
 //! Also provides CLI access to the hardware check.
 use clap::{self, crate_authors, crate_name, crate_version, Arg, ArgMatches};
-use failure::Error;
+use failure::{bail, format_err, Error};
 use fernspielapparat::{
-    books,
-    check::check_system,
+    app::TerminationCause,
+    books::{self, adjacency_matrix, to_dot, tts_cache, Book, Overrides},
+    check::{benchmark_tick_rate, check_system, lint_phonebook},
     log::{init_logging, log_fatal},
-    App,
+    App, AppBuilder,
 };
-use log::{debug, info, warn};
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::io::stdin;
+use std::path::Path;
 use std::process::exit;
+use std::time::{Duration, Instant};
+use websocket::{client::builder::ClientBuilder, OwnedMessage};
 
 /// When `--serve` is used without a bind point, use this.
 const DEFAULT_ADDRESS: &str = "0.0.0.0";
 const DEFAULT_PORT: &str = "38397";
+/// When `--status` is used without an address, connect to this.
+const STATUS_ADDRESS: &str = "127.0.0.1";
 
 fn main() {
     if bootstrap().is_err() {
@@ -39,8 +47,64 @@ fn bootstrap() -> Result<(), Error> {
             Arg::with_name("phonebook")
                 .help("Phone book to run at startup")
                 .long_help("Path to a phone book to load and run at startup.")
-                .required_unless_one(&["serve", "serve_address", "serve_port", "demo", "test"])
+                .required_unless_one(&[
+                    "url",
+                    "serve",
+                    "serve_address",
+                    "serve_port",
+                    "demo",
+                    "stdin",
+                    "config",
+                    "test",
+                    "benchmark-ticks",
+                    "status",
+                    "lint",
+                ])
                 .conflicts_with("demo")
+                .conflicts_with("stdin")
+                .conflicts_with("test"),
+        )
+        .arg(
+            Arg::with_name("url")
+                .long("url")
+                .value_name("URL")
+                .help("Downloads the phonebook to run at startup")
+                .long_help(
+                    "Downloads a phonebook over HTTP or HTTPS and runs it at startup. YAML, JSON \
+                     and ZIP archives are told apart by the Content-Type of the response. \
+                     A phonebook argument starting with http:// or https:// is treated the same.",
+                )
+                .takes_value(true)
+                .conflicts_with("phonebook")
+                .conflicts_with("demo")
+                .conflicts_with("stdin")
+                .conflicts_with("test"),
+        )
+        .arg(
+            Arg::with_name("stdin")
+                .long("stdin")
+                .help("Reads the phonebook from stdin")
+                .long_help(
+                    "Reads a phonebook in YAML format from stdin until EOF and runs it at startup. \
+                     When stdin is not piped, prompts for the phonebook to be typed in.",
+                )
+                .conflicts_with("demo")
+                .conflicts_with("test"),
+        )
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Loads settings from a YAML configuration file")
+                .long_help(
+                    "Loads settings from a YAML configuration file with the optional keys \
+                     phonebook, serve, i2c_device, i2c_address, tick_interval_ms and \
+                     exit_on_terminal. Explicit command line arguments override the \
+                     configuration file. When using a configuration file, the phone is \
+                     only connected if i2c_device is configured.",
+                )
                 .conflicts_with("test"),
         )
         .arg(
@@ -93,6 +157,19 @@ fn bootstrap() -> Result<(), Error> {
                 .default_value_if("serve", None, DEFAULT_PORT)
                 .default_value_if("serve_address", None, DEFAULT_PORT),
         )
+        .arg(
+            Arg::with_name("ws-backlog")
+                .long("ws-backlog")
+                .help("Connections that may wait for the WebSockets server")
+                .long_help(
+                    "Sets how many connection attempts may wait for being accepted by the \
+                     WebSockets server, e.g. when many clients connect at the same time. \
+                     Defaults to the default of the operating system, usually 128. \
+                     Only has an effect with --serve and on unix systems.",
+                )
+                .takes_value(true)
+                .value_name("N"),
+        )
         .arg(
             Arg::with_name("demo")
                 .short("d")
@@ -108,16 +185,193 @@ fn bootstrap() -> Result<(), Error> {
                     "Instead of starting over, exit with status 0 when reaching a terminal state.",
                 ),
         )
+        .arg(
+            Arg::with_name("variables")
+                .long("variables")
+                .value_name("FILE")
+                .help("JSON file with values for placeholders in speech")
+                .long_help(
+                    "Reads a JSON object of strings, e.g. {\"name\": \"Harry\"}, and fills in \
+                     placeholders like {name} in the speech of the phonebook, wherever it is \
+                     loaded from. The values take precedence over the variables in the \
+                     phonebook.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .help("Reload the phonebook when its file changes")
+                .long_help(
+                    "Watches the phonebook file and switches to the new version when it is saved. \
+                     If the changed phonebook does not compile, the error is logged and the \
+                     current phonebook keeps running.",
+                )
+                .requires("phonebook"),
+        )
+        .arg(
+            Arg::with_name("allow-dead-ends")
+                .long("allow-dead-ends")
+                .help("Only warn about states without transitions")
+                .long_help(
+                    "Compiles the phonebook even if it has states that are not terminal \
+                     but have no transitions, as if it set allow_dead_ends: true. The dead \
+                     ends are logged as warnings instead.",
+                ),
+        )
+        .arg(
+            Arg::with_name("clear-tts-cache")
+                .long("clear-tts-cache")
+                .help("Delete cached speech before starting")
+                .long_help(
+                    "Synthesized speech is cached across restarts in \
+                     $XDG_CACHE_HOME/fernspielapparat/tts or ~/.cache/fernspielapparat/tts. \
+                     Deletes all of it before loading the phonebook, so speech is synthesized \
+                     again, e.g. after installing another voice.",
+                ),
+        )
+        .arg(
+            Arg::with_name("simulate-phone")
+                .long("simulate-phone")
+                .help("Simulate the phone with the keyboard")
+                .long_help(
+                    "Instead of connecting to a phone via I2C, reads keys from the terminal: \
+                     0-9 to dial, p to pick up, h to hang up and r for the recall button. \
+                     Shows the name of the current state. Default when not running on Linux \
+                     and the terminal is interactive.",
+                )
+                .conflicts_with("test"),
+        )
+        .arg(
+            Arg::with_name("line-input")
+                .long("line-input")
+                .help("Read whole lines from stdin as commands")
+                .long_help(
+                    "Reads commands from stdin line by line instead of single characters: \
+                     digits to dial, e.g. 12 or \"dial 12\", p to pick up, h to hang up, \
+                     r to reset the phonebook and q to quit.",
+                )
+                .conflicts_with("simulate-phone"),
+        )
         .arg(
             Arg::with_name("test")
                 .short("t")
                 .long("test")
                 .help("Perform hardware and speech synth check, then exit")
                 .long_help(
-                    "Lets the phone ring and speak for one second as a basic hardware \
-                     check, tries to speak a sentence through speech synthesis, then exits.",
+                    "Polls the phone over I2C, lets it ring for one second as a basic hardware \
+                     check, tries to speak a sentence through speech synthesis, plays a \
+                     test tone, then exits.",
                 ),
         )
+        .arg(
+            Arg::with_name("check-audio-device")
+                .long("check-audio-device")
+                .help("ALSA device to play the test tone on with --test")
+                .long_help(
+                    "Plays the test tone of the hardware check on the ALSA device with the \
+                     given name instead of the default audio device.",
+                )
+                .takes_value(true)
+                .value_name("NAME")
+                .requires("test"),
+        )
+        .arg(
+            Arg::with_name("benchmark-ticks")
+                .long("benchmark-ticks")
+                .help("Measure the overhead of a tick, then exit")
+                .long_help(
+                    "Measures the average time a tick of a phonebook takes without any \
+                     input or sounds, prints it, then exits. Compare with the tick interval \
+                     to estimate how much CPU headroom is left.",
+                )
+                .conflicts_with("phonebook")
+                .conflicts_with("test"),
+        )
+        .arg(
+            Arg::with_name("dump-matrix")
+                .long("dump-matrix")
+                .help("Print the transitions of the phonebook as a matrix, then exit")
+                .long_help(
+                    "Prints a CSV table with a row and a column for every state of the \
+                     phonebook, with a 1 where the state of the row has a direct transition \
+                     to the state of the column, then exits.",
+                )
+                .conflicts_with("serve")
+                .conflicts_with("serve_address")
+                .conflicts_with("serve_port")
+                .conflicts_with("test")
+                .conflicts_with("benchmark-ticks"),
+        )
+        .arg(
+            Arg::with_name("graph")
+                .long("graph")
+                .help("Print the phonebook as a Graphviz graph, then exit")
+                .long_help(
+                    "Prints the states and transitions of the phonebook as a graph in the DOT \
+                     language of Graphviz, then exits. Render it with e.g. \
+                     `fernspielapparat --graph phonebook.yaml | dot -Tsvg -o phonebook.svg`.",
+                )
+                .conflicts_with("serve")
+                .conflicts_with("serve_address")
+                .conflicts_with("serve_port")
+                .conflicts_with("test")
+                .conflicts_with("benchmark-ticks")
+                .conflicts_with("dump-matrix"),
+        )
+        .arg(
+            Arg::with_name("status")
+                .long("status")
+                .help("Print the current state of a running server, then exit")
+                .long_help(&format!(
+                    "Connects to a fernspielapparat running with --serve, prints the name of \
+                     its current state and how long it has been active, then exits. \
+                     Connects to {address}:{port} per default. \
+                     See --addr and --port to connect to another server.",
+                    address = STATUS_ADDRESS,
+                    port = DEFAULT_PORT
+                ))
+                .conflicts_with("phonebook")
+                .conflicts_with("serve")
+                .conflicts_with("test")
+                .conflicts_with("benchmark-ticks"),
+        )
+        .arg(
+            Arg::with_name("validate")
+                .long("validate")
+                .help("Check the phonebook and configuration, then exit")
+                .long_help(
+                    "Checks the phonebook and configuration for errors without running it, \
+                     logs warnings about probably unintended configuration, then exits. \
+                     Exits with a non-zero status if errors were found.",
+                )
+                .requires("phonebook")
+                .conflicts_with("serve")
+                .conflicts_with("serve_address")
+                .conflicts_with("serve_port")
+                .conflicts_with("test"),
+        )
+        .arg(
+            Arg::with_name("lint")
+                .long("lint")
+                .help("Check a phonebook file for problems, then exit")
+                .long_help(
+                    "Checks the phonebook file at the given path without running it, e.g. for \
+                     unreachable states, states that can never be left, undefined sound IDs, \
+                     missing sound files, state IDs defined more than once and transitions \
+                     of states to themselves, then exits. \
+                     Unlike loading the phonebook, all problems are reported at once. \
+                     Exits with a non-zero status if any of the problems is an error.",
+                )
+                .takes_value(true)
+                .value_name("PHONEBOOK")
+                .conflicts_with("phonebook")
+                .conflicts_with("serve")
+                .conflicts_with("serve_address")
+                .conflicts_with("serve_port")
+                .conflicts_with("test")
+                .conflicts_with("benchmark-ticks"),
+        )
         .arg(
             Arg::with_name("quiet")
                 .short("q")
@@ -146,46 +400,306 @@ fn bootstrap() -> Result<(), Error> {
         Some(matches.occurrences_of("verbose"))
     };
     init_logging(verbosity_level);
+    prepare_tts_cache(matches.is_present("clear-tts-cache"));
 
     if matches.is_present("test") {
-        check_system()
-    } else {
-        let result = build_app(matches).and_then(|mut a| {
-            debug!("initialization complete, starting");
-            a.run()
+        check_system(matches.value_of("check-audio-device"))
+    } else if matches.is_present("benchmark-ticks") {
+        println!("average tick duration: {:?}", benchmark_tick_rate());
+        Ok(())
+    } else if matches.is_present("dump-matrix") {
+        let result = load_startup_book(&matches).and_then(|book| {
+            book.ok_or_else(|| format_err!("a phonebook is required to dump the matrix"))
+        });
+
+        match result {
+            Ok(ref book) => print_matrix(book),
+            Err(ref err) => log_fatal(err),
+        }
+
+        result.map(|_| ())
+    } else if matches.is_present("graph") {
+        let result = load_startup_book(&matches).and_then(|book| {
+            book.ok_or_else(|| format_err!("a phonebook is required to print the graph"))
         });
 
         match result {
-            Ok(_) => debug!("exiting after normal operation."),
+            Ok(ref book) => print!("{}", to_dot(book)),
             Err(ref err) => log_fatal(err),
         }
 
+        result.map(|_| ())
+    } else if matches.is_present("status") {
+        // the default bind address of --serve does not apply here
+        let address = if matches.occurrences_of("serve_address") > 0 {
+            matches.value_of("serve_address").unwrap()
+        } else {
+            STATUS_ADDRESS
+        };
+        let port = matches.value_of("serve_port").unwrap_or(DEFAULT_PORT);
+        let result = print_status(&format!("{}:{}", address, port));
+
+        if let Err(ref err) = result {
+            log_fatal(err);
+        }
+
+        result
+    } else if let Some(path) = matches.value_of("lint") {
+        let result = lint_phonebook(path).and_then(|report| {
+            for warning in &report.warnings {
+                if warning.kind.is_error() {
+                    error!("{}", warning);
+                } else {
+                    warn!("{}", warning);
+                }
+            }
+
+            if report.has_errors() {
+                Err(format_err!("found errors in {}", path))
+            } else {
+                info!("lint successful, {} warnings", report.warnings.len());
+                Ok(())
+            }
+        });
+
+        if let Err(ref err) = result {
+            log_fatal(err);
+        }
+
         result
+    } else if matches.is_present("validate") {
+        let result = configure_app(&matches).and_then(|a| a.validate());
+
+        match result {
+            Ok(ref warnings) => {
+                for warning in warnings {
+                    warn!("{}", warning);
+                }
+                info!("validation successful, {} warnings", warnings.len());
+            }
+            Err(ref err) => log_fatal(err),
+        }
+
+        result.map(|_| ())
+    } else {
+        let result = configure_app(&matches)
+            .and_then(AppBuilder::build)
+            .and_then(|mut a| {
+                debug!("initialization complete, starting");
+                a.run()
+            });
+
+        match result {
+            Ok(TerminationCause::Signal) => debug!("exiting after termination signal."),
+            Ok(TerminationCause::TerminalState) => debug!("exiting after reaching terminal state."),
+            Ok(TerminationCause::ShutdownCommand) => debug!("exiting after quit command."),
+            Err(ref err) => log_fatal(err),
+        }
+
+        result.map(|_| ())
     }
 }
 
-fn build_app(matches: ArgMatches) -> Result<App, Error> {
-    let mut app = App::builder();
+/// Clears the cache of synthesized speech if requested, or
+/// otherwise prunes entries that have not been used in a while.
+fn prepare_tts_cache(clear: bool) {
+    if clear {
+        match tts_cache::clear() {
+            Ok(()) => info!("cleared cached speech."),
+            Err(e) => warn!("failed to clear cached speech: {}", e),
+        }
+    } else {
+        match tts_cache::prune(tts_cache::MAX_AGE) {
+            Ok(0) => (),
+            Ok(pruned) => debug!("pruned {} unused cached speech files.", pruned),
+            Err(e) => warn!("failed to prune cached speech: {}", e),
+        }
+    }
+}
 
-    if matches.is_present("demo") || matches.is_present("phonebook") {
-        app.startup_phonebook(if matches.is_present("demo") {
-            books::from_str(include_str!("../resources/demo.yaml"))?
-        } else {
-            books::from_path(matches.value_of("phonebook").unwrap_or(""))?
-        });
+/// Loads the phonebook from the source specified on the command
+/// line, or returns `None` if no phonebook was specified.
+fn load_startup_book(matches: &ArgMatches) -> Result<Option<Book>, Error> {
+    let overrides = overrides_from_args(matches)?;
+    if matches.is_present("demo") {
+        Ok(Some(books::from_bytes_with_overrides(
+            include_bytes!("../resources/demo.yaml"),
+            &overrides,
+        )?))
+    } else if matches.is_present("stdin") {
+        if atty::is(atty::Stream::Stdin) {
+            eprintln!("Enter phonebook YAML, then finish with EOF (Ctrl+D):");
+        }
+        Ok(Some(books::from_reader_with_overrides(
+            stdin().lock(),
+            &overrides,
+        )?))
+    } else if let Some(url) = startup_book_url(matches) {
+        Ok(Some(books::from_url_with_overrides(url, &overrides)?))
+    } else if let Some(path) = startup_book_path(matches) {
+        Ok(Some(books::from_path_with_overrides(path, &overrides)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Variables and other settings from the command line that take
+/// precedence over the ones in the phonebook.
+fn overrides_from_args(matches: &ArgMatches) -> Result<Overrides, Error> {
+    let variables = match matches.value_of("variables") {
+        Some(variables) => books::load_variables(variables)?,
+        None => HashMap::new(),
+    };
+    Ok(Overrides {
+        variables,
+        allow_dead_ends: matches.is_present("allow-dead-ends"),
+    })
+}
+
+/// The phonebook file to run at startup, unless the demo or
+/// a phonebook from stdin take precedence.
+fn startup_book_path<'a>(matches: &'a ArgMatches) -> Option<&'a str> {
+    if matches.is_present("demo") || matches.is_present("stdin") {
+        None
+    } else {
+        matches.value_of("phonebook").filter(|path| !is_url(path))
+    }
+}
+
+/// The URL of the phonebook to download and run at startup, if any.
+fn startup_book_url<'a>(matches: &'a ArgMatches) -> Option<&'a str> {
+    matches
+        .value_of("url")
+        .or_else(|| matches.value_of("phonebook").filter(|path| is_url(path)))
+}
+
+fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// Prints the adjacency matrix of the book as CSV, with the
+/// state IDs as row and column headers.
+fn print_matrix(book: &Book) {
+    let ids: Vec<&str> = book.states().iter().map(|s| s.id()).collect();
+    println!(",{}", ids.join(","));
+    for (id, row) in ids.iter().zip(adjacency_matrix(book)) {
+        let cells: Vec<&str> = row.iter().map(|&t| if t { "1" } else { "0" }).collect();
+        println!("{},{}", id, cells.join(","));
+    }
+}
+
+/// Time to wait for the status before giving up, e.g. when the
+/// server is too busy to answer.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Queries the status of the server at the given address and
+/// port and prints the name of the current state and how long
+/// it has been active.
+fn print_status(address: &str) -> Result<(), Error> {
+    let url = format!("ws://{}/", address);
+    let mut client = ClientBuilder::new(&url)?
+        .add_protocol("fernspielctl")
+        .connect_insecure()
+        .map_err(|e| format_err!("failed to connect to {}: {}", url, e))?;
+
+    client.send_message(&OwnedMessage::Text(
+        "{ invoke: query, with: status }".to_string(),
+    ))?;
+
+    // skip events about other things until the status arrives
+    let deadline = Instant::now() + STATUS_TIMEOUT;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::from_secs(0) {
+            bail!("{} sent no status within {:?}", url, STATUS_TIMEOUT);
+        }
+        client.stream_ref().set_read_timeout(Some(remaining))?;
+
+        match client.recv_message() {
+            Ok(OwnedMessage::Text(text)) => {
+                let event: serde_yaml::Value = serde_yaml::from_str(&text)?;
+                if event["type"] == "status" {
+                    let name = event["name"].as_str().unwrap_or_default();
+                    let elapsed = event["elapsed_ms"].as_u64().unwrap_or_default();
+                    println!("{} for {:?}", name, Duration::from_millis(elapsed));
+                    client.send_message(&OwnedMessage::Close(None)).ok();
+                    return Ok(());
+                }
+            }
+            Ok(OwnedMessage::Close(_)) => break,
+            Ok(_) => (),
+            // the read timed out
+            Err(_) if Instant::now() >= deadline => {
+                bail!("{} sent no status within {:?}", url, STATUS_TIMEOUT)
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Err(format_err!(
+        "{} closed the connection without a status",
+        url
+    ))
+}
+
+fn configure_app(matches: &ArgMatches) -> Result<AppBuilder, Error> {
+    let overrides = overrides_from_args(matches)?;
+    App::builder_try(|app| {
+        // overrides need to be known before the configuration
+        // file loads its phonebook
+        app.variables(overrides.variables)
+            .allow_dead_ends(overrides.allow_dead_ends);
+        match matches.value_of("config") {
+            Some(config) => {
+                app.yaml_config(config)?;
+                configure_from_args(app, matches, true)
+            }
+            None => configure_from_args(app, matches, false),
+        }
+    })
+}
+
+/// Applies the command line arguments on top of the configuration
+/// file, if any.
+fn configure_from_args<'a>(
+    app: &'a mut AppBuilder,
+    matches: &ArgMatches,
+    has_config: bool,
+) -> Result<&'a mut AppBuilder, Error> {
+    if let Some(path) = startup_book_path(matches) {
+        app.startup_phonebook_path(Path::new(path))?;
+    } else if let Some(book) = load_startup_book(matches)? {
+        app.startup_phonebook(book);
     }
 
-    app.terminate_on_ctrlc_and_sigterm();
+    if matches.is_present("watch") {
+        app.reload_on_change();
+    }
+
+    app.terminate_on_ctrlc_and_sigterm()
+        .terminate_on_background_panic();
 
+    // rewinding is the default, unless configured otherwise
     if matches.is_present("exit-on-terminal") {
         app.exit_on_terminal_state();
-    } else {
-        app.rewind_on_terminal_state();
     }
 
-    match app.phone("/dev/i2c-1", 4) {
-        Ok(_) => info!("phone connected on dev/i2c-1, address 4."),
-        Err(e) => warn!("no phone available, error: {}", e),
+    let simulate_phone = matches.is_present("simulate-phone")
+        || (cfg!(not(target_os = "linux")) && atty::is(atty::Stream::Stdin));
+    if simulate_phone {
+        app.simulate_phone();
+    }
+
+    if matches.is_present("line-input") {
+        app.stdin_line_mode();
+    }
+
+    // with a configuration file, the phone is configured there
+    if !has_config && !simulate_phone {
+        match app.phone("/dev/i2c-1", 4) {
+            Ok(_) => info!("phone connected on dev/i2c-1, address 4."),
+            Err(e) => warn!("no phone available, error: {}", e),
+        }
     }
 
     let some_serve_arg_present = matches.is_present("serve")
@@ -207,8 +721,17 @@ fn build_app(matches: ArgMatches) -> Result<App, Error> {
             bind_to = bind_to
         );
 
+        if let Some(backlog) = matches.value_of("ws-backlog") {
+            let backlog = backlog
+                .parse()
+                .map_err(|e| format_err!("invalid --ws-backlog {}: {}", backlog, e))?;
+            app.ws_backlog(backlog);
+        }
+
+        // replaces the address of the configuration file, if any
         app.serve(bind_to)?;
+        app.set_wait_for_remote_phonebook();
     }
 
-    Ok(app.build()?)
+    Ok(app)
 }
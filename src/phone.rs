@@ -1,5 +1,44 @@
+use std::time::Duration;
+
 pub type Result<T> = std::result::Result<T, std::io::Error>;
 
+/// Controls how often and how long to wait before retrying
+/// when the phone does not acknowledge I2C messages.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryConfig {
+    /// Maximum number of attempts.
+    pub retries: u32,
+    /// Before the n-th retry, wait `base_ms^n` milliseconds.
+    pub base_ms: u64,
+    /// Adds a random delay of up to this fraction of `base_ms`
+    /// to every wait, so devices on a shared bus do not retry
+    /// at the same time.
+    pub jitter: f64,
+}
+
+impl RetryConfig {
+    /// Time to wait before the retry with the given number,
+    /// starting at one.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    fn delay(&self, attempt: u32) -> Duration {
+        let backoff = Duration::from_millis(self.base_ms.pow(attempt));
+        let max_jitter = self.base_ms as f64 * self.jitter.max(0.0) / 1000.0;
+        backoff + Duration::from_secs_f64(rand::random::<f64>() * max_jitter)
+    }
+}
+
+impl Default for RetryConfig {
+    /// First wait 5ms, then 25, then 125, ... up until 78_125ms,
+    /// each with up to 5ms of jitter.
+    fn default() -> Self {
+        RetryConfig {
+            retries: 8,
+            base_ms: 5,
+            jitter: 1.0,
+        }
+    }
+}
+
 #[cfg(target_os = "linux")]
 pub use linux::*;
 
@@ -8,7 +47,7 @@ pub use mock::*;
 
 #[cfg(target_os = "linux")]
 mod linux {
-    use super::Result;
+    use super::{Result, RetryConfig};
 
     use crate::senses::Input;
 
@@ -21,15 +60,11 @@ mod linux {
 
     type I2c = i2c_linux::I2c<File>;
 
-    // First wait 5ms, then 25, then 125, ... up  until 390_625ms
-    const RETRIES: u32 = 8;
-    const RETRY_BASE_MS: u64 = 5;
-
     pub struct Phone {
         i2c: I2c,
         /// Error code 121 is apparently returned from SMBus if
         /// no partner sent ACK. Retry a few times if this happens.
-        retries: u32,
+        retry: RetryConfig,
     }
 
     enum Msg {
@@ -50,13 +85,18 @@ mod linux {
 
     impl Phone {
         pub fn connect(i2c_device: &str, address: u16) -> Result<Self> {
+            Self::connect_with_config(i2c_device, address, RetryConfig::default())
+        }
+
+        pub fn connect_with_config(
+            i2c_device: &str,
+            address: u16,
+            retry: RetryConfig,
+        ) -> Result<Self> {
             let mut i2c = I2c::from_path(i2c_device)?;
             i2c.smbus_set_slave_address(address, false)?;
 
-            Ok(Phone {
-                i2c,
-                retries: RETRIES,
-            })
+            Ok(Phone { i2c, retry })
         }
 
         /// Tries to poll for input and takes a maximum of
@@ -65,25 +105,26 @@ mod linux {
         /// For a healthy connection, this should always
         /// return something, e.g. consecutive hangups.
         pub fn poll(&mut self) -> Result<Input> {
-            with_retries(self.retries, || self.i2c.smbus_read_byte()).and_then(Self::decode_input)
+            with_retries(self.retry, sleep, || self.i2c.smbus_read_byte())
+                .and_then(Self::decode_input)
         }
 
         pub fn ring(&mut self) -> Result<()> {
-            with_retries(self.retries, || {
+            with_retries(self.retry, sleep, || {
                 debug!("Ring start");
                 self.send(Msg::StartRing)
             })
         }
 
         pub fn unring(&mut self) -> Result<()> {
-            with_retries(self.retries, || {
+            with_retries(self.retry, sleep, || {
                 debug!("Ring end");
                 self.send(Msg::StopRing)
             })
         }
 
         fn send(&mut self, msg: Msg) -> Result<()> {
-            with_retries(self.retries, || {
+            with_retries(self.retry, sleep, || {
                 self.i2c.smbus_write_byte_data(msg.as_u8(), msg.as_u8())?;
                 Ok(())
             })
@@ -98,8 +139,8 @@ mod linux {
                 // 10 => // TODO general error
                 11 => Ok(Input::hang_up()),
                 12 => Ok(Input::pick_up()),
-                //13 => // TODO RECALL PRESS
-                //14 => // TODO RECALL RELEASE
+                13 => Ok(Input::recall_press()),
+                14 => Ok(Input::recall_release()),
                 255 => Err(Error::new(
                     ErrorKind::WouldBlock,
                     "Phone send buffer was empty",
@@ -112,19 +153,20 @@ mod linux {
         }
     }
 
-    fn with_retries<F, R>(retries: u32, mut trial: F) -> Result<R>
+    fn with_retries<W, F, R>(retry: RetryConfig, mut wait: W, mut trial: F) -> Result<R>
     where
+        W: FnMut(Duration),
         F: FnMut() -> Result<R>,
     {
         // Ignore errors retries minus 1 times
-        for attempt in 1_u32..retries {
+        for attempt in 1_u32..retry.retries {
             match trial() {
                 // Succeeded, ok
                 ok @ Ok(_) => return ok,
                 Err(e) => {
                     if e.raw_os_error() == Some(121) {
                         // 121, this may still succeed later, retry with exponential backoff
-                        sleep(Duration::from_millis(RETRY_BASE_MS.pow(attempt)))
+                        wait(retry.delay(attempt))
                     } else {
                         // everything else is probably fatal
                         return Err(e);
@@ -136,13 +178,83 @@ mod linux {
         // If the last is also 121, return it, or maybe we are lucky
         trial()
     }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn retry_delays_have_jitter() {
+            // given
+            let retry = RetryConfig {
+                retries: 4,
+                base_ms: 5,
+                jitter: 1.0,
+            };
+            let mut attempts = 0;
+            let mut waits = Vec::new();
+
+            // when
+            for _ in 0..10 {
+                let result: Result<()> = with_retries(
+                    retry,
+                    |delay| waits.push(delay),
+                    || {
+                        attempts += 1;
+                        Err(Error::from_raw_os_error(121))
+                    },
+                );
+                assert!(result.is_err());
+            }
+
+            // then
+            assert_eq!(attempts, 40);
+            assert_eq!(waits.len(), 30);
+            let jitters: Vec<Duration> = waits
+                .iter()
+                .enumerate()
+                .map(|(idx, wait)| {
+                    let backoff = Duration::from_millis(5_u64.pow(idx as u32 % 3 + 1));
+                    assert!(*wait >= backoff && *wait <= backoff + Duration::from_millis(5));
+                    *wait - backoff
+                })
+                .collect();
+            assert!(
+                jitters.iter().any(|j| *j != jitters[0]),
+                "Expected jitter to vary, but got {:?}",
+                jitters
+            );
+        }
+
+        #[test]
+        fn other_errors_are_not_retried() {
+            // given
+            let mut attempts = 0;
+            let mut waits = Vec::new();
+
+            // when
+            let result: Result<()> = with_retries(
+                RetryConfig::default(),
+                |delay| waits.push(delay),
+                || {
+                    attempts += 1;
+                    Err(Error::from(ErrorKind::NotFound))
+                },
+            );
+
+            // then
+            assert!(result.is_err());
+            assert_eq!(attempts, 1);
+            assert!(waits.is_empty());
+        }
+    }
 }
 
 /// Placeholder for a phone that can never be there because the target OS
 /// is not linux, which is the only platform we support i2c for.
 #[cfg(not(target_os = "linux"))]
 mod mock {
-    use super::Result;
+    use super::{Result, RetryConfig};
     use crate::senses::Input;
 
     use std::io::{Error, ErrorKind};
@@ -151,7 +263,15 @@ mod mock {
     pub enum Phone {}
 
     impl Phone {
-        pub fn connect(_i2c_device: &str, _address: u16) -> Result<Self> {
+        pub fn connect(i2c_device: &str, address: u16) -> Result<Self> {
+            Self::connect_with_config(i2c_device, address, RetryConfig::default())
+        }
+
+        pub fn connect_with_config(
+            _i2c_device: &str,
+            _address: u16,
+            _retry: RetryConfig,
+        ) -> Result<Self> {
             Err(Error::new(
                 ErrorKind::NotFound,
                 "I2C phone is not supported on this platform.",
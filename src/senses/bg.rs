@@ -18,9 +18,12 @@ impl BackgroundSense {
     pub fn spawn(sense: Box<dyn Sense + Send>, poll_interval: Option<Duration>) -> Box<dyn Sense> {
         // 0: Block when four unconsumed inputs in the queue
         let (tx, rx) = bounded(4);
-        thread::spawn(move || {
-            keep_polling(sense, poll_interval, tx);
-        });
+        thread::Builder::new()
+            .name("sense-poll".into())
+            .spawn(move || {
+                keep_polling(sense, poll_interval, tx);
+            })
+            .expect("failed to spawn sensor thread");
         Box::new(BackgroundSense(rx))
     }
 }
@@ -1,5 +1,11 @@
 pub use err::Error;
 
+use serde::de::{self, Deserialize, Deserializer};
+use serde::{Serialize, Serializer};
+
+use std::fmt;
+use std::str::FromStr;
+
 /// Anything you can input with a phone dial,
 /// including special characters like _#_,
 /// picking up the speaker and hanging up.
@@ -9,6 +15,10 @@ pub enum Input {
     Digit(u8),
     PickUp,
     HangUp,
+    /// The flash/recall button was pressed down.
+    RecallPress,
+    /// The flash/recall button was let go.
+    RecallRelease,
 }
 
 impl Input {
@@ -31,6 +41,60 @@ impl Input {
     pub fn hang_up() -> Self {
         Input::HangUp
     }
+
+    pub fn recall_press() -> Self {
+        Input::RecallPress
+    }
+
+    pub fn recall_release() -> Self {
+        Input::RecallRelease
+    }
+}
+
+/// Formats as `"type 0"` to `"type 9"`, `"pick up"`, `"hang up"`,
+/// `"press recall"` or `"release recall"`.
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Input::Digit(num) => write!(f, "type {}", num),
+            Input::PickUp => write!(f, "pick up"),
+            Input::HangUp => write!(f, "hang up"),
+            Input::RecallPress => write!(f, "press recall"),
+            Input::RecallRelease => write!(f, "release recall"),
+        }
+    }
+}
+
+/// Parses the format produced by `Display`.
+impl FromStr for Input {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s.trim() {
+            "pick up" => Ok(Input::PickUp),
+            "hang up" => Ok(Input::HangUp),
+            "press recall" => Ok(Input::RecallPress),
+            "release recall" => Ok(Input::RecallRelease),
+            other => match other.strip_prefix("type ").map(|n| n.trim().parse::<i32>()) {
+                Some(Ok(num)) => Input::digit(num),
+                _ => Err(Error::Unrecognized(s.to_string())),
+            },
+        }
+    }
+}
+
+impl Serialize for Input {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Input {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
 }
 
 mod err {
@@ -40,6 +104,11 @@ mod err {
     pub enum Error {
         #[fail(display = "digit {} was not in range [0,9]", _0)]
         DigitOutOfBounds(i32),
+        #[fail(
+            display = "unrecognized input {:?}, expected \"type 0\" to \"type 9\", \"pick up\", \"hang up\", \"press recall\" or \"release recall\"",
+            _0
+        )]
+        Unrecognized(String),
     }
 }
 
@@ -59,4 +128,57 @@ mod test {
     fn too_high_ten() {
         Input::digit(10).unwrap();
     }
+
+    #[test]
+    fn string_round_trip() {
+        // given
+        let inputs: Vec<Input> = (0..10)
+            .map(|n| Input::digit(n).unwrap())
+            .chain(vec![
+                Input::pick_up(),
+                Input::hang_up(),
+                Input::recall_press(),
+                Input::recall_release(),
+            ])
+            .collect();
+
+        // when
+        let round_tripped: Vec<Input> = inputs
+            .iter()
+            .map(|i| i.to_string().parse().unwrap())
+            .collect();
+
+        // then
+        assert_eq!(inputs, round_tripped);
+    }
+
+    #[test]
+    fn display_format() {
+        assert_eq!(Input::digit(1).unwrap().to_string(), "type 1");
+        assert_eq!(Input::pick_up().to_string(), "pick up");
+        assert_eq!(Input::hang_up().to_string(), "hang up");
+        assert_eq!(Input::recall_press().to_string(), "press recall");
+        assert_eq!(Input::recall_release().to_string(), "release recall");
+    }
+
+    #[test]
+    fn parse_unrecognized() {
+        assert!("type 10".parse::<Input>().is_err());
+        assert!("type".parse::<Input>().is_err());
+        assert!("dial 1".parse::<Input>().is_err());
+        assert!("".parse::<Input>().is_err());
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        // given
+        let inputs = vec![Input::digit(7).unwrap(), Input::pick_up(), Input::hang_up()];
+
+        // when
+        let yaml = serde_yaml::to_string(&inputs).unwrap();
+        let round_tripped: Vec<Input> = serde_yaml::from_str(&yaml).unwrap();
+
+        // then
+        assert_eq!(inputs, round_tripped);
+    }
 }
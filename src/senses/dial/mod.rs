@@ -5,5 +5,5 @@ mod stdin;
 
 pub use hardware::HardwareDial;
 pub use input::Input;
-pub use queue::{Queue, QueueInput};
-pub use stdin::Stdin as StdinDial;
+pub use queue::{Queue, QueueInput, QueuedInput};
+pub use stdin::{Stdin as StdinDial, StdinSignals};
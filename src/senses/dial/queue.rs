@@ -1,24 +1,46 @@
-use crate::senses::{Error, Input, Sense};
-use crossbeam_channel::{unbounded, Receiver, Sender, TryRecvError};
+use crate::senses::{Error, Input};
+use crate::serve::ConnectionHandle;
+use crossbeam_channel::{unbounded, Receiver, SendError, Sender, TryRecvError};
 use failure::format_err;
 
-pub type QueueInput = Sender<Input>;
+/// An input along with the remote control connection that dialed
+/// it, if any.
+pub type QueuedInput = (Input, Option<ConnectionHandle>);
+
+/// Sending end of a `Queue`.
+#[derive(Clone)]
+pub struct QueueInput(Sender<QueuedInput>);
+
+impl QueueInput {
+    /// Enqueues input that was not dialed remotely.
+    pub fn send(&self, input: Input) -> Result<(), SendError<QueuedInput>> {
+        self.0.send((input, None))
+    }
+
+    /// Enqueues input that was dialed by the given remote control
+    /// connection, if any, so transitions can be attributed to it.
+    pub fn send_from(
+        &self,
+        input: Input,
+        source: Option<ConnectionHandle>,
+    ) -> Result<(), SendError<QueuedInput>> {
+        self.0.send((input, source))
+    }
+}
 
 /// FIFO structure where inputs from different sources, e.g.
 /// remote control can come in in bulk and are then emitted
 /// per timestep.
-pub struct Queue(Receiver<Input>);
+pub struct Queue(Receiver<QueuedInput>);
 
 impl Queue {
     pub fn new() -> (Self, QueueInput) {
         let (tx, rx) = unbounded();
-        (Queue(rx), tx)
+        (Queue(rx), QueueInput(tx))
     }
-}
 
-impl Sense for Queue {
-    /// Tries to get the next input from stdin, if any.
-    fn poll(&mut self) -> Result<Input, Error> {
+    /// Tries to get the next input from the queue, if any.
+    pub fn poll(&mut self) -> Result<QueuedInput, Error> {
         self.0.try_recv().map_err(|e| match e {
             TryRecvError::Empty => Error::WouldBlock,
             TryRecvError::Disconnected => {
@@ -1,19 +1,89 @@
 use crate::senses::dial::Input;
 use crate::senses::{Error, Sense};
-use std::io::{stdin, Read};
+use log::debug;
+use std::collections::VecDeque;
+use std::io::{stdin, BufRead, Read};
+use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+use std::sync::Arc;
 
 /// A dial that reads from stdin.
 pub struct Stdin {
     buf: [u8; 1],
     last_input: Option<Input>,
+    /// If `true`, whole lines are read and parsed as commands.
+    line_mode: bool,
+    /// Inputs of the last line that have not been polled yet.
+    pending: VecDeque<Input>,
+    signals: StdinSignals,
+}
+
+/// Flags that are set by commands in line mode that are not
+/// dial input.
+#[derive(Clone, Debug, Default)]
+pub struct StdinSignals {
+    reset: Arc<AtomicBool>,
+    quit: Arc<AtomicBool>,
+}
+
+/// What a line of input means in line mode.
+#[derive(Debug, PartialEq)]
+enum Command {
+    Dial(Vec<Input>),
+    Reset,
+    Quit,
+    Ignore,
 }
 
 impl Sense for Stdin {
     /// Tries to get the next input from stdin, if any.
     fn poll(&mut self) -> Result<Input, Error> {
+        let next_input = if self.line_mode {
+            self.poll_line()
+        } else {
+            self.poll_byte()
+        };
+
+        if let Some(next_input) = next_input {
+            Ok(next_input)
+        } else {
+            Err(Error::WouldBlock)
+        }
+    }
+}
+
+impl Stdin {
+    /// Locks on stdin.
+    pub fn new() -> Stdin {
+        Stdin {
+            buf: [0],
+            last_input: None,
+            line_mode: false,
+            pending: VecDeque::new(),
+            signals: StdinSignals::default(),
+        }
+    }
+
+    /// If `true`, reads whole lines instead of single characters
+    /// and parses them as commands: digits to dial, e.g. `12` or
+    /// `dial 12`, `p` to pick up, `h` to hang up, `r` to reset and
+    /// `q` to quit.
+    ///
+    /// Reset and quit set the flags configured with `signals`.
+    pub fn line_mode(&mut self, line_mode: bool) -> &mut Self {
+        self.line_mode = line_mode;
+        self
+    }
+
+    /// Sets the flags for reset and quit commands in line mode.
+    pub fn signals(&mut self, signals: &StdinSignals) -> &mut Self {
+        self.signals = signals.clone();
+        self
+    }
+
+    fn poll_byte(&mut self) -> Option<Input> {
         self.buf[0] = 0;
 
-        let next_input = match stdin().lock().read(&mut self.buf) {
+        match stdin().lock().read(&mut self.buf) {
             Ok(1) => {
                 let next_input = parse_byte_input(self.buf[0]);
                 match (self.last_input, next_input) {
@@ -28,22 +98,38 @@ impl Sense for Stdin {
             // This catches errors on windows for UTF-8, or when non-blocking IO
             // Also catches Ok(0)
             _ => None,
-        };
+        }
+    }
 
-        if let Some(next_input) = next_input {
-            Ok(next_input)
-        } else {
-            Err(Error::WouldBlock)
+    fn poll_line(&mut self) -> Option<Input> {
+        if let Some(input) = self.pending.pop_front() {
+            return Some(input);
         }
+
+        let mut line = String::new();
+        match stdin().lock().read_line(&mut line) {
+            Ok(0) | Err(_) => return None,
+            Ok(_) => (),
+        }
+
+        match parse_line(&line) {
+            Command::Dial(inputs) => self.pending.extend(inputs),
+            Command::Reset => self.signals.reset.store(true, SeqCst),
+            Command::Quit => self.signals.quit.store(true, SeqCst),
+            Command::Ignore => debug!("ignoring unrecognized command {:?}", line.trim()),
+        }
+
+        self.pending.pop_front()
     }
 }
 
-impl Stdin {
-    /// Locks on stdin.
-    pub fn new() -> Stdin {
-        Stdin {
-            buf: [0],
-            last_input: None,
+impl StdinSignals {
+    /// Makes signals that set the given flags on reset and quit
+    /// commands.
+    pub fn new(reset: &Arc<AtomicBool>, quit: &Arc<AtomicBool>) -> Self {
+        StdinSignals {
+            reset: Arc::clone(reset),
+            quit: Arc::clone(quit),
         }
     }
 }
@@ -59,3 +145,41 @@ fn parse_byte_input(byte: u8) -> Option<Input> {
         _ => None,
     }
 }
+
+fn parse_line(line: &str) -> Command {
+    let line = line.trim();
+    match line {
+        "p" => Command::Dial(vec![Input::pick_up()]),
+        "h" => Command::Dial(vec![Input::hang_up()]),
+        "r" | "reset" => Command::Reset,
+        "q" | "quit" => Command::Quit,
+        _ => {
+            let digits = line.strip_prefix("dial").unwrap_or(line).trim();
+            if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+                Command::Dial(digits.bytes().filter_map(parse_byte_input).collect())
+            } else {
+                Command::Ignore
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_line_commands() {
+        assert_eq!(parse_line("p\n"), Command::Dial(vec![Input::pick_up()]));
+        assert_eq!(parse_line("h\n"), Command::Dial(vec![Input::hang_up()]));
+        assert_eq!(parse_line("r\n"), Command::Reset);
+        assert_eq!(parse_line("q\n"), Command::Quit);
+        assert_eq!(parse_line("7\n"), Command::Dial(vec![Input::Digit(7)]));
+        assert_eq!(
+            parse_line("dial 12\n"),
+            Command::Dial(vec![Input::Digit(1), Input::Digit(2)])
+        );
+        assert_eq!(parse_line("dial\n"), Command::Ignore);
+        assert_eq!(parse_line("hello\n"), Command::Ignore);
+    }
+}
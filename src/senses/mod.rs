@@ -4,7 +4,7 @@ mod err;
 mod sense;
 mod sensors;
 
-pub use dial::{Input, Queue, QueueInput};
+pub use dial::{Input, Queue, QueueInput, QueuedInput, StdinSignals};
 pub use err::Error;
 pub use sense::Sense;
 pub use sensors::{Sensors, SensorsBuilder};
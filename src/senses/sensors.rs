@@ -1,12 +1,18 @@
 pub use builder::Builder as SensorsBuilder;
 
-use crate::senses::dial::Input;
+use crate::senses::dial::{Queue, QueueInput, QueuedInput};
 use crate::senses::{Error, Sense};
 use log::error;
 
 /// Runs senses in the background, making it possible to
 /// poll them without blocking.
-pub struct Sensors(Vec<Box<dyn Sense>>);
+pub struct Sensors {
+    senses: Vec<Box<dyn Sense>>,
+    /// Polled after the senses, keeping track of remote sources.
+    queues: Vec<Queue>,
+    /// Queues that receive a copy of every polled input.
+    mirrors: Vec<QueueInput>,
+}
 
 impl Sensors {
     /// Creates a builder for sensors, where background
@@ -16,42 +22,92 @@ impl Sensors {
         SensorsBuilder::new()
     }
 
-    /// Sensors where polled input is always `None`.
+    /// Returns a sensor set that never yields any input, suitable
+    /// for state machines driven entirely by timeouts and actuator
+    /// completion.
     pub fn blind() -> Self {
-        Sensors(vec![])
+        Sensors {
+            senses: vec![],
+            queues: vec![],
+            mirrors: vec![],
+        }
+    }
+
+    /// Forwards a copy of every input that is polled from now
+    /// on to the given queue.
+    ///
+    /// Mirroring stops silently when the receiving end of the
+    /// queue hangs up.
+    pub fn mirror(&mut self, to: &QueueInput) {
+        self.mirrors.push(to.clone());
     }
 
     /// Polls all sensors and exits early if input has
     /// been received.
-    pub fn poll(&mut self) -> Option<Input> {
-        let mut first_input = None;
-        let mut removals = Vec::new();
-        for (idx, sensor) in self.0.iter_mut().enumerate() {
-            match sensor.poll() {
-                Err(Error::Fatal(e)) => {
-                    error!("Giving up on sensor after fatal error: {}", e);
-                    removals.push(idx);
-                }
-                Err(Error::WouldBlock) => (),
-                Ok(input) => {
-                    first_input = Some(input);
-                    break;
-                }
-            }
+    ///
+    /// Input from queues comes with the remote control connection
+    /// that dialed it, if any.
+    pub fn poll(&mut self) -> Option<QueuedInput> {
+        let first_input = self.poll_senses();
+
+        if let Some((input, _)) = first_input {
+            self.mirrors.retain(|mirror| mirror.send(input).is_ok());
         }
 
-        for idx in removals {
-            self.0.swap_remove(idx);
+        first_input
+    }
+
+    /// Takes all input that is currently available from the
+    /// senses, without mirroring it.
+    pub fn drain(&mut self) -> Vec<QueuedInput> {
+        let mut drained = Vec::new();
+        while let Some(input) = self.poll_senses() {
+            drained.push(input);
         }
+        drained
+    }
 
-        first_input
+    fn poll_senses(&mut self) -> Option<QueuedInput> {
+        poll_first(&mut self.senses, |sense| {
+            sense.poll().map(|input| (input, None))
+        })
+        .or_else(|| poll_first(&mut self.queues, Queue::poll))
+    }
+}
+
+/// Polls the given sources in order until one has input and
+/// removes the sources that failed fatally.
+fn poll_first<T>(
+    sources: &mut Vec<T>,
+    mut poll: impl FnMut(&mut T) -> Result<QueuedInput, Error>,
+) -> Option<QueuedInput> {
+    let mut first_input = None;
+    let mut removals = Vec::new();
+    for (idx, source) in sources.iter_mut().enumerate() {
+        match poll(source) {
+            Err(Error::Fatal(e)) => {
+                error!("Giving up on sensor after fatal error: {}", e);
+                removals.push(idx);
+            }
+            Err(Error::WouldBlock) => (),
+            Ok(input) => {
+                first_input = Some(input);
+                break;
+            }
+        }
+    }
+
+    for idx in removals {
+        sources.swap_remove(idx);
     }
+
+    first_input
 }
 
 mod builder {
     use super::{Sense, Sensors};
     use crate::senses::bg::BackgroundSense;
-    use crate::senses::dial::{HardwareDial, Queue, QueueInput, StdinDial};
+    use crate::senses::dial::{HardwareDial, Queue, QueueInput, StdinDial, StdinSignals};
     use crate::Phone;
 
     use std::sync::{Arc, Mutex};
@@ -59,17 +115,15 @@ mod builder {
 
     const POLL_INTERVAL: Duration = Duration::from_millis(150);
 
+    #[derive(Default)]
     pub struct Builder {
         may_block: Vec<Box<dyn Sense + Send>>,
-        non_blocking: Vec<Box<dyn Sense>>,
+        queues: Vec<Queue>,
     }
 
     impl Builder {
         pub fn new() -> Self {
-            Builder {
-                may_block: Vec::new(),
-                non_blocking: Vec::new(),
-            }
+            Self::default()
         }
 
         /// Enables background input via the given sense
@@ -82,11 +136,6 @@ mod builder {
             self
         }
 
-        fn non_blocking(&mut self, sense: impl Sense + 'static) -> &mut Self {
-            self.non_blocking.push(Box::new(sense));
-            self
-        }
-
         /// Enables input from stdin. It accepts 0-9 (dial),
         /// h (hang up) and p (pick up). Newlines may be required
         /// for flushing.
@@ -94,24 +143,40 @@ mod builder {
             self.background(StdinDial::new())
         }
 
+        /// Enables input from stdin, reading whole lines as
+        /// commands, see `StdinDial::line_mode`.
+        pub fn stdin_lines(&mut self, signals: &StdinSignals) -> &mut Self {
+            let mut dial = StdinDial::new();
+            dial.line_mode(true).signals(signals);
+            self.background(dial)
+        }
+
         pub fn i2c_dial(&mut self, phone: &Arc<Mutex<Phone>>) -> &mut Self {
             self.background(HardwareDial::new(phone))
         }
 
+        /// A builder without any senses, so the built sensors never
+        /// yield any input, like `Sensors::blind`.
+        pub fn no_input() -> Self {
+            Self::new()
+        }
+
         pub fn queue(&mut self) -> (&mut Self, QueueInput) {
             let (queue, input) = Queue::new();
-            self.non_blocking(queue);
+            self.queues.push(queue);
             (self, input)
         }
 
         pub fn build(self) -> Sensors {
-            Sensors(
-                self.may_block
+            Sensors {
+                senses: self
+                    .may_block
                     .into_iter()
                     .map(|sensor| BackgroundSense::spawn(sensor, Some(POLL_INTERVAL)))
-                    .chain(self.non_blocking.into_iter())
                     .collect(),
-            )
+                queues: self.queues,
+                mirrors: vec![],
+            }
         }
     }
 }
@@ -1,67 +1,96 @@
+use super::bind::bind;
 use super::decoder::Decoder;
 use super::handle::{ConnectionHandle, ConnectionHandleGenerator};
-use super::relay::Relay;
+use super::relay::{Address, Relay};
 use super::ws::{WebSocketClient, WebSocketServer, WebSocketUpgrade};
 
 use crate::result::Result;
-use crate::serve::{FernspielEvent, Request};
+use crate::serve::{FernspielEvent, Format, Request};
 
-use crossbeam_channel::{bounded, Receiver, Sender, TrySendError, select};
+use crossbeam_channel::{bounded, select, Receiver, Sender, TryRecvError, TrySendError};
 use failure::{bail, format_err};
 use log::{debug, error, info, trace};
-use std::thread::spawn;
+use std::thread::{sleep, Builder, JoinHandle};
+use std::time::Duration;
 
 const WS_PROTOCOL: &str = "fernspielctl";
 
+/// Header clients can use to ask for events in a format other
+/// than the default, e.g. `json`.
+const EVENT_FORMAT_HEADER: &str = "Fernspiel-Event-Format";
+
+/// Time to wait before checking for new connections again when
+/// none were pending.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
 /// Receives requests from websocket connections, negotiates the `fernspielctl`
 /// protocol, and registers the new connections with the relay. A decoder thread
 /// is launched for each new connection that decodes incoming requests and compiles
 /// phonebooks.
 pub struct Acceptor {
-    channel: Sender<Request>,
+    channel: Sender<(ConnectionHandle, Request)>,
     relay: Relay,
     handle_gen: ConnectionHandleGenerator,
+    /// Event format for clients that do not ask for one.
+    default_format: Format,
     shutdown_signal: Receiver<()>,
+    /// Disconnects on drop, which stops the listener.
+    stop_listening: Option<Sender<()>>,
+    listener: Option<JoinHandle<()>>,
+    relay_worker: Option<JoinHandle<()>>,
+    decoders: Vec<JoinHandle<()>>,
 }
 
 impl Acceptor {
-    /// Spawns a worker and returns a sender that triggers shutdown.
+    /// Spawns a worker and returns a sender that triggers shutdown,
+    /// the relay to the clients, and a handle to wait for the worker
+    /// to finish.
+    ///
+    /// After shutdown, the worker closes all connections and waits
+    /// for the other server threads before finishing.
+    ///
+    /// Without a backlog, the OS default is used.
     pub fn spawn(
         on_hostname_and_port: &str,
-        sender: Sender<Request>,
-        receiver: Receiver<FernspielEvent>,
-    ) -> Result<Sender<()>> {
-        let server = WebSocketServer::bind(on_hostname_and_port)?;
-        let (shutdown_tx, shutdown_rx) = bounded(1);
-
-        spawn(move || {
-            Self {
-                channel: sender,
-                relay: Relay::spawn(receiver),
-                handle_gen: ConnectionHandle::generate(),
-                shutdown_signal: shutdown_rx,
-            }
-            .run(server)
-        });
+        backlog: Option<u32>,
+        sender: Sender<(ConnectionHandle, Request)>,
+        receiver: Receiver<(Address, FernspielEvent)>,
+        default_format: Format,
+    ) -> Result<(Sender<()>, Relay, JoinHandle<()>)> {
+        let server = bind(on_hostname_and_port, backlog)?;
+        // poll for connections instead of blocking, so the listener can be stopped
+        server.set_nonblocking(true)?;
 
-        Ok(shutdown_tx)
+        let (shutdown_tx, shutdown_rx) = bounded(1);
+        let (accept_tx, accept_rx) = bounded(4);
+        let (stop_listening, listening) = bounded(0);
+
+        let listener = Builder::new()
+            .name("ctl-listen".into())
+            .spawn(move || listen(server, accept_tx, listening))?;
+        let (relay, relay_worker) = Relay::spawn(receiver)?;
+
+        let acceptor = Self {
+            channel: sender,
+            relay: relay.clone(),
+            handle_gen: ConnectionHandle::generate(),
+            default_format,
+            shutdown_signal: shutdown_rx,
+            stop_listening: Some(stop_listening),
+            listener: Some(listener),
+            relay_worker: Some(relay_worker),
+            decoders: vec![],
+        };
+        let worker = Builder::new()
+            .name("ctl-accept".into())
+            .spawn(move || acceptor.run(accept_rx))?;
+
+        Ok((shutdown_tx, relay, worker))
     }
 
     /// Keeps the acceptor running until the shutdown signal
-    /// is received.
-    fn run(&mut self, mut ws: WebSocketServer) {
-        let (accept_tx, accept_rx) = bounded(4);
-
-        spawn(move || {
-            loop {
-                if let Ok(request) = ws.accept() {
-                    if let Err(_) = accept_tx.send(request) {
-                        break;
-                    }
-                }
-            }
-        });
-
+    /// is received, then stops the other workers.
+    fn run(mut self, accept_rx: Receiver<WebSocketUpgrade>) {
         // run until shutdown signal received
         loop {
             select! {
@@ -69,7 +98,8 @@ impl Acceptor {
                 recv(accept_rx) -> connection => {
                     match connection {
                         Ok(conn) => {
-                            if let Err(err) = accept(conn).and_then(|c| self.communicate(c)) {
+                            let format = requested_format(&conn).unwrap_or(self.default_format);
+                            if let Err(err) = accept(conn).and_then(|c| self.communicate(c, format)) {
                                 error!("could not accept connection {:?}", err);
                             }
                         },
@@ -83,12 +113,32 @@ impl Acceptor {
             }
         }
 
-        trace!("shutting down connection acceptor")
+        trace!("shutting down connection acceptor");
+
+        // unblocks the listener if waiting to pass on a connection
+        drop(accept_rx);
+        self.join_workers();
+    }
+
+    /// Stops listening, closes all connections and waits for the
+    /// listener, relay and decoders to finish.
+    fn join_workers(&mut self) {
+        self.stop_listening.take();
+        join("listener", self.listener.take());
+
+        // closing the connections also ends the decoders
+        self.relay.shutdown();
+        join("relay", self.relay_worker.take());
+        for decoder in self.decoders.drain(..) {
+            join("decoder", Some(decoder));
+        }
+
+        trace!("all fernspielctl workers finished");
     }
 
     /// Loops through incoming messages from the client and handles
     /// them.
-    fn communicate(&mut self, client: WebSocketClient) -> Result<()> {
+    fn communicate(&mut self, client: WebSocketClient, format: Format) -> Result<()> {
         if let Err(e) = client.set_nonblocking(false) {
             error!("failed to make blocking websocket connection pair: {}", e);
         }
@@ -101,7 +151,7 @@ impl Acceptor {
             )
         })?;
 
-        match self.relay.connect(handle, sender) {
+        match self.relay.connect(handle, sender, format) {
             Ok(()) => (),
             Err(TrySendError::Disconnected((_, sender))) => {
                 sender.shutdown_all().unwrap_or_else(|e| {
@@ -121,12 +171,51 @@ impl Acceptor {
             }
         }
 
-        Decoder::spawn(handle, receiver, &self.relay, self.channel.clone());
+        let decoder = Decoder::spawn(handle, format, receiver, &self.relay, self.channel.clone())?;
+        self.decoders.push(decoder);
 
         Ok(())
     }
 }
 
+/// Polls for new connections and passes them on to the acceptor,
+/// until the stop signal disconnects or the acceptor hangs up.
+fn listen(mut ws: WebSocketServer, connections: Sender<WebSocketUpgrade>, stop: Receiver<()>) {
+    while let Err(TryRecvError::Empty) = stop.try_recv() {
+        match ws.accept() {
+            Ok(request) => {
+                if connections.send(request).is_err() {
+                    break;
+                }
+            }
+            // nothing pending or failed handshake, check again later
+            Err(_) => sleep(ACCEPT_POLL_INTERVAL),
+        }
+    }
+
+    trace!("stopped listening for fernspielctl connections")
+}
+
+fn join(worker: &str, handle: Option<JoinHandle<()>>) {
+    if let Some(handle) = handle {
+        if handle.join().is_err() {
+            error!("fernspielctl {} worker panicked", worker);
+        }
+    }
+}
+
+/// The event format requested in the headers of the upgrade
+/// request, if any.
+fn requested_format(request: &WebSocketUpgrade) -> Option<Format> {
+    let values = request.request.headers.get_raw(EVENT_FORMAT_HEADER)?;
+    let value = std::str::from_utf8(values.first()?).ok()?;
+    let format = Format::from_header(value);
+    if format.is_none() {
+        debug!("ignoring unknown event format {:?}", value);
+    }
+    format
+}
+
 /// rejects or accepts the given request, sets the protocol
 /// and returns the client on success.
 ///
@@ -0,0 +1,84 @@
+use super::ws::WebSocketServer;
+
+use crate::result::Result;
+
+use std::io;
+use std::net::{TcpListener, ToSocketAddrs};
+
+/// Binds a websocket server, optionally with a backlog of pending
+/// connections other than the OS default, which is usually 128.
+pub fn bind(on_hostname_and_port: &str, backlog: Option<u32>) -> Result<WebSocketServer> {
+    Ok(match backlog {
+        None => WebSocketServer::bind(on_hostname_and_port)?,
+        Some(backlog) => WebSocketServer::from_tcp_listener(listen(on_hostname_and_port, backlog)?),
+    })
+}
+
+fn listen<A: ToSocketAddrs>(addr: A, backlog: u32) -> io::Result<TcpListener> {
+    let listener = TcpListener::bind(addr)?;
+    set_backlog(&listener, backlog)?;
+    Ok(listener)
+}
+
+/// Listening again on a listening socket only updates the backlog.
+#[cfg(unix)]
+fn set_backlog(listener: &TcpListener, backlog: u32) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let backlog = backlog.min(libc::c_int::MAX as u32) as libc::c_int;
+    if unsafe { libc::listen(listener.as_raw_fd(), backlog) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(unix))]
+fn set_backlog(_listener: &TcpListener, backlog: u32) -> io::Result<()> {
+    log::warn!(
+        "ignoring websocket backlog of {}, only supported on unix",
+        backlog
+    );
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use std::net::{SocketAddr, TcpStream};
+    use std::time::Duration;
+
+    const BURST_SIZE: usize = 200;
+
+    #[test]
+    fn larger_backlog_accepts_burst() {
+        // given
+        let small = listen("127.0.0.1:0", 1).unwrap();
+        let large = listen("127.0.0.1:0", BURST_SIZE as u32 + 16).unwrap();
+
+        // when
+        let pending_small = burst(small.local_addr().unwrap());
+        let pending_large = burst(large.local_addr().unwrap());
+
+        // then
+        assert!(
+            pending_small < BURST_SIZE,
+            "expected small backlog to reject some connections"
+        );
+        assert_eq!(pending_large, BURST_SIZE);
+    }
+
+    /// Connects without ever accepting until a connection attempt
+    /// times out or the burst size is reached, returns the number
+    /// of pending connections.
+    fn burst(addr: SocketAddr) -> usize {
+        let mut connections = vec![];
+        while connections.len() < BURST_SIZE {
+            match TcpStream::connect_timeout(&addr, Duration::from_millis(100)) {
+                Ok(conn) => connections.push(conn),
+                Err(_) => break,
+            }
+        }
+        connections.len()
+    }
+}
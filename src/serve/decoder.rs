@@ -3,38 +3,46 @@ use crate::result::Result;
 use super::cause::ShutdownCause;
 use super::handle::ConnectionHandle;
 use super::relay::Relay;
+use super::report::Report;
 use super::ws::WebSocketReader;
-use super::Request;
+use super::{Format, Request};
 
 use crossbeam_channel::Sender;
 use failure::format_err;
-use log::{debug, trace};
+use log::{debug, error, trace};
 use websocket::OwnedMessage;
 
-use std::thread::spawn;
+use std::thread::{Builder, JoinHandle};
 
 pub struct Decoder {
     handle: ConnectionHandle,
+    /// Event format of the connection, also used for reports.
+    format: Format,
     relay: Relay,
-    channel: Sender<Request>,
+    channel: Sender<(ConnectionHandle, Request)>,
 }
 
 impl Decoder {
     pub fn spawn(
         handle: ConnectionHandle,
+        format: Format,
         connection: WebSocketReader,
         relay: &Relay,
-        request_channel: Sender<Request>,
-    ) {
+        request_channel: Sender<(ConnectionHandle, Request)>,
+    ) -> Result<JoinHandle<()>> {
         let mut decoder = Decoder {
             handle,
+            format,
             relay: relay.clone(),
             channel: request_channel,
         };
-        spawn(move || match decoder.receive(connection) {
-            Ok(()) => debug!("decoder exiting after successful operation"),
-            Err(err) => debug!("decoder exiting after error {:?}", err),
-        });
+        let worker = Builder::new().name("ctl-decode".into()).spawn(move || {
+            match decoder.receive(connection) {
+                Ok(()) => debug!("decoder exiting after successful operation"),
+                Err(err) => debug!("decoder exiting after error {:?}", err),
+            }
+        })?;
+        Ok(worker)
     }
 
     fn receive(&mut self, mut connection: WebSocketReader) -> Result<()> {
@@ -98,16 +106,29 @@ impl Decoder {
         }
     }
 
+    /// Sends the report only to the client this decoder is
+    /// receiving from.
+    fn report(&self, report: &Report) {
+        match report.to_message(self.format) {
+            Ok(msg) => {
+                if let Err(err) = self.relay.unicast(self.handle, msg) {
+                    debug!("failed to enqueue report: {}", err)
+                }
+            }
+            Err(err) => error!("failed to serialize report: {}", err),
+        }
+    }
+
     fn handle_request(&mut self, request: String) -> Result<()> {
         match Request::decode(request) {
             Err(err) => {
                 debug!("received invalid request {}", err);
-                // TODO send error back
+                self.report(&Report::from(&err));
                 Ok(())
             }
             Ok(request) => self
                 .channel
-                .send(request)
+                .send((self.handle, request))
                 .map_err(|e| format_err!("request received but server is shutting down: {:?}", e)),
         }
     }
@@ -1,6 +1,9 @@
+use serde::Serialize;
+
 use std::num::NonZeroU64;
 
-#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+/// Identifies a connection to a client, serialized as a number.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, Serialize)]
 pub struct ConnectionHandle(NonZeroU64);
 
 impl ConnectionHandle {
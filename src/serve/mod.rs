@@ -1,15 +1,18 @@
 mod acceptor;
+mod bind;
 mod cause;
 mod decoder;
 mod handle;
 mod publish;
 mod relay;
+mod report;
 mod req;
 mod server;
 mod summary;
 mod ws;
 
+pub use handle::ConnectionHandle;
 pub use publish::EventPublisher;
-pub use req::Request;
+pub use req::{Query, Request};
 pub use server::Server;
-pub use summary::FernspielEvent;
+pub use summary::{ActuatorProgress, FernspielEvent, Format};
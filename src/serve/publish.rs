@@ -1,4 +1,4 @@
-use super::Server;
+use super::{FernspielEvent, Server};
 
 use crate::evt::{Event, Responder};
 use crate::states::State;
@@ -7,17 +7,27 @@ use failure::Error;
 
 use std::rc::Rc;
 
-pub struct EventPublisher(Rc<Server>);
+pub struct EventPublisher {
+    server: Rc<Server>,
+    /// Included in all published events.
+    book_id: Option<String>,
+}
 
 impl EventPublisher {
-    pub fn through(server: &Rc<Server>) -> Self {
-        EventPublisher(Rc::clone(server))
+    /// Publishes events through the given server, attributing them
+    /// to the phonebook with the given ID.
+    pub fn through(server: &Rc<Server>, book_id: Option<&str>) -> Self {
+        EventPublisher {
+            server: Rc::clone(server),
+            book_id: book_id.map(String::from),
+        }
     }
 }
 
 impl Responder<State> for EventPublisher {
     fn respond(&mut self, event: &Event<State>) -> Result<(), Error> {
-        self.0.publish(event.into());
+        let event = FernspielEvent::from(event).in_book(self.book_id.as_deref());
+        self.server.publish(event);
         Ok(())
     }
 }
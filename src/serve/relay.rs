@@ -1,47 +1,103 @@
 use super::handle::ConnectionHandle;
 use super::ws::WebSocketWriter;
-use super::FernspielEvent;
+use super::{FernspielEvent, Format};
 
 use super::cause::ShutdownCause;
 use crate::result::Result;
 
 use crossbeam_channel::{bounded, select, Receiver, Sender, TrySendError};
+use failure::{bail, format_err};
 use log::{debug, error, trace};
 use websocket::OwnedMessage;
 
-use std::thread::spawn;
+use std::net::SocketAddr;
+use std::thread::{Builder, JoinHandle};
+use std::time::Duration;
 
 pub type ConnectResult = std::result::Result<(), TrySendError<(ConnectionHandle, WebSocketWriter)>>;
+type NewConnection = (ConnectionHandle, WebSocketWriter, Format);
+/// Queues for new connections and messages of a running worker,
+/// and its thread.
+type SpawnedWorker = (
+    Sender<NewConnection>,
+    Sender<(Address, OwnedMessage)>,
+    JoinHandle<()>,
+);
 pub type UnicastResult = std::result::Result<(), TrySendError<(ConnectionHandle, OwnedMessage)>>;
 pub type BroadcastResult = std::result::Result<(), TrySendError<OwnedMessage>>;
 
 const MSG_QUEUE_SIZE: usize = 256;
+/// How long to wait for the worker to answer which peers
+/// are connected.
+const PEER_QUERY_TIMEOUT: Duration = Duration::from_secs(1);
 
 #[derive(Clone)]
 pub struct Relay {
-    new_connections: Sender<(ConnectionHandle, WebSocketWriter)>,
+    new_connections: Sender<NewConnection>,
     messages: Sender<(Address, OwnedMessage)>,
+    /// Receives a channel to answer with the connected peers.
+    peer_queries: Sender<Sender<Vec<SocketAddr>>>,
+    shutdown: Sender<()>,
 }
 
 impl Relay {
-    pub fn spawn(events: Receiver<FernspielEvent>) -> Self {
-        let (conn_tx, msg_tx) = RelayWorker::spawn(events);
-        Self {
+    /// Spawns the relay worker and returns the relay along with a
+    /// handle to wait for the worker after shutdown.
+    ///
+    /// Events are sent either to all connections or to a single one.
+    pub fn spawn(events: Receiver<(Address, FernspielEvent)>) -> Result<(Self, JoinHandle<()>)> {
+        let (shutdown_tx, shutdown_rx) = bounded(1);
+        let (peer_query_tx, peer_query_rx) = bounded(MSG_QUEUE_SIZE);
+        let (conn_tx, msg_tx, worker) = RelayWorker::spawn(events, peer_query_rx, shutdown_rx)?;
+        let relay = Self {
             new_connections: conn_tx,
             messages: msg_tx,
-        }
+            peer_queries: peer_query_tx,
+            shutdown: shutdown_tx,
+        };
+        Ok((relay, worker))
+    }
+
+    /// Closes all connections and stops the worker, even if
+    /// other copies of the relay are still in use.
+    pub fn shutdown(&self) {
+        self.shutdown
+            .try_send(())
+            .unwrap_or_else(|e| debug!("relay already shutting down: {}", e));
     }
 
-    pub fn connect(&self, handle: ConnectionHandle, connection: WebSocketWriter) -> ConnectResult {
-        match self.new_connections.try_send((handle, connection)) {
+    /// Registers a new connection that receives events in the
+    /// given format.
+    pub fn connect(
+        &self,
+        handle: ConnectionHandle,
+        connection: WebSocketWriter,
+        format: Format,
+    ) -> ConnectResult {
+        match self.new_connections.try_send((handle, connection, format)) {
             Ok(_) => Ok(()),
-            Err(TrySendError::Full((handle, writer))) => Err(TrySendError::Full((handle, writer))),
-            Err(TrySendError::Disconnected((handle, writer))) => {
+            Err(TrySendError::Full((handle, writer, _))) => {
+                Err(TrySendError::Full((handle, writer)))
+            }
+            Err(TrySendError::Disconnected((handle, writer, _))) => {
                 Err(TrySendError::Disconnected((handle, writer)))
             }
         }
     }
 
+    /// Addresses of the currently connected clients.
+    ///
+    /// Blocks until the worker answers, which should not take long.
+    pub fn peers(&self) -> Result<Vec<SocketAddr>> {
+        let (reply_tx, reply_rx) = bounded(1);
+        self.peer_queries
+            .try_send(reply_tx)
+            .map_err(|e| format_err!("failed to query peers: {}", e))?;
+        reply_rx
+            .recv_timeout(PEER_QUERY_TIMEOUT)
+            .map_err(|e| format_err!("relay did not report peers: {}", e))
+    }
+
     pub fn unicast(&self, address: ConnectionHandle, msg: OwnedMessage) -> UnicastResult {
         trace!("sending message: \"{:?}\" to {:?}", &msg, &address);
         match self.messages.try_send((Address::Unicast(address), msg)) {
@@ -64,41 +120,54 @@ impl Relay {
     }
 }
 
-enum Address {
-    #[allow(dead_code)]
+/// Recipients of a message or event.
+pub enum Address {
     Broadcast,
     Unicast(ConnectionHandle),
+    /// All current connections and every connection made later,
+    /// until the next retained event replaces it.
+    Retained,
 }
 
 struct RelayWorker {
-    new_connections: Receiver<(ConnectionHandle, WebSocketWriter)>,
-    connections: Vec<(ConnectionHandle, WebSocketWriter)>,
+    new_connections: Receiver<NewConnection>,
+    connections: Vec<NewConnection>,
     messages: Receiver<(Address, OwnedMessage)>,
-    events: Receiver<FernspielEvent>,
+    events: Receiver<(Address, FernspielEvent)>,
+    /// Sent to every new connection.
+    retained: Option<FernspielEvent>,
+    peer_queries: Receiver<Sender<Vec<SocketAddr>>>,
+    shutdown: Receiver<()>,
 }
 
 impl RelayWorker {
     pub fn spawn(
-        events: Receiver<FernspielEvent>,
-    ) -> (
-        Sender<(ConnectionHandle, WebSocketWriter)>,
-        Sender<(Address, OwnedMessage)>,
-    ) {
+        events: Receiver<(Address, FernspielEvent)>,
+        peer_queries: Receiver<Sender<Vec<SocketAddr>>>,
+        shutdown: Receiver<()>,
+    ) -> Result<SpawnedWorker> {
         let (conn_tx, conn_rx) = bounded(MSG_QUEUE_SIZE);
         let (msg_tx, msg_rx) = bounded(MSG_QUEUE_SIZE);
-        spawn(move || Self::new(conn_rx, msg_rx, events).run());
-        (conn_tx, msg_tx)
+        let worker = Builder::new()
+            .name("ctl-relay".into())
+            .spawn(move || Self::new(conn_rx, msg_rx, events, peer_queries, shutdown).run())?;
+        Ok((conn_tx, msg_tx, worker))
     }
 
     fn new(
-        new_connections: Receiver<(ConnectionHandle, WebSocketWriter)>,
+        new_connections: Receiver<NewConnection>,
         messages: Receiver<(Address, OwnedMessage)>,
-        events: Receiver<FernspielEvent>,
+        events: Receiver<(Address, FernspielEvent)>,
+        peer_queries: Receiver<Sender<Vec<SocketAddr>>>,
+        shutdown: Receiver<()>,
     ) -> Self {
         Self {
             new_connections,
             messages,
             events,
+            retained: None,
+            peer_queries,
+            shutdown,
             connections: vec![],
         }
     }
@@ -111,28 +180,95 @@ impl RelayWorker {
     fn recv(&mut self) -> Result<()> {
         select! {
             // return with error when remote end hung up
-            recv(self.new_connections) -> connection => self.connections.push(connection?),
+            recv(self.new_connections) -> connection => self.connect(connection?),
             recv(self.messages) -> msg => match msg? {
-                (Address::Broadcast, ref msg) => self.broadcast_message(msg),
+                // messages are never retained
+                (Address::Broadcast, ref msg) | (Address::Retained, ref msg) => {
+                    self.broadcast_message(msg)
+                }
                 (Address::Unicast(handle), ref msg) => self.unicast_message(handle, msg),
             },
-            recv(self.events) -> evt => self.broadcast_event(evt?)
+            recv(self.events) -> evt => match evt? {
+                (Address::Broadcast, evt) => self.broadcast_event(evt),
+                (Address::Unicast(handle), evt) => self.unicast_event(handle, evt),
+                (Address::Retained, evt) => {
+                    self.retained = Some(evt.clone());
+                    self.broadcast_event(evt)
+                }
+            },
+            recv(self.peer_queries) -> reply => {
+                // asking side may have timed out already, ignore
+                reply?.try_send(self.peers()).ok();
+            },
+            recv(self.shutdown) -> _ => bail!("relay shutting down")
         }
         Ok(())
     }
 
+    /// Registers the connection and sends it the retained event,
+    /// if any.
+    fn connect(&mut self, connection: NewConnection) {
+        let handle = connection.0;
+        self.connections.push(connection);
+        if let Some(retained) = self.retained.clone() {
+            self.unicast_event(handle, retained);
+        }
+    }
+
+    /// Sends the event to all connections, serialized in the
+    /// format of each connection.
     fn broadcast_event(&mut self, evt: FernspielEvent) {
-        serde_yaml::to_string(&evt)
-            .map(OwnedMessage::Text)
-            .map(|msg| self.broadcast_message(&msg))
-            .unwrap_or_else(|e| {
-                error!("failed to broadcast event: {}", e);
-            })
+        let mut encoded: Vec<(Format, OwnedMessage)> = vec![];
+        for (_, _, format) in self.connections.iter() {
+            if encoded.iter().any(|(f, _)| f == format) {
+                continue;
+            }
+
+            match evt.serialize(*format) {
+                Ok(text) => encoded.push((*format, OwnedMessage::Text(text))),
+                Err(e) => error!("failed to broadcast event as {:?}: {}", format, e),
+            }
+        }
+
+        self.broadcast_with(|format| {
+            encoded
+                .iter()
+                .find(|(f, _)| *f == format)
+                .map(|(_, msg)| msg)
+        })
+    }
+
+    /// Sends the event to a single connection, in its format.
+    fn unicast_event(&mut self, handle: ConnectionHandle, evt: FernspielEvent) {
+        let format = self
+            .connections
+            .iter()
+            .find(|(conn_handle, _, _)| *conn_handle == handle)
+            .map(|(_, _, format)| *format);
+
+        match format.map(|format| evt.serialize(format)) {
+            Some(Ok(text)) => self.unicast_message(handle, &OwnedMessage::Text(text)),
+            Some(Err(e)) => error!("failed to send event to {:?}: {}", handle, e),
+            None => debug!("dropping event for closed connection {:?}", handle),
+        }
+    }
+
+    /// Addresses of the connected clients, skipping connections
+    /// where the address is unknown.
+    fn peers(&self) -> Vec<SocketAddr> {
+        self.connections
+            .iter()
+            .filter_map(|(_, writer, _)| writer.stream.peer_addr().ok())
+            .collect()
     }
 
     fn broadcast_message(&mut self, msg: &OwnedMessage) {
         trace!("broadcasting message {:?}", msg);
+        self.broadcast_with(|_| Some(msg))
+    }
 
+    /// Sends the message for the format of each connection, if any.
+    fn broadcast_with<'a>(&mut self, msg_for: impl Fn(Format) -> Option<&'a OwnedMessage>) {
         // more efficient but nightly-only:
         // self.connections
         //    .drain_filter(|(h, c)| !Self::try_send(*h, c, msg))
@@ -140,7 +276,15 @@ impl RelayWorker {
 
         let mut i = 0;
         while i < self.connections.len() {
-            let (h, c) = &mut self.connections[i];
+            let (h, c, format) = &mut self.connections[i];
+            let msg = match msg_for(*format) {
+                Some(msg) => msg,
+                None => {
+                    // could not encode for this format, skip
+                    i += 1;
+                    continue;
+                }
+            };
             if Self::try_send(*h, c, msg) {
                 // could send, next
                 i += 1;
@@ -155,15 +299,15 @@ impl RelayWorker {
         let addressee_idx = self
             .connections
             .iter_mut()
-            .position(|(conn_handle, _)| *conn_handle == handle);
+            .position(|(conn_handle, _, _)| *conn_handle == handle);
 
         if let Some(addressee_idx) = addressee_idx {
             let ok = {
-                let (handle, ref mut connection) = &mut self.connections[addressee_idx];
+                let (handle, ref mut connection, _) = &mut self.connections[addressee_idx];
                 Self::try_send(*handle, connection, msg)
             };
             if !ok {
-                let (_, conn) = self.connections.swap_remove(addressee_idx);
+                let (_, conn, _) = self.connections.swap_remove(addressee_idx);
                 Self::shutdown(conn);
             }
         }
@@ -198,9 +342,14 @@ impl RelayWorker {
 
 impl Drop for RelayWorker {
     fn drop(&mut self) {
-        // send close message,
-        // this will shut down the writing half of connections
-        // and clear the connections vector
-        self.broadcast_message(&ShutdownCause::Done.into_close_msg());
+        // send close message and shut down both halves of the
+        // connections, so decoders blocked on reading exit too
+        let close = ShutdownCause::Done.into_close_msg();
+        for (handle, mut connection, _) in self.connections.drain(..) {
+            Self::try_send(handle, &mut connection, &close);
+            connection
+                .shutdown_all()
+                .unwrap_or_else(|e| debug!("failed to orderly shutdown connection on exit: {}", e));
+        }
     }
 }
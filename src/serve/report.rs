@@ -0,0 +1,95 @@
+use super::req::DecodeError;
+use super::Format;
+
+use crate::result::Result;
+
+use serde::Serialize;
+use websocket::OwnedMessage;
+
+/// Sent only to the client that caused it, in contrast to events
+/// that are broadcast to all clients.
+#[derive(Serialize, Clone, PartialEq, Debug)]
+#[serde(tag = "type")]
+pub enum Report {
+    /// A request of the client could not be handled.
+    #[serde(rename = "error")]
+    Error { code: ErrorCode, message: String },
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum ErrorCode {
+    /// The request itself could not be decoded.
+    #[serde(rename = "INVALID_REQUEST")]
+    InvalidRequest,
+}
+
+impl Report {
+    /// Serializes the report into a message for the client, in
+    /// the event format of its connection.
+    pub fn to_message(&self, format: Format) -> Result<OwnedMessage> {
+        let text = match format {
+            Format::Yaml => serde_yaml::to_string(self)?,
+            Format::Json => serde_json::to_string(self)?,
+        };
+        Ok(OwnedMessage::Text(text))
+    }
+}
+
+impl From<&DecodeError> for Report {
+    fn from(error: &DecodeError) -> Self {
+        let code = match error {
+            DecodeError::Malformed(_) => ErrorCode::InvalidRequest,
+        };
+        Report::Error {
+            code,
+            message: error.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn serialize_invalid_request_error() {
+        // given
+        let error = DecodeError::Malformed("unknown variant `explode`".into());
+
+        // when
+        let message = Report::from(&error).to_message(Format::Yaml).unwrap();
+
+        // then
+        match message {
+            OwnedMessage::Text(text) => {
+                let report: serde_yaml::Value = serde_yaml::from_str(&text).unwrap();
+                assert_eq!(report["type"], "error");
+                assert_eq!(report["code"], "INVALID_REQUEST");
+                assert_eq!(
+                    report["message"],
+                    "malformed fernspielctl request: unknown variant `explode`"
+                );
+            }
+            other => panic!("Expected text message, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn serialize_report_as_json() {
+        // given
+        let error = DecodeError::Malformed("unknown variant `explode`".into());
+
+        // when
+        let message = Report::from(&error).to_message(Format::Json).unwrap();
+
+        // then
+        match message {
+            OwnedMessage::Text(text) => {
+                let report: serde_json::Value = serde_json::from_str(&text).unwrap();
+                assert_eq!(report["type"], "error");
+                assert_eq!(report["code"], "INVALID_REQUEST");
+            }
+            other => panic!("Expected text message, got: {:?}", other),
+        }
+    }
+}
@@ -1,61 +1,144 @@
-use crate::books::{compile, spec::Book as BookSpec, Book};
-use crate::result::Result;
+use crate::books::spec::Book as BookSpec;
 use crate::senses::Input;
 
-use failure::format_err;
+use failure::Fail;
 use serde::Deserialize;
 use serde_yaml::from_str;
 
+use std::collections::HashMap;
+use std::time::Duration;
+
 /// A request of a controlling application sent over web socket,
 /// indicating to the runtime what action to perform.
 ///
 /// It can be converted from YAML with its `decode` method.
 #[derive(Debug)]
 pub enum Request {
-    /// Terminate the currently running phonebook and load the
-    /// compiled phonebook received from the client.
-    Run(Book),
+    /// Compile the phonebook received from the client in the
+    /// background, then terminate the currently running phonebook
+    /// and load the compiled one.
+    ///
+    /// If a crossfade duration is set, the sounds of the old
+    /// phonebook fade out while the new sounds fade in.
+    Run {
+        book: Box<BookSpec>,
+        crossfade: Option<Duration>,
+    },
     /// Keep the current phonebook but start over from the initial state,
     /// and revert all state to initial values, e.g. set playback positions
     /// to the start offset.
     Reset,
+    /// Jump to the state with the given ID in the current phonebook,
+    /// as if a transition was forced.
+    Goto(String),
+    /// Silence all sounds, speech and ringing right away, e.g.
+    /// for an emergency stop, but stay in the current state.
+    Stop,
     /// A remote request to dial a sequence of inputs.
     Dial(Vec<Input>),
+    /// Asks for information about the running phonebook, which
+    /// is answered with an event.
+    Query(Query),
+}
+
+/// Information that can be queried by clients.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum Query {
+    /// The last transitions of the running phonebook, answered
+    /// only to the asking client.
+    #[serde(rename = "history")]
+    History,
+    /// Whether no phonebook has been loaded yet, answered only
+    /// to the asking client.
+    #[serde(rename = "idle")]
+    Idle,
+    /// The clients that are currently connected, answered only
+    /// to the asking client.
+    #[serde(rename = "peers")]
+    Peers,
+    /// The current state and how long it has been active,
+    /// answered only to the asking client.
+    #[serde(rename = "status")]
+    Status,
+    /// The states of the running phonebook and an estimate of
+    /// how long it plays.
+    #[serde(rename = "book")]
+    Book,
 }
 
 /// A raw request after decoding it from YAML.
-/// Needs to be compiled before use.
+/// Needs to be converted before use.
 #[derive(Debug, Deserialize)]
 #[serde(tag = "invoke", content = "with")]
 enum Spec {
     #[serde(rename = "run")]
-    Run(BookSpec),
+    Run(Box<RunSpec>),
     #[serde(rename = "reset")]
     Reset,
+    #[serde(rename = "goto")]
+    Goto(String),
+    #[serde(rename = "stop")]
+    Stop,
     /// 0-9 mean numeric input.
     /// h is hanging up.
     /// p is picking up.
     /// All other characters are ignored.
     #[serde(rename = "dial")]
     Dial(String),
+    #[serde(rename = "query")]
+    Query(Query),
+}
+
+/// Arguments of a run request, the phonebook with optional
+/// additional keys.
+#[derive(Debug, Deserialize)]
+struct RunSpec {
+    #[serde(flatten)]
+    book: BookSpec,
+    /// Crossfade duration in milliseconds.
+    crossfade_ms: Option<u64>,
+    /// Values for placeholders in speech, e.g. `{name}`, taking
+    /// precedence over the variables of the book.
+    #[serde(default)]
+    variables: HashMap<String, String>,
+}
+
+/// Reason why a request could not be decoded.
+#[derive(Debug, Fail)]
+pub enum DecodeError {
+    #[fail(display = "malformed fernspielctl request: {}", _0)]
+    Malformed(String),
 }
 
 impl Request {
     /// Decodes a YAML string into a request.
     ///
-    /// If it is a run request
-    pub fn decode<S: AsRef<str>>(yaml_source: S) -> Result<Self> {
+    /// The phonebook of a run request is not compiled yet.
+    pub fn decode<S: AsRef<str>>(yaml_source: S) -> Result<Self, DecodeError> {
         from_str(yaml_source.as_ref())
-            .map_err(|e| format_err!("malformed fernspielctl request: {}", e))
-            .and_then(Spec::compile)
+            .map_err(|e| DecodeError::Malformed(e.to_string()))
+            .map(Spec::into_request)
     }
 }
 
 impl Spec {
-    fn compile(self) -> Result<Request> {
-        Ok(match self {
-            Spec::Run(string) => Request::Run(compile(string)?),
+    fn into_request(self) -> Request {
+        match self {
+            Spec::Run(run) => {
+                let RunSpec {
+                    mut book,
+                    crossfade_ms,
+                    variables,
+                } = *run;
+                book.variables.extend(variables);
+                Request::Run {
+                    book: Box::new(book),
+                    crossfade: crossfade_ms.map(Duration::from_millis),
+                }
+            }
             Spec::Reset => Request::Reset,
+            Spec::Goto(state_id) => Request::Goto(state_id),
+            Spec::Stop => Request::Stop,
             Spec::Dial(seq) => Request::Dial(
                 seq.chars()
                     .filter_map(|c| match c {
@@ -69,7 +152,8 @@ impl Spec {
                     })
                     .collect(),
             ),
-        })
+            Spec::Query(query) => Request::Query(query),
+        }
     }
 }
 
@@ -96,7 +180,192 @@ mod test {
 
         // then
         match decoded {
-            Request::Run(book) => assert_eq!(book.states().len(), 1),
+            Request::Run { book, crossfade } => {
+                assert_eq!(book.states.len(), 1);
+                assert_eq!(crossfade, None);
+            }
+            other => panic!("Unexpected request type: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_run_with_crossfade() {
+        // given
+        let run = "{
+            \"invoke\":\"run\",
+            \"with\": {
+                \"initial\": \"lonelystate\",
+                \"states\":{
+                    \"lonelystate\":{}
+                },
+                \"crossfade_ms\": 1500
+            }
+        }";
+
+        // when
+        let decoded = Request::decode(run).expect("failed to decode run request");
+
+        // then
+        match decoded {
+            Request::Run { book, crossfade } => {
+                assert_eq!(book.states.len(), 1);
+                assert_eq!(crossfade, Some(Duration::from_millis(1500)));
+            }
+            other => panic!("Unexpected request type: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_run_with_variables() {
+        // given
+        let run = "{
+            \"invoke\":\"run\",
+            \"with\": {
+                \"initial\": \"lonelystate\",
+                \"states\":{
+                    \"lonelystate\":{}
+                },
+                \"variables\": { \"name\": \"Harry\" },
+                \"strict\": false
+            }
+        }";
+
+        // when
+        let decoded = Request::decode(run).expect("failed to decode run request");
+
+        // then
+        match decoded {
+            Request::Run { book, .. } => {
+                assert_eq!(book.variables["name"], "Harry");
+                assert_eq!(book.strict, Some(false));
+            }
+            other => panic!("Unexpected request type: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_run_without_compiling() {
+        // given
+        let run = "{
+            \"invoke\":\"run\",
+            \"with\": {
+                \"initial\": \"missing\",
+                \"states\":{
+                    \"lonelystate\":{}
+                }
+            }
+        }";
+
+        // when
+        let decoded = Request::decode(run);
+
+        // then
+        match decoded {
+            Ok(Request::Run { book, .. }) => assert_eq!(book.initial.to_string(), "missing"),
+            other => panic!("Expected run request, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_unknown_invocation() {
+        // given
+        let unknown = "{
+            \"invoke\":\"explode\"
+        }";
+
+        // when
+        let decoded = Request::decode(unknown);
+
+        // then
+        match decoded {
+            Err(DecodeError::Malformed(_)) => (),
+            other => panic!("Expected malformed request, got: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_query_history() {
+        // given
+        let query = "{
+            \"invoke\":\"query\",
+            \"with\":\"history\"
+        }";
+
+        // when
+        let decoded = Request::decode(query).expect("failed to decode query request");
+
+        // then
+        match decoded {
+            Request::Query(Query::History) => (),
+            other => panic!("Unexpected request type: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_query_idle() {
+        // given
+        let query = "{
+            \"invoke\":\"query\",
+            \"with\":\"idle\"
+        }";
+
+        // when
+        let decoded = Request::decode(query).expect("failed to decode query request");
+
+        // then
+        match decoded {
+            Request::Query(Query::Idle) => (),
+            other => panic!("Unexpected request type: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_query_status() {
+        // given
+        let query = "{ invoke: query, with: status }";
+
+        // when
+        let decoded = Request::decode(query).expect("failed to decode query request");
+
+        // then
+        match decoded {
+            Request::Query(Query::Status) => (),
+            other => panic!("Unexpected request type: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_query_peers() {
+        // given
+        let query = "{
+            \"invoke\":\"query\",
+            \"with\":\"peers\"
+        }";
+
+        // when
+        let decoded = Request::decode(query).expect("failed to decode query request");
+
+        // then
+        match decoded {
+            Request::Query(Query::Peers) => (),
+            other => panic!("Unexpected request type: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_query_book() {
+        // given
+        let query = "{
+            \"invoke\":\"query\",
+            \"with\":\"book\"
+        }";
+
+        // when
+        let decoded = Request::decode(query).expect("failed to decode query request");
+
+        // then
+        match decoded {
+            Request::Query(Query::Book) => (),
             other => panic!("Unexpected request type: {:?}", other),
         }
     }
@@ -118,6 +387,41 @@ mod test {
         }
     }
 
+    #[test]
+    fn decode_goto() {
+        // given
+        let goto = "{
+            \"invoke\":\"goto\",
+            \"with\":\"chapter_two\"
+        }";
+
+        // when
+        let decoded = Request::decode(goto).expect("failed to decode goto request");
+
+        // then
+        match decoded {
+            Request::Goto(state_id) => assert_eq!(state_id, "chapter_two"),
+            other => panic!("Unexpected request type: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decode_stop() {
+        // given
+        let stop = "{
+            \"invoke\":\"stop\"
+        }";
+
+        // when
+        let decoded = Request::decode(stop).expect("failed to decode stop request");
+
+        // then
+        match decoded {
+            Request::Stop => (),
+            other => panic!("Unexpected request type: {:?}", other),
+        }
+    }
+
     #[test]
     fn decode_9_hang_up() {
         // given
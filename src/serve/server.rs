@@ -1,16 +1,23 @@
 use super::acceptor::Acceptor;
-use super::{FernspielEvent, Request};
+use super::relay::{Address, Relay};
+use super::{ConnectionHandle, FernspielEvent, Format, Request};
 
 use crate::result::Result;
 
-use crossbeam_channel::{bounded, Receiver, Sender, TryRecvError};
+use crossbeam_channel::{bounded, never, Receiver, Sender, TryRecvError};
 use log::{error, trace};
 
+use std::cell::{Cell, RefCell};
+use std::net::SocketAddr;
+use std::thread::JoinHandle;
+
 pub struct Server {
-    events: Sender<FernspielEvent>,
+    events: Sender<(Address, FernspielEvent)>,
+    relay: Relay,
     signal_shutdown: Sender<()>,
-    shutdown: bool,
-    invocations: Receiver<Request>,
+    shutdown: Cell<bool>,
+    invocations: RefCell<Receiver<(ConnectionHandle, Request)>>,
+    worker: RefCell<Option<JoinHandle<()>>>,
 }
 
 /// A websocket server running in the background and listening for
@@ -24,37 +31,81 @@ impl Server {
     /// Spins up a background server on the given hostname
     /// and port. Client code needs to regularly poll for
     /// requests and can publish events through the server.
-    pub fn spawn(on_hostname_and_port: &str) -> Result<Server> {
+    ///
+    /// Events are sent in the given format, unless clients ask
+    /// for another one when connecting.
+    pub fn spawn(on_hostname_and_port: &str, event_format: Format) -> Result<Server> {
+        Self::spawn_with_optional_backlog(on_hostname_and_port, event_format, None)
+    }
+
+    /// Like `spawn`, but allows the given number of connections
+    /// to wait for being accepted instead of the OS default, e.g.
+    /// for many clients connecting at the same time.
+    ///
+    /// Only supported on unix, elsewhere the backlog is ignored.
+    pub fn spawn_with_backlog(
+        on_hostname_and_port: &str,
+        event_format: Format,
+        backlog: u32,
+    ) -> Result<Server> {
+        Self::spawn_with_optional_backlog(on_hostname_and_port, event_format, Some(backlog))
+    }
+
+    fn spawn_with_optional_backlog(
+        on_hostname_and_port: &str,
+        event_format: Format,
+        backlog: Option<u32>,
+    ) -> Result<Server> {
         let (invoke_tx, invoke_rx) = bounded(Self::MSG_QUEUE_SIZE);
         let (event_tx, event_rx) = bounded(Self::MSG_QUEUE_SIZE);
 
-        let signal_shutdown = Acceptor::spawn(on_hostname_and_port, invoke_tx, event_rx)?;
+        let (signal_shutdown, relay, worker) = Acceptor::spawn(
+            on_hostname_and_port,
+            backlog,
+            invoke_tx,
+            event_rx,
+            event_format,
+        )?;
 
         Ok(Server {
             events: event_tx,
-            invocations: invoke_rx,
+            relay,
+            invocations: RefCell::new(invoke_rx),
             signal_shutdown,
-            shutdown: false,
+            shutdown: Cell::new(false),
+            worker: RefCell::new(Some(worker)),
         })
     }
 
-    /// Terminates the background thread, cannot be undone.
-    pub fn shutdown(&mut self) {
-        if !self.shutdown {
-            self.shutdown = true;
+    /// Terminates the background threads, cannot be undone.
+    ///
+    /// Stops accepting connections, closes all connections and
+    /// blocks until all background threads have finished.
+    pub fn shutdown(&self) {
+        if !self.shutdown.replace(true) {
             self.signal_shutdown
                 .try_send(())
-                .unwrap_or_else(|e| error!("failed to shut down fernspielctl server: {}", e))
+                .unwrap_or_else(|e| error!("failed to shut down fernspielctl server: {}", e));
+
+            // hang up so decoders waiting for a full queue can exit
+            self.invocations.replace(never());
+
+            if let Some(worker) = self.worker.borrow_mut().take() {
+                if worker.join().is_err() {
+                    error!("fernspielctl server worker panicked");
+                }
+            }
         }
     }
 
-    /// Tries to get the next request from the server, if any.
-    pub fn poll(&self) -> Option<Request> {
-        if self.shutdown {
+    /// Tries to get the next request from the server, if any,
+    /// along with the connection it was received from.
+    pub fn poll(&self) -> Option<(ConnectionHandle, Request)> {
+        if self.shutdown.get() {
             return None;
         }
 
-        match self.invocations.try_recv() {
+        match self.invocations.borrow().try_recv() {
             Ok(req) => Some(req),
             Err(TryRecvError::Empty) => None,
             Err(error) => {
@@ -67,12 +118,43 @@ impl Server {
         }
     }
 
+    /// Addresses of the currently connected clients.
+    pub fn connected_peers(&self) -> Vec<SocketAddr> {
+        if self.shutdown.get() {
+            return vec![];
+        }
+
+        self.relay.peers().unwrap_or_else(|error| {
+            error!("failed to get connected peers: {}", error);
+            vec![]
+        })
+    }
+
     /// Publishes the given event to all connected clients.
     pub fn publish(&self, evt: FernspielEvent) {
         trace!("publishing event {:?}", evt);
-        if !self.shutdown {
+        self.send(Address::Broadcast, evt)
+    }
+
+    /// Publishes the given event to all connected clients and sends
+    /// it to every client that connects later, until the next
+    /// retained event replaces it.
+    pub fn publish_retained(&self, evt: FernspielEvent) {
+        trace!("publishing retained event {:?}", evt);
+        self.send(Address::Retained, evt)
+    }
+
+    /// Sends the given event only to the client with the given
+    /// connection.
+    pub fn send_to(&self, connection: ConnectionHandle, evt: FernspielEvent) {
+        trace!("sending event {:?} to {:?}", evt, connection);
+        self.send(Address::Unicast(connection), evt)
+    }
+
+    fn send(&self, address: Address, evt: FernspielEvent) {
+        if !self.shutdown.get() {
             self.events
-                .try_send(evt)
+                .try_send((address, evt))
                 .unwrap_or_else(|error| error!("failed to publish event: {}", error));
         }
     }
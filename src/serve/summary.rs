@@ -1,9 +1,15 @@
-use crate::evt::Event as MachineEventWithState;
-use crate::senses::Input;
+use super::ConnectionHandle;
+
+use crate::books::Book;
+use crate::evt::{Event as MachineEventWithState, ResetReason};
+use crate::result::Result;
 use crate::states::{State, Symbol};
 
 use serde::Serialize;
 
+use std::net::SocketAddr;
+use std::time::Duration;
+
 type MachineEvent<'a> = MachineEventWithState<'a, State>;
 
 /// Used by the server to describe an event through the
@@ -15,23 +21,262 @@ pub enum FernspielEvent {
     /// state or it reached the initial state again, resetting
     /// the phonebook.
     ///
-    /// If this is not the first start, the event is preceded by
-    /// a normal transition event.
+    /// If the initial state was reached through a normal transition,
+    /// the event is preceded by the transition event and has no
+    /// reason.
     #[serde(rename = "start")]
-    Start { initial: StateSummary },
+    Start {
+        initial: StateSummary,
+        /// Why the phonebook started over, if not through a transition.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<ResetReason>,
+        /// ID of the phonebook producing the event, if it has one.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        book_id: Option<String>,
+    },
     /// A terminal state has been reached by the user progressing
     /// through states.
     #[serde(rename = "finish")]
-    Finish { terminal: StateSummary },
+    Finish {
+        terminal: StateSummary,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        book_id: Option<String>,
+    },
     #[serde(rename = "transition")]
     Transition {
         /// The trigger for this transition.
-        reason: TransitionCause,
+        reason: TransitionReason,
         /// `None` on reset or newly started.
         from: StateSummary,
         /// The new current state.
         to: StateSummary,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        book_id: Option<String>,
+    },
+    /// Answers a query for the last transitions, oldest first.
+    #[serde(rename = "history")]
+    History { transitions: Vec<HistoryEntry> },
+    /// Sent on startup if there is nothing to run, and as an
+    /// answer to a query for idleness.
+    #[serde(rename = "idle")]
+    Idle {
+        idle: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<IdleReason>,
     },
+    /// Answers a query for the connected clients.
+    #[serde(rename = "peers")]
+    Peers { count: usize, addrs: Vec<String> },
+    /// Answers a query for the current state and how long it has
+    /// been active.
+    #[serde(rename = "status")]
+    Status {
+        state: StateSummary,
+        /// Name of the current state.
+        name: String,
+        /// Milliseconds since the current state was entered.
+        elapsed_ms: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        book_id: Option<String>,
+    },
+    /// Sent periodically if configured, e.g. to show a progress
+    /// bar for the current state.
+    #[serde(rename = "progress")]
+    Progress {
+        state: StateSummary,
+        actuator: ActuatorProgress,
+    },
+    /// A phonebook sent by a client is being compiled in the
+    /// background, e.g. speech is being synthesized.
+    #[serde(rename = "compiling")]
+    Compiling { sounds_remaining: usize },
+    /// A phonebook sent by a client could not be compiled, the
+    /// current phonebook keeps running.
+    #[serde(rename = "compilation_failed")]
+    CompilationFailed { error: String },
+    /// Answers a query for the structure of the running phonebook.
+    #[serde(rename = "book")]
+    Book {
+        states: Vec<StateSummary>,
+        /// Best-effort estimate of the time from the initial state
+        /// to a terminal state, `None` if it cannot be estimated.
+        estimated_duration_ms: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        book_id: Option<String>,
+    },
+}
+
+/// How far the actuators are with the current state.
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct ActuatorProgress {
+    /// All sounds that do not loop have been played.
+    pub sounds_done: bool,
+    /// Milliseconds until the phone stops ringing, if ringing.
+    pub ring_remaining_ms: Option<u64>,
+    /// Nothing is being spoken.
+    pub speech_done: bool,
+}
+
+/// Why the runtime is idle.
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum IdleReason {
+    /// No phonebook has been loaded since startup.
+    #[serde(rename = "no_phonebook")]
+    NoPhonebook,
+}
+
+/// Serialization format of events sent to clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Yaml,
+    /// More compact and easier to parse for JavaScript clients.
+    Json,
+}
+
+impl Format {
+    /// Parses the value of a `Fernspiel-Event-Format` header,
+    /// e.g. `json`.
+    pub fn from_header(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "yaml" => Some(Format::Yaml),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+/// A past transition in a history event.
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct HistoryEntry {
+    /// Human-readable cause of the transition.
+    cause: String,
+    /// Name of the state that was entered.
+    state: String,
+}
+
+impl FernspielEvent {
+    /// Makes a history event from pairs of cause and state name.
+    pub fn history(transitions: Vec<(String, String)>) -> Self {
+        FernspielEvent::History {
+            transitions: transitions
+                .into_iter()
+                .map(|(cause, state)| HistoryEntry { cause, state })
+                .collect(),
+        }
+    }
+
+    /// Makes an event describing the structure of the given book.
+    pub fn book(book: &Book) -> Self {
+        FernspielEvent::Book {
+            states: book.states().iter().map(StateSummary::from).collect(),
+            estimated_duration_ms: book.estimated_duration().map(|d| d.as_millis() as u64),
+            book_id: None,
+        }
+        .in_book(book.book_id())
+    }
+
+    /// Makes an event listing the addresses of connected clients.
+    pub fn peers(addrs: &[SocketAddr]) -> Self {
+        FernspielEvent::Peers {
+            count: addrs.len(),
+            addrs: addrs.iter().map(SocketAddr::to_string).collect(),
+        }
+    }
+
+    /// Makes an event naming the current state and how long ago
+    /// it was entered.
+    pub fn status(state: &State, elapsed: Duration) -> Self {
+        FernspielEvent::Status {
+            state: state.into(),
+            name: state.name().to_string(),
+            elapsed_ms: elapsed.as_millis() as u64,
+            book_id: None,
+        }
+    }
+
+    /// Serializes the event for sending it to clients.
+    pub fn serialize(&self, format: Format) -> Result<String> {
+        Ok(match format {
+            Format::Yaml => serde_yaml::to_string(self)?,
+            Format::Json => serde_json::to_string(self)?,
+        })
+    }
+
+    /// Makes an event that reports progress in the given state.
+    pub fn progress(state: &State, actuator: ActuatorProgress) -> Self {
+        FernspielEvent::Progress {
+            state: state.into(),
+            actuator,
+        }
+    }
+
+    /// Makes an event that tells if a phonebook has been loaded.
+    pub fn idle(idle: bool) -> Self {
+        FernspielEvent::Idle {
+            idle,
+            reason: if idle {
+                Some(IdleReason::NoPhonebook)
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Attributes the event to the phonebook with the given ID.
+    ///
+    /// Has no effect on events that are not about states.
+    pub fn in_book(mut self, id: Option<&str>) -> Self {
+        match &mut self {
+            FernspielEvent::Start { book_id, .. }
+            | FernspielEvent::Finish { book_id, .. }
+            | FernspielEvent::Transition { book_id, .. }
+            | FernspielEvent::Book { book_id, .. }
+            | FernspielEvent::Status { book_id, .. } => *book_id = id.map(String::from),
+            FernspielEvent::History { .. }
+            | FernspielEvent::Idle { .. }
+            | FernspielEvent::Peers { .. }
+            | FernspielEvent::Progress { .. }
+            | FernspielEvent::Compiling { .. }
+            | FernspielEvent::CompilationFailed { .. } => (),
+        }
+        self
+    }
+}
+
+/// The cause of a transition, for dial transitions optionally
+/// with the client that dialed, e.g.
+/// `{ dial: "type 1", description: "dialed 1", connection_id: 2 }`.
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct TransitionReason {
+    #[serde(flatten)]
+    cause: TransitionCause,
+    /// Human-readable cause, the same as in logs and history events.
+    description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connection_id: Option<ConnectionHandle>,
+}
+
+impl<'a> From<&'a Symbol> for TransitionReason {
+    fn from(symbol: &'a Symbol) -> Self {
+        let cause = match symbol {
+            Symbol::Dial(input) | Symbol::RemoteDial(input, _) => {
+                TransitionCause::Dial(input.to_string())
+            }
+            Symbol::Done(for_dur) => {
+                TransitionCause::Timeout((for_dur.as_millis() as f64) / 1000.0)
+            }
+            Symbol::Forced => TransitionCause::Forced(true),
+        };
+        TransitionReason {
+            cause,
+            description: symbol.to_string(),
+            connection_id: match symbol {
+                Symbol::RemoteDial(_, source) => Some(*source),
+                _ => None,
+            },
+        }
+    }
 }
 
 #[derive(Serialize, Clone, PartialEq, Debug)]
@@ -44,6 +289,10 @@ pub enum TransitionCause {
     /// keyboard.
     #[serde(rename = "dial")]
     Dial(String),
+    /// Code embedding the runtime forced the transition, the
+    /// value is always `true`.
+    #[serde(rename = "forced")]
+    Forced(bool),
 }
 
 impl<'a> From<&MachineEvent<'a>> for FernspielEvent {
@@ -51,23 +300,23 @@ impl<'a> From<&MachineEvent<'a>> for FernspielEvent {
         match event {
             MachineEvent::Start { initial } => FernspielEvent::Start {
                 initial: (*initial).into(),
+                reason: None,
+                book_id: None,
+            },
+            MachineEvent::Reset { initial, reason } => FernspielEvent::Start {
+                initial: (*initial).into(),
+                reason: Some(*reason),
+                book_id: None,
             },
             MachineEvent::Finish { terminal } => FernspielEvent::Finish {
                 terminal: (*terminal).into(),
+                book_id: None,
             },
             MachineEvent::Transition { cause, from, to } => FernspielEvent::Transition {
-                reason: match cause {
-                    Symbol::Dial(input) => TransitionCause::Dial(match input {
-                        Input::Digit(num) => format!("type {}", num),
-                        Input::HangUp => "hang up".to_string(),
-                        Input::PickUp => "pick up".to_string(),
-                    }),
-                    Symbol::Done(for_dur) => {
-                        TransitionCause::Timeout((for_dur.as_millis() as f64) / 1000.0)
-                    }
-                },
+                reason: cause.into(),
                 from: (*from).into(),
                 to: (*to).into(),
+                book_id: None,
             },
         }
     }
@@ -91,6 +340,8 @@ impl<'a> From<&'a State> for StateSummary {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::senses::Input;
+    use std::time::Duration;
 
     #[test]
     fn convert_transition_event() {
@@ -106,17 +357,194 @@ mod test {
 
         // then
         let expected_public_event = FernspielEvent::Transition {
-            reason: TransitionCause::Dial("pick up".to_string()),
+            reason: TransitionReason {
+                cause: TransitionCause::Dial("pick up".to_string()),
+                description: "picked up".to_string(),
+                connection_id: None,
+            },
             from: StateSummary {
                 id: "1".to_string(),
             },
             to: StateSummary {
                 id: "2".to_string(),
             },
+            book_id: None,
         };
         assert_eq!(public_event, expected_public_event)
     }
 
+    #[test]
+    fn connection_id_in_dial_transition_yaml() {
+        // given
+        let source = ConnectionHandle::generate().next().unwrap();
+        let dial = MachineEvent::Transition {
+            cause: Symbol::RemoteDial(Input::digit(1).unwrap(), source),
+            from: &State::builder().id("1").build(),
+            to: &State::builder().id("2").build(),
+        };
+        let timeout = MachineEvent::Transition {
+            cause: Symbol::Done(Duration::from_millis(1500)),
+            from: &State::builder().id("2").build(),
+            to: &State::builder().id("3").build(),
+        };
+
+        // when
+        let dial = FernspielEvent::from(&dial);
+        let timeout = FernspielEvent::from(&timeout);
+        let dial: serde_yaml::Value =
+            serde_yaml::from_str(&dial.serialize(Format::Yaml).unwrap()).unwrap();
+        let timeout: serde_yaml::Value =
+            serde_yaml::from_str(&timeout.serialize(Format::Yaml).unwrap()).unwrap();
+
+        // then
+        assert_eq!(dial["reason"]["dial"], "type 1");
+        assert_eq!(dial["reason"]["description"], "dialed 1");
+        assert_eq!(dial["reason"]["connection_id"], 1);
+        assert_eq!(timeout["reason"]["timeout"], 1.5);
+        assert_eq!(timeout["reason"]["description"], "done for 1.500s");
+        assert!(timeout["reason"].get("connection_id").is_none());
+    }
+
+    #[test]
+    fn book_id_in_start_event_yaml() {
+        // given
+        let internal_evt = MachineEvent::Start {
+            initial: &State::builder().id("1").build(),
+        };
+
+        // when
+        let public_event = FernspielEvent::from(&internal_evt).in_book(Some("intro"));
+        let serialized = serde_yaml::to_string(&public_event).unwrap();
+
+        // then
+        let parsed: serde_yaml::Value = serde_yaml::from_str(&serialized).unwrap();
+        assert_eq!(parsed["type"], "start");
+        assert_eq!(parsed["book_id"], "intro");
+    }
+
+    #[test]
+    fn history_event_yaml() {
+        // given
+        let history = FernspielEvent::history(vec![
+            ("picked up".to_string(), "intro".to_string()),
+            ("dialed 1".to_string(), "talk".to_string()),
+        ]);
+
+        // when
+        let serialized = serde_yaml::to_string(&history).unwrap();
+
+        // then
+        let parsed: serde_yaml::Value = serde_yaml::from_str(&serialized).unwrap();
+        assert_eq!(parsed["type"], "history");
+        assert_eq!(parsed["transitions"][0]["cause"], "picked up");
+        assert_eq!(parsed["transitions"][0]["state"], "intro");
+        assert_eq!(parsed["transitions"][1]["cause"], "dialed 1");
+        assert_eq!(parsed["transitions"][1]["state"], "talk");
+    }
+
+    #[test]
+    fn serialize_in_both_formats() {
+        // given
+        let event = FernspielEvent::history(vec![("picked up".to_string(), "intro".to_string())]);
+
+        // when
+        let yaml = event.serialize(Format::Yaml).unwrap();
+        let json = event.serialize(Format::Json).unwrap();
+
+        // then
+        let from_yaml: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(from_yaml["type"], "history");
+        assert_eq!(from_yaml["transitions"][0]["state"], "intro");
+        assert_eq!(
+            json,
+            r#"{"type":"history","transitions":[{"cause":"picked up","state":"intro"}]}"#
+        );
+    }
+
+    #[test]
+    fn format_from_header() {
+        assert_eq!(Format::from_header("json"), Some(Format::Json));
+        assert_eq!(Format::from_header(" JSON "), Some(Format::Json));
+        assert_eq!(Format::from_header("yaml"), Some(Format::Yaml));
+        assert_eq!(Format::from_header("xml"), None);
+    }
+
+    #[test]
+    fn peers_event_yaml() {
+        // given
+        let addrs: Vec<SocketAddr> = vec![
+            "127.0.0.1:50000".parse().unwrap(),
+            "192.168.0.2:50001".parse().unwrap(),
+        ];
+
+        // when
+        let event = FernspielEvent::peers(&addrs);
+        let parsed: serde_yaml::Value =
+            serde_yaml::from_str(&event.serialize(Format::Yaml).unwrap()).unwrap();
+
+        // then
+        assert_eq!(parsed["type"], "peers");
+        assert_eq!(parsed["count"], 2);
+        assert_eq!(parsed["addrs"][0], "127.0.0.1:50000");
+        assert_eq!(parsed["addrs"][1], "192.168.0.2:50001");
+    }
+
+    #[test]
+    fn status_event_yaml() {
+        // given
+        let state = State::builder().id("talk").name("Talking").build();
+
+        // when
+        let event = FernspielEvent::status(&state, Duration::from_millis(2500)).in_book(Some("b"));
+        let parsed: serde_yaml::Value =
+            serde_yaml::from_str(&event.serialize(Format::Yaml).unwrap()).unwrap();
+
+        // then
+        assert_eq!(parsed["type"], "status");
+        assert_eq!(parsed["state"]["id"], "talk");
+        assert_eq!(parsed["name"], "Talking");
+        assert_eq!(parsed["elapsed_ms"], 2500);
+        assert_eq!(parsed["book_id"], "b");
+    }
+
+    #[test]
+    fn idle_event_yaml() {
+        // given
+        let idle = FernspielEvent::idle(true);
+        let busy = FernspielEvent::idle(false);
+
+        // when
+        let idle: serde_yaml::Value =
+            serde_yaml::from_str(&serde_yaml::to_string(&idle).unwrap()).unwrap();
+        let busy: serde_yaml::Value =
+            serde_yaml::from_str(&serde_yaml::to_string(&busy).unwrap()).unwrap();
+
+        // then
+        assert_eq!(idle["type"], "idle");
+        assert_eq!(idle["idle"], true);
+        assert_eq!(idle["reason"], "no_phonebook");
+        assert_eq!(busy["idle"], false);
+        assert!(busy.get("reason").is_none());
+    }
+
+    #[test]
+    fn reason_in_reset_event_yaml() {
+        // given
+        let internal_evt = MachineEvent::Reset {
+            initial: &State::builder().id("1").build(),
+            reason: ResetReason::PhonebookSwitch,
+        };
+
+        // when
+        let public_event = FernspielEvent::from(&internal_evt);
+        let parsed: serde_yaml::Value =
+            serde_yaml::from_str(&public_event.serialize(Format::Yaml).unwrap()).unwrap();
+
+        // then
+        assert_eq!(parsed["type"], "start");
+        assert_eq!(parsed["reason"], "switch");
+    }
+
     #[test]
     fn generate_start_event_yaml() {
         // given
@@ -124,6 +552,8 @@ mod test {
             initial: StateSummary {
                 id: "1".to_string(),
             },
+            reason: None,
+            book_id: None,
         };
 
         // when
@@ -0,0 +1,51 @@
+use std::time::Instant;
+
+#[cfg(test)]
+pub use mock::MockClock;
+
+/// Source of the current time, e.g. for timeouts of a machine
+/// or fades of sounds.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+/// The real time of the system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+#[cfg(test)]
+mod mock {
+    use super::Clock;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    /// A clock that only advances when told to, so timeouts can
+    /// be tested without waiting.
+    ///
+    /// Clones share the same time.
+    #[derive(Clone, Debug)]
+    pub struct MockClock(Rc<Cell<Instant>>);
+
+    impl MockClock {
+        pub fn new() -> Self {
+            MockClock(Rc::new(Cell::new(Instant::now())))
+        }
+
+        pub fn advance(&self, by: Duration) {
+            self.0.set(self.0.get() + by)
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            self.0.get()
+        }
+    }
+}
@@ -1,52 +1,103 @@
+use super::clock::{Clock, SystemClock};
 use super::sym::Symbol;
 
-use crate::evt::{Event as EventForState, Responder, ResponderState};
+use crate::evt::{Event as EventForState, ResetReason, Responder, ResponderState};
 use crate::result::Result;
-use crate::senses::Sensors;
-use crate::states::State;
+use crate::senses::{QueueInput, QueuedInput, Sensors};
+use crate::states::{State, TransitionTarget};
 
+use failure::format_err;
 use log::{debug, error};
 
+#[cfg(test)]
+use failure::Fail;
+
 use std::mem::replace;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 type Event<'a> = EventForState<'a, State>;
 
 /// A state machine modelled after a mealy machine.
-pub struct Machine<R> {
+///
+/// Timeouts are measured with the given clock, which is the
+/// system time unless testing.
+pub struct Machine<R, C: Clock = SystemClock> {
     sensors: Sensors,
     responder: R,
+    clock: C,
     states: Vec<State>,
     current_state_idx: usize,
+    /// The state before the most recent transition, target of
+    /// transitions to history. `None` before the first transition.
+    prev_state_idx: Option<usize>,
     /// The time of the last transition and initially the startup time.
     last_enter_time: Instant,
     last_responder_state: ResponderState,
     /// Time when it was first detected that all actuators such as speech
     /// are finished. `None` if some actuator is still working.
     responder_done_time: Option<Instant>,
+    /// The last transitions with their cause and target state index,
+    /// oldest first.
+    history: Vec<(Symbol, usize)>,
+    /// Maximum number of transitions kept in the history.
+    history_size: usize,
+}
+
+/// Amount of transitions kept in the history if not configured
+/// otherwise.
+pub const DEFAULT_HISTORY_SIZE: usize = 32;
+
+/// A machine did not reach a terminal state within the allowed
+/// number of updates.
+#[cfg(test)]
+#[derive(Debug, Fail)]
+#[fail(display = "no terminal state reached after {} updates", ticks)]
+pub struct Timeout {
+    pub ticks: usize,
 }
 
 impl<R: Responder<State>> Machine<R> {
     pub fn new(sensors: Sensors, responder: R, states: &[State]) -> Self {
-        let now = Instant::now();
+        Self::with_clock(sensors, responder, states, SystemClock)
+    }
+}
+
+impl<R: Responder<State>, C: Clock + Clone> Machine<R, C> {
+    /// Makes a machine that measures time with the given clock.
+    pub fn with_clock(sensors: Sensors, responder: R, states: &[State], clock: C) -> Self {
+        Self::new_with_reason(sensors, responder, states, clock, ResetReason::InitialLoad)
+    }
+
+    fn new_with_reason(
+        sensors: Sensors,
+        responder: R,
+        states: &[State],
+        clock: C,
+        reason: ResetReason,
+    ) -> Self {
+        let now = clock.now();
         let mut machine = Machine {
             sensors,
             responder,
+            clock,
             states: states.to_vec(),
             current_state_idx: 0,
+            prev_state_idx: None,
             last_enter_time: now,
             // consider running until end of first update
             last_responder_state: ResponderState::Running,
             responder_done_time: None,
+            history: vec![],
+            history_size: DEFAULT_HISTORY_SIZE,
         };
-        machine.init();
+        machine.init(reason);
         machine
     }
 
-    fn init(&mut self) {
+    fn init(&mut self, reason: ResetReason) {
         assert!(!self.states.is_empty(), "Expected at least one state");
 
-        self.reset(); // some redundant work on first init, but needed on load
+        self.reset_with_reason(reason); // some redundant work on first init, but needed on load
         if let Err(err) = self.enter() {
             error!("Failed to enter initial state: {}", err);
         }
@@ -60,13 +111,28 @@ impl<R: Responder<State>> Machine<R> {
         let sensors = replace(&mut self.sensors, Sensors::blind());
 
         // Then overwrite self with newly initialized machine,
-        // re-using the old sensors
-        *self = Machine::new(sensors, responder, states);
+        // re-using the old sensors and history configuration
+        let history_size = self.history_size;
+        let clock = self.clock.clone();
+        *self = Machine::new_with_reason(
+            sensors,
+            responder,
+            states,
+            clock,
+            ResetReason::PhonebookSwitch,
+        );
+        self.history_size = history_size;
     }
 
+    /// Starts over with the initial state of the same states.
     pub fn reset(&mut self) {
+        self.reset_with_reason(ResetReason::PhonebookRewind)
+    }
+
+    fn reset_with_reason(&mut self, reason: ResetReason) {
         self.current_state_idx = 0;
-        self.last_enter_time = Instant::now();
+        self.prev_state_idx = None;
+        self.last_enter_time = self.clock.now();
         self.responder_done_time = None;
         // consider running until end of first update after reset
         self.last_responder_state = ResponderState::Running;
@@ -74,7 +140,7 @@ impl<R: Responder<State>> Machine<R> {
         // let actuators react to reset or load
         let initial = &self.states[self.current_state_idx];
         self.responder
-            .respond(&Event::Start { initial })
+            .respond(&Event::Reset { initial, reason })
             .unwrap_or_else(|e| {
                 error!(
                     "failed to react to transition to initial state, \
@@ -134,10 +200,150 @@ impl<R: Responder<State>> Machine<R> {
         !terminal
     }
 
-    fn current_state(&self) -> &State {
+    /// Updates the machine up to `n` times.
+    ///
+    /// Returns `true` if still running after all updates, `false`
+    /// if a terminal state was reached earlier.
+    #[cfg(test)]
+    pub fn step_n(&mut self, n: usize) -> bool {
+        (0..n).all(|_| self.update())
+    }
+
+    /// Updates the machine until a terminal state is reached, but
+    /// at most `max_ticks` times.
+    #[cfg(test)]
+    pub fn run_to_terminal(&mut self, max_ticks: usize) -> std::result::Result<(), Timeout> {
+        if self.step_n(max_ticks) {
+            Err(Timeout { ticks: max_ticks })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Transitions to the state at the index the given predicate
+    /// returns for the current state, if any, without waiting for
+    /// input, e.g. to go to a help state when a button is pressed.
+    ///
+    /// Returns `true` if a transition was performed.
+    pub fn transition_if(&mut self, predicate: impl Fn(&State) -> Option<usize>) -> bool {
+        let target = match predicate(self.current_state()) {
+            Some(target) => target,
+            None => return false,
+        };
+
+        if target >= self.states.len() {
+            error!(
+                "Ignoring forced transition to non-existing state {}",
+                target
+            );
+            return false;
+        }
+
+        if let Err(err) = self.transition_to(Symbol::Forced, target) {
+            error!("Failed to enter state after forced transition: {}", err);
+        }
+        true
+    }
+
+    /// Jumps to the state with the given ID as if a transition
+    /// was forced, e.g. for an operator to skip to a known point
+    /// in a story.
+    ///
+    /// Actuators respond like for any other transition, with a
+    /// start event if the target is the initial state.
+    pub fn reset_to(&mut self, state_id: &str) -> Result<()> {
+        let target = self
+            .states
+            .iter()
+            .position(|state| state.id() == state_id)
+            .ok_or_else(|| format_err!("No state with ID {:?}", state_id))?;
+
+        self.transition_to(Symbol::Forced, target)
+    }
+
+    /// The state the machine is currently in.
+    pub fn current_state(&self) -> &State {
         &self.states[self.current_state_idx]
     }
 
+    /// Name of the state the machine is currently in.
+    pub fn current_state_name(&self) -> &str {
+        self.current_state().name()
+    }
+
+    /// How long ago the current state was entered, as measured
+    /// by the clock of the machine.
+    pub fn time_in_current_state(&self) -> Duration {
+        self.clock.now() - self.last_enter_time
+    }
+
+    /// ID of the state the machine is currently in.
+    pub fn current_state_id(&self) -> &str {
+        self.current_state().id()
+    }
+
+    /// Index of the current state in `states`.
+    pub fn current_state_idx(&self) -> usize {
+        self.current_state_idx
+    }
+
+    /// Index of the state before the most recent transition, which
+    /// transitions to history go to, or `None` before the first
+    /// transition after loading or resetting.
+    #[cfg(test)]
+    pub fn prev_state_idx(&self) -> Option<usize> {
+        self.prev_state_idx
+    }
+
+    /// All states of the machine, in the order they were loaded.
+    pub fn states(&self) -> &[State] {
+        &self.states
+    }
+
+    /// Finds the state with the given unique ID.
+    pub fn state_by_id(&self, id: &str) -> Option<&State> {
+        self.states.iter().find(|state| state.id() == id)
+    }
+
+    /// Finds the first state with the given name, names are not
+    /// necessarily unique.
+    pub fn state_by_name(&self, name: &str) -> Option<&State> {
+        self.states.iter().find(|state| state.name() == name)
+    }
+
+    /// The last transitions since the states were loaded, oldest
+    /// first, with the cause and the index of the target state.
+    pub fn history(&self) -> &[(Symbol, usize)] {
+        &self.history
+    }
+
+    /// Sets the maximum number of transitions to keep in the history,
+    /// dropping the oldest ones if there are currently more.
+    pub fn set_history_size(&mut self, size: usize) {
+        self.history_size = size;
+        self.trim_history();
+    }
+
+    fn trim_history(&mut self) {
+        if self.history.len() > self.history_size {
+            let excess = self.history.len() - self.history_size;
+            self.history.drain(..excess);
+        }
+    }
+
+    /// Takes all pending input from the sensors without
+    /// processing it.
+    pub fn drain_input(&mut self) -> Vec<QueuedInput> {
+        self.sensors.drain()
+    }
+
+    /// Sends a copy of all input polled from now on to the
+    /// given queue, e.g. to let another machine advance on the
+    /// same input.
+    pub fn mirror_input(&mut self, to: &QueueInput) {
+        self.sensors.mirror(to)
+    }
+
     fn in_initial_state(&self) -> bool {
         self.current_state_idx == 0
     }
@@ -164,29 +370,55 @@ impl<R: Responder<State>> Machine<R> {
     fn poll_input(&mut self) -> Option<Symbol> {
         self.sensors
             .poll()
-            .map(Symbol::Dial)
+            .map(|(input, source)| match source {
+                Some(source) => Symbol::RemoteDial(input, source),
+                None => Symbol::Dial(input),
+            })
             // timeouts are only considered when there is no simultaneous input
-            .or_else(|| self.responder_done_time.map(|t| Symbol::Done(t.elapsed())))
+            .or_else(|| {
+                // and only after the minimum silence of the state has passed
+                let min_silence = self.current_state().min_silence();
+                let now = self.clock.now();
+                self.responder_done_time
+                    .and_then(|t| now.saturating_duration_since(t).checked_sub(min_silence))
+                    .map(Symbol::Done)
+            })
     }
 
     /// Finds a transition target index that should be transitioned to
     /// after reading the given symbol.
+    ///
+    /// Transitions to history go to the previous state, or nowhere
+    /// if there was no transition yet.
     fn find_transition(&mut self, symbol: &Symbol) -> Option<usize> {
         let state = self.current_state();
-        match symbol {
+        let target = match symbol {
             // Priority 1: transitions from dialing in this tick
-            Symbol::Dial(input) => state.transition_for_input(*input),
+            Symbol::Dial(input) | Symbol::RemoteDial(input, _) => state.target_for_input(*input),
             Symbol::Done(duration) => {
                 // Priority 2: timeout with time value
                 state
-                    .transition_for_timeout(duration)
+                    .target_for_timeout(duration)
                     // Priority 3: end transition from last tick
-                    .or_else(|| state.transition_end())
+                    .or_else(|| state.target_end())
             }
+            // forced transitions already know their target
+            Symbol::Forced => None,
+        };
+
+        match target? {
+            TransitionTarget::State(idx) => Some(idx),
+            TransitionTarget::History => self.prev_state_idx,
         }
     }
 
     fn actuate(&mut self) {
+        if self.responder_done_time.is_none() && !self.current_state().has_actuators() {
+            // nothing to wait for, time out from entering the state
+            // rather than from when the responder reports being idle
+            self.responder_done_time = Some(self.last_enter_time);
+        }
+
         self.last_responder_state = self.responder.update().unwrap_or_else(|e| {
             error!(
                 "failed to update actuators, \
@@ -198,7 +430,7 @@ impl<R: Responder<State>> Machine<R> {
 
         if self.responder_done_time.is_none() && self.responder_done() {
             debug!("Actuators done: {:?}", self.current_state().name());
-            self.responder_done_time = Some(Instant::now());
+            self.responder_done_time = Some(self.clock.now());
         }
     }
 
@@ -217,6 +449,17 @@ impl<R: Responder<State>> Machine<R> {
     fn transition_to(&mut self, cause: Symbol, idx: usize) -> Result<()> {
         let prev_idx = self.current_state_idx;
         self.current_state_idx = idx;
+        self.prev_state_idx = Some(prev_idx);
+
+        debug!(
+            "transition from \"{from}\" to \"{to}\", {cause}",
+            from = self.states[prev_idx].name(),
+            to = self.states[idx].name(),
+            cause = cause
+        );
+
+        self.history.push((cause, idx));
+        self.trim_history();
 
         self.respond_to_transition(cause, prev_idx, idx)
             .unwrap_or_else(|e| {
@@ -250,7 +493,7 @@ impl<R: Responder<State>> Machine<R> {
 
     /// Enters the current state.
     fn enter(&mut self) -> Result<()> {
-        self.last_enter_time = Instant::now();
+        self.last_enter_time = self.clock.now();
         self.responder_done_time = None;
         Ok(())
     }
@@ -259,12 +502,14 @@ impl<R: Responder<State>> Machine<R> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::acts::{Actuators, SoundSpec};
-    use crate::testutil::{
-        actual_speech_time, assert_duration, MediaInfo, TEST_MUSIC, WILHELM_SCREAM,
-    };
+    use crate::acts::{Actuators, Ensemble, SoundSpec};
+    use crate::senses::{Input, SensorsBuilder};
+    use crate::serve::ConnectionHandle;
+    use crate::states::MockClock;
+    use crate::testutil::{TEST_MUSIC, WILHELM_SCREAM};
+    use std::cell::RefCell;
+    use std::rc::Rc;
     use std::thread::yield_now;
-    use std::time::Duration;
 
     #[derive(Clone)]
     struct ValuedNullResponder(String);
@@ -303,9 +548,8 @@ mod test {
     fn timeout_starts_after_ringing() {
         crate::log::init_test_logging();
 
-        let ring_time = Duration::from_millis(200);
+        let ring_time = Duration::from_millis(50);
         let timeout = Duration::from_millis(350);
-        let expected_duration = ring_time + timeout;
 
         let states = &[
             State::builder()
@@ -316,31 +560,339 @@ mod test {
             State::builder().name("done").terminal(true).build(),
         ];
 
-        let mut machine = machine_with_states(states);
-        let test_duration = time_until_done_when_no_input(&mut machine);
+        let clock = MockClock::new();
+        let mut machine = machine_with_clock(states, &clock);
+        update_until_responder_done(&mut machine);
 
-        assert_duration("execution time", expected_duration, test_duration);
+        assert!(!done_after(&mut machine, &clock, timeout));
+        assert!(done_after(&mut machine, &clock, Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn timeout_starts_after_min_silence() {
+        crate::log::init_test_logging();
+
+        let min_silence = Duration::from_millis(200);
+        let timeout = Duration::from_millis(500);
+        let expected_duration = min_silence + timeout;
+
+        let states = &[
+            State::builder()
+                .name("silent")
+                .timeout_after_silence(min_silence, timeout, 1)
+                .build(),
+            State::builder().name("done").terminal(true).build(),
+        ];
+
+        let clock = MockClock::new();
+        let mut machine = machine_with_clock(states, &clock);
+        machine.update();
+
+        assert!(!done_after(&mut machine, &clock, expected_duration));
+        assert!(done_after(&mut machine, &clock, Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn silent_state_times_out_after_timeout() {
+        crate::log::init_test_logging();
+
+        let timeout = Duration::from_millis(300);
+        let states = &[
+            State::builder().name("silent").timeout(timeout, 1).build(),
+            State::builder().name("done").terminal(true).build(),
+        ];
+
+        let clock = MockClock::new();
+        let mut machine = machine_with_clock(states, &clock);
+        machine.update();
+
+        assert!(!done_after(&mut machine, &clock, timeout));
+        assert!(done_after(&mut machine, &clock, Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn timeouts_escalate() {
+        // given
+        let states = &[
+            State::builder()
+                .name("waiting")
+                .timeout(Duration::from_secs(5), 1)
+                .timeout(Duration::from_secs(30), 2)
+                .build(),
+            State::builder().name("prompt").build(),
+            State::builder().name("hint").build(),
+        ];
+        let target_after = |wait: Duration| {
+            let clock = MockClock::new();
+            let mut machine = machine_with_clock(states, &clock);
+            machine.update();
+            clock.advance(wait);
+            machine.update();
+            machine.current_state_name().to_string()
+        };
+
+        // when
+        let after_six_seconds = target_after(Duration::from_secs(6));
+        let after_thirty_one_seconds = target_after(Duration::from_secs(31));
+
+        // then
+        assert_eq!(after_six_seconds, "prompt");
+        assert_eq!(after_thirty_one_seconds, "hint");
+    }
+
+    #[test]
+    fn current_state_name_follows_transitions() {
+        // given
+        let states = &[
+            State::builder()
+                .name("waiting")
+                .timeout(Duration::from_secs(5), 1)
+                .build(),
+            State::builder().name("prompt").build(),
+        ];
+        let clock = MockClock::new();
+        let mut machine = machine_with_clock(states, &clock);
+
+        // when
+        let before = machine.current_state_name().to_string();
+        clock.advance(Duration::from_secs(6));
+        machine.update();
+        let after = machine.current_state_name().to_string();
+
+        // then
+        assert_eq!(before, "waiting");
+        assert_eq!(after, "prompt");
+    }
+
+    #[test]
+    fn time_in_current_state_restarts_on_transition() {
+        // given
+        let states = &[
+            State::builder()
+                .name("waiting")
+                .timeout(Duration::from_secs(5), 1)
+                .build(),
+            State::builder().name("prompt").build(),
+        ];
+        let clock = MockClock::new();
+        let mut machine = machine_with_clock(states, &clock);
+
+        // when
+        clock.advance(Duration::from_secs(3));
+        let while_waiting = machine.time_in_current_state();
+        clock.advance(Duration::from_secs(3));
+        machine.update();
+        let after_transition = machine.time_in_current_state();
+        clock.advance(Duration::from_secs(2));
+        let in_prompt = machine.time_in_current_state();
+
+        // then
+        assert_eq!(while_waiting, Duration::from_secs(3));
+        assert_eq!(after_transition, Duration::from_secs(0));
+        assert_eq!(in_prompt, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn step_n_keeps_running_without_transitions() {
+        // given
+        let mut machine = machine_with_states(&[State::builder().name("forever").build()]);
+
+        // when
+        let still_running = machine.step_n(100);
+        let result = machine.run_to_terminal(5);
+
+        // then
+        assert!(still_running);
+        assert_eq!(result.unwrap_err().ticks, 5);
+    }
+
+    #[test]
+    fn run_to_terminal_stops_at_terminal_state() {
+        // given
+        let mut machine = machine_with_states(&[
+            State::builder().name("start").end(1).build(),
+            State::builder().name("done").terminal(true).build(),
+        ]);
+
+        // when
+        let result = machine.run_to_terminal(10);
+
+        // then
+        assert!(result.is_ok());
+        assert_eq!(machine.current_state_name(), "done");
+        assert!(!machine.step_n(1));
+    }
+
+    #[test]
+    fn lookup_states_by_id_and_name() {
+        // given
+        let machine = machine_with_states(&[
+            State::builder().id("1").name("twin").build(),
+            State::builder().id("2").name("twin").build(),
+            State::builder().id("3").name("single").build(),
+        ]);
+
+        // when
+        let ids: Vec<&str> = machine.states().iter().map(State::id).collect();
+        let by_id = machine.state_by_id("2").map(State::name);
+        let by_name = machine.state_by_name("twin").map(State::id);
+        let missing_id = machine.state_by_id("4");
+        let missing_name = machine.state_by_name("nobody");
+
+        // then
+        assert_eq!(ids, vec!["1", "2", "3"]);
+        assert_eq!(by_id, Some("twin"));
+        assert_eq!(by_name, Some("1"));
+        assert!(missing_id.is_none());
+        assert!(missing_name.is_none());
+    }
+
+    #[test]
+    fn transition_if_goes_to_help_from_any_state() {
+        // given
+        let mut machine = machine_with_states(&[
+            State::builder()
+                .name("start")
+                .input(Input::pick_up(), 1)
+                .build(),
+            State::builder().name("talk").build(),
+            State::builder().name("help").build(),
+        ]);
+        let to_help = |state: &State| match state.name() {
+            "help" => None,
+            _ => Some(2),
+        };
+
+        // when
+        let from_start = machine.transition_if(to_help);
+        let name_after_start = machine.current_state_name().to_string();
+        let from_help = machine.transition_if(to_help);
+        machine.reset();
+        machine.transition_if(|_| Some(1));
+        let from_talk = machine.transition_if(to_help);
+        let out_of_bounds = machine.transition_if(|_| Some(3));
+
+        // then
+        assert!(from_start);
+        assert_eq!(name_after_start, "help");
+        assert!(!from_help);
+        assert!(from_talk);
+        assert_eq!(machine.current_state_name(), "help");
+        assert!(!out_of_bounds);
+        assert_eq!(machine.history().last().unwrap().0.to_string(), "forced");
     }
 
     #[cfg_attr(not(feature = "expensive_tests"), ignore)]
     #[test]
     fn timeout_starts_after_speech() {
-        let text = ".........";
-        let speech_time = actual_speech_time(text);
         let timeout = Duration::from_millis(220);
-        let expected_duration = speech_time + timeout;
-
-        let mut machine = machine_with_states(&[
+        let states = &[
             State::builder()
                 .name("speaking")
-                .speech(text)
+                .speech(".........")
                 .timeout(timeout, 1)
                 .build(),
             State::builder().name("done").terminal(true).build(),
-        ]);
-        let test_duration = time_until_done_when_no_input(&mut machine);
+        ];
 
-        assert_duration("execution time", expected_duration, test_duration);
+        let clock = MockClock::new();
+        let mut machine = machine_with_clock(states, &clock);
+        update_until_responder_done(&mut machine);
+
+        assert!(!done_after(&mut machine, &clock, timeout));
+        assert!(done_after(&mut machine, &clock, Duration::from_millis(1)));
+    }
+
+    /// Records the reasons of reset events.
+    struct ResetRecorder(Rc<RefCell<Vec<ResetReason>>>);
+    impl Responder<State> for ResetRecorder {
+        fn respond(&mut self, event: &Event) -> Result<()> {
+            if let Event::Reset { reason, .. } = event {
+                self.0.borrow_mut().push(*reason);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn reset_events_carry_reason() {
+        // given
+        let reasons = Rc::new(RefCell::new(vec![]));
+        let states = &[State::builder().name("only").build()];
+
+        // when
+        let mut machine = Machine::new(
+            SensorsBuilder::no_input().build(),
+            ResetRecorder(Rc::clone(&reasons)),
+            states,
+        );
+        machine.reset();
+        machine.load(ResetRecorder(Rc::clone(&reasons)), states);
+
+        // then
+        assert_eq!(
+            *reasons.borrow(),
+            vec![
+                ResetReason::InitialLoad,
+                ResetReason::PhonebookRewind,
+                ResetReason::PhonebookSwitch
+            ]
+        );
+    }
+
+    /// Records transition, start and finish events with state IDs.
+    struct EventRecorder(Rc<RefCell<Vec<String>>>);
+    impl Responder<State> for EventRecorder {
+        fn respond(&mut self, event: &Event) -> Result<()> {
+            let recorded = match event {
+                Event::Transition { cause, from, to } => {
+                    format!("{} -> {} ({})", from.id(), to.id(), cause)
+                }
+                Event::Start { initial } => format!("start {}", initial.id()),
+                Event::Finish { terminal } => format!("finish {}", terminal.id()),
+                _ => return Ok(()),
+            };
+            self.0.borrow_mut().push(recorded);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn reset_to_jumps_to_state() {
+        // given
+        let events = Rc::new(RefCell::new(vec![]));
+        let states = &[
+            State::builder().id("intro").build(),
+            State::builder().id("story").build(),
+            State::builder().id("end").terminal(true).build(),
+        ];
+        let mut machine = Machine::new(
+            SensorsBuilder::no_input().build(),
+            EventRecorder(Rc::clone(&events)),
+            states,
+        );
+
+        // when
+        machine.reset_to("end").unwrap();
+        let after_jump_to_end = machine.current_state_id().to_string();
+        machine.reset_to("story").unwrap();
+        machine.reset_to("intro").unwrap();
+        let unknown = machine.reset_to("nowhere");
+
+        // then
+        assert_eq!(after_jump_to_end, "end");
+        assert!(unknown.is_err(), "expected unknown ID to be an error");
+        assert_eq!(machine.current_state_id(), "intro");
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                "intro -> end (forced)",
+                "finish end",
+                "end -> story (forced)",
+                "story -> intro (forced)",
+                "start intro",
+            ]
+        );
     }
 
     #[test]
@@ -357,7 +909,7 @@ mod test {
         ];
 
         // when
-        let mut machine = Machine::new(Sensors::blind(), responder1, states);
+        let mut machine = Machine::new(SensorsBuilder::no_input().build(), responder1, states);
         let ValuedNullResponder(before) = machine.responder.clone();
         machine.load(responder2, states);
         let ValuedNullResponder(after) = machine.responder.clone();
@@ -405,7 +957,6 @@ mod test {
             SoundSpec::builder().source(TEST_MUSIC).build(),
             SoundSpec::builder().source(WILHELM_SCREAM).build(),
         ];
-        let scream_duration = MediaInfo::obtain(WILHELM_SCREAM).unwrap().actual_duration();
 
         // when
         let mut machine = machine_with_sound(&initial_states[..], initial_sounds);
@@ -415,7 +966,9 @@ mod test {
             &loaded_states,
         );
         let active_after_load = machine.update();
-        let duration = time_until_done_when_no_input(&mut machine);
+        while machine.update() {
+            yield_now()
+        }
 
         // then
         assert!(
@@ -426,30 +979,333 @@ mod test {
             active_after_load,
             "expected update to return true after loading new states"
         );
-        assert_duration("execution time", scream_duration, duration);
+        assert_eq!(machine.current_state_id(), "loaded terminal");
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "expensive_tests"), ignore)]
+    fn hanging_up_fades_out_sounds() {
+        // given
+        let fade_out = Duration::from_millis(200);
+        let mut sensors = Sensors::builder();
+        let (_, queue) = sensors.queue();
+        let states = &[
+            State::builder()
+                .name("music")
+                .sounds(vec![0])
+                .input(Input::hang_up(), 1)
+                .build(),
+            State::builder().name("silence").terminal(true).build(),
+        ];
+        let sounds = &[SoundSpec::builder()
+            .source(TEST_MUSIC)
+            .looping(true)
+            .build()];
+        let clock = MockClock::new();
+        let mut ensemble = Ensemble::from_specs(sounds).unwrap();
+        ensemble.set_clock(Rc::new(clock.clone()));
+        let mut actuators = Actuators::with_ensemble(&None, &Rc::new(RefCell::new(ensemble)));
+        actuators.set_fade_out(fade_out);
+        let mut machine = Machine::with_clock(sensors.build(), actuators, states, clock.clone());
+        machine.update();
+
+        // when
+        queue.send(Input::hang_up()).unwrap();
+        let fading = machine.update();
+        clock.advance(fade_out - Duration::from_millis(1));
+        let fading_before_end = machine.update();
+        clock.advance(Duration::from_millis(1));
+        let fading_after_end = machine.update();
+
+        // then
+        assert!(fading, "expected sound to fade out after hanging up");
+        assert!(
+            fading_before_end,
+            "expected fade out to take {:?}",
+            fade_out
+        );
+        assert!(!fading_after_end, "expected sound to stop after fade out");
+        assert!(machine.is_terminal());
     }
 
     fn null_actuators() -> Actuators {
         Actuators::new(&None, &[]).unwrap()
     }
 
+    #[test]
+    fn history_in_order() {
+        // given
+        let mut sensors = Sensors::builder();
+        let (_, queue) = sensors.queue();
+        let states = &[
+            State::builder()
+                .name("down")
+                .input(Input::pick_up(), 1)
+                .build(),
+            State::builder()
+                .name("up")
+                .input(Input::hang_up(), 0)
+                .build(),
+        ];
+        let mut machine = Machine::new(sensors.build(), null_actuators(), states);
+        let inputs = [
+            Input::pick_up(),
+            Input::hang_up(),
+            Input::pick_up(),
+            Input::hang_up(),
+            Input::pick_up(),
+        ];
+
+        // when
+        for input in inputs.iter() {
+            queue.send(*input).unwrap();
+            machine.update();
+        }
+
+        // then
+        let history: Vec<(String, usize)> = machine
+            .history()
+            .iter()
+            .map(|(sym, idx)| (sym.to_string(), *idx))
+            .collect();
+        assert_eq!(
+            history,
+            vec![
+                ("picked up".to_string(), 1),
+                ("hung up".to_string(), 0),
+                ("picked up".to_string(), 1),
+                ("hung up".to_string(), 0),
+                ("picked up".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn queued_dials_keep_their_source() {
+        // given
+        let mut sensors = Sensors::builder();
+        let (_, queue) = sensors.queue();
+        let states = &[
+            State::builder().input(Input::pick_up(), 1).build(),
+            State::builder().input(Input::hang_up(), 0).build(),
+        ];
+        let mut machine = Machine::new(sensors.build(), null_actuators(), states);
+        let mut handles = ConnectionHandle::generate();
+        let first = handles.next();
+        let second = handles.next();
+
+        // when
+        queue.send_from(Input::pick_up(), first).unwrap();
+        queue.send_from(Input::hang_up(), second).unwrap();
+        queue.send(Input::pick_up()).unwrap();
+        for _ in 0..3 {
+            machine.update();
+        }
+
+        // then
+        let sources: Vec<Option<ConnectionHandle>> = machine
+            .history()
+            .iter()
+            .map(|(sym, _)| match sym {
+                Symbol::RemoteDial(_, source) => Some(*source),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sources, vec![first, second, None]);
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        // given
+        let mut sensors = Sensors::builder();
+        let (_, queue) = sensors.queue();
+        let states = &[
+            State::builder().input(Input::digit(1).unwrap(), 1).build(),
+            State::builder().input(Input::digit(2).unwrap(), 0).build(),
+        ];
+        let mut machine = Machine::new(sensors.build(), null_actuators(), states);
+        machine.set_history_size(2);
+
+        // when
+        for digit in &[1, 2, 1, 2, 1] {
+            queue.send(Input::digit(*digit).unwrap()).unwrap();
+            machine.update();
+        }
+
+        // then
+        let causes: Vec<String> = machine
+            .history()
+            .iter()
+            .map(|(sym, _)| sym.to_string())
+            .collect();
+        assert_eq!(causes, vec!["dialed 2", "dialed 1"]);
+    }
+
+    #[test]
+    fn current_state_after_transitions() {
+        // given
+        let mut sensors = Sensors::builder();
+        let (_, queue) = sensors.queue();
+        let states = &[
+            State::builder()
+                .id("down")
+                .name("Receiver down")
+                .input(Input::pick_up(), 1)
+                .build(),
+            State::builder()
+                .id("up")
+                .name("Receiver up")
+                .input(Input::hang_up(), 0)
+                .build(),
+        ];
+        let mut machine = Machine::new(sensors.build(), ValuedNullResponder("null".into()), states);
+        let current = |machine: &Machine<ValuedNullResponder>| {
+            (
+                machine.current_state_idx(),
+                machine.current_state_id().to_string(),
+                machine.current_state_name().to_string(),
+            )
+        };
+
+        // when
+        let initial = current(&machine);
+        queue.send(Input::pick_up()).unwrap();
+        machine.update();
+        let after_pick_up = current(&machine);
+        queue.send(Input::hang_up()).unwrap();
+        machine.update();
+        let after_hang_up = current(&machine);
+
+        // then
+        assert_eq!(machine.states().len(), 2);
+        assert_eq!(initial, (0, "down".into(), "Receiver down".into()));
+        assert_eq!(after_pick_up, (1, "up".into(), "Receiver up".into()));
+        assert_eq!(after_hang_up, (0, "down".into(), "Receiver down".into()));
+    }
+
+    #[test]
+    fn history_transitions_go_back() {
+        // given
+        let mut sensors = Sensors::builder();
+        let (_, queue) = sensors.queue();
+        let back = Input::digit(0).unwrap();
+        let next = Input::digit(1).unwrap();
+        let states = &[
+            State::builder()
+                .id("first")
+                .input(next, 1)
+                .input(back, TransitionTarget::History)
+                .build(),
+            State::builder()
+                .id("second")
+                .input(next, 2)
+                .input(back, TransitionTarget::History)
+                .build(),
+            State::builder()
+                .id("third")
+                .input(back, TransitionTarget::History)
+                .build(),
+        ];
+        let mut machine = Machine::new(sensors.build(), null_actuators(), states);
+        let mut dial = |input: Input| {
+            queue.send(input).unwrap();
+            machine.update();
+            machine.current_state_id().to_string()
+        };
+
+        // when
+        let visited: Vec<String> = [next, next, back, back, back, next]
+            .iter()
+            .map(|input| dial(*input))
+            .collect();
+
+        // then
+        assert_eq!(
+            visited,
+            vec!["second", "third", "second", "third", "second", "third"]
+        );
+    }
+
+    #[test]
+    fn history_is_forgotten_on_reset() {
+        // given
+        let mut sensors = Sensors::builder();
+        let (_, queue) = sensors.queue();
+        let back = Input::digit(0).unwrap();
+        let states = &[
+            State::builder()
+                .input(Input::digit(1).unwrap(), 1)
+                .input(back, TransitionTarget::History)
+                .build(),
+            State::builder().input(Input::digit(2).unwrap(), 0).build(),
+        ];
+        let mut machine = Machine::new(sensors.build(), null_actuators(), states);
+        queue.send(Input::digit(1).unwrap()).unwrap();
+        machine.update();
+        queue.send(Input::digit(2).unwrap()).unwrap();
+        machine.update();
+        let prev_before_reset = machine.prev_state_idx();
+
+        // when
+        machine.reset();
+        let prev_after_reset = machine.prev_state_idx();
+        queue.send(back).unwrap();
+        machine.update();
+
+        // then
+        assert_eq!(prev_before_reset, Some(1));
+        assert_eq!(prev_after_reset, None);
+        assert_eq!(
+            machine.current_state_idx(),
+            0,
+            "expected going back without history to stay"
+        );
+        assert_eq!(
+            machine.history().len(),
+            2,
+            "expected no transition after reset"
+        );
+    }
+
     fn machine_with_states(states: &[State]) -> Machine<Actuators> {
-        Machine::new(Sensors::blind(), null_actuators(), states)
+        Machine::new(SensorsBuilder::no_input().build(), null_actuators(), states)
     }
 
     fn machine_with_sound(states: &[State], sounds: &[SoundSpec]) -> Machine<Actuators> {
         Machine::new(
-            Sensors::blind(),
+            SensorsBuilder::no_input().build(),
             Actuators::new(&None, sounds).unwrap(),
             states,
         )
     }
 
-    fn time_until_done_when_no_input<R: Responder<State>>(machine: &mut Machine<R>) -> Duration {
-        let test_start = Instant::now();
-        while machine.update() {
+    fn machine_with_clock(states: &[State], clock: &MockClock) -> Machine<Actuators, MockClock> {
+        Machine::with_clock(
+            SensorsBuilder::no_input().build(),
+            null_actuators(),
+            states,
+            clock.clone(),
+        )
+    }
+
+    /// Advances the clock by the given duration and checks if
+    /// the machine is in a terminal state afterwards.
+    fn done_after<R: Responder<State>>(
+        machine: &mut Machine<R, MockClock>,
+        clock: &MockClock,
+        duration: Duration,
+    ) -> bool {
+        clock.advance(duration);
+        machine.update();
+        machine.is_terminal()
+    }
+
+    /// Updates the machine without advancing its clock until its
+    /// responder reports being done, e.g. after ringing or speaking.
+    fn update_until_responder_done<R: Responder<State>>(machine: &mut Machine<R, MockClock>) {
+        while machine.responder_done_time.is_none() {
+            machine.update();
             yield_now()
-        } // Run until finished
-        test_start.elapsed() // And report how long it took
+        }
     }
 }
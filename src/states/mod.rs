@@ -1,9 +1,13 @@
 //! State machine and states
 
+mod clock;
 mod machine;
 mod state;
 mod sym;
 
-pub use machine::Machine;
-pub use state::{State, StateBuilder};
+#[cfg(test)]
+pub use clock::MockClock;
+pub use clock::{Clock, SystemClock};
+pub use machine::{Machine, DEFAULT_HISTORY_SIZE};
+pub use state::{State, StateBuilder, TransitionTarget};
 pub use sym::Symbol;
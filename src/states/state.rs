@@ -1,8 +1,61 @@
 use crate::senses::Input;
 pub use builder::StateBuilder;
 use std::collections::HashMap;
+use std::fmt;
 use std::time::Duration;
 
+/// Where a transition goes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransitionTarget {
+    /// The state with the index.
+    State(usize),
+    /// The state that was current before the most recent
+    /// transition, e.g. to go back.
+    History,
+}
+
+impl TransitionTarget {
+    /// Index of the target state, or `None` for history, which
+    /// is only known when running.
+    pub fn state(self) -> Option<usize> {
+        match self {
+            TransitionTarget::State(idx) => Some(idx),
+            TransitionTarget::History => None,
+        }
+    }
+}
+
+impl From<usize> for TransitionTarget {
+    fn from(idx: usize) -> Self {
+        TransitionTarget::State(idx)
+    }
+}
+
+/// What causes a transition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Input(Input),
+    /// A digit without a transition of its own.
+    AnyDigit,
+    /// Idle for the duration after the minimum silence.
+    Timeout(Duration),
+    /// All actuators are done.
+    End,
+}
+
+/// Formats like inputs, e.g. `"type 1"`, or as `"type any digit"`,
+/// `"timeout 1.5s"` or `"end"`.
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trigger::Input(input) => write!(f, "{}", input),
+            Trigger::AnyDigit => write!(f, "type any digit"),
+            Trigger::Timeout(after) => write!(f, "timeout {:?}", after),
+            Trigger::End => write!(f, "end"),
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone)]
 pub struct State {
     /// Unique ID of this state, for communication with
@@ -13,15 +66,20 @@ pub struct State {
     speech: String,
     sounds: Vec<usize>,
     /// Inputs against states to transition to
-    input_transitions: HashMap<Input, usize>,
-    /// If some, transitions to the state with the index
-    /// after the specified duration has passed after the
-    /// end of speech and all other actuators such as
-    /// ringing.
-    timeout_transition: Option<(Duration, usize)>,
+    input_transitions: HashMap<Input, TransitionTarget>,
+    /// Target for digits without a transition of their own
+    /// in `input_transitions`.
+    any_digit_transition: Option<TransitionTarget>,
+    /// Transitions to the target after the specified duration
+    /// has passed after the end of speech and all other actuators
+    /// such as ringing, sorted by duration, shortest first.
+    timeout_transitions: Vec<(Duration, TransitionTarget)>,
+    /// Time that actuators need to be idle before the state
+    /// considers them done, delaying timeout and end transitions.
+    min_silence: Duration,
     /// Transition to make after the speech has been
     /// spoken.
-    transition_end: Option<usize>,
+    transition_end: Option<TransitionTarget>,
     ring_time: Option<Duration>,
     terminal: bool,
 }
@@ -51,35 +109,135 @@ impl State {
         &self.sounds
     }
 
+    /// `true` if entering the state speaks, plays sounds or rings.
+    pub fn has_actuators(&self) -> bool {
+        !self.speech.is_empty() || !self.sounds.is_empty() || self.ring_time.is_some()
+    }
+
     /// Returns a transition target ID or `None` for no
-    /// transition.
+    /// transition or a transition to history.
     pub fn transition_for_input(&self, input: Input) -> Option<usize> {
-        self.input_transitions.get(&input).copied()
+        self.target_for_input(input)
+            .and_then(TransitionTarget::state)
+    }
+
+    /// Returns the target of the transition on the input, if any.
+    ///
+    /// Digits without a transition of their own fall back to the
+    /// transition for any digit.
+    pub fn target_for_input(&self, input: Input) -> Option<TransitionTarget> {
+        self.input_transitions.get(&input).copied().or(match input {
+            Input::Digit(_) => self.any_digit_transition,
+            _ => None,
+        })
     }
 
     /// Returns a transition target ID or `None` for no
-    /// transition.
+    /// transition or a transition to history.
     pub fn transition_for_timeout(&self, done_for: &Duration) -> Option<usize> {
-        if let Some((timeout_duration, timeout_target)) = self.timeout_transition.as_ref() {
-            if done_for > timeout_duration {
-                return Some(*timeout_target);
-            }
-        }
+        self.target_for_timeout(done_for)
+            .and_then(TransitionTarget::state)
+    }
+
+    /// Returns the target of the longest timeout that has passed
+    /// after being done for the given time, if any.
+    pub fn target_for_timeout(&self, done_for: &Duration) -> Option<TransitionTarget> {
+        self.timeout_transitions
+            .iter()
+            .rev()
+            .find(|(timeout_duration, _)| done_for > timeout_duration)
+            .map(|(_, target)| *target)
+    }
+
+    /// Minimum time of actuator idleness before timeout and
+    /// end transitions are considered.
+    ///
+    /// Timeouts are measured from the end of this silence.
+    pub fn min_silence(&self) -> Duration {
+        self.min_silence
+    }
 
-        None
+    /// Times of idleness after the minimum silence before the
+    /// timeout transitions, with their targets, shortest first.
+    ///
+    /// Transitions to history are left out.
+    pub fn timeout_transitions(&self) -> impl Iterator<Item = (Duration, usize)> + '_ {
+        self.timeout_transitions
+            .iter()
+            .filter_map(|(after, target)| target.state().map(|idx| (*after, idx)))
     }
 
     pub fn transition_end(&self) -> Option<usize> {
+        self.target_end().and_then(TransitionTarget::state)
+    }
+
+    /// Returns the target of the end transition, if any.
+    pub fn target_end(&self) -> Option<TransitionTarget> {
         self.transition_end
     }
 
+    /// Indexes of all states this state has a transition to,
+    /// on input, timeout or end, possibly with duplicates.
+    ///
+    /// Transitions to history are left out.
+    pub fn transition_targets(&self) -> impl Iterator<Item = usize> + '_ {
+        self.all_targets().filter_map(TransitionTarget::state)
+    }
+
+    /// `true` if any transition goes to the previous state.
+    pub fn has_history_transition(&self) -> bool {
+        self.all_targets()
+            .any(|target| target == TransitionTarget::History)
+    }
+
+    /// All transitions of the state with their trigger, inputs
+    /// first, in the order of digits, pick up, hang up and recall,
+    /// then timeouts, shortest first, and the end transition.
+    pub fn transitions(&self) -> Vec<(Trigger, TransitionTarget)> {
+        let mut inputs: Vec<(Input, TransitionTarget)> = self
+            .input_transitions
+            .iter()
+            .map(|(input, target)| (*input, *target))
+            .collect();
+        inputs.sort_by_key(|(input, _)| input_order(*input));
+
+        inputs
+            .into_iter()
+            .map(|(input, target)| (Trigger::Input(input), target))
+            .chain(
+                self.any_digit_transition
+                    .map(|target| (Trigger::AnyDigit, target)),
+            )
+            .chain(
+                self.timeout_transitions
+                    .iter()
+                    .map(|(after, target)| (Trigger::Timeout(*after), *target)),
+            )
+            .chain(self.transition_end.map(|target| (Trigger::End, target)))
+            .collect()
+    }
+
+    fn all_targets(&self) -> impl Iterator<Item = TransitionTarget> {
+        self.transitions().into_iter().map(|(_, target)| target)
+    }
+
     pub fn is_terminal(&self) -> bool {
         self.terminal
     }
 }
 
+fn input_order(input: Input) -> u8 {
+    match input {
+        Input::Digit(digit) => digit,
+        Input::PickUp => 10,
+        Input::HangUp => 11,
+        Input::RecallPress => 12,
+        Input::RecallRelease => 13,
+    }
+}
+
 mod builder {
-    use super::{Duration, Input, State};
+    use super::{Duration, Input, State, TransitionTarget};
 
     #[derive(Default)]
     pub struct StateBuilder {
@@ -105,18 +263,55 @@ mod builder {
             self
         }
 
-        pub fn input(mut self, on_input: Input, transition_to: usize) -> Self {
-            self.state.input_transitions.insert(on_input, transition_to);
+        pub fn input(
+            mut self,
+            on_input: Input,
+            transition_to: impl Into<TransitionTarget>,
+        ) -> Self {
+            self.state
+                .input_transitions
+                .insert(on_input, transition_to.into());
+            self
+        }
+
+        /// Transitions when dialing a digit that has no transition
+        /// set with `input`.
+        pub fn any_digit(mut self, transition_to: impl Into<TransitionTarget>) -> Self {
+            self.state.any_digit_transition = Some(transition_to.into());
             self
         }
 
-        pub fn timeout(mut self, after_duration: Duration, transition_to: usize) -> Self {
-            self.state.timeout_transition = Some((after_duration, transition_to));
+        /// Adds a timeout transition, can be called multiple times
+        /// for timeouts at different durations.
+        pub fn timeout(
+            mut self,
+            after_duration: Duration,
+            transition_to: impl Into<TransitionTarget>,
+        ) -> Self {
+            let timeouts = &mut self.state.timeout_transitions;
+            timeouts.push((after_duration, transition_to.into()));
+            timeouts.sort_by_key(|(after, _)| *after);
             self
         }
 
-        pub fn end(mut self, transition_to: usize) -> Self {
-            self.state.transition_end = Some(transition_to);
+        /// Transitions after the given timeout, but only starts
+        /// measuring it after actuators have been idle for at least
+        /// `min_silence`.
+        ///
+        /// The transition fires `min_silence + then_timeout_at`
+        /// after the actuators are done.
+        pub fn timeout_after_silence(
+            mut self,
+            min_silence: Duration,
+            then_timeout_at: Duration,
+            transition_to: impl Into<TransitionTarget>,
+        ) -> Self {
+            self.state.min_silence = min_silence;
+            self.timeout(then_timeout_at, transition_to)
+        }
+
+        pub fn end(mut self, transition_to: impl Into<TransitionTarget>) -> Self {
+            self.state.transition_end = Some(transition_to.into());
             self
         }
 
@@ -140,3 +335,52 @@ mod builder {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn longest_passed_timeout_wins() {
+        // given
+        let state = State::builder()
+            .timeout(Duration::from_secs(30), 2)
+            .timeout(Duration::from_secs(5), 1)
+            .build();
+
+        // when
+        let before = state.transition_for_timeout(&Duration::from_secs(4));
+        let after_first = state.transition_for_timeout(&Duration::from_secs(6));
+        let after_second = state.transition_for_timeout(&Duration::from_secs(31));
+
+        // then
+        assert_eq!(before, None);
+        assert_eq!(after_first, Some(1));
+        assert_eq!(after_second, Some(2));
+        assert_eq!(
+            state.timeout_transitions().collect::<Vec<_>>(),
+            vec![(Duration::from_secs(5), 1), (Duration::from_secs(30), 2)]
+        );
+    }
+
+    #[test]
+    fn specific_digit_before_any_digit() {
+        // given
+        let state = State::builder()
+            .input(Input::digit(1).unwrap(), 1)
+            .any_digit(2)
+            .build();
+
+        // when
+        let targets: Vec<Option<usize>> = (0..10)
+            .map(|digit| state.transition_for_input(Input::digit(digit).unwrap()))
+            .collect();
+
+        // then
+        assert_eq!(targets[1], Some(1));
+        for digit in [0, 2, 3, 4, 5, 6, 7, 8, 9] {
+            assert_eq!(targets[digit], Some(2), "expected {} to go to any", digit);
+        }
+        assert_eq!(state.transition_for_input(Input::hang_up()), None);
+    }
+}
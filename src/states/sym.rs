@@ -1,4 +1,6 @@
 use crate::senses::Input;
+use crate::serve::ConnectionHandle;
+use std::fmt;
 use std::time::Duration;
 
 /// A symbol of the input alphabet to the state machine.
@@ -6,7 +8,53 @@ use std::time::Duration;
 pub enum Symbol {
     /// Emitted once when receiving input from the hardware phone.
     Dial(Input),
+    /// Emitted once when receiving input from a remote control
+    /// client with the given connection.
+    RemoteDial(Input, ConnectionHandle),
     /// Emitted when all actuators are done with the duration
     /// indicating how long this condition is already true.
     Done(Duration),
+    /// Emitted when code embedding the machine forces a transition.
+    Forced,
+}
+
+/// Human-readable description for log messages, e.g. `"dialed 1"`
+/// or `"done for 2.500s"`.
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Symbol::Dial(input) | Symbol::RemoteDial(input, _) => match input {
+                Input::Digit(num) => write!(f, "dialed {}", num),
+                Input::PickUp => write!(f, "picked up"),
+                Input::HangUp => write!(f, "hung up"),
+                Input::RecallPress => write!(f, "pressed recall"),
+                Input::RecallRelease => write!(f, "released recall"),
+            },
+            Symbol::Done(duration) => write!(f, "done for {:.3}s", duration.as_secs_f64()),
+            Symbol::Forced => write!(f, "forced"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn display_done() {
+        assert_eq!(
+            format!("{}", Symbol::Done(Duration::from_secs_f64(2.5))),
+            "done for 2.500s"
+        );
+    }
+
+    #[test]
+    fn display_dial() {
+        assert_eq!(
+            format!("{}", Symbol::Dial(Input::digit(1).unwrap())),
+            "dialed 1"
+        );
+        assert_eq!(format!("{}", Symbol::Dial(Input::pick_up())), "picked up");
+        assert_eq!(format!("{}", Symbol::Dial(Input::hang_up())), "hung up");
+    }
 }
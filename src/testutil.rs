@@ -1,4 +1,7 @@
+use crate::acts::{self, Act, Wait};
+use failure::Error;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tavla::{any_voice, Speech, Voice};
 use tempfile::tempdir;
@@ -73,6 +76,31 @@ pub fn actual_speech_time(for_str: &str) -> Duration {
         .playing_duration()
 }
 
+/// A voice that finishes speaking right away, unless made with
+/// `speaking_for`, and remembers what it was asked to say.
+///
+/// Clones share the spoken texts.
+#[derive(Clone, Default)]
+pub struct MockVoice(Arc<Mutex<Vec<String>>>, Duration);
+
+impl MockVoice {
+    /// A voice that takes the given time for every text.
+    pub fn speaking_for(duration: Duration) -> Self {
+        MockVoice(Default::default(), duration)
+    }
+
+    pub fn spoken_texts(&self) -> Vec<String> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl acts::Voice for MockVoice {
+    fn speak(&self, text: &str) -> Result<Box<dyn Act>, Error> {
+        self.0.lock().unwrap().push(text.to_string());
+        Ok(Box::new(Wait::new(self.1)))
+    }
+}
+
 mod media {
     use crate::result::Result;
 
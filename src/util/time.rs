@@ -27,10 +27,10 @@ pub fn to_duration(secs: f64) -> Result<Duration, Error> {
             bail!("Duration is too high, numeric overflow: {}", secs)
         }
 
-        // nanos are always less than a million and cannot overflow
-        let nanos = ((secs - whole_secs_floating) * 1_000_000.0) as u32;
+        // micros are always less than a million and cannot overflow
+        let micros = ((secs - whole_secs_floating) * 1_000_000.0) as u32;
 
-        Ok(Duration::new(whole_secs_floating as u64, nanos))
+        Ok(Duration::new(whole_secs_floating as u64, micros * 1_000))
     }
 }
 
@@ -38,6 +38,18 @@ pub fn to_duration(secs: f64) -> Result<Duration, Error> {
 mod test {
     use super::*;
 
+    #[test]
+    fn fractional_seconds() {
+        // given
+        let duration = 1.5;
+
+        // when
+        let result = to_duration(duration).unwrap();
+
+        // then
+        assert_eq!(result, Duration::from_millis(1500));
+    }
+
     #[test]
     fn infinite_errs() {
         // given
@@ -0,0 +1,61 @@
+//! Checks that transitions under the `any` key apply to every
+//! state, unless a state defines its own transition for the same
+//! sensor.
+
+use fernspielapparat::books;
+use std::time::Duration;
+
+const PHONEBOOK_WITH_ANY: &str = "
+initial: intro
+states:
+  intro:
+  menu:
+  goodbye:
+    terminal: true
+transitions:
+  any:
+    end: goodbye
+    timeout:
+      after: 30
+      to: intro
+  menu:
+    end: intro
+";
+
+#[test]
+fn any_transitions_apply_to_all_states() {
+    // when
+    let book = books::from_str(PHONEBOOK_WITH_ANY).unwrap();
+
+    // then
+    let intro = position(&book, "intro");
+    let goodbye = position(&book, "goodbye");
+    for state in book.states() {
+        assert_eq!(
+            state.timeout_transitions().collect::<Vec<_>>(),
+            vec![(Duration::from_secs(30), intro)],
+            "Expected {} to have the timeout of any",
+            state.id()
+        );
+    }
+    assert_eq!(book.states()[intro].transition_end(), Some(goodbye));
+    assert_eq!(book.states()[goodbye].transition_end(), Some(goodbye));
+}
+
+#[test]
+fn state_transitions_override_any() {
+    // when
+    let book = books::from_str(PHONEBOOK_WITH_ANY).unwrap();
+
+    // then
+    let menu = position(&book, "menu");
+    let intro = position(&book, "intro");
+    assert_eq!(book.states()[menu].transition_end(), Some(intro));
+}
+
+fn position(book: &books::Book, id: &str) -> usize {
+    book.states()
+        .iter()
+        .position(|s| s.id() == id)
+        .unwrap_or_else(|| panic!("Expected state {} to exist", id))
+}
@@ -0,0 +1,70 @@
+//! Checks that errors when compiling phonebook files point to
+//! the line and column of the problem.
+
+use fernspielapparat::books;
+use std::fs::write;
+use tempfile::tempdir;
+
+#[test]
+fn unknown_transition_target_has_file_line_and_column() {
+    // given
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("phonebook.yaml");
+    write(
+        &path,
+        "initial: ring
+states:
+  ring:
+  talk:
+transitions:
+  ring:
+    pick_up: talk
+  talk:
+    hang_up: nowhere
+",
+    )
+    .unwrap();
+
+    // when
+    let message = books::from_path(&path).unwrap_err().to_string();
+
+    // then
+    assert_eq!(
+        message,
+        format!(
+            "{}:9:5: Transition to unknown state \"nowhere\"",
+            path.display()
+        )
+    );
+}
+
+#[test]
+fn bad_dial_pattern_in_any_has_line() {
+    // given
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("phonebook.yaml");
+    write(
+        &path,
+        "initial: ring
+states:
+  ring:
+transitions:
+  any:
+    dial:
+      12: ring
+",
+    )
+    .unwrap();
+
+    // when
+    let message = books::from_path(&path).unwrap_err().to_string();
+
+    // then
+    let expected_prefix = format!("{}:7:7: ", path.display());
+    assert!(
+        message.starts_with(&expected_prefix),
+        "Expected error at {}, got: {}",
+        expected_prefix,
+        message
+    );
+}
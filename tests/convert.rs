@@ -0,0 +1,37 @@
+//! Checks that sounds in formats other than WAV and MP3 are
+//! transcoded when compiling a phonebook.
+//!
+//! Requires `ffmpeg` to be installed, so only runs with the
+//! `expensive_tests` feature.
+
+use fernspielapparat::books;
+
+#[test]
+#[cfg_attr(not(feature = "expensive_tests"), ignore)]
+fn ogg_sound_is_converted_to_wav() {
+    // given
+    let yaml = "
+initial: scream
+states:
+  scream:
+    terminal: true
+    sounds:
+      - wilhelm
+sounds:
+  wilhelm:
+    file: test/Wilhelm_Scream.ogx
+";
+
+    // when
+    let book = books::from_str(yaml).unwrap();
+
+    // then
+    let source = book.sounds()[0].source();
+    assert_eq!(
+        source.extension().and_then(|ext| ext.to_str()),
+        Some("wav"),
+        "Expected {:?} to be converted to WAV",
+        source
+    );
+    assert!(source.is_file(), "Expected {:?} to exist", source);
+}
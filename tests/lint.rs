@@ -0,0 +1,158 @@
+//! Lints the phonebooks in `test/lint`, each with a single kind
+//! of problem.
+
+use fernspielapparat::check::{lint_phonebook, LintKind, LintReport};
+
+fn lint(fixture: &str) -> LintReport {
+    lint_phonebook(format!("test/lint/{}.yaml", fixture)).unwrap()
+}
+
+fn kinds(report: &LintReport) -> Vec<LintKind> {
+    report.warnings.iter().map(|w| w.kind).collect()
+}
+
+#[test]
+fn clean_phonebook_has_no_warnings() {
+    // when
+    let report = lint("clean");
+
+    // then
+    assert_eq!(report.warnings, vec![]);
+    assert!(!report.has_errors());
+}
+
+#[test]
+fn unreachable_state_is_a_warning() {
+    // when
+    let report = lint("unreachable_state");
+
+    // then
+    assert_eq!(kinds(&report), vec![LintKind::UnreachableState]);
+    assert!(!report.has_errors());
+    let warning = &report.warnings[0];
+    assert!(warning.message.contains("\"forgotten\""), "{}", warning);
+    assert_eq!(warning.location.as_ref().map(|l| l.line), Some(6));
+}
+
+#[test]
+fn self_transition_is_a_warning() {
+    // when
+    let report = lint("self_transition");
+
+    // then
+    assert_eq!(kinds(&report), vec![LintKind::SelfTransition]);
+    assert!(!report.has_errors());
+    assert!(
+        report.warnings[0]
+            .message
+            .contains("\"talk\" transitions to itself on type 0"),
+        "{}",
+        report.warnings[0]
+    );
+}
+
+#[test]
+fn undefined_sound_is_an_error() {
+    // when
+    let report = lint("undefined_sound");
+
+    // then
+    assert_eq!(kinds(&report), vec![LintKind::UndefinedSound]);
+    assert!(report.has_errors());
+    let warning = &report.warnings[0];
+    assert!(warning.message.contains("\"drums\""), "{}", warning);
+    assert_eq!(warning.location.as_ref().map(|l| l.line), Some(3));
+}
+
+#[test]
+fn missing_audio_file_is_an_error() {
+    // when
+    let report = lint("missing_audio_file");
+
+    // then
+    assert_eq!(kinds(&report), vec![LintKind::MissingAudioFile]);
+    assert!(report.has_errors());
+    let warning = &report.warnings[0];
+    assert!(
+        warning.message.contains("does_not_exist.wav"),
+        "{}",
+        warning
+    );
+    assert_eq!(warning.location.as_ref().map(|l| l.line), Some(9));
+}
+
+#[test]
+fn structure_is_checked_despite_broken_sounds() {
+    // when
+    let report = lint("broken_sound_and_unreachable_state");
+
+    // then
+    assert_eq!(
+        kinds(&report),
+        vec![
+            LintKind::UndefinedSound,
+            LintKind::MissingAudioFile,
+            LintKind::UnreachableState
+        ]
+    );
+    assert!(report.has_errors());
+    let unreachable = &report.warnings[2];
+    assert!(
+        unreachable.message.contains("\"forgotten\""),
+        "{}",
+        unreachable
+    );
+    assert_eq!(unreachable.location.as_ref().map(|l| l.line), Some(9));
+}
+
+#[test]
+fn duplicate_state_is_an_error() {
+    // when
+    let report = lint("duplicate_state");
+
+    // then
+    assert_eq!(kinds(&report), vec![LintKind::DuplicateState]);
+    assert!(report.has_errors());
+    let warning = &report.warnings[0];
+    assert!(warning.message.contains("\"ring\""), "{}", warning);
+    assert_eq!(warning.location.as_ref().map(|l| l.line), Some(6));
+}
+
+#[test]
+fn dead_end_is_an_error() {
+    // when
+    let report = lint("dead_end");
+
+    // then
+    assert_eq!(kinds(&report), vec![LintKind::DeadEnd]);
+    assert!(report.has_errors());
+    let warning = &report.warnings[0];
+    assert!(warning.message.contains("\"stuck\""), "{}", warning);
+    assert_eq!(warning.location.as_ref().map(|l| l.line), Some(4));
+}
+
+#[test]
+fn compile_error_is_invalid() {
+    // when
+    let report = lint("invalid");
+
+    // then
+    assert_eq!(kinds(&report), vec![LintKind::Invalid]);
+    assert!(report.has_errors());
+    assert!(
+        report.warnings[0]
+            .message
+            .contains("unknown state \"nowhere\""),
+        "{}",
+        report.warnings[0]
+    );
+}
+
+#[test]
+fn unreadable_file_is_error() {
+    // when
+    let result = lint_phonebook("test/lint/does_not_exist.yaml");
+
+    // then
+    assert!(result.is_err());
+}
@@ -1,3 +1,4 @@
+use fernspielapparat::app::TerminationCause;
 use std::thread::spawn;
 use websocket::client::builder::ClientBuilder;
 use websocket::OwnedMessage;
@@ -29,18 +30,31 @@ const HANG_UP: &str = "{
     \"with\": \"h\"
 }";
 
+const SET_PHONEBOOK_WITH_UNDEFINED_INITIAL: &str = "{
+    \"invoke\": \"run\",
+    \"with\": {
+        \"initial\":\"missing\",
+        \"states\":{
+            \"initial\":{}
+        }
+    }
+}";
+
 const START_ON_PASSIVE_EVT: &str = "---
 type: start
 initial:
-  id: passive";
+  id: passive
+reason: initial_load";
 const START_ON_INITIAL_EVT: &str = "---
 type: start
 initial:
-  id: initial";
+  id: initial
+reason: switch";
 const INITIAL_TO_TERMINAL_EVT: &str = "---
 type: transition
 reason:
   timeout: 0.0
+  description: done for 0.000s
 from:
   id: initial
 to:
@@ -65,11 +79,14 @@ transitions:
 const START_ON_ONE_EVT: &str = "---
 type: start
 initial:
-  id: one";
+  id: one
+reason: initial_load";
 const TRANSITION_TO_TWO_EVT: &str = "---
 type: transition
 reason:
   dial: type 1
+  description: dialed 1
+  connection_id: 1
 from:
   id: one
 to:
@@ -92,7 +109,7 @@ fn deploy_and_then_observe_transition() {
             .unwrap();
         app.exit_on_terminal_state();
         let mut app = app.build().unwrap();
-        app.run().unwrap();
+        assert_eq!(app.run().unwrap(), TerminationCause::TerminalState);
     });
 
     let mut client = ClientBuilder::new(&format!("ws://127.0.0.1:{port}", port = port))
@@ -105,7 +122,7 @@ fn deploy_and_then_observe_transition() {
         .send_message(&OwnedMessage::Text(SET_PHONEBOOK.to_string()))
         .unwrap();
 
-    let mut incoming = client.incoming_messages();
+    let mut incoming = client.incoming_messages().filter(|msg| !is_idle_event(msg));
     let event_start_passive = incoming
         .next()
         .expect("expected message for the transition to start next")
@@ -162,7 +179,7 @@ fn observe_transition_from_dial() {
     app.exit_on_terminal_state();
     spawn(move || {
         let mut app = app.build().unwrap();
-        app.run().unwrap();
+        assert_eq!(app.run().unwrap(), TerminationCause::TerminalState);
     });
     //std::thread::sleep(std::time::Duration::from_secs(5));
     let client = ClientBuilder::new(&format!("ws://127.0.0.1:{port}/", port = port))
@@ -172,7 +189,7 @@ fn observe_transition_from_dial() {
         .expect("failed to make ws connection");
     let (mut rx, mut tx) = client.split().unwrap();
 
-    let mut incoming = rx.incoming_messages();
+    let mut incoming = rx.incoming_messages().filter(|msg| !is_idle_event(msg));
     let event_start_passive = incoming
         .next()
         .expect("expected message of starting at the initial state")
@@ -224,7 +241,7 @@ fn avoid_double_transition() {
     app.exit_on_terminal_state();
     spawn(move || {
         let mut app = app.build().unwrap();
-        app.run().unwrap();
+        assert_eq!(app.run().unwrap(), TerminationCause::TerminalState);
     });
     let client = ClientBuilder::new(&format!("ws://127.0.0.1:{port}/", port = port))
         .unwrap()
@@ -233,7 +250,7 @@ fn avoid_double_transition() {
         .expect("failed to make ws connection");
     let (mut rx, mut tx) = client.split().unwrap();
 
-    let mut incoming = rx.incoming_messages();
+    let mut incoming = rx.incoming_messages().filter(|msg| !is_idle_event(msg));
     let _event_start_ring = incoming
         .next()
         .expect("expected message of starting at the initial state")
@@ -273,48 +290,144 @@ fn avoid_double_transition() {
     // then: there should be only two transitions triggered by the dialing of ones
     assert_eq!(
         event_transition_to_introduce,
-        dial_transition_evt_msg("pick up", "RING", "INTRODUCE"),
+        dial_transition_evt_msg("pick up", "picked up", "RING", "INTRODUCE"),
         "Expecting picking up to result in transition to INTRODUCE"
     );
     assert_eq!(
         event_transition_to_talk,
-        dial_transition_evt_msg("type 1", "INTRODUCE", "TALK"),
+        dial_transition_evt_msg("type 1", "dialed 1", "INTRODUCE", "TALK"),
         "Expecting first dial of one to result in transition to TALK"
     );
     assert_eq!(
         event_transition_to_quiet,
-        dial_transition_evt_msg("type 1", "TALK", "QUIET"),
+        dial_transition_evt_msg("type 1", "dialed 1", "TALK", "QUIET"),
         "Expecting second dial of one to result in transition to QUIET"
     );
     assert_eq!(
         event_transition_to_talk_second_time,
-        dial_transition_evt_msg("type 1", "QUIET", "TALK"),
+        dial_transition_evt_msg("type 1", "dialed 1", "QUIET", "TALK"),
         "Expecting third dial of one to result in transition back to QUIET"
     );
     assert_eq!(
         event_transition_to_pause,
-        dial_transition_evt_msg("hang up", "TALK", "PAUSE"),
+        dial_transition_evt_msg("hang up", "hung up", "TALK", "PAUSE"),
         "Expecting hanging up to result in transition to PAUSE"
     );
 }
 
+#[test]
+fn invalid_phonebook_is_reported_to_clients() {
+    // given
+    let port = random_port();
+    let mut app = fernspielapparat::App::builder();
+    app.serve(&format!("127.0.0.1:{port}", port = port))
+        .unwrap();
+    spawn(move || {
+        let mut app = app.build().unwrap();
+        app.run().unwrap();
+    });
+    let client = ClientBuilder::new(&format!("ws://127.0.0.1:{port}/", port = port))
+        .unwrap()
+        .add_protocol("fernspielctl")
+        .connect_insecure()
+        .expect("failed to make ws connection");
+    let (mut rx, mut tx) = client.split().unwrap();
+
+    // when
+    tx.send_message(&OwnedMessage::Text(
+        SET_PHONEBOOK_WITH_UNDEFINED_INITIAL.to_string(),
+    ))
+    .unwrap();
+    let report = rx
+        .incoming_messages()
+        .filter_map(|msg| match msg.expect("expected ok message") {
+            OwnedMessage::Text(text) => Some(text),
+            _ => None,
+        })
+        .find(|text| text.contains("type: compilation_failed"))
+        .expect("expected a compilation failure event");
+
+    tx.send_message(&OwnedMessage::Close(None)).unwrap();
+    tx.shutdown_all().unwrap();
+
+    // then
+    assert!(
+        report.contains("missing"),
+        "Expected compile error message in report: {}",
+        report
+    );
+}
+
+#[test]
+fn idle_state_is_sent_on_connect() {
+    // given
+    let port = random_port();
+    let mut app = fernspielapparat::App::builder();
+    app.serve(&format!("127.0.0.1:{port}", port = port))
+        .unwrap();
+    spawn(move || {
+        let mut app = app.build().unwrap();
+        app.run().unwrap();
+    });
+
+    // when
+    let client = ClientBuilder::new(&format!("ws://127.0.0.1:{port}/", port = port))
+        .unwrap()
+        .add_protocol("fernspielctl")
+        .connect_insecure()
+        .expect("failed to make ws connection");
+    let (mut rx, mut tx) = client.split().unwrap();
+    let first = rx
+        .incoming_messages()
+        .next()
+        .expect("expected idle event after connecting")
+        .expect("expected ok message");
+
+    tx.send_message(&OwnedMessage::Close(None)).unwrap();
+    tx.shutdown_all().unwrap();
+
+    // then
+    assert_eq!(
+        first,
+        OwnedMessage::Text(
+            "---
+type: idle
+idle: true
+reason: no_phonebook"
+                .to_string()
+        )
+    );
+}
+
 fn random_port() -> u32 {
     let rand: u32 = rand::random();
     10_000 + rand % 50_000
 }
 
-fn dial_transition_evt_msg(dial: &str, from: &str, to: &str) -> OwnedMessage {
+fn dial_transition_evt_msg(dial: &str, description: &str, from: &str, to: &str) -> OwnedMessage {
     OwnedMessage::Text(format!(
         "---
 type: transition
 reason:
   dial: {dial}
+  description: {description}
+  connection_id: 1
 from:
   id: {from}
 to:
   id: {to}",
         dial = dial,
+        description = description,
         from = from,
         to = to
     ))
 }
+
+/// Idle events are sent to every client when it connects, before
+/// any other event.
+fn is_idle_event<E>(msg: &Result<OwnedMessage, E>) -> bool {
+    match msg {
+        Ok(OwnedMessage::Text(text)) => text.contains("type: idle"),
+        _ => false,
+    }
+}
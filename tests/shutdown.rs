@@ -0,0 +1,93 @@
+//! Checks that the remote control server does not leave threads
+//! behind after the app exits.
+//!
+//! Kept in its own test binary, so threads of other tests do not
+//! interfere with counting.
+#![cfg(target_os = "linux")]
+
+use fernspielapparat::app::TerminationCause;
+use std::fs::{read_dir, read_to_string};
+use std::thread::spawn;
+use websocket::client::builder::ClientBuilder;
+use websocket::OwnedMessage;
+
+const PHONEBOOK_WITH_DIAL_TRANSITION: &str = "---
+initial: one
+states:
+  one:
+    terminal: false
+  two:
+    terminal: true
+transitions:
+  one:
+    dial:
+      1: two";
+
+const DIAL_ONE: &str = "{
+    \"invoke\": \"dial\",
+    \"with\": \"1\"
+}";
+
+/// Prefix of the names of all threads of the remote control server.
+const SERVER_THREAD_PREFIX: &str = "ctl-";
+
+#[test]
+fn no_server_threads_after_exit() {
+    // given
+    let port = random_port();
+    let mut app = fernspielapparat::App::builder();
+    app.startup_phonebook(
+        fernspielapparat::books::from_str(PHONEBOOK_WITH_DIAL_TRANSITION).unwrap(),
+    );
+    app.serve(&format!("127.0.0.1:{port}", port = port))
+        .unwrap();
+    app.exit_on_terminal_state();
+    let app = spawn(move || {
+        let mut app = app.build().unwrap();
+        assert_eq!(app.run().unwrap(), TerminationCause::TerminalState);
+    });
+    let client = ClientBuilder::new(&format!("ws://127.0.0.1:{port}/", port = port))
+        .unwrap()
+        .add_protocol("fernspielctl")
+        .connect_insecure()
+        .expect("failed to make ws connection");
+    let (mut rx, mut tx) = client.split().unwrap();
+
+    // when
+    let server_threads_while_running = server_threads();
+    tx.send_message(&OwnedMessage::Text(DIAL_ONE.to_string()))
+        .unwrap();
+    // read until the server closes the connection on exit
+    let closed_by_server = rx
+        .incoming_messages()
+        .any(|msg| msg.map(|msg| msg.is_close()).unwrap_or(true));
+    app.join().expect("app exited with panic");
+    let server_threads_after_exit = server_threads();
+
+    // then
+    assert!(
+        !server_threads_while_running.is_empty(),
+        "expected server threads while app is running"
+    );
+    assert!(closed_by_server, "expected server to close connection");
+    assert!(
+        server_threads_after_exit.is_empty(),
+        "expected no server threads after app exited, but found: {:?}",
+        server_threads_after_exit
+    );
+}
+
+/// Names of threads of this process that belong to the server.
+fn server_threads() -> Vec<String> {
+    read_dir("/proc/self/task")
+        .unwrap()
+        .filter_map(|task| read_to_string(task.ok()?.path().join("comm")).ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| name.starts_with(SERVER_THREAD_PREFIX))
+        .collect()
+}
+
+fn random_port() -> u32 {
+    let rand: u32 = rand::random();
+    10_000 + rand % 50_000
+}
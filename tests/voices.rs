@@ -0,0 +1,109 @@
+//! Checks that speech of sounds is synthesized with the voice,
+//! speed and pitch configured in the phonebook.
+//!
+//! Requires `espeak` to be installed with English and German
+//! voices.
+
+use fernspielapparat::books;
+use hound::WavReader;
+use std::fs::read;
+use std::path::Path;
+use std::time::Duration;
+
+fn wav_duration(path: &Path) -> Duration {
+    let reader = WavReader::open(path).unwrap();
+    Duration::from_secs_f64(reader.duration() as f64 / reader.spec().sample_rate as f64)
+}
+
+#[test]
+fn different_voices_speak_differently() {
+    // given
+    let yaml = "
+initial: greeting
+states:
+  greeting:
+    terminal: true
+    sounds:
+      - english
+      - german
+sounds:
+  english:
+    speech: Hello
+    voice: en-us
+  german:
+    speech: Hello
+    voice: de
+";
+
+    // when
+    let book = books::from_str(yaml).unwrap();
+
+    // then
+    let english = book.sounds()[0].source();
+    let german = book.sounds()[1].source();
+    assert_ne!(english, german);
+    assert_ne!(
+        read(english).unwrap(),
+        read(german).unwrap(),
+        "Expected the same text to sound different with other voices"
+    );
+}
+
+#[test]
+fn unknown_voice_is_an_error() {
+    // given
+    let yaml = "
+initial: greeting
+states:
+  greeting:
+    terminal: true
+    sounds:
+      - greeting
+sounds:
+  greeting:
+    speech: Hello
+    voice: no-such-voice
+";
+
+    // when
+    let error = books::from_str(yaml).unwrap_err();
+
+    // then
+    assert!(error.to_string().contains("no-such-voice"), "{}", error);
+}
+
+#[test]
+fn slower_speech_takes_longer() {
+    // given
+    let yaml = "
+initial: greeting
+states:
+  greeting:
+    terminal: true
+    sounds:
+      - slow
+      - fast
+sounds:
+  slow:
+    speech: Welcome to the exhibition
+    tts_rate: 80
+  fast:
+    speech: Welcome to the exhibition
+    tts_rate: 300
+    tts_pitch: 80
+";
+
+    // when
+    let book = books::from_str(yaml).unwrap();
+
+    // then
+    let slow = wav_duration(book.sounds()[0].source());
+    let fast = wav_duration(book.sounds()[1].source());
+    assert!(
+        slow > fast * 2,
+        "Expected speech at 80 words per minute to take much longer than at 300, \
+         but took {:?} and {:?}",
+        slow,
+        fast
+    );
+}